@@ -0,0 +1,43 @@
+//! Benchmarks for the core's hot paths: drawing sprites and copying the screen into the window's
+//! buffer.
+
+use chip_8_emu::chip8::Chip8;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use std::hint::black_box;
+
+/// DXYN for the full 15 rows at a spot that wraps around both edges, XORing over what's there
+fn sprite_draw(c: &mut Criterion) {
+    let mut chip8 = Chip8::builder().seed(0).build();
+    chip8.set_register(0, 60);
+    chip8.set_register(1, 25);
+    chip8.write_memory(0x300, &[0xFF; 15]);
+    chip8.set_index(0x300);
+    // Each run on a fresh copy, as the program counter would otherwise run off the end of memory
+    c.bench_function("draw 8x15 sprite", |b| {
+        b.iter_batched_ref(
+            || chip8.clone(),
+            |chip8| chip8.execute_opcode(black_box(0xD01F)).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+/// The whole screen into a 0RGB buffer
+fn framebuffer_conversion(c: &mut Criterion) {
+    let mut chip8 = Chip8::builder().seed(0).build();
+    let mut buffer = vec![0u32; 64 * 32];
+    for (x, y) in (0..16).map(|i| (i * 4, i * 2)) {
+        chip8.set_register(0, x);
+        chip8.set_register(1, y);
+        chip8.execute_opcode(0xD01F).unwrap();
+    }
+    c.bench_function("draw screen to buffer", |b| {
+        b.iter(|| {
+            chip8.force_redraw();
+            chip8.draw_to_buffer(black_box(&mut buffer));
+        })
+    });
+}
+
+criterion_group!(benches, sprite_draw, framebuffer_conversion);
+criterion_main!(benches);
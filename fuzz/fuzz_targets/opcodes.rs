@@ -0,0 +1,37 @@
+//! Streams of random instructions run from a random machine state. Every one must either run or
+//! be refused with a fault, never panic.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use chip_8_emu::chip8::{Chip8, Variant};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    chip8x: bool,
+    registers: [u8; 16],
+    index: u16,
+    delay: u8,
+    sound: u8,
+    opcodes: Vec<u16>,
+}
+
+fuzz_target!(|input: Input| {
+    let mut chip8 = Chip8::builder()
+        .variant(if input.chip8x { Variant::Chip8X } else { Variant::Chip8 })
+        .seed(0)
+        .build();
+    let program: Vec<u8> = input.opcodes.iter().take(0x700).flat_map(|opcode| opcode.to_be_bytes()).collect();
+    chip8.load_program(&program);
+    for (register, value) in input.registers.iter().enumerate() {
+        chip8.set_register(register, *value);
+    }
+    chip8.set_index(input.index);
+    chip8.set_timers(input.delay, input.sound);
+    for _ in 0..input.opcodes.len() {
+        if chip8.try_emulate_cycle().is_err() {
+            break;
+        }
+    }
+});
@@ -0,0 +1,63 @@
+//! Random ROMs run from power-on under random settings and input. The machine must stop with a
+//! fault when it can't go on, never panic.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use chip_8_emu::chip8::{Chip8, IndexWidth, MemoryBounds, Variant, WriteProtection};
+use libfuzzer_sys::fuzz_target;
+
+/// Instructions run before giving up on the ROM faulting
+const CYCLES: usize = 10_000;
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    chip8x: bool,
+    wrap: bool,
+    write_protection: u8,
+    index_12_bits: bool,
+    stack_depth: u8,
+    memory_64k: bool,
+    seed: u64,
+    // Keypads held, changing every 256 instructions
+    keypads: Vec<(u16, u16)>,
+    rom: Vec<u8>,
+}
+
+fn keys(bits: u16) -> [bool; 16] {
+    let mut keys = [false; 16];
+    for (key, pressed) in keys.iter_mut().enumerate() {
+        *pressed = bits & (1 << key) != 0;
+    }
+    keys
+}
+
+fuzz_target!(|input: Input| {
+    let mut chip8 = Chip8::builder()
+        .variant(if input.chip8x { Variant::Chip8X } else { Variant::Chip8 })
+        .memory_bounds(if input.wrap { MemoryBounds::Wrap } else { MemoryBounds::Fault })
+        .write_protection(match input.write_protection % 3 {
+            0 => WriteProtection::Off,
+            1 => WriteProtection::Fault,
+            _ => WriteProtection::Ignore,
+        })
+        .index_width(if input.index_12_bits { IndexWidth::Bits12 } else { IndexWidth::Bits16 })
+        .stack_depth(input.stack_depth.max(1) as u16)
+        .memory_size(if input.memory_64k { 0x10000 } else { 0x1000 })
+        .seed(input.seed)
+        .build();
+    let room = chip8.memory().len() - 0x200;
+    chip8.load_program(&input.rom[..input.rom.len().min(room)]);
+    let mut buffer = vec![0; 64 * 32];
+    for cycle in 0..CYCLES {
+        if let Some((keypad, second_keypad)) = input.keypads.get(cycle / 256) {
+            chip8.set_keys(keys(*keypad));
+            chip8.set_second_keypad(keys(*second_keypad));
+        }
+        if chip8.try_emulate_cycle().is_err() {
+            break;
+        }
+        chip8.draw_to_buffer(&mut buffer);
+    }
+    chip8.state_checksum();
+});
@@ -0,0 +1,895 @@
+//! Assembler for Octo source (.8o files).
+//!
+//! Covers the Octo language for the instructions this emulator runs:
+//!
+//! - `: name` defines a label at the current address. Labels can be used before they are
+//!   defined; writing a label on its own calls it as a subroutine.
+//! - `:alias name v3` gives a register another name, `:const name 10` a number.
+//! - Numbers on their own are data bytes, written in decimal, hex (`0x1F`) or binary
+//!   (`0b00011111`). `:byte` does the same and `:org` moves on to a later address.
+//! - `if ... then`, `if ... begin ... else ... end` and `loop ... while ... again` are compiled
+//!   to skips and jumps.
+//! - `:macro name a b { ... }` defines a macro; writing its name followed by two arguments
+//!   expands the body with `a` and `b` replaced.
+//!
+//! On top of Octo there are a few directives for bigger programs:
+//!
+//! - `:include "file.8o"` assembles another file in place, relative to the one including it.
+//! - `:align 16` pads with zeros up to a multiple of 16, `:pad 4` adds 4 zero bytes.
+//! - `:sprite name { .XX. X..X X..X .XX. }` defines a label and a sprite drawn with an `X` for
+//!   every pixel that is on, a row per word. Rows wider than 8 pixels take two bytes, as in 16x16 SCHIP sprites.
+//!
+//! As in Octo, the ROM starts with a jump to the `main` label, left out when `main` is the very
+//! first thing in the program.
+
+use crate::chip8::{AluOp, Instruction};
+use crate::disasm::LOAD_ADDRESS;
+use crate::symbols::{SourceLine, Symbols};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Words that can't be used as names
+const KEYWORDS: [&str; 20] = [
+    "clear", "return", "jump", "jump0", "sprite", "bcd", "save", "load", "delay", "buzzer", "i", "if", "then", "begin",
+    "else", "end", "loop", "while", "again", "random",
+];
+
+/// Macro expansions allowed before assuming a macro expands itself forever
+const MAX_EXPANSIONS: usize = 10_000;
+
+/// What went wrong and where in the source
+#[derive(Debug, PartialEq)]
+pub struct Error {
+    /// `None` when assembling source that isn't from a file
+    pub file: Option<PathBuf>,
+    /// 0 when the error isn't about a particular line
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.file, self.line) {
+            (Some(file), 0) => write!(f, "{}: {}", file.display(), self.message),
+            (Some(file), line) => write!(f, "{}:{}: {}", file.display(), line, self.message),
+            (None, 0) => write!(f, "{}", self.message),
+            (None, line) => write!(f, "line {}: {}", line, self.message),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    file: Option<Rc<Path>>,
+}
+
+/// Right hand side of a comparison
+#[derive(Clone, Copy)]
+enum Operand {
+    Register(u8),
+    Byte(u8),
+}
+
+#[derive(Clone, Copy)]
+enum Condition {
+    Equal(u8, Operand),
+    NotEqual(u8, Operand),
+    Key(u8),
+    NotKey(u8),
+}
+
+impl Condition {
+    fn negate(self) -> Condition {
+        match self {
+            Condition::Equal(x, operand) => Condition::NotEqual(x, operand),
+            Condition::NotEqual(x, operand) => Condition::Equal(x, operand),
+            Condition::Key(x) => Condition::NotKey(x),
+            Condition::NotKey(x) => Condition::Key(x),
+        }
+    }
+
+    /// Instruction that skips the next one when the condition holds
+    fn skip(self) -> Instruction {
+        match self {
+            Condition::Equal(x, Operand::Byte(nn)) => Instruction::SkipIfEqual(x, nn),
+            Condition::Equal(x, Operand::Register(y)) => Instruction::SkipIfRegistersEqual(x, y),
+            Condition::NotEqual(x, Operand::Byte(nn)) => Instruction::SkipIfNotEqual(x, nn),
+            Condition::NotEqual(x, Operand::Register(y)) => Instruction::SkipIfRegistersNotEqual(x, y),
+            Condition::Key(x) => Instruction::SkipIfKey(x),
+            Condition::NotKey(x) => Instruction::SkipIfNotKey(x),
+        }
+    }
+}
+
+/// Control flow construct waiting for its closing word. Offsets are of jumps to patch, tokens
+/// the opening words.
+enum Block {
+    If { jump: usize, token: Token },
+    Else { jump: usize, token: Token },
+    Loop { start: u16, breaks: Vec<usize>, token: Token },
+}
+
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// Assembles Octo source into a ROM to be loaded at 0x200. The path of the source, if it comes
+/// from a file, is used in errors and to find included files, which are otherwise relative to
+/// the working directory.
+pub fn assemble(source: &str, path: Option<&Path>) -> Result<Vec<u8>, Error> {
+    assemble_with_symbols(source, path).map(|(rom, _)| rom)
+}
+
+/// Assembles Octo source, also returning where each label and instruction ended up
+pub fn assemble_with_symbols(source: &str, path: Option<&Path>) -> Result<(Vec<u8>, Symbols), Error> {
+    let mut assembler = Assembler::new(tokenize(source, path.map(Rc::from)));
+    if let Some(path) = path {
+        assembler.included.insert(path.to_path_buf());
+    }
+    assembler.run()
+}
+
+/// Splits source into words, leaving out comments
+fn tokenize(source: &str, file: Option<Rc<Path>>) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let code = line.split('#').next().unwrap();
+        for text in code.split_whitespace() {
+            tokens.push(Token { text: text.to_string(), line: index + 1, file: file.clone() });
+        }
+    }
+    tokens
+}
+
+struct Assembler {
+    tokens: Vec<Token>,
+    position: usize,
+    rom: Vec<u8>,
+    jump_to_main: bool,
+    labels: HashMap<String, u16>,
+    aliases: HashMap<String, u8>,
+    constants: HashMap<String, i64>,
+    macros: HashMap<String, Macro>,
+    // Address fields to fill in once every label is known
+    fixups: Vec<(usize, Token)>,
+    blocks: Vec<Block>,
+    included: HashSet<PathBuf>,
+    expansions: usize,
+    // First word of the statement being assembled, and the lines of those assembled so far
+    statement: Option<Token>,
+    lines: Vec<SourceLine>,
+}
+
+impl Assembler {
+    fn new(tokens: Vec<Token>) -> Self {
+        Assembler {
+            tokens,
+            position: 0,
+            // Room for the jump to main
+            rom: vec![0, 0],
+            jump_to_main: true,
+            labels: HashMap::new(),
+            aliases: HashMap::new(),
+            constants: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            included: HashSet::new(),
+            expansions: 0,
+            statement: None,
+            lines: Vec::new(),
+        }
+    }
+
+    fn run(mut self) -> Result<(Vec<u8>, Symbols), Error> {
+        while self.position < self.tokens.len() {
+            self.statement()?;
+        }
+        self.finish()
+    }
+
+    fn statement(&mut self) -> Result<(), Error> {
+        let token = self.next("a statement")?;
+        self.statement = Some(token.clone());
+        match token.text.as_str() {
+            ":" => {
+                let name = self.name()?;
+                self.define_label(&name)?;
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.aliases.insert(name.text, register);
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.next("a value")?;
+                match self.value(&value.text) {
+                    Some(value) => {
+                        self.constants.insert(name.text, value);
+                    }
+                    None => return Err(error(&value, format!("expected a number, found '{}'", value.text))),
+                }
+            }
+            ":macro" => {
+                let name = self.name()?;
+                let mut parameters = Vec::new();
+                while self.peek().is_some_and(|text| text != "{") {
+                    parameters.push(self.next("a parameter")?.text);
+                }
+                let body = self.braced()?;
+                self.macros.insert(name.text, Macro { parameters, body });
+            }
+            ":include" => {
+                let path = self.next("a file name")?;
+                self.include(&path)?;
+            }
+            ":sprite" => {
+                let name = self.name()?;
+                self.define_label(&name)?;
+                for row in self.braced()? {
+                    let bytes = sprite_row(&row)?;
+                    self.rom.extend_from_slice(&bytes);
+                }
+            }
+            ":byte" => {
+                let byte = self.byte()?;
+                self.rom.push(byte);
+            }
+            ":org" => {
+                let next = self.next("an address")?;
+                let address = self.address_literal(&next)?;
+                let offset = (address as usize).checked_sub(LOAD_ADDRESS as usize).filter(|offset| *offset >= self.rom.len());
+                match offset {
+                    Some(offset) => self.rom.resize(offset, 0),
+                    None => return Err(error(&next, format!("can't move back to {:#05X} from {:#05X}", address, self.here()))),
+                }
+            }
+            ":align" => {
+                let next = self.next("an alignment")?;
+                match self.value(&next.text) {
+                    Some(alignment @ 1..=0x1000) => {
+                        let padding = (alignment as usize - self.here() as usize % alignment as usize) % alignment as usize;
+                        self.rom.resize(self.rom.len() + padding, 0);
+                    }
+                    _ => return Err(error(&next, format!("expected an alignment from 1 to 0x1000, found '{}'", next.text))),
+                }
+            }
+            ":pad" => {
+                let next = self.next("a byte count")?;
+                match self.value(&next.text) {
+                    Some(count @ 0..=0x1000) => self.rom.resize(self.rom.len() + count as usize, 0),
+                    _ => return Err(error(&next, format!("expected a byte count from 0 to 0x1000, found '{}'", next.text))),
+                }
+            }
+            ":call" => {
+                let address = self.target()?;
+                self.emit(Instruction::Call(address));
+            }
+            "clear" => self.emit(Instruction::ClearScreen),
+            "return" | ";" => self.emit(Instruction::Return),
+            "jump" => {
+                let address = self.target()?;
+                self.emit(Instruction::Jump(address));
+            }
+            "jump0" => {
+                let address = self.target()?;
+                self.emit(Instruction::JumpOffset(address));
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let next = self.next("a sprite height")?;
+                match self.value(&next.text) {
+                    Some(n @ 0..=15) => self.emit(Instruction::Draw(x, y, n as u8)),
+                    _ => return Err(error(&next, format!("expected a sprite height from 0 to 15, found '{}'", next.text))),
+                }
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(Instruction::StoreBcd(x));
+            }
+            "save" => {
+                let x = self.register()?;
+                self.emit(Instruction::StoreRegisters(x));
+            }
+            "load" => {
+                let x = self.register()?;
+                self.emit(Instruction::LoadRegisters(x));
+            }
+            "delay" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                self.emit(Instruction::SetDelay(x));
+            }
+            "buzzer" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                self.emit(Instruction::SetSound(x));
+            }
+            "i" => self.index()?,
+            "if" => self.conditional(token)?,
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => {
+                    let end = self.emit_jump();
+                    self.patch(jump, self.here());
+                    self.blocks.push(Block::Else { jump: end, token });
+                }
+                _ => return Err(error(&token, String::from("'else' without 'if ... begin'"))),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) | Some(Block::Else { jump, .. }) => self.patch(jump, self.here()),
+                _ => return Err(error(&token, String::from("'end' without 'if ... begin'"))),
+            },
+            "loop" => self.blocks.push(Block::Loop { start: self.here(), breaks: Vec::new(), token }),
+            "while" => {
+                let condition = self.condition()?;
+                self.emit(condition.skip());
+                let jump = self.emit_jump();
+                match self.blocks.iter_mut().rev().find_map(|block| match block {
+                    Block::Loop { breaks, .. } => Some(breaks),
+                    _ => None,
+                }) {
+                    Some(breaks) => breaks.push(jump),
+                    None => return Err(error(&token, String::from("'while' outside of 'loop ... again'"))),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, breaks, .. }) => {
+                    self.emit(Instruction::Jump(start));
+                    for jump in breaks {
+                        self.patch(jump, self.here());
+                    }
+                }
+                _ => return Err(error(&token, String::from("'again' without 'loop'"))),
+            },
+            text => {
+                if let Some(value) = self.value(text) {
+                    let byte = to_byte(&token, value)?;
+                    self.rom.push(byte);
+                } else if self.is_register(text) {
+                    self.assignment(&token)?;
+                } else if self.macros.contains_key(text) {
+                    self.expand(&token)?;
+                } else if is_name(text) {
+                    // A label on its own is a subroutine call
+                    let address = self.label(token);
+                    self.emit(Instruction::Call(address));
+                } else {
+                    return Err(error(&token, format!("unexpected '{}'", text)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn define_label(&mut self, name: &Token) -> Result<(), Error> {
+        if self.labels.contains_key(&name.text) {
+            return Err(error(name, format!("label '{}' is already defined", name.text)));
+        }
+        if name.text == "main" && self.rom.len() == 2 && self.labels.is_empty() {
+            // Nothing before main, so there's nothing to jump over
+            self.rom.clear();
+            self.jump_to_main = false;
+        }
+        self.labels.insert(name.text.clone(), self.here());
+        Ok(())
+    }
+
+    /// Replaces a macro call with the macro's body, the parameters replaced by the arguments
+    fn expand(&mut self, call: &Token) -> Result<(), Error> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(error(call, format!("macro '{}' keeps expanding into itself", call.text)));
+        }
+        let count = self.macros[&call.text].parameters.len();
+        let mut arguments = Vec::with_capacity(count);
+        for _ in 0..count {
+            arguments.push(self.next("a macro argument")?);
+        }
+        let definition = &self.macros[&call.text];
+        let body: Vec<Token> = definition
+            .body
+            .iter()
+            .map(|token| match definition.parameters.iter().position(|parameter| *parameter == token.text) {
+                Some(index) => arguments[index].clone(),
+                None => token.clone(),
+            })
+            .collect();
+        self.tokens.splice(self.position..self.position, body);
+        Ok(())
+    }
+
+    /// Inserts the tokens of another file after the include directive
+    fn include(&mut self, path: &Token) -> Result<(), Error> {
+        let name = path.text.trim_matches('"');
+        let base = path.file.as_deref().and_then(Path::parent).unwrap_or_else(|| Path::new(""));
+        let full_path = base.join(name);
+        if !self.included.insert(full_path.clone()) {
+            return Err(error(path, format!("{} is included more than once", full_path.display())));
+        }
+        let source = std::fs::read_to_string(&full_path)
+            .map_err(|e| error(path, format!("could not read {}: {}", full_path.display(), e)))?;
+        let tokens = tokenize(&source, Some(Rc::from(full_path.as_path())));
+        self.tokens.splice(self.position..self.position, tokens);
+        Ok(())
+    }
+
+    /// Words between `{` and the matching `}`
+    fn braced(&mut self) -> Result<Vec<Token>, Error> {
+        let open = self.next("'{'")?;
+        if open.text != "{" {
+            return Err(error(&open, format!("expected '{{', found '{}'", open.text)));
+        }
+        let mut depth = 1;
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.next("'}'")?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                return Ok(tokens);
+            }
+            tokens.push(token);
+        }
+    }
+
+    /// `vx` followed by an assignment operator and its operand
+    fn assignment(&mut self, register: &Token) -> Result<(), Error> {
+        let x = self.register_named(register)?;
+        let operator = self.next("an operator")?;
+        let alu = match operator.text.as_str() {
+            ":=" => {
+                let operand = self.next("a value")?;
+                let instruction = match operand.text.as_str() {
+                    "random" => Instruction::Random(x, self.byte()?),
+                    "delay" => Instruction::GetDelay(x),
+                    "key" => return Err(error(&operand, String::from("waiting for a key isn't supported by this emulator"))),
+                    _ => match self.operand(&operand)? {
+                        Operand::Register(y) => Instruction::Alu(AluOp::Move, x, y),
+                        Operand::Byte(nn) => Instruction::Load(x, nn),
+                    },
+                };
+                self.emit(instruction);
+                return Ok(());
+            }
+            "+=" => {
+                let operand = self.next("a value")?;
+                match self.operand(&operand)? {
+                    Operand::Register(y) => self.emit(Instruction::Alu(AluOp::Add, x, y)),
+                    Operand::Byte(nn) => self.emit(Instruction::Add(x, nn)),
+                }
+                return Ok(());
+            }
+            "-=" => {
+                let operand = self.next("a value")?;
+                match self.operand(&operand)? {
+                    Operand::Register(y) => self.emit(Instruction::Alu(AluOp::Sub, x, y)),
+                    Operand::Byte(nn) => self.emit(Instruction::Add(x, nn.wrapping_neg())),
+                }
+                return Ok(());
+            }
+            "=-" => AluOp::SubReverse,
+            "|=" => AluOp::Or,
+            "&=" => AluOp::And,
+            "^=" => AluOp::Xor,
+            ">>=" => AluOp::ShiftRight,
+            "<<=" => AluOp::ShiftLeft,
+            _ => return Err(error(&operator, format!("expected an assignment operator, found '{}'", operator.text))),
+        };
+        let y = self.register()?;
+        self.emit(Instruction::Alu(alu, x, y));
+        Ok(())
+    }
+
+    /// `i := address`, `i := hex vx` or `i += vx`
+    fn index(&mut self) -> Result<(), Error> {
+        let operator = self.next("an operator")?;
+        match operator.text.as_str() {
+            ":=" => {
+                if self.peek() == Some("hex") {
+                    self.position += 1;
+                    let x = self.register()?;
+                    self.emit(Instruction::LoadFont(x));
+                } else {
+                    let address = self.target()?;
+                    self.emit(Instruction::LoadIndex(address));
+                }
+            }
+            "+=" => {
+                let x = self.register()?;
+                self.emit(Instruction::AddIndex(x));
+            }
+            _ => return Err(error(&operator, format!("expected ':=' or '+=' after 'i', found '{}'", operator.text))),
+        }
+        Ok(())
+    }
+
+    /// `if condition then` skips the next statement unless the condition holds, `if condition
+    /// begin` opens a block
+    fn conditional(&mut self, token: Token) -> Result<(), Error> {
+        let condition = self.condition()?;
+        let word = self.next("'then' or 'begin'")?;
+        match word.text.as_str() {
+            "then" => self.emit(condition.negate().skip()),
+            "begin" => {
+                self.emit(condition.skip());
+                let jump = self.emit_jump();
+                self.blocks.push(Block::If { jump, token });
+            }
+            _ => return Err(error(&word, format!("expected 'then' or 'begin', found '{}'", word.text))),
+        }
+        Ok(())
+    }
+
+    fn condition(&mut self) -> Result<Condition, Error> {
+        let x = self.register()?;
+        let operator = self.next("a comparison")?;
+        match operator.text.as_str() {
+            "key" => Ok(Condition::Key(x)),
+            "-key" => Ok(Condition::NotKey(x)),
+            "==" | "!=" => {
+                let next = self.next("a value")?;
+                let operand = self.operand(&next)?;
+                Ok(if operator.text == "==" { Condition::Equal(x, operand) } else { Condition::NotEqual(x, operand) })
+            }
+            _ => Err(error(&operator, format!("expected '==', '!=', 'key' or '-key', found '{}'", operator.text))),
+        }
+    }
+
+    fn finish(mut self) -> Result<(Vec<u8>, Symbols), Error> {
+        if let Some(block) = self.blocks.last() {
+            let (opening, closing, token) = match block {
+                Block::If { token, .. } => ("if ... begin", "end", token),
+                Block::Else { token, .. } => ("else", "end", token),
+                Block::Loop { token, .. } => ("loop", "again", token),
+            };
+            return Err(error(token, format!("'{}' is never closed by '{}'", opening, closing)));
+        }
+
+        let file = self.tokens.first().and_then(|token| token.file.as_deref().map(Path::to_path_buf));
+        let main = match self.labels.get("main") {
+            Some(main) => *main,
+            None => return Err(Error { file, line: 0, message: String::from("the program has no 'main' label") }),
+        };
+        if self.jump_to_main {
+            self.rom[..2].copy_from_slice(&Instruction::Jump(main).encode().to_be_bytes());
+        }
+
+        for (offset, name) in std::mem::take(&mut self.fixups) {
+            match self.labels.get(&name.text) {
+                Some(address) => self.patch(offset, *address),
+                None => return Err(error(&name, format!("undefined label '{}'", name.text))),
+            }
+        }
+
+        let end = LOAD_ADDRESS as usize + self.rom.len();
+        if end > 0x1000 {
+            return Err(Error { file, line: 0, message: format!("the program is {} bytes too long", end - 0x1000) });
+        }
+        let labels = self.labels.into_iter().collect();
+        Ok((self.rom, Symbols { labels, lines: self.lines, notes: Vec::new() }))
+    }
+
+    fn here(&self) -> u16 {
+        LOAD_ADDRESS + self.rom.len() as u16
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        if let Some(token) = &self.statement {
+            let file = token.file.as_deref().map_or_else(String::new, |file| file.display().to_string());
+            self.lines.push(SourceLine { address: self.here(), file, line: token.line });
+        }
+        self.rom.extend_from_slice(&instruction.encode().to_be_bytes());
+    }
+
+    /// Emits a jump whose address is filled in later, returning its offset
+    fn emit_jump(&mut self) -> usize {
+        self.emit(Instruction::Jump(0));
+        self.rom.len() - 2
+    }
+
+    /// Sets the address field of the instruction at an offset
+    fn patch(&mut self, offset: usize, address: u16) {
+        self.rom[offset] = (self.rom[offset] & 0xF0) | (address >> 8) as u8;
+        self.rom[offset + 1] = address as u8;
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(|token| token.text.as_str())
+    }
+
+    fn next(&mut self, expected: &str) -> Result<Token, Error> {
+        match self.tokens.get(self.position) {
+            Some(token) => {
+                self.position += 1;
+                Ok(token.clone())
+            }
+            None => {
+                let message = format!("expected {}, found the end of the file", expected);
+                match self.tokens.last() {
+                    Some(last) => Err(error(last, message)),
+                    None => Err(Error { file: None, line: 0, message }),
+                }
+            }
+        }
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), Error> {
+        let token = self.next(&format!("'{}'", text))?;
+        if token.text != text {
+            return Err(error(&token, format!("expected '{}', found '{}'", text, token.text)));
+        }
+        Ok(())
+    }
+
+    /// Name for a new label, alias, constant or macro
+    fn name(&mut self) -> Result<Token, Error> {
+        let token = self.next("a name")?;
+        if !is_name(&token.text) || register_number(&token.text).is_some() {
+            return Err(error(&token, format!("'{}' can't be used as a name", token.text)));
+        }
+        Ok(token)
+    }
+
+    fn is_register(&self, text: &str) -> bool {
+        register_number(text).is_some() || self.aliases.contains_key(text)
+    }
+
+    fn register(&mut self) -> Result<u8, Error> {
+        let token = self.next("a register")?;
+        self.register_named(&token)
+    }
+
+    fn register_named(&self, token: &Token) -> Result<u8, Error> {
+        register_number(&token.text)
+            .or_else(|| self.aliases.get(&token.text).copied())
+            .ok_or_else(|| error(token, format!("expected a register, found '{}'", token.text)))
+    }
+
+    /// Number or constant
+    fn value(&self, text: &str) -> Option<i64> {
+        number(text).or_else(|| self.constants.get(text).copied())
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        let token = self.next("a number")?;
+        match self.value(&token.text) {
+            Some(value) => to_byte(&token, value),
+            None => Err(error(&token, format!("expected a number, found '{}'", token.text))),
+        }
+    }
+
+    fn operand(&self, token: &Token) -> Result<Operand, Error> {
+        if self.is_register(&token.text) {
+            return Ok(Operand::Register(self.register_named(token)?));
+        }
+        match self.value(&token.text) {
+            Some(value) => Ok(Operand::Byte(to_byte(token, value)?)),
+            None => Err(error(token, format!("expected a register or a number, found '{}'", token.text))),
+        }
+    }
+
+    /// Address given as a number, a constant or a label
+    fn target(&mut self) -> Result<u16, Error> {
+        let token = self.next("an address or a label")?;
+        if self.value(&token.text).is_some() {
+            self.address_literal(&token)
+        } else if is_name(&token.text) {
+            Ok(self.label(token))
+        } else {
+            Err(error(&token, format!("expected an address or a label, found '{}'", token.text)))
+        }
+    }
+
+    fn address_literal(&self, token: &Token) -> Result<u16, Error> {
+        match self.value(&token.text) {
+            Some(value @ 0..=0xFFF) => Ok(value as u16),
+            _ => Err(error(token, format!("expected an address from 0 to 0xFFF, found '{}'", token.text))),
+        }
+    }
+
+    /// Address of a label, or 0 to be filled in once it is defined
+    fn label(&mut self, token: Token) -> u16 {
+        match self.labels.get(&token.text) {
+            Some(address) => *address,
+            None => {
+                // The instruction using it is emitted next
+                self.fixups.push((self.rom.len(), token));
+                0
+            }
+        }
+    }
+}
+
+fn error(token: &Token, message: String) -> Error {
+    Error { file: token.file.as_deref().map(Path::to_path_buf), line: token.line, message }
+}
+
+fn register_number(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+    if digit.len() != 1 {
+        return None;
+    }
+    digit.chars().next()?.to_digit(16).map(|digit| digit as u8)
+}
+
+fn is_name(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        && !KEYWORDS.contains(&text)
+}
+
+/// Decimal, hex or binary number, optionally negative
+fn number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(binary, 2).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+/// Byte from a number, negative numbers wrapping around as in two's complement
+fn to_byte(token: &Token, value: i64) -> Result<u8, Error> {
+    match value {
+        -128..=255 => Ok(value as u8),
+        _ => Err(error(token, format!("{} doesn't fit in a byte", token.text))),
+    }
+}
+
+/// Bytes of a sprite row written as `X` for pixels that are on and `.` for those that are off.
+/// `#` would start a comment.
+fn sprite_row(row: &Token) -> Result<Vec<u8>, Error> {
+    let width = row.text.chars().count();
+    if width > 16 || !row.text.chars().all(|c| c == 'X' || c == '.') {
+        return Err(error(row, format!("expected a sprite row of up to 16 'X' and '.', found '{}'", row.text)));
+    }
+    let bits = row.text.chars().enumerate().fold(0u16, |bits, (x, c)| if c == 'X' { bits | 0x8000 >> x } else { bits });
+    let bytes = bits.to_be_bytes();
+    Ok(if width > 8 { bytes.to_vec() } else { vec![bytes[0]] })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::{assemble, Error};
+    use crate::chip8::Variant;
+    use crate::disasm::{disassemble, to_octo};
+
+    /// Labels are resolved forwards and backwards, main at the start needs no jump
+    #[test]
+    fn test_labels() {
+        let source = "
+            : main
+                i := sprite   # defined later
+                v0 := 0x10
+            : again-label
+                sprite v0 v1 1
+                jump again-label
+            : sprite
+                0b11110000
+        ";
+        // `sprite` is an instruction, so the label name is rejected
+        assert!(assemble(source, None).is_err());
+
+        let source = source.replace("sprite   #", "dot   #").replace(": sprite", ": dot");
+        assert_eq!(assemble(&source, None).unwrap(), vec![0xA2, 0x08, 0x60, 0x10, 0xD0, 0x11, 0x12, 0x04, 0xF0]);
+
+        // Anything before main is jumped over
+        assert_eq!(assemble("7 : main 8", None).unwrap(), vec![0x12, 0x03, 0x07, 0x08]);
+    }
+
+    /// Control flow blocks become skips and jumps
+    #[test]
+    fn test_blocks() {
+        let source = "
+            :alias counter v3
+            : main
+                loop
+                    counter += 1
+                    while counter != 5
+                    if counter == v2 begin
+                        clear
+                    else
+                        counter -= 1
+                    end
+                    if v1 key then return
+                again
+        ";
+        let rom = assemble(source, None).unwrap();
+        let opcodes: Vec<u16> = rom.chunks(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect();
+        assert_eq!(
+            opcodes,
+            vec![
+                0x7301, // 0x200: counter += 1
+                0x4305, // 0x202: while, skips the jump out of the loop while counter != 5
+                0x1216, // 0x204
+                0x5320, // 0x206: if begin, skips the jump to else when counter == v2
+                0x120E, // 0x208
+                0x00E0, // 0x20A: clear
+                0x1210, // 0x20C: else, the end of the if part jumps over the else part
+                0x73FF, // 0x20E: counter -= 1
+                0xE1A1, // 0x210: then, skips return unless v1 is pressed
+                0x00EE, // 0x212: return
+                0x1200, // 0x214: again
+            ]
+        );
+    }
+
+    /// Constants, macros, sprites and padding
+    #[test]
+    fn test_directives() {
+        let source = "
+            :const SPEED 3
+            :macro move register amount { register += amount }
+            : main
+                move v1 SPEED
+                move v2 -1
+                :align 8
+                :pad 1
+            :sprite box {
+                XXXX
+                X..X
+                ........XXXXXXXX
+            }
+        ";
+        assert_eq!(
+            assemble(source, None).unwrap(),
+            vec![0x71, 0x03, 0x72, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0xF0, 0x90, 0x00, 0xFF]
+        );
+
+        let error = assemble(":macro forever { forever } : main forever", None).unwrap_err();
+        assert!(error.message.contains("keeps expanding"));
+    }
+
+    /// Included files are found next to the file including them and errors name the file
+    #[test]
+    fn test_include() {
+        let directory = std::env::temp_dir().join(format!("chip8-include-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("game.8o"), ": main\n:include \"sprites.8o\"\n").unwrap();
+        std::fs::write(directory.join("sprites.8o"), "0xF0\n\n0x1FF\n").unwrap();
+
+        let path = directory.join("game.8o");
+        let source = std::fs::read_to_string(&path).unwrap();
+        let error = assemble(&source, Some(&path)).unwrap_err();
+        assert_eq!(error.file, Some(directory.join("sprites.8o")));
+        assert_eq!(error.line, 3);
+
+        std::fs::write(directory.join("sprites.8o"), "0xF0\n0x90\n").unwrap();
+        assert_eq!(assemble(&source, Some(&path)).unwrap(), vec![0xF0, 0x90]);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    /// Errors point at the line they were found on
+    #[test]
+    fn test_errors() {
+        let error = |source: &str| assemble(source, None).unwrap_err();
+        assert_eq!(error(": main\nv0 := 256"), Error { file: None, line: 2, message: String::from("256 doesn't fit in a byte") });
+        assert_eq!(error(": main\n\njump nowhere").line, 3);
+        assert_eq!(error(": main\nloop\nv0 += 1").message, "'loop' is never closed by 'again'");
+        assert!(error("v0 := 1").message.contains("main"));
+    }
+
+    /// Octo written by the disassembler assembles back into the same ROM
+    #[test]
+    fn test_disassembly_round_trip() {
+        let rom = include_bytes!("../roms/pong.rom");
+        let source = to_octo(&disassemble(rom, Variant::Chip8));
+        assert_eq!(assemble(&source, None).unwrap(), rom.to_vec());
+    }
+}
@@ -0,0 +1,186 @@
+//! Buzzer output.
+//!
+//! With the `audio` feature enabled, tones are rendered through cpal. Requests coming from the
+//! emulation thread are timestamped and converted to sample positions inside the audio callback,
+//! using the stream's reported playback latency, so a tone always starts a fixed delay after it
+//! was requested and lasts exactly as many samples as the sound timer asked for - no matter where
+//! in the buffer period the request arrived. A request of 0 cuts the tone short at the same delay.
+//! Without the feature, tones are just logged.
+
+#[cfg(feature = "audio")]
+pub use self::cpal_beeper::Beeper;
+
+#[cfg(not(feature = "audio"))]
+use crate::profile::Profiler;
+#[cfg(not(feature = "audio"))]
+use std::time::Duration;
+#[cfg(not(feature = "audio"))]
+use tracing::info;
+
+#[cfg(not(feature = "audio"))]
+pub struct Beeper;
+
+#[cfg(not(feature = "audio"))]
+impl Beeper {
+    /// Nothing to profile without an audio callback
+    pub fn new(_profiler: Option<Profiler>) -> Self {
+        Beeper
+    }
+
+    /// Sound the buzzer for `ticks` 60Hz timer ticks, or stop it for 0
+    pub fn beep(&self, ticks: u8) {
+        match ticks {
+            0 => info!("BEEP stopped"),
+            _ => info!("BEEP ({} ticks)", ticks),
+        }
+    }
+
+    /// No time goes into audio callbacks without them
+    pub fn take_callback_time(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+#[cfg(feature = "audio")]
+mod cpal_beeper {
+    use crate::profile::{Profiler, Thread};
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use tracing::{error, warn};
+
+    /// Rate at which the CHIP-8 sound timer counts down
+    const TIMER_HZ: u64 = 60;
+    const TONE_HZ: f32 = 440.0;
+    const VOLUME: f32 = 0.15;
+
+    /// Converts a sound timer value into a number of samples, never shorter than one timer tick
+    /// so that FX18 with VX = 1 still produces an audible blip
+    fn tone_length(ticks: u8, sample_rate: u32) -> u64 {
+        ticks.max(1) as u64 * sample_rate as u64 / TIMER_HZ
+    }
+
+    /// Shared between the emulation thread and the audio callback
+    #[derive(Default)]
+    struct Schedule {
+        // Requests not yet placed on the sample clock: (time requested, sound timer value)
+        pending: Vec<(Instant, u8)>,
+        // Current tone window on the sample clock, [start, stop)
+        start: u64,
+        stop: u64,
+        // Largest output latency seen so far; requests are delayed by this much so every tone
+        // has the same request-to-speaker delay
+        latency: Duration,
+    }
+
+    pub struct Beeper {
+        schedule: Arc<Mutex<Schedule>>,
+        // Nanoseconds spent in the audio callback since last taken
+        callback_time: Arc<AtomicU64>,
+        // Kept alive for as long as the beeper exists, playback stops when dropped
+        _stream: Option<cpal::Stream>,
+    }
+
+    impl Beeper {
+        /// Opens the default output device, timing its callbacks if profiling
+        pub fn new(profiler: Option<Profiler>) -> Self {
+            let schedule = Arc::new(Mutex::new(Schedule::default()));
+            let callback_time = Arc::new(AtomicU64::new(0));
+            let stream = match open_stream(Arc::clone(&schedule), Arc::clone(&callback_time), profiler) {
+                Ok(stream) => Some(stream),
+                Err(error) => {
+                    warn!("Could not open audio output, sound disabled\n{}", error);
+                    None
+                }
+            };
+            Beeper { schedule, callback_time, _stream: stream }
+        }
+
+        /// Sound the buzzer for `ticks` 60Hz timer ticks, or stop it for 0
+        pub fn beep(&self, ticks: u8) {
+            self.schedule.lock().unwrap().pending.push((Instant::now(), ticks));
+        }
+
+        /// Time spent in the audio callback since the last call
+        pub fn take_callback_time(&self) -> Duration {
+            Duration::from_nanos(self.callback_time.swap(0, Ordering::Relaxed))
+        }
+    }
+
+    fn open_stream(schedule: Arc<Mutex<Schedule>>, callback_time: Arc<AtomicU64>, profiler: Option<Profiler>) -> Result<cpal::Stream, String> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or_else(|| String::from("No output device available"))?;
+        let config = device.default_output_config().map_err(|e| e.to_string())?;
+        let sample_rate = config.sample_rate().0;
+        let channels = config.channels() as usize;
+
+        // Absolute index of the next sample frame to be written
+        let mut clock: u64 = 0;
+        let mut phase: f32 = 0.0;
+
+        let stream = device
+            .build_output_stream(
+                &config.into(),
+                move |data: &mut [f32], info: &cpal::OutputCallbackInfo| {
+                    let _callback = profiler.as_ref().map(|profiler| profiler.span("audio callback", Thread::Audio));
+                    let now = Instant::now();
+                    let timestamp = info.timestamp();
+                    let lead = timestamp
+                        .playback
+                        .duration_since(&timestamp.callback)
+                        .unwrap_or_default();
+
+                    let mut schedule = schedule.lock().unwrap();
+                    if lead > schedule.latency {
+                        schedule.latency = lead;
+                    }
+
+                    // The first frame of this buffer is heard at `now + lead`. A request made at
+                    // `at` should be heard at `at + latency`, so place it that far from frame 0.
+                    let pending: Vec<(Instant, u8)> = schedule.pending.drain(..).collect();
+                    for (at, ticks) in pending {
+                        let heard_at = at + schedule.latency;
+                        let offset = heard_at
+                            .checked_duration_since(now + lead)
+                            .map_or(0, |d| (d.as_secs_f64() * sample_rate as f64) as u64);
+                        let start = clock + offset;
+                        if ticks == 0 {
+                            // Cut the tone short where the request is heard, if it's still playing
+                            schedule.stop = schedule.stop.min(start.max(schedule.start));
+                            continue;
+                        }
+                        let stop = start + tone_length(ticks, sample_rate);
+                        if schedule.stop > clock && start <= schedule.stop {
+                            // Overlaps the tone already playing, extend it
+                            schedule.stop = schedule.stop.max(stop);
+                        } else {
+                            schedule.start = start;
+                            schedule.stop = stop;
+                        }
+                    }
+
+                    for frame in data.chunks_mut(channels) {
+                        let playing = clock >= schedule.start && clock < schedule.stop;
+                        let value = if playing {
+                            phase = (phase + TONE_HZ / sample_rate as f32) % 1.0;
+                            if phase < 0.5 { VOLUME } else { -VOLUME }
+                        } else {
+                            0.0
+                        };
+                        for sample in frame.iter_mut() {
+                            *sample = value;
+                        }
+                        clock += 1;
+                    }
+                    callback_time.fetch_add(now.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                },
+                |error| error!("Audio stream error: {}", error),
+                None,
+            )
+            .map_err(|e| e.to_string())?;
+        stream.play().map_err(|e| e.to_string())?;
+        Ok(stream)
+    }
+}
@@ -0,0 +1,184 @@
+//! `chip8 batch`, running every ROM in a directory headless and in parallel, and writing how each
+//! one ended to a report, to smoke-test a whole library after changing the core.
+//!
+//! A ROM ends `ok` if it ran or halted, with the hash of its screen, `error` if it couldn't be
+//! loaded or faulted, and `panic` if the emulator itself panicked. Running again over an existing
+//! report lists the ROMs that now end differently before replacing it.
+
+use crate::chip8::{Chip8, Variant};
+use crate::headless::{self, Outcome};
+use crate::run;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+/// Files taken for ROMs, Octo source and Octocarts included
+const EXTENSIONS: [&str; 5] = ["ch8", "c8x", "rom", "8o", "gif"];
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Error,
+    Panic,
+}
+
+impl Status {
+    pub fn name(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Error => "error",
+            Status::Panic => "panic",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RomReport {
+    /// Relative to the directory
+    pub path: String,
+    pub status: Status,
+    pub detail: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct Report {
+    #[serde(default)]
+    pub rom: Vec<RomReport>,
+}
+
+/// The ROMs in a directory and the ones under it, in order
+pub fn find_roms(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut roms = Vec::new();
+    let entries = fs::read_dir(dir).map_err(|e| format!("Could not read {}\n{}", dir.display(), e))?;
+    for entry in entries {
+        let path = entry.map_err(|e| format!("Could not read {}\n{}", dir.display(), e))?.path();
+        if path.is_dir() {
+            roms.extend(find_roms(&path)?);
+        } else if path.extension().and_then(|extension| extension.to_str()).is_some_and(|extension| EXTENSIONS.contains(&extension)) {
+            roms.push(path);
+        }
+    }
+    roms.sort();
+    Ok(roms)
+}
+
+/// Runs every ROM in a directory for up to a number of frames, compiled to native code if asked
+pub fn run(dir: &Path, variant: Variant, seed: u64, frames: u64, jit: bool) -> Result<Report, String> {
+    let roms = find_roms(dir)?;
+    if roms.is_empty() {
+        return Err(format!("No ROMs in {}", dir.display()));
+    }
+    // Panics are reported with the ROM instead of printed from whichever thread hit them
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let rom = roms.par_iter().map(|path| run_rom(dir, path, variant, seed, frames, jit)).collect();
+    panic::set_hook(hook);
+    Ok(Report { rom })
+}
+
+fn run_rom(dir: &Path, path: &Path, variant: Variant, seed: u64, frames: u64, jit: bool) -> RomReport {
+    let name = path.strip_prefix(dir).unwrap_or(path).to_string_lossy().replace('\\', "/");
+    let report = |status, detail: String, hash| RomReport { path: name.clone(), status, detail, hash };
+    let program = match run::load_program(path) {
+        Ok(program) => program,
+        Err(error) => return report(Status::Error, error.to_string(), None),
+    };
+    let step = match headless::stepper(jit) {
+        Ok(step) => step,
+        Err(error) => return report(Status::Error, error, None),
+    };
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let options = &program.options;
+        let builder = Chip8::builder().variant(variant).seed(seed).tickrate(options.tickrate.unwrap_or(1)).quirks(options.quirks.unwrap_or_default());
+        let mut chip8 = run::power_on(builder, &program.bytes);
+        let outcome = headless::run_frames_with(&mut chip8, options.speed(), frames, true, step);
+        (outcome, headless::screen_hash(&chip8))
+    }));
+    match result {
+        Ok((outcome @ Outcome::Failed { .. }, _)) => report(Status::Error, outcome.describe(), None),
+        Ok((outcome, hash)) => report(Status::Ok, outcome.describe(), Some(hash)),
+        Err(payload) => {
+            let message = payload.downcast_ref::<&str>().map(|message| message.to_string()).or_else(|| payload.downcast_ref::<String>().cloned());
+            report(Status::Panic, message.unwrap_or_else(|| String::from("Panicked")), None)
+        }
+    }
+}
+
+/// A line per ROM that ends differently from the earlier report, or is new
+pub fn changes(before: &Report, after: &Report) -> Vec<String> {
+    after
+        .rom
+        .iter()
+        .filter_map(|rom| match before.rom.iter().find(|earlier| earlier.path == rom.path) {
+            None => Some(format!("{}: new, {}", rom.path, rom.detail)),
+            Some(earlier) if earlier.status != rom.status => {
+                Some(format!("{}: was {} ({}), now {} ({})", rom.path, earlier.status.name(), earlier.detail, rom.status.name(), rom.detail))
+            }
+            Some(earlier) if earlier.hash != rom.hash => Some(format!("{}: ends on a different screen, {}", rom.path, rom.detail)),
+            Some(_) => None,
+        })
+        .collect()
+}
+
+/// A line per ROM and the totals
+pub fn summary(report: &Report) -> String {
+    let mut lines: Vec<String> = report.rom.iter().map(|rom| format!("{:<7} {}  {}", rom.status.name(), rom.path, rom.detail)).collect();
+    let count = |status| report.rom.iter().filter(|rom| rom.status == status).count();
+    lines.push(format!("{} ok, {} errors, {} panics", count(Status::Ok), count(Status::Error), count(Status::Panic)));
+    lines.join("\n")
+}
+
+/// The report next to the ROMs, if not given
+pub fn report_path(dir: &Path, path: Option<PathBuf>) -> PathBuf {
+    path.unwrap_or_else(|| dir.join("batch-report.toml"))
+}
+
+/// An earlier report, empty if there isn't one
+pub fn load_report(path: &Path) -> Result<Report, String> {
+    if !path.exists() {
+        return Ok(Report::default());
+    }
+    let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}\n{}", path.display(), e))?;
+    toml::from_str(&text).map_err(|e| format!("Could not parse {}\n{}", path.display(), e))
+}
+
+pub fn save_report(report: &Report, path: &Path) -> Result<(), String> {
+    let text = toml::to_string(report).map_err(|e| e.to_string())?;
+    fs::write(path, text).map_err(|e| format!("Could not write {}\n{}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::batch::{changes, run, Report, RomReport, Status};
+    use crate::chip8::Variant;
+    use std::fs;
+
+    /// Every ROM gets a line, a broken one included, and changes are the ones that end differently
+    #[test]
+    fn test_run() {
+        let dir = std::env::temp_dir().join(format!("chip8-batch-{}", std::process::id()));
+        fs::create_dir_all(dir.join("more")).unwrap();
+        // Jump to itself
+        fs::write(dir.join("halt.ch8"), [0x12, 0x00]).unwrap();
+        // Return with nothing to return to
+        fs::write(dir.join("more/return.ch8"), [0x00, 0xEE]).unwrap();
+        fs::write(dir.join("notes.txt"), "not a ROM").unwrap();
+        let report = run(&dir, Variant::Chip8, 0, 10, false).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let statuses: Vec<(&str, Status)> = report.rom.iter().map(|rom| (rom.path.as_str(), rom.status)).collect();
+        assert_eq!(statuses, vec![("halt.ch8", Status::Ok), ("more/return.ch8", Status::Error)]);
+        assert!(report.rom[0].hash.is_some());
+
+        assert!(changes(&report, &report).is_empty());
+        let mut before = Report { rom: report.rom.clone() };
+        before.rom[0] = RomReport { hash: Some(String::from("ab")), ..before.rom[0].clone() };
+        before.rom.pop();
+        assert_eq!(changes(&before, &report).len(), 2);
+    }
+}
@@ -0,0 +1,95 @@
+//! `chip8 bench`, running a ROM as fast as it goes for a while, without a window or sound, and
+//! reporting how many instructions and frames that came to a second. A frame is the instructions
+//! run between window updates and the copy of the screen into the window's buffer, like `run`
+//! does without the wait for the next one.
+
+use crate::chip8::Chip8;
+use crate::cli::RunArgs;
+use crate::fatal;
+use crate::headless::{self, Step};
+use crate::run::{self, HEIGHT, WIDTH};
+use std::time::{Duration, Instant};
+
+/// How far a run got
+pub struct Speed {
+    pub instructions: u64,
+    pub frames: u64,
+    pub elapsed: Duration,
+    /// Why it stopped early, if the program failed
+    pub failure: Option<String>,
+}
+
+impl Speed {
+    pub fn describe(&self) -> String {
+        let seconds = self.elapsed.as_secs_f64();
+        let mut text = format!(
+            "{} instructions and {} frames in {:.2}s: {:.0} instructions/s, {:.0} frames/s",
+            self.instructions,
+            self.frames,
+            seconds,
+            self.instructions as f64 / seconds,
+            self.frames as f64 / seconds
+        );
+        if let Some(failure) = &self.failure {
+            text.push_str(&format!("\nStopped early: {}", failure));
+        }
+        text
+    }
+}
+
+/// Runs the ROM for the time given and prints how fast it went
+pub fn run(args: &RunArgs, duration: Duration) {
+    let program = run::load_program(&args.rom).unwrap_or_else(fatal);
+    let font = run::machine_font(args, &program.options);
+    let builder = run::configure(args, &program.options, args.variant, font.as_ref()).seed(args.seed.unwrap_or(0));
+    let mut chip8 = run::power_on(builder, &program.bytes);
+    let step = headless::stepper(args.jit()).unwrap_or_else(fatal);
+    println!("{}", measure(&mut chip8, program.options.speed(), duration, step).describe());
+}
+
+/// Runs frames of `speed` instructions for about as long as given
+pub fn measure(chip8: &mut Chip8, speed: u32, duration: Duration, mut step: Step) -> Speed {
+    let mut buffer = vec![0; WIDTH * HEIGHT];
+    let start = Instant::now();
+    let mut frames = 0;
+    let mut failure = None;
+    'frames: while start.elapsed() < duration {
+        let mut left = speed as u64;
+        while left > 0 {
+            match step(chip8, left) {
+                Ok(ran) => left -= ran,
+                Err(reason) => {
+                    failure = Some(reason);
+                    break 'frames;
+                }
+            }
+        }
+        chip8.draw_to_buffer(&mut buffer);
+        frames += 1;
+    }
+    Speed { instructions: chip8.cycles(), frames, elapsed: start.elapsed(), failure }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bench::measure;
+    use crate::chip8::Chip8;
+    use crate::headless::interpreter;
+    use crate::run::power_on;
+    use std::time::Duration;
+
+    /// Runs count every instruction, and stop at a failure
+    #[test]
+    fn test_measure() {
+        // Loop forever
+        let mut chip8 = power_on(Chip8::builder(), &[0x12, 0x00]);
+        let speed = measure(&mut chip8, 4, Duration::from_millis(20), interpreter());
+        assert!(speed.frames > 0 && speed.failure.is_none());
+        assert_eq!(speed.instructions, speed.frames * 4);
+
+        let mut chip8 = power_on(Chip8::builder(), &[0x00, 0xEE]);
+        let speed = measure(&mut chip8, 4, Duration::from_secs(10), interpreter());
+        assert_eq!((speed.instructions, speed.frames), (0, 0));
+        assert!(speed.failure.is_some());
+    }
+}
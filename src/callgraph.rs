@@ -0,0 +1,152 @@
+//! Calls between subroutines as the program runs, with how often each was called and the
+//! instructions run inside it, written out as a Graphviz DOT graph:
+//!
+//! ```text
+//! chip8 --call-graph calls.dot game.ch8
+//! dot -Tsvg calls.dot -o calls.svg
+//! ```
+//!
+//! A subroutine's cost counts the instructions of the subroutines it calls as well as its own.
+//! The code the program started in stands for the caller of everything else.
+
+use crate::chip8::{Chip8, Instruction};
+use crate::symbols::Symbols;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+#[derive(Default)]
+pub struct CallGraph {
+    /// Where the program started
+    root: Option<u16>,
+    /// Calls from one subroutine to another
+    edges: BTreeMap<(u16, u16), u64>,
+    routines: BTreeMap<u16, Routine>,
+    /// Subroutines being run and the instruction count when each was called, innermost last
+    frames: Vec<(u16, u64)>,
+    instructions: u64,
+}
+
+#[derive(Default)]
+struct Routine {
+    calls: u64,
+    /// Instructions run in the subroutine and those it called
+    cost: u64,
+    /// Instructions run in the subroutine itself
+    own: u64,
+}
+
+impl CallGraph {
+    /// Looks at the instruction at the program counter, before it runs
+    pub fn record(&mut self, chip8: &Chip8) {
+        let root = *self.root.get_or_insert(chip8.program_counter());
+        // Follow the machine's own stack, after a reset or a savestate was loaded
+        if self.frames.len() != chip8.call_stack().len() {
+            let instructions = self.instructions;
+            self.frames = chip8.call_stack().iter().map(|call| (call_target(chip8, *call).unwrap_or(root), instructions)).collect();
+        }
+        let current = self.frames.last().map_or(root, |(routine, _)| *routine);
+        self.instructions += 1;
+        self.routines.entry(current).or_default().own += 1;
+        match decode(chip8, chip8.program_counter()) {
+            Some(Instruction::Call(target)) => {
+                *self.edges.entry((current, target)).or_default() += 1;
+                self.routines.entry(target).or_default().calls += 1;
+                self.frames.push((target, self.instructions));
+            }
+            Some(Instruction::Return) => {
+                if let Some((routine, called_at)) = self.frames.pop() {
+                    self.routines.entry(routine).or_default().cost += self.instructions - called_at;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Subroutines by cost, most first
+    pub fn report(&self, symbols: &Symbols) -> String {
+        let mut routines: Vec<(&u16, &Routine)> = self.routines.iter().filter(|(address, _)| Some(**address) != self.root).collect();
+        if routines.is_empty() {
+            return String::from("No subroutines called");
+        }
+        routines.sort_by_key(|(_, routine)| Reverse(routine.cost));
+        let lines: Vec<String> = routines
+            .iter()
+            .map(|(address, routine)| {
+                format!(
+                    "  {}: {} calls, {} instructions, {} of them its own",
+                    symbols.describe(**address),
+                    routine.calls,
+                    routine.cost,
+                    routine.own
+                )
+            })
+            .collect();
+        format!("Subroutines:\n{}", lines.join("\n"))
+    }
+
+    pub fn to_dot(&self, symbols: &Symbols) -> String {
+        let name = |address: u16| symbols.label_at(address).map_or_else(|| format!("{:#05X}", address), str::to_string);
+        let mut lines = vec![String::from("digraph calls {"), String::from("    node [shape=box];")];
+        for (address, routine) in &self.routines {
+            let label = if Some(*address) == self.root {
+                format!("{}\\n{} instructions", name(*address), routine.own)
+            } else {
+                format!("{}\\n{} calls\\n{} instructions ({} own)", name(*address), routine.calls, routine.cost, routine.own)
+            };
+            lines.push(format!("    \"{:#05X}\" [label=\"{}\"];", address, label));
+        }
+        for ((caller, callee), calls) in &self.edges {
+            lines.push(format!("    \"{:#05X}\" -> \"{:#05X}\" [label=\"{}\"];", caller, callee, calls));
+        }
+        lines.push(String::from("}"));
+        lines.join("\n") + "\n"
+    }
+
+    pub fn save(&self, symbols: &Symbols, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_dot(symbols)).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+}
+
+fn decode(chip8: &Chip8, address: u16) -> Option<Instruction> {
+    let opcode = chip8.memory().get(address as usize..address as usize + 2)?;
+    Instruction::decode(u16::from_be_bytes([opcode[0], opcode[1]]), chip8.variant())
+}
+
+/// Subroutine a call on the stack went to
+fn call_target(chip8: &Chip8, call: u16) -> Option<u16> {
+    match decode(chip8, call)? {
+        Instruction::Call(target) => Some(target),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assemble_with_symbols;
+    use crate::callgraph::CallGraph;
+    use crate::chip8::Chip8;
+
+    /// Nested calls add to the cost of their caller
+    #[test]
+    fn test_call_graph() {
+        let source = ": main\n\touter\n\touter\n: halt\n\tjump halt\n: outer\n\tinner\n\treturn\n: inner\n\tv0 += 1\n\treturn";
+        let (rom, symbols) = assemble_with_symbols(source, None).unwrap();
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&rom);
+        let mut graph = CallGraph::default();
+        for _ in 0..11 {
+            graph.record(&chip8);
+            chip8.emulate_cycle();
+        }
+        assert_eq!(
+            graph.report(&symbols),
+            "Subroutines:\n  0x206 (outer): 2 calls, 8 instructions, 4 of them its own\n  \
+             0x20A (inner): 2 calls, 4 instructions, 4 of them its own"
+        );
+        let dot = graph.to_dot(&symbols);
+        assert!(dot.contains("\"0x200\" [label=\"main\\n3 instructions\"];"));
+        assert!(dot.contains("\"0x206\" -> \"0x20A\" [label=\"2\"];"));
+    }
+}
@@ -0,0 +1,262 @@
+//! Cheats that freeze memory at a value or poke it once, loaded from a file next to the ROM with
+//! the `.cht` extension, or given with `--cheats`:
+//!
+//! ```toml
+//! [[cheat]]
+//! name = "Infinite lives"
+//! freeze = ["0x300:3"]     # written before every frame while the cheat is on
+//! enabled = true
+//!
+//! [[cheat]]
+//! name = "Start at level 5"
+//! poke = ["level:5"]       # written once when the cheat is turned on
+//! ```
+//!
+//! Codes are `address:value`, the address a number or a label. The cheats hotkey, Insert by
+//! default, opens a menu to turn them on and off with the arrow keys and Enter, which is saved
+//! back to the file. The console also has `cheat`, `freeze` and `unfreeze` commands.
+
+use crate::chip8::Chip8;
+use crate::debugger;
+use crate::hotkeys::Hotkeys;
+use crate::run;
+use crate::symbols::Symbols;
+use crate::text::{self, CHAR_WIDTH, LINE_HEIGHT};
+use minifb::Key;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{error, info};
+
+/// The menu is drawn at this many times the resolution of the game
+pub const SCALE: usize = 4;
+pub const WIDTH: usize = run::WIDTH * SCALE;
+pub const HEIGHT: usize = run::HEIGHT * SCALE;
+
+const BACKGROUND: u32 = 0x101010;
+const ENABLED: u32 = 0x00FF_C000;
+const DISABLED: u32 = 0x808080;
+const SELECTED: u32 = 0x304060;
+
+#[derive(Default, Deserialize, Serialize)]
+struct CheatFile {
+    #[serde(default, rename = "cheat")]
+    cheats: Vec<CheatConfig>,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+struct CheatConfig {
+    name: String,
+    freeze: Vec<String>,
+    poke: Vec<String>,
+    enabled: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Code {
+    address: u16,
+    value: u8,
+}
+
+struct Cheat {
+    config: CheatConfig,
+    freeze: Vec<Code>,
+    poke: Vec<Code>,
+    /// Saved to the cheat file, unlike freezes added from the console
+    saved: bool,
+}
+
+#[derive(Default)]
+pub struct Cheats {
+    /// File the cheats came from and go back to when toggled from the menu
+    path: Option<PathBuf>,
+    cheats: Vec<Cheat>,
+    /// Pokes of cheats just turned on, written before the next frame
+    pending: Vec<Code>,
+}
+
+impl Cheats {
+    pub fn load(path: &Path, symbols: &Symbols) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let mut cheats = Self::parse(&contents, symbols).map_err(|e| format!("Invalid cheat file {}\n{}", path.display(), e))?;
+        cheats.path = Some(path.to_path_buf());
+        info!("Loaded {} cheats from {}", cheats.cheats.len(), path.display());
+        Ok(cheats)
+    }
+
+    fn parse(contents: &str, symbols: &Symbols) -> Result<Self, String> {
+        let file: CheatFile = toml::from_str(contents).map_err(|e| e.to_string())?;
+        let mut cheats = Cheats::default();
+        for config in file.cheats {
+            let codes = |codes: &[String]| codes.iter().map(|code| parse_code(code, symbols)).collect::<Result<Vec<_>, _>>();
+            let (freeze, poke) = (codes(&config.freeze)?, codes(&config.poke)?);
+            if config.enabled {
+                cheats.pending.extend(&poke);
+            }
+            cheats.cheats.push(Cheat { config, freeze, poke, saved: true });
+        }
+        Ok(cheats)
+    }
+
+    /// Writes the pokes of the cheats turned on since the last frame and the frozen values
+    pub fn apply(&mut self, chip8: &mut Chip8) {
+        for code in self.pending.drain(..) {
+            chip8.write_memory(code.address, &[code.value]);
+        }
+        for cheat in self.cheats.iter().filter(|cheat| cheat.config.enabled) {
+            for code in &cheat.freeze {
+                chip8.write_memory(code.address, &[code.value]);
+            }
+        }
+    }
+
+    /// Turns a cheat on or off, returning what it did
+    pub fn toggle(&mut self, index: usize) -> Result<String, String> {
+        let cheat = self.cheats.get_mut(index).ok_or_else(|| format!("No cheat {}", index + 1))?;
+        cheat.config.enabled = !cheat.config.enabled;
+        if cheat.config.enabled {
+            self.pending.extend(&cheat.poke);
+        }
+        Ok(format!("{}: {}", cheat.config.name, if cheat.config.enabled { "on" } else { "off" }))
+    }
+
+    /// Keeps an address at a value until unfrozen, without saving it to the cheat file
+    pub fn freeze(&mut self, code: &str, symbols: &Symbols) -> Result<String, String> {
+        let code = parse_code(code, symbols)?;
+        self.unfreeze(code.address);
+        let name = format!("Freeze {:#05X} at {:#04X}", code.address, code.value);
+        let config = CheatConfig { name: name.clone(), enabled: true, ..CheatConfig::default() };
+        self.cheats.push(Cheat { config, freeze: vec![code], poke: Vec::new(), saved: false });
+        Ok(name)
+    }
+
+    /// Removes the freezes added for an address, returning whether there were any
+    pub fn unfreeze(&mut self, address: u16) -> bool {
+        let count = self.cheats.len();
+        self.cheats.retain(|cheat| cheat.saved || cheat.freeze.iter().all(|code| code.address != address));
+        self.cheats.len() != count
+    }
+
+    /// One line per cheat, numbered from 1
+    pub fn list(&self) -> String {
+        if self.cheats.is_empty() {
+            return String::from("No cheats");
+        }
+        let lines: Vec<String> = self
+            .cheats
+            .iter()
+            .enumerate()
+            .map(|(index, cheat)| format!("{} [{}] {}", index + 1, if cheat.config.enabled { "x" } else { " " }, cheat.config.name))
+            .collect();
+        lines.join("\n")
+    }
+
+    /// Writes the cheats back to their file, with whether each is on
+    pub fn save(&self) -> Result<(), String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let file = CheatFile { cheats: self.cheats.iter().filter(|cheat| cheat.saved).map(|cheat| cheat.config.clone()).collect() };
+        let contents = toml::to_string_pretty(&file).map_err(|e| e.to_string())?;
+        fs::write(path, contents).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+}
+
+/// `address:value`, the address a number or a label
+fn parse_code(code: &str, symbols: &Symbols) -> Result<Code, String> {
+    let (address, value) = code.split_once(':').ok_or_else(|| format!("Cheat codes are address:value, not {}", code))?;
+    let (address, value) = (address.trim(), value.trim());
+    let address = symbols.labels.get(address).copied().or_else(|| debugger::parse_address(address)).ok_or_else(|| format!("Not an address or label: {}", address))?;
+    let value = debugger::parse_address(value).filter(|value| *value <= 0xFF).ok_or_else(|| format!("Not a byte: {}", value))?;
+    Ok(Code { address, value: value as u8 })
+}
+
+/// Menu over the game listing the cheats, replaces the game while open
+pub struct CheatMenu {
+    selected: usize,
+}
+
+impl CheatMenu {
+    pub fn new() -> Self {
+        CheatMenu { selected: 0 }
+    }
+
+    /// Moves the selection with the arrow keys and toggles the selected cheat with Enter, saving
+    /// the cheat file
+    pub fn handle_keys(&mut self, pressed: &[Key], hotkeys: &Hotkeys, cheats: &mut Cheats) {
+        let count = cheats.cheats.len();
+        for key in pressed {
+            if hotkeys.is_hotkey(*key) || count == 0 {
+                continue;
+            }
+            match key {
+                Key::Up => self.selected = (self.selected + count - 1) % count,
+                Key::Down => self.selected = (self.selected + 1) % count,
+                Key::Enter | Key::Space => {
+                    if let Ok(message) = cheats.toggle(self.selected) {
+                        info!("{}", message);
+                    }
+                    if let Err(error) = cheats.save() {
+                        error!("{}", error);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn draw(&self, cheats: &Cheats, buffer: &mut [u32]) {
+        buffer.iter_mut().for_each(|pixel| *pixel = BACKGROUND);
+        text::draw(buffer, WIDTH, (1, 1), "CHEATS - ENTER TO TOGGLE", DISABLED);
+        if cheats.cheats.is_empty() {
+            text::draw(buffer, WIDTH, (1, 1 + 2 * LINE_HEIGHT), "NO CHEAT FILE FOR THIS ROM", DISABLED);
+            return;
+        }
+        let rows = HEIGHT / LINE_HEIGHT - 2;
+        let first = self.selected.saturating_sub(rows - 1);
+        for (row, (index, cheat)) in cheats.cheats.iter().enumerate().skip(first).take(rows).enumerate() {
+            let y = (row + 2) * LINE_HEIGHT;
+            if index == self.selected {
+                buffer[y * WIDTH..(y + LINE_HEIGHT) * WIDTH].iter_mut().for_each(|pixel| *pixel = SELECTED);
+            }
+            let color = if cheat.config.enabled { ENABLED } else { DISABLED };
+            let line: String = format!("{} {}", if cheat.config.enabled { "ON " } else { "OFF" }, cheat.config.name).chars().take(WIDTH / CHAR_WIDTH).collect();
+            text::draw(buffer, WIDTH, (1, y + 1), &line, color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cheats::Cheats;
+    use crate::chip8::Chip8;
+    use crate::symbols::Symbols;
+
+    /// Freezes hold every frame while on, pokes are written once when turned on
+    #[test]
+    fn test_cheats() {
+        let mut symbols = Symbols::default();
+        symbols.labels.insert(String::from("level"), 0x301);
+        let file = "[[cheat]]\nname = \"Lives\"\nfreeze = [\"0x300:3\"]\nenabled = true\n\n[[cheat]]\nname = \"Level\"\npoke = [\"level:5\"]\n";
+        let mut cheats = Cheats::parse(file, &symbols).unwrap();
+        let mut chip8 = Chip8::new();
+
+        cheats.apply(&mut chip8);
+        assert_eq!(chip8.memory()[0x300..0x302], [3, 0]);
+        assert_eq!(cheats.toggle(1), Ok(String::from("Level: on")));
+        chip8.write_memory(0x300, &[0, 0]);
+        cheats.apply(&mut chip8);
+        assert_eq!(chip8.memory()[0x300..0x302], [3, 5]);
+        chip8.write_memory(0x300, &[0, 0]);
+        cheats.apply(&mut chip8);
+        assert_eq!(chip8.memory()[0x300..0x302], [3, 0]);
+
+        assert_eq!(cheats.freeze("0x302:0x7F", &symbols), Ok(String::from("Freeze 0x302 at 0x7F")));
+        assert_eq!(cheats.list(), "1 [x] Lives\n2 [x] Level\n3 [x] Freeze 0x302 at 0x7F");
+        assert!(cheats.unfreeze(0x302));
+        assert!(!cheats.unfreeze(0x300));
+        assert!(Cheats::parse("[[cheat]]\nname = \"Bad\"\nfreeze = [\"lives:3\"]\n", &symbols).is_err());
+    }
+}
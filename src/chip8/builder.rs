@@ -0,0 +1,160 @@
+use crate::chip8::random::{self, RandomSource};
+use crate::chip8::{Chip8, Font, IndexWidth, MemoryBounds, Peripheral, Quirks, Variant, WriteProtection, DEFAULT_MEMORY_SIZE, DEFAULT_STACK_DEPTH};
+
+/// Configures a `Chip8` before it is created. `Chip8::new()` is the same as
+/// `Chip8::builder().build()`.
+pub struct Chip8Builder {
+    variant: Variant,
+    memory_bounds: MemoryBounds,
+    write_protection: WriteProtection,
+    memory_size: usize,
+    index_width: IndexWidth,
+    quirks: Quirks,
+    tickrate: u32,
+    font: Option<Font>,
+    font_address: u16,
+    stack_depth: u16,
+    peripherals: Vec<Box<dyn Peripheral>>,
+    random: Option<Box<dyn RandomSource>>,
+    background: Option<u32>,
+    foreground: Option<u32>,
+}
+
+impl Default for Chip8Builder {
+    fn default() -> Self {
+        Chip8Builder {
+            variant: Variant::default(),
+            memory_bounds: MemoryBounds::default(),
+            write_protection: WriteProtection::default(),
+            memory_size: DEFAULT_MEMORY_SIZE,
+            index_width: IndexWidth::default(),
+            quirks: Quirks::default(),
+            tickrate: 1,
+            font: None,
+            font_address: 0,
+            stack_depth: DEFAULT_STACK_DEPTH,
+            peripherals: Vec::new(),
+            random: None,
+            background: None,
+            foreground: None,
+        }
+    }
+}
+
+impl Chip8Builder {
+    pub fn variant(mut self, variant: Variant) -> Self {
+        self.variant = variant;
+        self
+    }
+
+    /// What I-relative instructions do with memory past the end, a fault unless changed
+    pub fn memory_bounds(mut self, memory_bounds: MemoryBounds) -> Self {
+        self.memory_bounds = memory_bounds;
+        self
+    }
+
+    /// What FX33 and FX55 do with memory below 0x200, written like the rest unless changed
+    pub fn write_protection(mut self, write_protection: WriteProtection) -> Self {
+        self.write_protection = write_protection;
+        self
+    }
+
+    /// Bytes of memory, 4K unless changed. XO-CHIP programs need 64K
+    pub fn memory_size(mut self, size: usize) -> Self {
+        self.memory_size = size;
+        self
+    }
+
+    /// How wide I is, 16 bits unless changed
+    pub fn index_width(mut self, index_width: IndexWidth) -> Self {
+        self.index_width = index_width;
+        self
+    }
+
+    /// How the instructions interpreters disagree on behave, see `Quirks`
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+
+    /// Instructions to a tick of the timers, 1 unless changed. Running this many times 60
+    /// instructions a second counts the timers down at 60 Hz
+    pub fn tickrate(mut self, tickrate: u32) -> Self {
+        self.tickrate = tickrate.max(1);
+        self
+    }
+
+    /// Digits to load in place of the usual ones, see `Font`
+    pub fn font(mut self, font: Font) -> Self {
+        self.font = Some(font);
+        self
+    }
+
+    /// Where to load the digits, 0 unless changed. Traditionally 0x050 on later interpreters
+    pub fn font_address(mut self, address: u16) -> Self {
+        self.font_address = address;
+        self
+    }
+
+    /// Calls deep the stack goes before a call overflows it, 15 unless changed
+    pub fn stack_depth(mut self, depth: u16) -> Self {
+        self.stack_depth = depth;
+        self
+    }
+
+    /// Maps a device into memory, see `Peripheral`. Devices added first win where they overlap
+    pub fn peripheral(mut self, peripheral: Box<dyn Peripheral>) -> Self {
+        self.peripherals.push(peripheral);
+        self
+    }
+
+    /// Seeds the random number generator used by CXNN. Without a seed it's seeded from entropy.
+    pub fn seed(self, seed: u64) -> Self {
+        self.random(random::seeded(Some(seed)))
+    }
+
+    /// Source of the random bytes of CXNN, in place of the seeded generator
+    pub fn random(mut self, source: Box<dyn RandomSource>) -> Self {
+        self.random = Some(source);
+        self
+    }
+
+    /// Color `draw_to_buffer` uses for pixels that are off
+    pub fn background(mut self, color: u32) -> Self {
+        self.background = Some(color);
+        self
+    }
+
+    /// Color `draw_to_buffer` uses for pixels that are on
+    pub fn foreground(mut self, color: u32) -> Self {
+        self.foreground = Some(color);
+        self
+    }
+
+    pub fn build(self) -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.variant = self.variant;
+        chip8.memory_bounds = self.memory_bounds;
+        chip8.write_protection = self.write_protection;
+        chip8.memory.resize(self.memory_size, 0);
+        chip8.index_width = self.index_width;
+        chip8.quirks = self.quirks;
+        chip8.tickrate = self.tickrate;
+        chip8.peripherals = self.peripherals;
+        chip8.stack = vec![0; self.stack_depth as usize + 1];
+        if self.font.is_some() || self.font_address != 0 {
+            let font = self.font.unwrap_or_else(|| Font::named("chip8").unwrap());
+            chip8.load_font(&font, self.font_address);
+        }
+        if let Some(source) = self.random {
+            chip8.rng = source;
+        }
+        if let Some(color) = self.background {
+            chip8.colors[0] = color;
+        }
+        if let Some(color) = self.foreground {
+            chip8.colors[1] = color;
+        }
+        chip8
+    }
+}
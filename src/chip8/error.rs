@@ -0,0 +1,42 @@
+use crate::chip8::Instruction;
+use std::fmt;
+
+/// Why the instruction at the program counter can't run. The machine is left as the instruction
+/// found it, so it can still be looked at or dumped.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Chip8Error {
+    /// The program counter is past the last address an opcode fits at
+    ProgramCounterPastEnd(u16),
+    UnknownOpcode { opcode: u16, address: u16 },
+    /// Return with the stack empty
+    StackUnderflow(u16),
+    /// Call with the stack full, with the addresses of the calls it's nested in
+    StackOverflow { address: u16, calls: Vec<u16> },
+    /// The program counter would go past 0xFFFF
+    RunsOffEnd { instruction: Instruction, address: u16 },
+    /// Memory past the end accessed through I
+    OutOfBounds { instruction: Instruction, address: u16, index: u16 },
+    /// The interpreter's memory written through I, with write protection faulting
+    WriteProtected { instruction: Instruction, address: u16, index: u16 },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chip8Error::ProgramCounterPastEnd(address) => write!(f, "Program counter {:#05X} is past the end of memory", address),
+            Chip8Error::UnknownOpcode { opcode, address } => write!(f, "Unknown opcode {:#06X} at {:#05X}", opcode, address),
+            Chip8Error::StackUnderflow(address) => write!(f, "Return with the stack empty at {:#05X}", address),
+            Chip8Error::StackOverflow { address, calls } => {
+                let calls: Vec<String> = calls.iter().map(|call| format!("{:#05X}", call)).collect();
+                write!(f, "Call with the stack full at {:#05X}, {} calls deep: {}", address, calls.len(), calls.join(" > "))
+            }
+            Chip8Error::RunsOffEnd { instruction, address } => write!(f, "{} at {:#05X} runs off the end of memory", instruction, address),
+            Chip8Error::OutOfBounds { instruction, address, index } => {
+                write!(f, "{} at {:#05X} accesses memory past the end, I = {:#05X}", instruction, address, index)
+            }
+            Chip8Error::WriteProtected { instruction, address, index } => {
+                write!(f, "{} at {:#05X} writes to the interpreter's memory, I = {:#05X}", instruction, address, index)
+            }
+        }
+    }
+}
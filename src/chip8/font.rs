@@ -0,0 +1,116 @@
+use crate::chip8::CHIP8_FONTSET;
+use std::fs;
+use std::str::FromStr;
+
+/// Bytes of a small digit, and of all 16
+const SMALL_DIGIT: usize = 5;
+const SMALL_FONT: usize = 16 * SMALL_DIGIT;
+/// Bytes of a big digit, 8x10 pixels
+const BIG_DIGIT: usize = 10;
+
+/// Digits loaded into the interpreter's memory: the 4x5 digits FX29 points I at, and 8x10 digits
+/// right after them for the SCHIP FX30, either for 0 to 9 or for all 16. This emulator
+/// doesn't run FX30, but programs can still point I at the big digits.
+///
+/// Fonts are named, `chip8`, `vip`, `dream6800`, `schip` or `octo`, or read from a file of the
+/// small digits followed by the big ones if it has them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Font {
+    small: Vec<u8>,
+    big: Vec<u8>,
+}
+
+const VIP_FONT: [u8; SMALL_FONT] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, 0x60, 0x20, 0x20, 0x20, 0x70, 0xF0, 0x10, 0xF0, 0x80, 0xF0, 0xF0, 0x10, 0x70, 0x10, 0xF0,
+    0xA0, 0xA0, 0xF0, 0x20, 0x20, 0xF0, 0x80, 0xF0, 0x10, 0xF0, 0xF0, 0x80, 0xF0, 0x90, 0xF0, 0xF0, 0x10, 0x10, 0x10, 0x10,
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, 0xF0, 0x90, 0xF0, 0x10, 0xF0, 0xF0, 0x90, 0xF0, 0x90, 0x90, 0xF0, 0x50, 0x70, 0x50, 0xF0,
+    0xF0, 0x80, 0x80, 0x80, 0xF0, 0xF0, 0x50, 0x50, 0x50, 0xF0, 0xF0, 0x80, 0xF0, 0x80, 0xF0, 0xF0, 0x80, 0xF0, 0x80, 0x80,
+];
+
+const DREAM_6800_FONT: [u8; SMALL_FONT] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, 0x40, 0x40, 0x40, 0x40, 0x40, 0xE0, 0x20, 0xE0, 0x80, 0xE0, 0xE0, 0x20, 0xE0, 0x20, 0xE0,
+    0x80, 0xA0, 0xA0, 0xE0, 0x20, 0xE0, 0x80, 0xE0, 0x20, 0xE0, 0xE0, 0x80, 0xE0, 0xA0, 0xE0, 0xE0, 0x20, 0x20, 0x20, 0x20,
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0, 0xE0, 0xA0, 0xE0, 0x20, 0xE0, 0xE0, 0xA0, 0xE0, 0xA0, 0xA0, 0xC0, 0xA0, 0xE0, 0xA0, 0xC0,
+    0xE0, 0x80, 0x80, 0x80, 0xE0, 0xC0, 0xA0, 0xA0, 0xA0, 0xC0, 0xE0, 0x80, 0xE0, 0x80, 0xE0, 0xE0, 0x80, 0xC0, 0x80, 0x80,
+];
+
+/// SCHIP's big digits, 0 to 9
+const SCHIP_BIG_FONT: [u8; 10 * BIG_DIGIT] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+];
+
+/// Octo's big digits, 0 to F
+const OCTO_BIG_FONT: [u8; 16 * BIG_DIGIT] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+impl Font {
+    /// Small digits followed by big digits for 0 to 9, 0 to F, or none
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let big = bytes.len().checked_sub(SMALL_FONT);
+        if ![Some(0), Some(SCHIP_BIG_FONT.len()), Some(OCTO_BIG_FONT.len())].contains(&big) {
+            return Err(format!(
+                "A font is {} bytes of small digits, then {} or {} bytes of big digits if any, not {} bytes",
+                SMALL_FONT,
+                SCHIP_BIG_FONT.len(),
+                OCTO_BIG_FONT.len(),
+                bytes.len()
+            ));
+        }
+        Ok(Font { small: bytes[..SMALL_FONT].to_vec(), big: bytes[SMALL_FONT..].to_vec() })
+    }
+
+    pub fn named(name: &str) -> Option<Self> {
+        let (small, big): (&[u8], &[u8]) = match name.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" => (&CHIP8_FONTSET, &[]),
+            "vip" => (&VIP_FONT, &[]),
+            "dream6800" => (&DREAM_6800_FONT, &[]),
+            "schip" => (&CHIP8_FONTSET, &SCHIP_BIG_FONT),
+            "octo" => (&CHIP8_FONTSET, &OCTO_BIG_FONT),
+            _ => return None,
+        };
+        Some(Font { small: small.to_vec(), big: big.to_vec() })
+    }
+
+    /// The small digits and the big ones, as laid out from address 0
+    pub fn bytes(&self) -> Vec<u8> {
+        [&self.small[..], &self.big[..]].concat()
+    }
+}
+
+impl FromStr for Font {
+    type Err = String;
+
+    /// A font by name, or else from a file
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(font) = Font::named(s) {
+            return Ok(font);
+        }
+        let bytes = fs::read(s).map_err(|e| format!("{} is neither chip8, vip, dream6800, schip nor octo, nor a font file: {}", s, e))?;
+        Font::from_bytes(&bytes).map_err(|e| format!("Invalid font {}: {}", s, e))
+    }
+}
@@ -0,0 +1,420 @@
+use crate::chip8::Variant;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+/// Operation of an 0x8XYN instruction, the value is N
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AluOp {
+    Move = 0x0,
+    Or = 0x1,
+    And = 0x2,
+    Xor = 0x3,
+    Add = 0x4,
+    Sub = 0x5,
+    ShiftRight = 0x6,
+    SubReverse = 0x7,
+    ShiftLeft = 0xE,
+}
+
+/// Whether an instruction reads or writes memory
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// A decoded opcode. Registers are given by number, addresses and constants as in the opcode.
+/// Small enough that caching one per address of memory stays cheap.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction {
+    /// 00E0
+    ClearScreen,
+    /// 00EE
+    Return,
+    /// 1NNN
+    Jump(u16),
+    /// 2NNN
+    Call(u16),
+    /// 3XNN
+    SkipIfEqual(u8, u8),
+    /// 4XNN
+    SkipIfNotEqual(u8, u8),
+    /// 5XY0
+    SkipIfRegistersEqual(u8, u8),
+    /// 6XNN
+    Load(u8, u8),
+    /// 7XNN
+    Add(u8, u8),
+    /// 8XYN
+    Alu(AluOp, u8, u8),
+    /// 9XY0
+    SkipIfRegistersNotEqual(u8, u8),
+    /// ANNN
+    LoadIndex(u16),
+    /// BNNN
+    JumpOffset(u16),
+    /// CXNN
+    Random(u8, u8),
+    /// DXYN
+    Draw(u8, u8, u8),
+    /// EX9E
+    SkipIfKey(u8),
+    /// EXA1
+    SkipIfNotKey(u8),
+    /// EXF2, CHIP-8X only
+    SkipIfSecondKey(u8),
+    /// EXF5, CHIP-8X only
+    SkipIfNotSecondKey(u8),
+    /// FX07
+    GetDelay(u8),
+    /// FX15
+    SetDelay(u8),
+    /// FX18
+    SetSound(u8),
+    /// FX1E
+    AddIndex(u8),
+    /// FX29
+    LoadFont(u8),
+    /// FX33
+    StoreBcd(u8),
+    /// FX55
+    StoreRegisters(u8),
+    /// FX65
+    LoadRegisters(u8),
+}
+
+impl Instruction {
+    /// Decodes an opcode, returning `None` if the variant doesn't know it
+    pub fn decode(opcode: u16, variant: Variant) -> Option<Instruction> {
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let n = (opcode & 0x000F) as u8;
+        let nn = (opcode & 0x00FF) as u8;
+        let nnn = opcode & 0x0FFF;
+
+        let instruction = match opcode >> 12 {
+            0x0 => match opcode {
+                0x00E0 => Instruction::ClearScreen,
+                0x00EE => Instruction::Return,
+                _ => return None,
+            },
+            0x1 => Instruction::Jump(nnn),
+            0x2 => Instruction::Call(nnn),
+            0x3 => Instruction::SkipIfEqual(x, nn),
+            0x4 => Instruction::SkipIfNotEqual(x, nn),
+            0x5 if n == 0 => Instruction::SkipIfRegistersEqual(x, y),
+            0x6 => Instruction::Load(x, nn),
+            0x7 => Instruction::Add(x, nn),
+            0x8 => {
+                let op = match n {
+                    0x0 => AluOp::Move,
+                    0x1 => AluOp::Or,
+                    0x2 => AluOp::And,
+                    0x3 => AluOp::Xor,
+                    0x4 => AluOp::Add,
+                    0x5 => AluOp::Sub,
+                    0x6 => AluOp::ShiftRight,
+                    0x7 => AluOp::SubReverse,
+                    0xE => AluOp::ShiftLeft,
+                    _ => return None,
+                };
+                Instruction::Alu(op, x, y)
+            }
+            0x9 if n == 0 => Instruction::SkipIfRegistersNotEqual(x, y),
+            0xA => Instruction::LoadIndex(nnn),
+            0xB => Instruction::JumpOffset(nnn),
+            0xC => Instruction::Random(x, nn),
+            0xD => Instruction::Draw(x, y, n),
+            0xE => match nn {
+                0x9E => Instruction::SkipIfKey(x),
+                0xA1 => Instruction::SkipIfNotKey(x),
+                0xF2 if variant == Variant::Chip8X => Instruction::SkipIfSecondKey(x),
+                0xF5 if variant == Variant::Chip8X => Instruction::SkipIfNotSecondKey(x),
+                _ => return None,
+            },
+            0xF => match nn {
+                0x07 => Instruction::GetDelay(x),
+                0x15 => Instruction::SetDelay(x),
+                0x18 => Instruction::SetSound(x),
+                0x1E => Instruction::AddIndex(x),
+                0x29 => Instruction::LoadFont(x),
+                0x33 => Instruction::StoreBcd(x),
+                0x55 => Instruction::StoreRegisters(x),
+                0x65 => Instruction::LoadRegisters(x),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(instruction)
+    }
+
+    /// Opcode for the instruction, the inverse of `decode`
+    pub fn encode(&self) -> u16 {
+        let xnn = |x: u8, nn: u8| ((x as u16) << 8) | nn as u16;
+        let xy = |x: u8, y: u8| ((x as u16) << 8) | ((y as u16) << 4);
+        match *self {
+            Instruction::ClearScreen => 0x00E0,
+            Instruction::Return => 0x00EE,
+            Instruction::Jump(nnn) => 0x1000 | nnn,
+            Instruction::Call(nnn) => 0x2000 | nnn,
+            Instruction::SkipIfEqual(x, nn) => 0x3000 | xnn(x, nn),
+            Instruction::SkipIfNotEqual(x, nn) => 0x4000 | xnn(x, nn),
+            Instruction::SkipIfRegistersEqual(x, y) => 0x5000 | xy(x, y),
+            Instruction::Load(x, nn) => 0x6000 | xnn(x, nn),
+            Instruction::Add(x, nn) => 0x7000 | xnn(x, nn),
+            Instruction::Alu(op, x, y) => 0x8000 | xy(x, y) | op as u16,
+            Instruction::SkipIfRegistersNotEqual(x, y) => 0x9000 | xy(x, y),
+            Instruction::LoadIndex(nnn) => 0xA000 | nnn,
+            Instruction::JumpOffset(nnn) => 0xB000 | nnn,
+            Instruction::Random(x, nn) => 0xC000 | xnn(x, nn),
+            Instruction::Draw(x, y, n) => 0xD000 | xy(x, y) | n as u16,
+            Instruction::SkipIfKey(x) => 0xE09E | xnn(x, 0),
+            Instruction::SkipIfNotKey(x) => 0xE0A1 | xnn(x, 0),
+            Instruction::SkipIfSecondKey(x) => 0xE0F2 | xnn(x, 0),
+            Instruction::SkipIfNotSecondKey(x) => 0xE0F5 | xnn(x, 0),
+            Instruction::GetDelay(x) => 0xF007 | xnn(x, 0),
+            Instruction::SetDelay(x) => 0xF015 | xnn(x, 0),
+            Instruction::SetSound(x) => 0xF018 | xnn(x, 0),
+            Instruction::AddIndex(x) => 0xF01E | xnn(x, 0),
+            Instruction::LoadFont(x) => 0xF029 | xnn(x, 0),
+            Instruction::StoreBcd(x) => 0xF033 | xnn(x, 0),
+            Instruction::StoreRegisters(x) => 0xF055 | xnn(x, 0),
+            Instruction::LoadRegisters(x) => 0xF065 | xnn(x, 0),
+        }
+    }
+
+    /// Memory the instruction reads or writes through I, given the value of I
+    pub fn memory_access(&self, index: u16) -> Option<(Access, RangeInclusive<u16>)> {
+        let access = match *self {
+            Instruction::Draw(_, _, rows) if rows > 0 => Access::Read,
+            Instruction::StoreBcd(_) | Instruction::StoreRegisters(_) => Access::Write,
+            Instruction::LoadRegisters(_) => Access::Read,
+            _ => return None,
+        };
+        Some((access, index..=index.saturating_add(self.memory_length() as u16 - 1)))
+    }
+
+    /// Bytes the instruction reads or writes through I, 0 if it doesn't
+    pub fn memory_length(&self) -> usize {
+        match *self {
+            Instruction::Draw(_, _, rows) => rows as usize,
+            Instruction::StoreBcd(_) => 3,
+            Instruction::StoreRegisters(x) | Instruction::LoadRegisters(x) => x as usize + 1,
+            _ => 0,
+        }
+    }
+
+    /// Registers the instruction reads or writes, not counting VF set as a flag
+    pub fn registers(&self) -> Vec<usize> {
+        match *self {
+            Instruction::SkipIfEqual(x, _)
+            | Instruction::SkipIfNotEqual(x, _)
+            | Instruction::Load(x, _)
+            | Instruction::Add(x, _)
+            | Instruction::Random(x, _)
+            | Instruction::SkipIfKey(x)
+            | Instruction::SkipIfNotKey(x)
+            | Instruction::SkipIfSecondKey(x)
+            | Instruction::SkipIfNotSecondKey(x)
+            | Instruction::GetDelay(x)
+            | Instruction::SetDelay(x)
+            | Instruction::SetSound(x)
+            | Instruction::AddIndex(x)
+            | Instruction::LoadFont(x)
+            | Instruction::StoreBcd(x) => vec![x as usize],
+            Instruction::SkipIfRegistersEqual(x, y)
+            | Instruction::SkipIfRegistersNotEqual(x, y)
+            | Instruction::Alu(_, x, y)
+            | Instruction::Draw(x, y, _) => vec![x as usize, y as usize],
+            Instruction::StoreRegisters(x) | Instruction::LoadRegisters(x) => (0..=x as usize).collect(),
+            Instruction::JumpOffset(_) => vec![0],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Opcode pattern of the instruction, like `8XY4`
+    pub fn pattern(&self) -> &'static str {
+        match *self {
+            Instruction::ClearScreen => "00E0",
+            Instruction::Return => "00EE",
+            Instruction::Jump(_) => "1NNN",
+            Instruction::Call(_) => "2NNN",
+            Instruction::SkipIfEqual(..) => "3XNN",
+            Instruction::SkipIfNotEqual(..) => "4XNN",
+            Instruction::SkipIfRegistersEqual(..) => "5XY0",
+            Instruction::Load(..) => "6XNN",
+            Instruction::Add(..) => "7XNN",
+            Instruction::Alu(op, ..) => match op {
+                AluOp::Move => "8XY0",
+                AluOp::Or => "8XY1",
+                AluOp::And => "8XY2",
+                AluOp::Xor => "8XY3",
+                AluOp::Add => "8XY4",
+                AluOp::Sub => "8XY5",
+                AluOp::ShiftRight => "8XY6",
+                AluOp::SubReverse => "8XY7",
+                AluOp::ShiftLeft => "8XYE",
+            },
+            Instruction::SkipIfRegistersNotEqual(..) => "9XY0",
+            Instruction::LoadIndex(_) => "ANNN",
+            Instruction::JumpOffset(_) => "BNNN",
+            Instruction::Random(..) => "CXNN",
+            Instruction::Draw(..) => "DXYN",
+            Instruction::SkipIfKey(_) => "EX9E",
+            Instruction::SkipIfNotKey(_) => "EXA1",
+            Instruction::SkipIfSecondKey(_) => "EXF2",
+            Instruction::SkipIfNotSecondKey(_) => "EXF5",
+            Instruction::GetDelay(_) => "FX07",
+            Instruction::SetDelay(_) => "FX15",
+            Instruction::SetSound(_) => "FX18",
+            Instruction::AddIndex(_) => "FX1E",
+            Instruction::LoadFont(_) => "FX29",
+            Instruction::StoreBcd(_) => "FX33",
+            Instruction::StoreRegisters(_) => "FX55",
+            Instruction::LoadRegisters(_) => "FX65",
+        }
+    }
+
+    /// Plain English description of what the instruction does
+    pub fn describe(&self) -> String {
+        match *self {
+            Instruction::ClearScreen => String::from("clear the screen"),
+            Instruction::Return => String::from("return from subroutine"),
+            Instruction::Jump(nnn) => format!("jump to {:#05X}", nnn),
+            Instruction::Call(nnn) => format!("call subroutine at {:#05X}", nnn),
+            Instruction::SkipIfEqual(x, nn) => format!("skip next if V{:X} == {:#04X}", x, nn),
+            Instruction::SkipIfNotEqual(x, nn) => format!("skip next if V{:X} != {:#04X}", x, nn),
+            Instruction::SkipIfRegistersEqual(x, y) => format!("skip next if V{:X} == V{:X}", x, y),
+            Instruction::Load(x, nn) => format!("V{:X} = {:#04X}", x, nn),
+            Instruction::Add(x, nn) => format!("V{:X} += {:#04X}", x, nn),
+            Instruction::Alu(op, x, y) => match op {
+                AluOp::Move => format!("V{:X} = V{:X}", x, y),
+                AluOp::Or => format!("V{:X} |= V{:X}", x, y),
+                AluOp::And => format!("V{:X} &= V{:X}", x, y),
+                AluOp::Xor => format!("V{:X} ^= V{:X}", x, y),
+                AluOp::Add => format!("V{:X} += V{:X}, VF = carry", x, y),
+                AluOp::Sub => format!("V{:X} -= V{:X}, VF = no borrow", x, y),
+                AluOp::ShiftRight => format!("V{:X} >>= 1, VF = bit shifted out", x),
+                AluOp::SubReverse => format!("V{:X} = V{:X} - V{:X}, VF = no borrow", x, y, x),
+                AluOp::ShiftLeft => format!("V{:X} <<= 1, VF = bit shifted out", x),
+            },
+            Instruction::SkipIfRegistersNotEqual(x, y) => format!("skip next if V{:X} != V{:X}", x, y),
+            Instruction::LoadIndex(nnn) => format!("I = {:#05X}", nnn),
+            Instruction::JumpOffset(nnn) => format!("jump to {:#05X} + V0", nnn),
+            Instruction::Random(x, nn) => format!("V{:X} = random & {:#04X}", x, nn),
+            Instruction::Draw(x, y, n) => format!("draw {}-byte sprite at (V{:X}, V{:X}), VF = collision", n, x, y),
+            Instruction::SkipIfKey(x) => format!("skip next if key V{:X} is held", x),
+            Instruction::SkipIfNotKey(x) => format!("skip next if key V{:X} is not held", x),
+            Instruction::SkipIfSecondKey(x) => format!("skip next if key V{:X} is held on keypad 2", x),
+            Instruction::SkipIfNotSecondKey(x) => format!("skip next if key V{:X} is not held on keypad 2", x),
+            Instruction::GetDelay(x) => format!("V{:X} = delay timer", x),
+            Instruction::SetDelay(x) => format!("delay timer = V{:X}", x),
+            Instruction::SetSound(x) => format!("sound timer = V{:X}", x),
+            Instruction::AddIndex(x) => format!("I += V{:X}", x),
+            Instruction::LoadFont(x) => format!("I = font character V{:X}", x),
+            Instruction::StoreBcd(x) => format!("store V{:X} as decimal at I..I+2", x),
+            Instruction::StoreRegisters(x) => format!("store V0..V{:X} at I", x),
+            Instruction::LoadRegisters(x) => format!("load V0..V{:X} from I", x),
+        }
+    }
+}
+
+/// Mnemonics in the style of Cowgod's technical reference
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::ClearScreen => write!(f, "CLS"),
+            Instruction::Return => write!(f, "RET"),
+            Instruction::Jump(nnn) => write!(f, "JP {:#05X}", nnn),
+            Instruction::Call(nnn) => write!(f, "CALL {:#05X}", nnn),
+            Instruction::SkipIfEqual(x, nn) => write!(f, "SE V{:X}, {:#04X}", x, nn),
+            Instruction::SkipIfNotEqual(x, nn) => write!(f, "SNE V{:X}, {:#04X}", x, nn),
+            Instruction::SkipIfRegistersEqual(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::Load(x, nn) => write!(f, "LD V{:X}, {:#04X}", x, nn),
+            Instruction::Add(x, nn) => write!(f, "ADD V{:X}, {:#04X}", x, nn),
+            Instruction::Alu(op, x, y) => {
+                let mnemonic = match op {
+                    AluOp::Move => "LD",
+                    AluOp::Or => "OR",
+                    AluOp::And => "AND",
+                    AluOp::Xor => "XOR",
+                    AluOp::Add => "ADD",
+                    AluOp::Sub => "SUB",
+                    AluOp::ShiftRight => "SHR",
+                    AluOp::SubReverse => "SUBN",
+                    AluOp::ShiftLeft => "SHL",
+                };
+                write!(f, "{} V{:X}, V{:X}", mnemonic, x, y)
+            }
+            Instruction::SkipIfRegistersNotEqual(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LoadIndex(nnn) => write!(f, "LD I, {:#05X}", nnn),
+            Instruction::JumpOffset(nnn) => write!(f, "JP V0, {:#05X}", nnn),
+            Instruction::Random(x, nn) => write!(f, "RND V{:X}, {:#04X}", x, nn),
+            Instruction::Draw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SkipIfKey(x) => write!(f, "SKP V{:X}", x),
+            Instruction::SkipIfNotKey(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::SkipIfSecondKey(x) => write!(f, "SKP2 V{:X}", x),
+            Instruction::SkipIfNotSecondKey(x) => write!(f, "SKNP2 V{:X}", x),
+            Instruction::GetDelay(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::SetDelay(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSound(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddIndex(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::LoadFont(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::StoreBcd(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::StoreRegisters(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::LoadRegisters(x) => write!(f, "LD V{:X}, [I]", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::instruction::{Access, AluOp, Instruction};
+    use crate::chip8::Variant;
+
+    /// Operands are pulled out of the opcode nibbles
+    #[test]
+    fn test_decode() {
+        assert_eq!(Instruction::decode(0x00E0, Variant::Chip8), Some(Instruction::ClearScreen));
+        assert_eq!(Instruction::decode(0x8AB4, Variant::Chip8), Some(Instruction::Alu(AluOp::Add, 0xA, 0xB)));
+        assert_eq!(Instruction::decode(0xD125, Variant::Chip8), Some(Instruction::Draw(1, 2, 5)));
+        assert_eq!(Instruction::decode(0x8AB8, Variant::Chip8), None);
+        assert_eq!(Instruction::decode(0x5121, Variant::Chip8), None);
+        assert_eq!(Instruction::decode(0xE1F2, Variant::Chip8), None);
+        assert_eq!(Instruction::decode(0xE1F2, Variant::Chip8X), Some(Instruction::SkipIfSecondKey(1)));
+    }
+
+    /// An instruction takes no more room than its opcode and a tag, cached or not
+    #[test]
+    fn test_size() {
+        assert_eq!(std::mem::size_of::<Option<Instruction>>(), 4);
+    }
+
+    /// Every opcode that decodes encodes back to itself
+    #[test]
+    fn test_encode() {
+        for opcode in 0..=0xFFFF {
+            if let Some(instruction) = Instruction::decode(opcode, Variant::Chip8X) {
+                assert_eq!(instruction.encode(), opcode);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mnemonics() {
+        assert_eq!(Instruction::Jump(0x2A0).to_string(), "JP 0x2A0");
+        assert_eq!(Instruction::Load(0xA, 2).to_string(), "LD VA, 0x02");
+        assert_eq!(Instruction::Alu(AluOp::SubReverse, 1, 2).to_string(), "SUBN V1, V2");
+        assert_eq!(Instruction::StoreRegisters(3).to_string(), "LD [I], V3");
+    }
+
+    /// Sprites are read and FX33/FX55 write from I on
+    #[test]
+    fn test_memory_access() {
+        assert_eq!(Instruction::Draw(0, 1, 5).memory_access(0x300), Some((Access::Read, 0x300..=0x304)));
+        assert_eq!(Instruction::StoreRegisters(3).memory_access(0xE00), Some((Access::Write, 0xE00..=0xE03)));
+        assert_eq!(Instruction::StoreBcd(3).memory_access(0xE00), Some((Access::Write, 0xE00..=0xE02)));
+        assert_eq!(Instruction::Draw(0, 1, 0).memory_access(0x300), None);
+        assert_eq!(Instruction::Load(0, 1).memory_access(0x300), None);
+    }
+}
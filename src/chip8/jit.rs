@@ -0,0 +1,349 @@
+//! Compiles straight runs of instructions to native code with Cranelift, for headless runs where
+//! nothing has to see each instruction go by.
+//!
+//! A block starts at the program counter and takes the register loads, arithmetic and changes to
+//! I after it, ending with a jump or skip if one comes next. Anything reading the memory, the
+//! screen, the keys, the timers or the stack is left to the interpreter, and so is a jump to
+//! itself, so a halt shows up as one jump not moving the program counter like without the JIT. Blocks keep the bytes they were compiled from and are compiled again once the program
+//! writes over them. None of their instructions look at the timers or the cycle count, so both
+//! are brought up to date after a block runs.
+
+use crate::chip8::instruction::{AluOp, Instruction};
+use crate::chip8::{Chip8, IndexWidth, Quirks, Variant};
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, InstBuilder, MemFlagsData, UserFuncName, Value};
+use cranelift_codegen::Context;
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Module};
+
+/// Most instructions in a block
+const MAX_BLOCK: usize = 64;
+
+/// Compiled block, taking V0 to VF and I and returning the next program counter
+type BlockFn = unsafe extern "C" fn(*mut u8, *mut u16) -> u32;
+
+struct Block {
+    /// None where the instruction at the start can't be compiled
+    function: Option<BlockFn>,
+    /// Memory the block was compiled from
+    bytes: Vec<u8>,
+    instructions: u64,
+}
+
+pub struct Jit {
+    // Only taken to free the code when dropped
+    module: Option<JITModule>,
+    context: Context,
+    builder_context: FunctionBuilderContext,
+    // By the address they start at
+    blocks: Vec<Option<Block>>,
+    // What the blocks were compiled for, decoding, FX1E and 8XYN depending on it
+    settings: (Variant, IndexWidth, Quirks),
+}
+
+impl Jit {
+    pub fn new() -> Result<Self, String> {
+        let builder = JITBuilder::new(default_libcall_names()).map_err(|e| format!("Can't compile to native code here: {}", e))?;
+        let module = JITModule::new(builder);
+        let context = module.make_context();
+        Ok(Jit {
+            module: Some(module),
+            context,
+            builder_context: FunctionBuilderContext::new(),
+            blocks: Vec::new(),
+            settings: (Variant::default(), IndexWidth::default(), Quirks::default()),
+        })
+    }
+
+    /// Runs the block at the program counter if it's no longer than `budget` instructions, or the
+    /// next instruction with the interpreter, returning how many ran
+    pub fn step(&mut self, chip8: &mut Chip8, budget: u64) -> Result<u64, String> {
+        let settings = (chip8.variant, chip8.index_width, chip8.quirks);
+        if settings != self.settings || self.blocks.len() != chip8.memory.len() {
+            self.blocks.clear();
+            self.blocks.resize_with(chip8.memory.len(), || None);
+            self.settings = settings;
+        }
+        let start = chip8.program_counter;
+        let block = self.blocks.get(start as usize).and_then(Option::as_ref);
+        let compiled = block.is_some_and(|block| chip8.memory.get(start as usize..start as usize + block.bytes.len()) == Some(&block.bytes[..]));
+        if !compiled && (start as usize) < self.blocks.len() {
+            self.blocks[start as usize] = Some(self.compile(chip8, start)?);
+        }
+        match self.blocks.get(start as usize).and_then(Option::as_ref) {
+            Some(Block { function: Some(function), instructions, .. }) if *instructions <= budget => {
+                let registers = chip8.cpu_registers.as_mut_ptr().cast::<u8>();
+                // Blocks only touch the registers and I, both laid out as the code expects
+                let next = unsafe { function(registers, &mut chip8.index_register.0) };
+                chip8.program_counter = next as u16;
+                chip8.count_down(*instructions);
+                chip8.cycles += instructions;
+                Ok(*instructions)
+            }
+            _ => chip8.try_emulate_cycle().map(|_| 1).map_err(|error| error.to_string()),
+        }
+    }
+
+    /// Compiles the instructions from an address on that can be
+    fn compile(&mut self, chip8: &Chip8, start: u16) -> Result<Block, String> {
+        let mut instructions = Vec::new();
+        let mut address = start as usize;
+        // Short of the end of memory, and of where the program counter can't go past
+        while instructions.len() < MAX_BLOCK && address + 1 < chip8.memory.len() && address + 4 <= u16::MAX as usize {
+            let opcode = u16::from_be_bytes([chip8.memory[address], chip8.memory[address + 1]]);
+            let instruction = match Instruction::decode(opcode, chip8.variant) {
+                Some(instruction) if compiles(instruction, address, chip8.quirks) => instruction,
+                _ => break,
+            };
+            instructions.push(instruction);
+            address += 2;
+            if ends_block(instruction) {
+                break;
+            }
+        }
+        // A single instruction runs faster in the interpreter than through a call
+        if instructions.len() < 2 {
+            let bytes = chip8.memory.get(start as usize..start as usize + 2).unwrap_or_default().to_vec();
+            return Ok(Block { function: None, bytes, instructions: 0 });
+        }
+
+        let module = self.module.as_mut().unwrap();
+        let config = module.target_config();
+        let pointer = config.pointer_type();
+        let mut signature = module.make_signature();
+        signature.params.push(AbiParam::new(pointer));
+        signature.params.push(AbiParam::new(pointer));
+        signature.returns.push(AbiParam::new(types::I32));
+        let id = module.declare_anonymous_function(&signature).map_err(|e| e.to_string())?;
+        self.context.func.signature = signature;
+        self.context.func.name = UserFuncName::user(0, id.as_u32());
+
+        let mut builder = FunctionBuilder::new(&mut self.context.func, &mut self.builder_context);
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        builder.seal_block(entry);
+        let (registers, index) = (builder.block_params(entry)[0], builder.block_params(entry)[1]);
+        let flags = MemFlagsData::trusted();
+        let v: Vec<Variable> = (0..16)
+            .map(|x| {
+                let variable = builder.declare_var(types::I8);
+                let value = builder.ins().load(types::I8, flags, registers, x);
+                builder.def_var(variable, value);
+                variable
+            })
+            .collect();
+        let i = builder.declare_var(types::I16);
+        let value = builder.ins().load(types::I16, flags, index, 0);
+        builder.def_var(i, value);
+
+        let mut next = None;
+        for (offset, instruction) in instructions.iter().enumerate() {
+            let address = start as i64 + offset as i64 * 2;
+            let byte = |builder: &mut FunctionBuilder, value: u8| builder.ins().iconst(types::I8, value as i64);
+            match *instruction {
+                Instruction::Load(x, nn) => {
+                    let value = byte(&mut builder, nn);
+                    builder.def_var(v[x as usize], value);
+                }
+                Instruction::Add(x, nn) => {
+                    let (vx, nn) = (builder.use_var(v[x as usize]), byte(&mut builder, nn));
+                    let sum = builder.ins().iadd(vx, nn);
+                    builder.def_var(v[x as usize], sum);
+                }
+                Instruction::Alu(op, x, y) => alu(&mut builder, &v, op, x, y),
+                Instruction::LoadIndex(nnn) => {
+                    let value = builder.ins().iconst(types::I16, nnn as i64);
+                    builder.def_var(i, value);
+                }
+                Instruction::AddIndex(x) => {
+                    let vx = builder.use_var(v[x as usize]);
+                    let vx = builder.ins().uextend(types::I16, vx);
+                    let value = builder.use_var(i);
+                    let mut sum = builder.ins().iadd(value, vx);
+                    if self.settings.1 == IndexWidth::Bits12 {
+                        sum = builder.ins().band_imm_u(sum, 0x0FFF);
+                    }
+                    builder.def_var(i, sum);
+                }
+                Instruction::Jump(nnn) => next = Some(builder.ins().iconst(types::I32, nnn as i64)),
+                Instruction::SkipIfEqual(x, nn) | Instruction::SkipIfNotEqual(x, nn) => {
+                    let (vx, nn) = (builder.use_var(v[x as usize]), byte(&mut builder, nn));
+                    let condition = if matches!(instruction, Instruction::SkipIfEqual(..)) { IntCC::Equal } else { IntCC::NotEqual };
+                    let skip = builder.ins().icmp(condition, vx, nn);
+                    next = Some(skip_to(&mut builder, skip, address));
+                }
+                Instruction::SkipIfRegistersEqual(x, y) | Instruction::SkipIfRegistersNotEqual(x, y) => {
+                    let (vx, vy) = (builder.use_var(v[x as usize]), builder.use_var(v[y as usize]));
+                    let condition = if matches!(instruction, Instruction::SkipIfRegistersEqual(..)) { IntCC::Equal } else { IntCC::NotEqual };
+                    let skip = builder.ins().icmp(condition, vx, vy);
+                    next = Some(skip_to(&mut builder, skip, address));
+                }
+                _ => unreachable!("{} isn't compiled", instruction),
+            }
+        }
+
+        for (x, variable) in v.iter().enumerate() {
+            let value = builder.use_var(*variable);
+            builder.ins().store(flags, value, registers, x as i32);
+        }
+        let value = builder.use_var(i);
+        builder.ins().store(flags, value, index, 0);
+        let next = next.unwrap_or_else(|| builder.ins().iconst(types::I32, address as i64));
+        builder.ins().return_(&[next]);
+        builder.finalize(config);
+
+        module.define_function(id, &mut self.context).map_err(|e| e.to_string())?;
+        module.clear_context(&mut self.context);
+        module.finalize_definitions().map_err(|e| e.to_string())?;
+        // Compiled for the signature above
+        let function = unsafe { std::mem::transmute::<*const u8, BlockFn>(module.get_finalized_function(id)) };
+        Ok(Block { function: Some(function), bytes: chip8.memory[start as usize..address].to_vec(), instructions: instructions.len() as u64 })
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // Nothing can call a block once the JIT is gone
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+/// Whether an instruction at an address goes in a block. 8XYN is only compiled for the usual
+/// quirks, and left to the interpreter with others
+fn compiles(instruction: Instruction, address: usize, quirks: Quirks) -> bool {
+    match instruction {
+        Instruction::Alu(..) => quirks == Quirks::default(),
+        Instruction::Load(..)
+        | Instruction::Add(..)
+        | Instruction::LoadIndex(_)
+        | Instruction::AddIndex(_)
+        | Instruction::SkipIfEqual(..)
+        | Instruction::SkipIfNotEqual(..)
+        | Instruction::SkipIfRegistersEqual(..)
+        | Instruction::SkipIfRegistersNotEqual(..) => true,
+        Instruction::Jump(nnn) => nnn as usize != address,
+        _ => false,
+    }
+}
+
+fn ends_block(instruction: Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Jump(_)
+            | Instruction::SkipIfEqual(..)
+            | Instruction::SkipIfNotEqual(..)
+            | Instruction::SkipIfRegistersEqual(..)
+            | Instruction::SkipIfRegistersNotEqual(..)
+    )
+}
+
+/// The instruction after the next if `skip` is 1, the next one if not
+fn skip_to(builder: &mut FunctionBuilder, skip: Value, address: i64) -> Value {
+    let skipped = builder.ins().iconst(types::I32, address + 4);
+    let next = builder.ins().iconst(types::I32, address + 2);
+    builder.ins().select(skip, skipped, next)
+}
+
+/// 8XYN, setting VF before VX like the interpreter so either can be VF
+fn alu(builder: &mut FunctionBuilder, v: &[Variable], op: AluOp, x: u8, y: u8) {
+    let (vx, vy) = (builder.use_var(v[x as usize]), builder.use_var(v[y as usize]));
+    let flag = match op {
+        AluOp::Move => {
+            builder.def_var(v[x as usize], vy);
+            return;
+        }
+        AluOp::Or | AluOp::And | AluOp::Xor => {
+            let value = match op {
+                AluOp::Or => builder.ins().bor(vx, vy),
+                AluOp::And => builder.ins().band(vx, vy),
+                _ => builder.ins().bxor(vx, vy),
+            };
+            builder.def_var(v[x as usize], value);
+            return;
+        }
+        AluOp::Add => {
+            let sum = builder.ins().iadd(vx, vy);
+            builder.ins().icmp(IntCC::UnsignedLessThan, sum, vx)
+        }
+        AluOp::Sub => builder.ins().icmp(IntCC::UnsignedLessThanOrEqual, vy, vx),
+        AluOp::SubReverse => builder.ins().icmp(IntCC::UnsignedLessThanOrEqual, vx, vy),
+        AluOp::ShiftRight => {
+            let one = builder.ins().iconst(types::I8, 1);
+            builder.ins().band(vx, one)
+        }
+        AluOp::ShiftLeft => builder.ins().ushr_imm_u(vx, 7),
+    };
+    builder.def_var(v[0xF], flag);
+    let (vx, vy) = (builder.use_var(v[x as usize]), builder.use_var(v[y as usize]));
+    let value = match op {
+        AluOp::Add => builder.ins().iadd(vx, vy),
+        AluOp::Sub => builder.ins().isub(vx, vy),
+        AluOp::SubReverse => builder.ins().isub(vy, vx),
+        AluOp::ShiftRight => builder.ins().ushr_imm_u(vx, 1),
+        _ => builder.ins().ishl_imm_u(vx, 1),
+    };
+    builder.def_var(v[x as usize], value);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::jit::Jit;
+    use crate::chip8::Chip8;
+
+    /// Runs a program both ways for a number of instructions, returning V0 to VF, I and the
+    /// program counter of each
+    fn run_both(program: &[u8], instructions: u64) -> [(Vec<u8>, u16, u16); 2] {
+        let mut interpreted = Chip8::builder().build();
+        interpreted.load_program(program);
+        let mut compiled = interpreted.clone();
+        for _ in 0..instructions {
+            interpreted.try_emulate_cycle().unwrap();
+        }
+        let mut jit = Jit::new().unwrap();
+        let mut left = instructions;
+        while left > 0 {
+            left -= jit.step(&mut compiled, left).unwrap();
+        }
+        assert_eq!(compiled.cycles(), instructions);
+        [&interpreted, &compiled].map(|chip8| (chip8.cpu_registers.iter().map(|v| v.0).collect(), chip8.index_register.0, chip8.program_counter))
+    }
+
+    /// Blocks do what the interpreter does, VF as an operand included
+    #[test]
+    fn test_blocks_match_interpreter() {
+        // V0 = 0xF0, V1 = 0x25, VF = 3, then add, subtract both ways and shift with VF as
+        // operands, I = 0x300 + V1, skip if V0 != 0x15, count up V2 and loop
+        let program = [
+            0x60, 0xF0, 0x61, 0x25, 0x6F, 0x03, 0x80, 0x14, 0x8F, 0x14, 0x81, 0xF5, 0x8F, 0x07, 0x80, 0x16, 0x8F, 0x1E, 0xA3, 0x00,
+            0xF1, 0x1E, 0x40, 0x15, 0x72, 0x01, 0x82, 0x03, 0x12, 0x02,
+        ];
+        for instructions in [1, 5, 14, 15, 16, 100, 1000] {
+            let [interpreted, compiled] = run_both(&program, instructions);
+            assert_eq!(interpreted, compiled, "after {} instructions", instructions);
+        }
+    }
+
+    /// A block written over is compiled again
+    #[test]
+    fn test_self_modifying_code() {
+        // V1 += 1, jump back
+        let mut chip8 = Chip8::builder().build();
+        chip8.load_program(&[0x71, 0x01, 0x12, 0x00]);
+        let mut jit = Jit::new().unwrap();
+        assert_eq!(jit.step(&mut chip8, 10), Ok(2));
+        chip8.write_memory(0x201, &[0x10]);
+        assert_eq!(jit.step(&mut chip8, 10), Ok(2));
+        assert_eq!(chip8.cpu_registers[1].0, 0x11);
+
+        // Too long for what's left, and a jump to itself, go to the interpreter
+        assert_eq!(jit.step(&mut chip8, 1), Ok(1));
+        assert_eq!(jit.step(&mut chip8, 10), Ok(1));
+        chip8.write_memory(0x200, &[0x12, 0x00]);
+        assert_eq!(jit.step(&mut chip8, 10), Ok(1));
+        assert_eq!((chip8.cpu_registers[1].0, chip8.program_counter), (0x21, 0x200));
+    }
+}
@@ -1,9 +1,153 @@
+mod builder;
+mod error;
+mod font;
+mod instruction;
+#[cfg(feature = "jit")]
+pub mod jit;
+mod peripheral;
+mod random;
+#[cfg(test)]
+mod vectors;
+
+pub use self::builder::Chip8Builder;
+pub use self::error::Chip8Error;
+pub use self::font::Font;
+pub use self::instruction::{Access, AluOp, Instruction};
+pub use self::peripheral::Peripheral;
+pub use self::random::RandomSource;
 use std::num::Wrapping;
-use device_query::Keycode;
-use rand::Rng;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// Interpreter being emulated, enabling its extra opcodes
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Variant {
+    #[default]
+    Chip8,
+    /// CHIP-8X, adds a second hex keypad (EXF2/EXF5)
+    Chip8X,
+}
+
+impl FromStr for Variant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" => Ok(Variant::Chip8),
+            "chip8x" | "chip-8x" => Ok(Variant::Chip8X),
+            _ => Err(format!("Unknown variant: {}", s)),
+        }
+    }
+}
+
+/// What DXYN, FX33, FX55 and FX65 do with memory past the end when I points near it
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MemoryBounds {
+    /// Stop with a fault, which writes a crash dump
+    #[default]
+    Fault,
+    /// Wrap around to the start of memory, like interpreters masking addresses to 12 bits
+    Wrap,
+}
+
+impl FromStr for MemoryBounds {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fault" => Ok(MemoryBounds::Fault),
+            "wrap" => Ok(MemoryBounds::Wrap),
+            _ => Err(format!("Unknown memory bounds policy: {}, expected fault or wrap", s)),
+        }
+    }
+}
+
+/// What FX33 and FX55 do with the interpreter's memory below 0x200, where the original
+/// interpreter kept its variables and a program writing there would have crashed it
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum WriteProtection {
+    /// Write to it like any other memory, for programs using it on purpose
+    #[default]
+    Off,
+    /// Stop with a fault, which writes a crash dump
+    Fault,
+    /// Leave it as it is
+    Ignore,
+}
+
+impl FromStr for WriteProtection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(WriteProtection::Off),
+            "fault" => Ok(WriteProtection::Fault),
+            "ignore" => Ok(WriteProtection::Ignore),
+            _ => Err(format!("Unknown write protection: {}, expected off, fault or ignore", s)),
+        }
+    }
+}
+
+/// How wide I is, which decides where FX1E wraps it around
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum IndexWidth {
+    /// Masked to 12 bits, like interpreters with 4K of memory
+    Bits12,
+    /// A full 16 bits, like SCHIP and XO-CHIP
+    #[default]
+    Bits16,
+}
+
+impl FromStr for IndexWidth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "12" => Ok(IndexWidth::Bits12),
+            "16" => Ok(IndexWidth::Bits16),
+            _ => Err(format!("I is 12 or 16 bits wide, not {}", s)),
+        }
+    }
+}
+
+/// Behaviors interpreters disagree on, named as in Octo. The defaults are how this emulator runs
+/// programs unless told otherwise, which isn't the same as Octo's defaults
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quirks {
+    /// 8XY6 and 8XYE shift VX in place, instead of shifting VY into VX
+    pub shift: bool,
+    /// FX55 and FX65 leave I as it is, instead of moving it past the last register
+    pub load_store: bool,
+    /// 8XY4 to 8XYE set VF before working out VX, which then reads VF's new value and wins when
+    /// it is VF
+    pub vf_order: bool,
+    /// Sprites are cut off at the edges of the screen instead of wrapping around
+    pub clip: bool,
+    /// DXYN waits for the next 60 Hz timer tick
+    pub vblank: bool,
+    /// BNNN jumps to NNN plus VX, X being the top digit of NNN, instead of plus V0
+    pub jump: bool,
+    /// 8XY1, 8XY2 and 8XY3 set VF to 0
+    pub logic: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks { shift: true, load_store: true, vf_order: true, clip: false, vblank: false, jump: false, logic: false }
+    }
+}
 
-pub(crate) struct Chip8 {
-    memory: [u8; 4096],
+impl Quirks {
+    /// None of them, Octo's defaults
+    pub const OCTO: Quirks = Quirks { shift: false, load_store: false, vf_order: false, clip: false, vblank: false, jump: false, logic: false };
+}
+
+#[derive(Clone)]
+pub struct Chip8 {
+    // 4K unless the builder changes it, 64K for XO-CHIP
+    memory: Vec<u8>,
+    // Instructions decoded so far, by address
+    decoded: DecodeCache,
     // V
     cpu_registers: [Wrapping<u8>; 16],
     // I
@@ -12,16 +156,90 @@ pub(crate) struct Chip8 {
     // True if we do not call subroutine or jump to a certain address in memory
     // Will increment by four if next opcode should be skipped
     program_counter: u16,
-    gfx: [u8; 64 * 32],
+    // A row of pixels each, the leftmost in the top bit
+    rows: [u64; 32],
+    // Rows changed since the screen was last drawn to a buffer, a bit each
+    dirty_rows: u32,
     delay_timer: u8,
     sound_timer: u8,
-    stack: [u16; 16],
+    // Instructions to a timer tick, 1 unless a tick rate makes the timers count at 60 Hz
+    tickrate: u32,
+    // Instructions run since the last timer tick
+    ticks: u32,
+    // Return addresses, slot 0 never being used
+    stack: Vec<u16>,
     stack_pointer: u16,
     keys: [u8; 16],
+    // Second hex keypad, only read by CHIP-8X
+    keys2: [u8; 16],
     draw_flag: bool,
+    variant: Variant,
+    memory_bounds: MemoryBounds,
+    write_protection: WriteProtection,
+    index_width: IndexWidth,
+    quirks: Quirks,
+    // Where the small digits start, FX29 pointing I into them
+    font_address: u16,
+    // Bytes of digits loaded, big ones included
+    font_length: u16,
+    // Devices mapped into memory, the first claiming an address answering for it
+    peripherals: Vec<Box<dyn Peripheral>>,
+    // Source for CXNN, seeded so runs can be reproduced
+    rng: Box<dyn RandomSource>,
+    // Set by FX18, consumed by the frontend to schedule a tone
+    sound_request: Option<u8>,
+    // Display colors of pixels that are off and on
+    colors: [u32; 2],
+    // Instructions run since power on
+    cycles: u64,
+}
+
+/// Instruction starting at each address once it has run, cleared when memory under it changes.
+/// Not part of the machine's state: copies start without it, as savestates and rewinding copy
+/// the machine often, and it's made room for on the first instruction run.
+#[derive(Default)]
+struct DecodeCache(Vec<Option<Instruction>>);
+
+impl Clone for DecodeCache {
+    fn clone(&self) -> Self {
+        DecodeCache::default()
+    }
+}
+
+impl DecodeCache {
+    fn get(&self, address: usize) -> Option<Instruction> {
+        self.0.get(address).copied().flatten()
+    }
+
+    fn insert(&mut self, address: usize, instruction: Option<Instruction>, memory_size: usize) {
+        if self.0.is_empty() {
+            self.0.resize(memory_size, None);
+        }
+        self.0[address] = instruction;
+    }
+
+    /// Forgets the instructions over a range of addresses, including the one starting just
+    /// before it
+    fn invalidate(&mut self, addresses: Range<usize>) {
+        if self.0.is_empty() {
+            return;
+        }
+        let start = addresses.start.checked_sub(1).unwrap_or(self.0.len() - 1);
+        self.0[start] = None;
+        self.0[addresses].fill(None);
+    }
 }
 
-const CHIP8_FONTSET: [u8; 80] = [0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+/// Bytes of memory unless the builder changes it
+pub const DEFAULT_MEMORY_SIZE: usize = 0x1000;
+/// Most memory a machine can have, all that 16 bits address
+pub const MAX_MEMORY_SIZE: usize = 0x10000;
+
+/// Calls deep the stack goes unless the builder changes it, the 16 slots of the original
+/// interpreter less the one never used
+pub const DEFAULT_STACK_DEPTH: u16 = 15;
+
+pub const CHIP8_FONTSET: [u8; 80] = [0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
     0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
     0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
@@ -39,188 +257,260 @@ const CHIP8_FONTSET: [u8; 80] = [0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0xF0, 0x80, 0xF0, 0x80, 0x80  // F
 ];
 
+impl Default for Chip8 {
+    fn default() -> Self {
+        Chip8::new()
+    }
+}
+
 impl Chip8 {
+    pub fn builder() -> Chip8Builder {
+        Chip8Builder::default()
+    }
+
     pub fn new() -> Self {
         // Initialize registers and memory once
         let mut new_chip8 = Chip8 {
-            memory: [0; 4096],
+            memory: vec![0; DEFAULT_MEMORY_SIZE],
+            decoded: DecodeCache::default(),
             cpu_registers: [Wrapping(0); 16],
             index_register: Wrapping(0),
             program_counter: 0x200,
             delay_timer: 0,
             sound_timer: 0,
-            stack: [0; 16],
+            tickrate: 1,
+            ticks: 0,
+            stack: vec![0; DEFAULT_STACK_DEPTH as usize + 1],
             stack_pointer: 0,
             keys: [0; 16],
+            keys2: [0; 16],
             draw_flag: false,
-            gfx: [0; 64 * 32],
+            variant: Variant::Chip8,
+            memory_bounds: MemoryBounds::Fault,
+            write_protection: WriteProtection::Off,
+            index_width: IndexWidth::Bits16,
+            quirks: Quirks::default(),
+            font_address: 0,
+            font_length: CHIP8_FONTSET.len() as u16,
+            peripherals: Vec::new(),
+            rng: random::seeded(None),
+            sound_request: None,
+            colors: [0x0000, 0x0FFF],
+            rows: [0; 32],
+            dirty_rows: u32::MAX,
+            cycles: 0,
         };
 
         // Load fontset
-        for i in 0..CHIP8_FONTSET.len() {
-            new_chip8.memory[i] = CHIP8_FONTSET[i];
-        }
+        new_chip8.memory[..CHIP8_FONTSET.len()].copy_from_slice(&CHIP8_FONTSET);
 
         new_chip8
     }
 
+    /// Runs the instruction at the program counter, panicking with the reason if it can't
     pub fn emulate_cycle(&mut self) {
-        // Fetch Opcode
-        let opcode: u16 = (self.memory[self.program_counter as usize] as u16) << 8
-            | (self.memory[self.program_counter as usize + 1] as u16);
-
-        let command_bit: u8 = ((opcode & 0xF000) >> 12) as u8;
+        if let Err(error) = self.try_emulate_cycle() {
+            panic!("{}", error);
+        }
+    }
 
-        let v_x: usize = ((opcode & 0x0F00) >> 8) as usize;
-        let v_y: usize = ((opcode & 0x00F0) >> 4) as usize;
-        let nn = (opcode & 0x00FF) as u8;
-        let nnn = opcode & 0x0FFF;
+    /// Runs the instruction at the program counter, or says why it can't, leaving the machine as
+    /// it was
+    pub fn try_emulate_cycle(&mut self) -> Result<(), Chip8Error> {
+        let address = self.program_counter as usize;
+        match self.decoded.get(address) {
+            Some(instruction) => self.execute(instruction),
+            None => {
+                // Fetch Opcode
+                let opcode = match self.memory.get(address..address + 2) {
+                    Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]),
+                    None => return Err(Chip8Error::ProgramCounterPastEnd(self.program_counter)),
+                };
+                self.decoded.insert(address, Instruction::decode(opcode, self.variant), self.memory.len());
+                self.execute_opcode(opcode)
+            }
+        }
+    }
 
+    /// Runs an opcode as if it had been fetched from the program counter, timers and all, without
+    /// it having to be in memory
+    pub fn execute_opcode(&mut self, opcode: u16) -> Result<(), Chip8Error> {
         // Decode and Execute Opcode
-        // Note: "NNN" denotes last three "nibbles" of two-byte opcode
-        // "NN" denotes last two "nibbles" of two-byte opcode
-        match command_bit {
-            // Calls machine code at address NNN
-            0x0 => {
-                match opcode {
-                    0x00E0 => self.clear_screen(),
-                    0x00EE => self.return_from_subroutine(),
-                    _ => panic!("Unknown opcode: {:#X}", opcode),
-                }
-            }
-            0x1 => self.process_1_command(nnn),
-            0x2 => self.process_2_command(nnn),
-            0x3 => self.process_3_command(v_x, nn),
-            0x4 => self.process_4_command(v_x, nn),
-            0x5 => {
-                match opcode & 0x000F {
-                    0x0000 => self.process_5_command(v_x, v_y),
-                    _ => panic!("Unknown opcode: {:#X}", opcode),
-                }
-            },
-            0x6 => self.process_6_command(v_x, nn),
-            0x7 => self.process_7_command(v_x, nn),
-            0x8 => self.process_8_command(opcode & 0x000F, v_x, v_y),
-            0x9 => {
-                match opcode & 0x000F {
-                    0x0000 => self.process_9_command(v_x, v_y),
-                    _ => panic!("Unknown opcode: {:#X}", opcode),
-                }
-            },
-            0xA => self.process_a_command(nnn),
-            0xB => self.process_b_command(nnn),
-            0xC => self.process_c_command(v_x, nn),
+        let address = self.program_counter;
+        let instruction = Instruction::decode(opcode, self.variant).ok_or(Chip8Error::UnknownOpcode { opcode, address })?;
+        self.execute(instruction)
+    }
+
+    /// Runs an instruction, unless it can't run, in which case nothing changes
+    fn execute(&mut self, instruction: Instruction) -> Result<(), Chip8Error> {
+        self.check_memory(instruction)?;
+        // The program counter stops at 0xFFFF, which only 64K of memory reaches, and a skip needs
+        // room for the instruction skipped
+        let length = match instruction {
+            Instruction::Jump(_) | Instruction::Call(_) | Instruction::JumpOffset(_) | Instruction::Return => 0,
+            Instruction::SkipIfEqual(..)
+            | Instruction::SkipIfNotEqual(..)
+            | Instruction::SkipIfRegistersEqual(..)
+            | Instruction::SkipIfRegistersNotEqual(..)
+            | Instruction::SkipIfKey(_)
+            | Instruction::SkipIfNotKey(_)
+            | Instruction::SkipIfSecondKey(_)
+            | Instruction::SkipIfNotSecondKey(_) => 4,
+            _ => 2,
+        };
+        if self.program_counter as usize + length > u16::MAX as usize {
+            return Err(Chip8Error::RunsOffEnd { instruction, address: self.program_counter });
+        }
+        // Sprites wait for the start of a tick, idling until then
+        if self.quirks.vblank && self.ticks != 0 && matches!(instruction, Instruction::Draw(..)) {
+            self.count_down(1);
+            self.cycles += 1;
+            return Ok(());
+        }
+
+        match instruction {
+            Instruction::ClearScreen => self.clear_screen(),
+            Instruction::Return => self.return_from_subroutine()?,
+            Instruction::Jump(nnn) => self.process_1_command(nnn),
+            Instruction::Call(nnn) => self.process_2_command(nnn)?,
+            Instruction::SkipIfEqual(v_x, nn) => self.process_3_command(v_x, nn),
+            Instruction::SkipIfNotEqual(v_x, nn) => self.process_4_command(v_x, nn),
+            Instruction::SkipIfRegistersEqual(v_x, v_y) => self.process_5_command(v_x, v_y),
+            Instruction::Load(v_x, nn) => self.process_6_command(v_x, nn),
+            Instruction::Add(v_x, nn) => self.process_7_command(v_x, nn),
+            Instruction::Alu(op, v_x, v_y) => self.process_8_command(op, v_x, v_y),
+            Instruction::SkipIfRegistersNotEqual(v_x, v_y) => self.process_9_command(v_x, v_y),
+            Instruction::LoadIndex(nnn) => self.process_a_command(nnn),
+            Instruction::JumpOffset(nnn) => self.process_b_command(nnn),
+            Instruction::Random(v_x, nn) => self.process_c_command(v_x, nn),
             // Draw sprite at coordinate (VX, VY) 8 pixels wide and N pixels high where N is last nibble
-            0xD => {
-                // Fetch position and height of sprite
-                let x = self.cpu_registers[v_x].0 as u16;
-                let y = self.cpu_registers[v_y].0 as u16;
-                // Pixel value
-                let height: u16 = opcode & 0x000F;
+            Instruction::Draw(v_x, v_y, height) => {
+                // Fetch position of sprite
+                let x = self.cpu_registers[v_x as usize].0 as usize;
+                let y = self.cpu_registers[v_y as usize].0 as usize;
 
                 // Reset register VF
                 self.cpu_registers[0x0F] = Wrapping(0);
-                for y_line in 0..height {
-                    // fetch pixel value from memory starting at location I
-                    let pixel = self.memory[(self.index_register.0 + y_line) as usize];
-                    // Sprite is always 8 wide, loop over 8 bits to draw one row
-                    for x_line in 0..8 {
-                        // Check if current pixel is set to 1 (using >> x_line to scan through byte)
-                        if (pixel & (0x80 >> x_line)) != 0 {
-                            let gfx_idx: usize = ((x + x_line + ((y + y_line) * 64)) as usize) % self.gfx.len();
-
-                            // If current pixel is 1 we need to set the VF register
-                            if self.gfx[gfx_idx] == 1 {
-                                self.cpu_registers[0x0F] = Wrapping(1);
-                            }
-                            // Set pixel value using XOR
-                            self.gfx[gfx_idx] ^= 1;
-                        }
+                for y_line in 0..height as usize {
+                    // fetch sprite row from memory starting at location I
+                    let sprite = self.load(y_line);
+                    // Pixels past the right edge carry on at the start of the next row, and the
+                    // bottom row's at the top, unless clipped
+                    let start = if self.quirks.clip { x % 64 + (y % 32 + y_line) * 64 } else { (x + (y + y_line) * 64) % (64 * 32) };
+                    if start >= 64 * 32 {
+                        break;
+                    }
+                    let (row, column) = (start / 64, start % 64);
+                    let next = (row + 1) % self.rows.len();
+                    // The row and the next one side by side, the leftmost pixel in the top bit
+                    let bits = (sprite as u128) << (120 - column);
+                    let (left, right) = ((bits >> 64) as u64, if self.quirks.clip { 0 } else { bits as u64 });
+
+                    // If any pixel drawn is already on we need to set the VF register
+                    if self.rows[row] & left != 0 || self.rows[next] & right != 0 {
+                        self.cpu_registers[0x0F] = Wrapping(1);
                     }
+                    // Set pixel values using XOR
+                    self.rows[row] ^= left;
+                    self.rows[next] ^= right;
+                    self.dirty_rows |= ((left != 0) as u32) << row | ((right != 0) as u32) << next;
                 }
 
-                // gfx array updated, need to draw screen
+                // Screen updated, need to draw it
                 self.draw_flag = true;
                 // Move to next opcode
                 self.program_counter += 2;
             },
-            0xE => {
-                match opcode & 0x00FF {
-                    0x009E => self.process_ex9e_command(v_x),
-                    0x00A1 => self.process_exa1_command(v_x),
-                    _ => panic!("Unknown opcode: {:#X}", opcode),
+            Instruction::SkipIfKey(v_x) => self.process_ex9e_command(v_x),
+            Instruction::SkipIfNotKey(v_x) => self.process_exa1_command(v_x),
+            Instruction::SkipIfSecondKey(v_x) => self.process_exf2_command(v_x),
+            Instruction::SkipIfNotSecondKey(v_x) => self.process_exf5_command(v_x),
+            // Store current value of delay timer in register VX
+            Instruction::GetDelay(v_x) => {
+                self.cpu_registers[v_x as usize] = Wrapping(self.delay_timer);
+                self.program_counter += 2;
+            }
+            // Set delay timer to value of register VX
+            Instruction::SetDelay(v_x) => {
+                self.delay_timer = self.cpu_registers[v_x as usize].0;
+                self.program_counter += 2;
+            }
+            // Set sound timer to VX
+            Instruction::SetSound(v_x) => {
+                self.sound_timer = self.cpu_registers[v_x as usize].0;
+                self.sound_request = Some(self.sound_timer);
+                self.program_counter += 2;
+            }
+            // 0xFX1E - Adds VX to I. VF not affected
+            Instruction::AddIndex(v_x) => {
+                self.index_register += Wrapping(self.cpu_registers[v_x as usize].0 as u16);
+                if self.index_width == IndexWidth::Bits12 {
+                    self.index_register &= Wrapping(0x0FFF);
                 }
-            },
-            0xF => {
-                match opcode & 0xF0FF {
-                    // Store current value of delay timer in register VX
-                    0xF007 => {
-                        self.cpu_registers[v_x] = Wrapping(self.delay_timer);
-                        self.program_counter += 2;
-                    }
-                    // Set delay timer to value of register VX
-                    0xF015 => {
-                        self.delay_timer = self.cpu_registers[v_x].0;
-                        self.program_counter += 2;
-                    }
-                    // Set sound timer to VX
-                    0xF018 => {
-                        self.sound_timer = self.cpu_registers[v_x].0;
-                        self.program_counter += 2;
-                    }
-                    // 0xFX1E - Adds VX to I. VF not affected
-                    0xF01E => {
-                        self.index_register += Wrapping(self.cpu_registers[v_x].0 as u16);
-                        self.program_counter += 2;
-                    }
-                    // Sets I to location of the sprite for character in VX
-                    0xF029 => {
-                        self.index_register = Wrapping((self.cpu_registers[v_x].0 as u16) * 5);
-                        self.program_counter += 2;
-                    }
-                    // Store binary-coded decimal representation of VX at addresses I, I+1, and I+2
-                    0xF033 => { // opcode 0xFX33
-                        self.memory[self.index_register.0 as usize] = self.cpu_registers[v_x].0 / 100;
-                        self.memory[self.index_register.0 as usize + 1] = (self.cpu_registers[v_x].0 / 10) % 10;
-                        self.memory[self.index_register.0 as usize + 2] = (self.cpu_registers[v_x].0 % 100) % 10;
-                        self.program_counter += 2;
-                    }
-                    // Stores V0 to VX in memory starting at address I
-                    0xF055 => {
-                        for i in 0..v_x + 1 {
-                            self.memory[self.index_register.0 as usize + i] = self.cpu_registers[i].0;
-                        }
-                        self.program_counter += 2;
-                    }
-                    // Fills V0 to VX (including VX) with values from memory starting at address I
-                    0xF065 => {
-                        for i in 0..v_x + 1 {
-                            self.cpu_registers[i] = Wrapping(self.memory[self.index_register.0 as usize + i]);
-                        }
-                        self.program_counter += 2;
-                    }
-                    _ => panic!("Unknown opcode: {:#X}", opcode),
+                self.program_counter += 2;
+            }
+            // Sets I to location of the sprite for character in VX
+            Instruction::LoadFont(v_x) => {
+                self.index_register = Wrapping(self.font_address + (self.cpu_registers[v_x as usize].0 as u16) * 5);
+                self.program_counter += 2;
+            }
+            // Store binary-coded decimal representation of VX at addresses I, I+1, and I+2
+            Instruction::StoreBcd(v_x) => {
+                let value = self.cpu_registers[v_x as usize].0;
+                for (offset, digit) in [value / 100, (value / 10) % 10, value % 10].iter().enumerate() {
+                    self.store(offset, *digit);
+                }
+                self.program_counter += 2;
+            }
+            // Stores V0 to VX in memory starting at address I
+            Instruction::StoreRegisters(v_x) => {
+                for i in 0..=v_x as usize {
+                    self.store(i, self.cpu_registers[i].0);
+                }
+                self.move_index_past(v_x);
+                self.program_counter += 2;
+            }
+            // Fills V0 to VX (including VX) with values from memory starting at address I
+            Instruction::LoadRegisters(v_x) => {
+                for i in 0..=v_x as usize {
+                    self.cpu_registers[i] = Wrapping(self.load(i));
                 }
+                self.move_index_past(v_x);
+                self.program_counter += 2;
             }
-            _ => panic!("Unknown opcode: {:#X}", opcode),
         }
 
-        // Update timers
-        if self.delay_timer > 0 {
-            self.delay_timer -= 1;
-        }
-        if self.sound_timer > 0 {
-            if self.sound_timer == 1 {
-                println!("BEEP");
+        self.count_down(1);
+        self.cycles += 1;
+        Ok(())
+    }
+
+    /// Counts instructions run towards the timer ticks, counting the timers down once a tick
+    pub(crate) fn count_down(&mut self, instructions: u64) {
+        let ticks = self.ticks as u64 + instructions;
+        self.ticks = (ticks % self.tickrate as u64) as u32;
+        let elapsed = (ticks / self.tickrate as u64).min(u8::MAX as u64) as u8;
+        self.delay_timer = self.delay_timer.saturating_sub(elapsed);
+        self.sound_timer = self.sound_timer.saturating_sub(elapsed);
+    }
+
+    /// Moves I past the registers FX55 and FX65 went through, unless the quirk leaves it
+    fn move_index_past(&mut self, v_x: u8) {
+        if !self.quirks.load_store {
+            self.index_register += Wrapping(v_x as u16 + 1);
+            if self.index_width == IndexWidth::Bits12 {
+                self.index_register &= Wrapping(0x0FFF);
             }
-            self.sound_timer -= 1;
         }
     }
 
     /// 0x00E0
     /// Clear the screen of all sprite data
     fn clear_screen(&mut self) {
-        self.gfx = [0; 64 * 32];
+        for (y, row) in self.rows.iter_mut().enumerate() {
+            self.dirty_rows |= ((*row != 0) as u32) << y;
+            *row = 0;
+        }
         self.draw_flag = true;
         self.program_counter += 2;
     }
@@ -228,9 +518,16 @@ impl Chip8 {
     /// 0x00EE
     /// Return from subroutine
     /// Stack pointer is decremented and program counter is set back to value retrieved from stack
-    fn return_from_subroutine(&mut self) {
-        self.program_counter = self.stack[self.stack_pointer as usize] + 2;
+    fn return_from_subroutine(&mut self) -> Result<(), Chip8Error> {
+        if self.stack_pointer == 0 {
+            return Err(Chip8Error::StackUnderflow(self.program_counter));
+        }
+        // A call from the top of memory has nowhere to return to
+        self.program_counter = self.stack[self.stack_pointer as usize]
+            .checked_add(2)
+            .ok_or(Chip8Error::RunsOffEnd { instruction: Instruction::Return, address: self.program_counter })?;
         self.stack_pointer -= 1;
+        Ok(())
     }
 
     /// 0x1NNN
@@ -239,123 +536,192 @@ impl Chip8 {
         self.program_counter = nnn;
     }
 
+    /// Address of the byte at an offset from I, wrapped around at the end of memory. With the
+    /// memory bounds faulting, `check_memory` has made sure it doesn't need to.
+    fn index_address(&self, offset: usize) -> usize {
+        (self.index_register.0 as usize + offset) % self.memory.len()
+    }
+
+    /// Checks the memory an instruction reads or writes through I, before it touches any of it:
+    /// none of it past the end unless the memory bounds wrap around, and none of the
+    /// interpreter's written to with write protection faulting, unless a device is mapped there
+    fn check_memory(&self, instruction: Instruction) -> Result<(), Chip8Error> {
+        let (address, index) = (self.program_counter, self.index_register.0);
+        let write = matches!(instruction.memory_access(index), Some((Access::Write, _)));
+        for offset in 0..instruction.memory_length() {
+            // I plus the offset, which can go past 0xFFFF
+            let byte = index as usize + offset;
+            if self.memory_bounds == MemoryBounds::Fault && byte >= self.memory.len() {
+                return Err(Chip8Error::OutOfBounds { instruction, address, index });
+            }
+            let byte = byte % self.memory.len();
+            if write && byte < 0x200 && self.write_protection == WriteProtection::Fault && !self.is_mapped(byte) {
+                return Err(Chip8Error::WriteProtected { instruction, address, index });
+            }
+        }
+        Ok(())
+    }
+
+    /// The device mapped at an address, if any
+    fn peripheral(&mut self, address: usize) -> Option<&mut Box<dyn Peripheral>> {
+        self.peripherals.iter_mut().find(|peripheral| peripheral.range().contains(&(address as u16)))
+    }
+
+    /// Whether a device is mapped at an address
+    fn is_mapped(&self, address: usize) -> bool {
+        self.peripherals.iter().any(|peripheral| peripheral.range().contains(&(address as u16)))
+    }
+
+    /// Reads the byte at an offset from I, from memory or the device mapped there
+    fn load(&mut self, offset: usize) -> u8 {
+        let address = self.index_address(offset);
+        match self.peripheral(address) {
+            Some(peripheral) => peripheral.read(address as u16),
+            None => self.memory[address],
+        }
+    }
+
+    /// Writes a byte at an offset from I, to the device mapped there or else to memory, unless
+    /// write protection keeps it out of the interpreter's memory. Writes there that fault were
+    /// stopped by `check_memory` before the instruction ran.
+    fn store(&mut self, offset: usize, value: u8) {
+        let address = self.index_address(offset);
+        if let Some(peripheral) = self.peripheral(address) {
+            peripheral.write(address as u16, value);
+            return;
+        }
+        if address < 0x200 && self.write_protection != WriteProtection::Off {
+            return;
+        }
+        self.memory[address] = value;
+        self.invalidate(address..address + 1);
+    }
+
+    /// Forgets the instructions decoded over a range of addresses
+    fn invalidate(&mut self, addresses: Range<usize>) {
+        self.decoded.invalidate(addresses);
+    }
+
     /// 0x2nnn
     /// Calls subroutine at NNN
-    fn process_2_command(&mut self, nnn: u16) {
+    fn process_2_command(&mut self, nnn: u16) -> Result<(), Chip8Error> {
+        if self.stack_pointer as usize == self.stack.len() - 1 {
+            return Err(Chip8Error::StackOverflow { address: self.program_counter, calls: self.call_stack().to_vec() });
+        }
         // Store current position of program counter on the stack
         self.stack_pointer += 1;
         self.stack[self.stack_pointer as usize] = self.program_counter;
         // Set program counter to nnn to start subroutine
         self.program_counter = nnn;
+        Ok(())
     }
 
     /// 0x3XNN
     /// Skip next instruction if VX equals NN
-    fn process_3_command(&mut self, v_x: usize, nn: u8) {
-        self.program_counter += if self.cpu_registers[v_x].0 == nn { 4 } else { 2 };
+    fn process_3_command(&mut self, v_x: u8, nn: u8) {
+        self.program_counter += if self.cpu_registers[v_x as usize].0 == nn { 4 } else { 2 };
     }
 
     /// 0x4XNN
     /// Skip next instruction if VX does NOT equals NN
-    fn process_4_command(&mut self, v_x: usize, nn: u8) {
-        self.program_counter += if self.cpu_registers[v_x].0 != nn { 4 } else { 2 };
+    fn process_4_command(&mut self, v_x: u8, nn: u8) {
+        self.program_counter += if self.cpu_registers[v_x as usize].0 != nn { 4 } else { 2 };
     }
 
     /// 0x5NNN
     /// Determine if opcode is 0x5XY0
     /// If so, skip next instruction if VX = VY
-    fn process_5_command(&mut self, v_x: usize, v_y: usize) {
-        self.program_counter += if self.cpu_registers[v_x] == self.cpu_registers[v_y] { 4 } else { 2 };
+    fn process_5_command(&mut self, v_x: u8, v_y: u8) {
+        self.program_counter += if self.cpu_registers[v_x as usize] == self.cpu_registers[v_y as usize] { 4 } else { 2 };
     }
 
     /// 0x6XNN
     /// Sets VX to NN
-    fn process_6_command(&mut self, v_x: usize, nn: u8) {
-        self.cpu_registers[v_x] = Wrapping(nn);
+    fn process_6_command(&mut self, v_x: u8, nn: u8) {
+        self.cpu_registers[v_x as usize] = Wrapping(nn);
         self.program_counter += 2;
     }
 
     /// 0x7XNN
     /// Adds NN to VX
-    fn process_7_command(&mut self, v_x: usize, nn: u8) {
-        self.cpu_registers[v_x] += Wrapping(nn);
+    fn process_7_command(&mut self, v_x: u8, nn: u8) {
+        self.cpu_registers[v_x as usize] += Wrapping(nn);
         self.program_counter += 2;
     }
 
     /// 0x8XYN
     /// Various arithmetic instructions
-    fn process_8_command(&mut self, operator: u16, v_x: usize, v_y: usize) {
+    fn process_8_command(&mut self, operator: AluOp, v_x: u8, v_y: u8) {
+        let x = v_x as usize;
+        // With the quirk VF is set first and the result worked out from the registers after, as
+        // the original interpreter does when VF is an operand
+        if self.quirks.vf_order {
+            if let Some(flag) = self.alu_flag(operator, v_x, v_y) {
+                self.cpu_registers[0xF] = Wrapping(flag);
+            }
+            self.cpu_registers[x] = self.alu_value(operator, v_x, v_y);
+        } else {
+            let flag = self.alu_flag(operator, v_x, v_y);
+            self.cpu_registers[x] = self.alu_value(operator, v_x, v_y);
+            if let Some(flag) = flag {
+                self.cpu_registers[0xF] = Wrapping(flag);
+            }
+        }
+        self.program_counter += 2;
+    }
+
+    /// The register shifts take VY unless the quirk shifts VX in place
+    fn shifted(&self, v_x: u8, v_y: u8) -> u8 {
+        self.cpu_registers[if self.quirks.shift { v_x } else { v_y } as usize].0
+    }
+
+    /// What 0x8XYN sets VX to
+    fn alu_value(&self, operator: AluOp, v_x: u8, v_y: u8) -> Wrapping<u8> {
+        let (vx, vy) = (self.cpu_registers[v_x as usize], self.cpu_registers[v_y as usize]);
         match operator {
             // 0x8XY0 - Sets VX to the value of VY
-            0x0000 => {
-                self.cpu_registers[v_x] = self.cpu_registers[v_y];
-                self.program_counter += 2;
-            }
+            AluOp::Move => vy,
             // 0x8XY1 - Sets VX to bitwise OR operation of VX and VY
-            0x0001 => {
-                self.cpu_registers[v_x] |= self.cpu_registers[v_y];
-                self.program_counter += 2;
-            }
+            AluOp::Or => vx | vy,
             // 0x8XY2 - Sets VX to bitwise AND operation of VX and VY
-            0x0002 => {
-                self.cpu_registers[v_x] &= self.cpu_registers[v_y];
-                self.program_counter += 2;
-            }
+            AluOp::And => vx & vy,
             // 0x8XY3 - Sets VX to bitwise XOR operation of VX and VY
-            0x0003 => {
-                self.cpu_registers[v_x] ^= self.cpu_registers[v_y];
-                self.program_counter += 2;
-            }
+            AluOp::Xor => vx ^ vy,
             // 0x8XY4 - Adds value of VY to VX
-            0x0004 => {
-                self.cpu_registers[0xF] = Wrapping(match self.cpu_registers[v_x].0 > (0xFF - self.cpu_registers[v_y].0) {
-                    true => 1, // carry
-                    false => 0
-                });
+            AluOp::Add => vx + vy,
+            // 0x8XY5 - Sets VX to VX - VY
+            AluOp::Sub => vx - vy,
+            // 0x8XY6 - Shifts to the right by 1
+            AluOp::ShiftRight => Wrapping(self.shifted(v_x, v_y) >> 1),
+            // 0x08XY7 - Sets VX to VY - VX
+            AluOp::SubReverse => vy - vx,
+            // 0x8XYE - Shifts to the left by 1
+            AluOp::ShiftLeft => Wrapping(self.shifted(v_x, v_y) << 1),
+        }
+    }
 
-                self.cpu_registers[v_x] += self.cpu_registers[v_y];
-                self.program_counter += 2;
-            }
-            // 0x8XY5 - Sets VX to VX - VY. VF set to 0 when there's borrow, 1 when there isn't
-            0x0005 => {
-                self.cpu_registers[0xF] = Wrapping(if self.cpu_registers[v_y] > self.cpu_registers[v_x] {
-                    0x00 // Borrow occurred
-                } else {
-                    0x01
-                });
-                self.cpu_registers[v_x] -= self.cpu_registers[v_y];
-                self.program_counter += 2;
-            }
-            // 0x8XY6 - Store least significant bit of VS in VF and then shifts VX to the right by 1
-            0x0006 => {
-                self.cpu_registers[0x0F] = Wrapping(self.cpu_registers[v_x].0 & 1);
-                self.cpu_registers[v_x] >>= 1;
-                self.program_counter += 2;
-            }
-            // 0x08XY7 - Sets VX to VY - VX. VF set to 0 when there's a borrow and 1 when there isn't
-            0x0007 => {
-                self.cpu_registers[0xF] = Wrapping(if self.cpu_registers[v_x] > self.cpu_registers[v_y] {
-                    0x00 // Borrow occurred
-                } else {
-                    0x01
-                });
-                self.cpu_registers[v_x] = self.cpu_registers[v_y] - self.cpu_registers[v_x];
-                self.program_counter += 2;
-            }
-            // 0x8XYE - Store most significant bit of VX in VF and then shifts VX to the left by 1
-            0x000E => {
-                self.cpu_registers[0x0F] = Wrapping((self.cpu_registers[v_x].0 & 0b10000000) >> 7);
-                self.cpu_registers[v_x] <<= 1;
-                self.program_counter += 2;
-            }
-            _ => panic!("Unknown opcode: {:#X}", operator),
+    /// What 0x8XYN sets VF to, if anything
+    fn alu_flag(&self, operator: AluOp, v_x: u8, v_y: u8) -> Option<u8> {
+        let (vx, vy) = (self.cpu_registers[v_x as usize].0, self.cpu_registers[v_y as usize].0);
+        match operator {
+            AluOp::Move => None,
+            AluOp::Or | AluOp::And | AluOp::Xor => self.quirks.logic.then_some(0),
+            // Carry
+            AluOp::Add => Some((vx > 0xFF - vy) as u8),
+            // 0 when there's a borrow, 1 when there isn't
+            AluOp::Sub => Some((vy <= vx) as u8),
+            // Least significant bit
+            AluOp::ShiftRight => Some(self.shifted(v_x, v_y) & 1),
+            AluOp::SubReverse => Some((vx <= vy) as u8),
+            // Most significant bit
+            AluOp::ShiftLeft => Some(self.shifted(v_x, v_y) >> 7),
         }
     }
 
     /// 0x9XY0
     /// Skips next instruction if VX doesn't equal VY (program counter increments by 4 instead of 2)
-    fn process_9_command(&mut self, v_x: usize, v_y: usize) {
-        self.program_counter += if self.cpu_registers[v_x] != self.cpu_registers[v_y] { 4 } else { 2 };
+    fn process_9_command(&mut self, v_x: u8, v_y: u8) {
+        self.program_counter += if self.cpu_registers[v_x as usize] != self.cpu_registers[v_y as usize] { 4 } else { 2 };
     }
 
     /// 0xANNN
@@ -366,84 +732,270 @@ impl Chip8 {
     }
 
     /// 0xBNNN
-    /// Sets program counter to address NNN plus value of V0
+    /// Sets program counter to address NNN plus value of V0, or of VX with the quirk, X being the
+    /// top digit of NNN
     fn process_b_command(&mut self, nnn: u16) {
-        self.program_counter = nnn + self.cpu_registers[0x0].0 as u16;
+        let register = if self.quirks.jump { (nnn >> 8) as usize & 0xF } else { 0 };
+        self.program_counter = nnn + self.cpu_registers[register].0 as u16;
     }
 
     /// 0xCNNN
     /// Sets VX to the result of bitwise AND on random number (0 to 255) and NN
-    fn process_c_command(&mut self, v_x: usize, nn: u8) {
-        self.cpu_registers[v_x] = Wrapping(rand::thread_rng().gen::<u8>() & nn);
+    fn process_c_command(&mut self, v_x: u8, nn: u8) {
+        self.cpu_registers[v_x as usize] = Wrapping(self.rng.next_byte() & nn);
         self.program_counter += 2;
     }
 
     /// 0xEX9E
-    /// Skips next instruction if key stored in VX is pressed
-    fn process_ex9e_command(&mut self, v_x: usize) {
-        let key_idx = self.cpu_registers[v_x].0 as usize;
+    /// Skips next instruction if key stored in VX is pressed. Only the low digit of VX picks the
+    /// key, like on the VIP, for this and the other key skips
+    fn process_ex9e_command(&mut self, v_x: u8) {
+        let key_idx = (self.cpu_registers[v_x as usize].0 & 0x0F) as usize;
         self.program_counter += if self.keys[key_idx] == 1 { 4 } else { 2 };
     }
 
     /// 0xEXA1
     /// Skips next instruction if key stored in VX is NOT pressed
-    fn process_exa1_command(&mut self, v_x: usize) {
-        let key_idx = self.cpu_registers[v_x].0 as usize;
+    fn process_exa1_command(&mut self, v_x: u8) {
+        let key_idx = (self.cpu_registers[v_x as usize].0 & 0x0F) as usize;
         self.program_counter += if self.keys[key_idx] != 1 { 4 } else { 2 };
     }
 
-    pub fn draw_to_buffer(&mut self, buffer: &mut Vec<u32>) -> bool {
+    /// 0xEXF2 (CHIP-8X)
+    /// Skips next instruction if key stored in VX is pressed on the second keypad
+    fn process_exf2_command(&mut self, v_x: u8) {
+        let key_idx = (self.cpu_registers[v_x as usize].0 & 0x0F) as usize;
+        self.program_counter += if self.keys2[key_idx] == 1 { 4 } else { 2 };
+    }
+
+    /// 0xEXF5 (CHIP-8X)
+    /// Skips next instruction if key stored in VX is NOT pressed on the second keypad
+    fn process_exf5_command(&mut self, v_x: u8) {
+        let key_idx = (self.cpu_registers[v_x as usize].0 & 0x0F) as usize;
+        self.program_counter += if self.keys2[key_idx] != 1 { 4 } else { 2 };
+    }
+
+    pub fn draw_to_buffer(&mut self, buffer: &mut [u32]) -> bool {
         let mut should_draw = false;
         if self.draw_flag {
-            for pixel_idx in 0..buffer.len() {
-                buffer[pixel_idx] = if self.gfx[pixel_idx] == 0 { 0x0000 } else { 0x0FFF };
+            for (y, (row, pixels)) in self.rows.iter().zip(buffer.chunks_mut(64)).enumerate() {
+                // Rows that didn't change are still in the buffer from last time
+                if self.dirty_rows >> y & 1 == 0 {
+                    continue;
+                }
+                for (x, pixel) in pixels.iter_mut().enumerate() {
+                    *pixel = self.colors[(row >> (63 - x) & 1) as usize];
+                }
             }
+            self.dirty_rows = 0;
             should_draw = true;
         }
         self.draw_flag = false;
         should_draw
     }
 
-    pub fn set_keys(&mut self, keys: Vec<Keycode>) {
-        for key in self.keys.iter_mut() {
-            *key = 0;
-        }
-
-        for key in keys {
-            match key {
-                Keycode::Key1 => self.keys[0] = 1,
-                Keycode::Key2 => self.keys[1] = 1,
-                Keycode::Key3 => self.keys[2] = 1,
-                Keycode::Key4 => self.keys[3] = 1,
-                Keycode::Q => self.keys[4] = 1,
-                Keycode::W => self.keys[5] = 1,
-                Keycode::E => self.keys[6] = 1,
-                Keycode::R => self.keys[7] = 1,
-                Keycode::A => self.keys[8] = 1,
-                Keycode::S => self.keys[9] = 1,
-                Keycode::D => self.keys[10] = 1,
-                Keycode::F => self.keys[11] = 1,
-                Keycode::Z => self.keys[12] = 1,
-                Keycode::X => self.keys[13] = 1,
-                Keycode::C => self.keys[14] = 1,
-                Keycode::V => self.keys[15] = 1,
-                _ => {}
-            }
+    /// Makes the next `draw_to_buffer` draw the whole screen even if nothing changed, e.g. after
+    /// restoring a savestate or when the buffer was drawn over
+    pub fn force_redraw(&mut self) {
+        self.draw_flag = true;
+        self.dirty_rows = u32::MAX;
+    }
+
+    pub fn set_keys(&mut self, keys: [bool; 16]) {
+        for (key, pressed) in self.keys.iter_mut().zip(keys.iter()) {
+            *key = *pressed as u8;
+        }
+    }
+
+    /// FNV-1a hash of the machine state (everything except input), used to detect when two runs
+    /// that should be identical have diverged
+    pub fn state_checksum(&self) -> u64 {
+        let mut hash: u64 = 0xCBF2_9CE4_8422_2325;
+        let mut feed = |byte: u8| {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01B3);
+        };
+        self.memory.iter().for_each(|b| feed(*b));
+        self.cpu_registers.iter().for_each(|r| feed(r.0));
+        self.index_register.0.to_le_bytes().iter().for_each(|b| feed(*b));
+        self.program_counter.to_le_bytes().iter().for_each(|b| feed(*b));
+        self.display().iter().for_each(|p| feed(*p));
+        feed(self.delay_timer);
+        feed(self.sound_timer);
+        self.stack.iter().flat_map(|s| s.to_le_bytes()).for_each(&mut feed);
+        self.stack_pointer.to_le_bytes().iter().for_each(|b| feed(*b));
+        hash
+    }
+
+    /// Current state of the keypad, as seen by EX9E/EXA1
+    pub fn keypad(&self) -> [bool; 16] {
+        let mut keypad = [false; 16];
+        for (pressed, key) in keypad.iter_mut().zip(self.keys.iter()) {
+            *pressed = *key == 1;
         }
+        keypad
     }
 
-    pub fn load_program(&mut self, program_buffer: &Vec<u8>) {
-        for i in 0..program_buffer.len() {
-            self.memory[i + 512] = program_buffer[i];
+    /// Sets the state of the second keypad, used by two-player CHIP-8X programs
+    pub fn set_second_keypad(&mut self, keys: [bool; 16]) {
+        for (key, pressed) in self.keys2.iter_mut().zip(keys.iter()) {
+            *key = *pressed as u8;
         }
     }
+
+    pub fn second_keypad(&self) -> [bool; 16] {
+        let mut keypad = [false; 16];
+        for (pressed, key) in keypad.iter_mut().zip(self.keys2.iter()) {
+            *pressed = *key == 1;
+        }
+        keypad
+    }
+
+    /// Sets the state of a single keypad key (0x0 - 0xF) on top of what `set_keys` reported,
+    /// for input sources other than the keyboard
+    pub fn set_key(&mut self, key: usize, pressed: bool) {
+        self.keys[key] = pressed as u8;
+    }
+
+    /// Returns the sound timer length (in 60Hz ticks) set by the last FX18, if any,
+    /// so the frontend can schedule the whole tone at once instead of polling the timer.
+    /// 0 cuts short the tone playing
+    pub fn take_sound_request(&mut self) -> Option<u8> {
+        self.sound_request.take()
+    }
+
+    pub fn program_counter(&self) -> u16 {
+        self.program_counter
+    }
+
+    /// V0 to VF
+    pub fn registers(&self) -> [u8; 16] {
+        let mut registers = [0; 16];
+        for (value, register) in registers.iter_mut().zip(self.cpu_registers.iter()) {
+            *value = register.0;
+        }
+        registers
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index_register.0
+    }
+
+    pub fn set_register(&mut self, register: usize, value: u8) {
+        self.cpu_registers[register] = Wrapping(value);
+    }
+
+    pub fn set_index(&mut self, value: u16) {
+        self.index_register = Wrapping(value);
+    }
+
+    /// Delay and sound timers
+    pub fn timers(&self) -> (u8, u8) {
+        (self.delay_timer, self.sound_timer)
+    }
+
+    pub fn set_timers(&mut self, delay: u8, sound: u8) {
+        self.delay_timer = delay;
+        self.sound_timer = sound;
+    }
+
+    /// Addresses of the calls into the subroutines being run, the innermost last
+    pub fn call_stack(&self) -> &[u16] {
+        &self.stack[1..=self.stack_pointer as usize]
+    }
+
+    /// Calls deep the stack goes
+    pub fn stack_depth(&self) -> usize {
+        self.stack.len() - 1
+    }
+
+    /// Every slot of the stack, slot 0 never being used, with what returned calls left behind
+    pub fn stack_slots(&self) -> &[u16] {
+        &self.stack
+    }
+
+    /// Instructions run since the machine was powered on
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    pub fn memory_bounds(&self) -> MemoryBounds {
+        self.memory_bounds
+    }
+
+    pub fn write_protection(&self) -> WriteProtection {
+        self.write_protection
+    }
+
+    pub fn index_width(&self) -> IndexWidth {
+        self.index_width
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Instructions to a timer tick
+    pub fn tickrate(&self) -> u32 {
+        self.tickrate
+    }
+
+    /// Pixels of the screen row by row, 1 when on
+    pub fn display(&self) -> [u8; 64 * 32] {
+        let mut pixels = [0; 64 * 32];
+        for (i, pixel) in pixels.iter_mut().enumerate() {
+            *pixel = (self.rows[i / 64] >> (63 - i % 64) & 1) as u8;
+        }
+        pixels
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Writes bytes into memory starting at an address, wrapping around at the end
+    pub fn write_memory(&mut self, address: u16, bytes: &[u8]) {
+        let length = self.memory.len();
+        for (offset, byte) in bytes.iter().enumerate() {
+            let address = (address as usize + offset) % length;
+            self.memory[address] = *byte;
+            self.invalidate(address..address + 1);
+        }
+    }
+
+    /// Replaces the digits in the interpreter's memory, loading them from an address. Digits
+    /// that don't fit below the program are cut off.
+    pub fn load_font(&mut self, font: &Font, address: u16) {
+        let bytes = font.bytes();
+        let length = bytes.len().min(0x200usize.saturating_sub(address as usize));
+        self.memory[..0x200].fill(0);
+        self.memory[address as usize..address as usize + length].copy_from_slice(&bytes[..length]);
+        self.invalidate(0..0x200);
+        self.font_address = address;
+        self.font_length = length as u16;
+    }
+
+    /// Addresses the digits were loaded to
+    pub fn font(&self) -> Range<usize> {
+        self.font_address as usize..(self.font_address + self.font_length) as usize
+    }
+
+    pub fn load_program(&mut self, program_buffer: &[u8]) {
+        self.memory[512..512 + program_buffer.len()].copy_from_slice(program_buffer);
+        self.invalidate(512..512 + program_buffer.len());
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::chip8::Chip8;
+    use crate::chip8::{AluOp, Chip8, Chip8Error, Font, IndexWidth, Instruction, MemoryBounds, Peripheral, Quirks, RandomSource, Variant, WriteProtection, DEFAULT_STACK_DEPTH};
     use std::num::Wrapping;
-    use device_query::Keycode;
+    use std::ops::RangeInclusive;
+    use std::sync::{Arc, Mutex};
 
     fn get_chip_8(command_to_test: Option<u16>) -> Chip8 {
         let mut mock_chip = Chip8::new();
@@ -456,6 +1008,14 @@ mod tests {
         mock_chip
     }
 
+    fn keys_pressed(pressed: &[usize]) -> [bool; 16] {
+        let mut keys = [false; 16];
+        for key in pressed {
+            keys[*key] = true;
+        }
+        keys
+    }
+
     /// Overall test of generic functionality
     /// Base program with simple jump command should load, emulate once, and program counter
     /// will have updated
@@ -484,7 +1044,7 @@ mod tests {
         assert_eq!(mock_chip8.program_counter, 0x0200);
         assert_eq!(mock_chip8.stack_pointer, 0);
         assert_eq!(mock_chip8.stack[1], 0);
-        mock_chip8.process_2_command(0x0EEE);
+        mock_chip8.process_2_command(0x0EEE).unwrap();
         assert_eq!(mock_chip8.program_counter, 0xEEE);
         assert_eq!(mock_chip8.stack_pointer, 1);
         assert_eq!(mock_chip8.stack[1], 0x0200);
@@ -585,7 +1145,7 @@ mod tests {
         mock_chip8.cpu_registers[1] = Wrapping(0x02);
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(0x01));
         assert_eq!(mock_chip8.cpu_registers[1], Wrapping(0x02));
-        mock_chip8.process_8_command(0x0000, 0, 1);
+        mock_chip8.process_8_command(AluOp::Move, 0, 1);
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(0x02));
         assert_eq!(mock_chip8.cpu_registers[1], Wrapping(0x02));
     }
@@ -598,7 +1158,7 @@ mod tests {
         mock_chip8.cpu_registers[1] = Wrapping(0x0F);
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(0xF0));
         assert_eq!(mock_chip8.cpu_registers[1], Wrapping(0x0F));
-        mock_chip8.process_8_command(0x0001, 0, 1);
+        mock_chip8.process_8_command(AluOp::Or, 0, 1);
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(0xFF));
         assert_eq!(mock_chip8.cpu_registers[1], Wrapping(0x0F));
     }
@@ -611,7 +1171,7 @@ mod tests {
         mock_chip8.cpu_registers[1] = Wrapping(0x0F);
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(0xF0));
         assert_eq!(mock_chip8.cpu_registers[1], Wrapping(0x0F));
-        mock_chip8.process_8_command(0x0002, 0, 1);
+        mock_chip8.process_8_command(AluOp::And, 0, 1);
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(0x00));
         assert_eq!(mock_chip8.cpu_registers[1], Wrapping(0x0F));
     }
@@ -624,7 +1184,7 @@ mod tests {
         mock_chip8.cpu_registers[1] = Wrapping(0x0F);
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(0xF4));
         assert_eq!(mock_chip8.cpu_registers[1], Wrapping(0x0F));
-        mock_chip8.process_8_command(0x0003, 0, 1);
+        mock_chip8.process_8_command(AluOp::Xor, 0, 1);
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(0xFB));
         assert_eq!(mock_chip8.cpu_registers[1], Wrapping(0x0F));
     }
@@ -638,7 +1198,7 @@ mod tests {
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(0xFF));
         assert_eq!(mock_chip8.cpu_registers[1], Wrapping(0x02));
         assert_eq!(mock_chip8.cpu_registers[0xF], Wrapping(0));
-        mock_chip8.process_8_command(0x0004, 0, 1);
+        mock_chip8.process_8_command(AluOp::Add, 0, 1);
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(0x01));
         assert_eq!(mock_chip8.cpu_registers[1], Wrapping(0x02));
         assert_eq!(mock_chip8.cpu_registers[0xF], Wrapping(1));
@@ -656,7 +1216,7 @@ mod tests {
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(0x00));
         assert_eq!(mock_chip8.cpu_registers[1], Wrapping(0x01));
         assert_eq!(mock_chip8.cpu_registers[0xF], Wrapping(1));
-        mock_chip8.process_8_command(0x0005, 0, 1);
+        mock_chip8.process_8_command(AluOp::Sub, 0, 1);
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(0xFF));
         assert_eq!(mock_chip8.cpu_registers[1], Wrapping(0x01));
         assert_eq!(mock_chip8.cpu_registers[0xF], Wrapping(0));
@@ -667,7 +1227,7 @@ mod tests {
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(0x01));
         assert_eq!(mock_chip8.cpu_registers[1], Wrapping(0x01));
         assert_eq!(mock_chip8.cpu_registers[0xF], Wrapping(0));
-        mock_chip8.process_8_command(0x0005, 0, 1);
+        mock_chip8.process_8_command(AluOp::Sub, 0, 1);
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(0x00));
         assert_eq!(mock_chip8.cpu_registers[1], Wrapping(0x01));
         assert_eq!(mock_chip8.cpu_registers[0xF], Wrapping(1));
@@ -681,7 +1241,7 @@ mod tests {
         mock_chip8.cpu_registers[0] = Wrapping(0x0F);
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(15));
         assert_eq!(mock_chip8.cpu_registers[0xF], Wrapping(0));
-        mock_chip8.process_8_command(0x0006, 0, 1);
+        mock_chip8.process_8_command(AluOp::ShiftRight, 0, 1);
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(7));
         assert_eq!(mock_chip8.cpu_registers[0xF], Wrapping(0b1));
 
@@ -690,7 +1250,7 @@ mod tests {
         mock_chip8.cpu_registers[0] = Wrapping(0x0E);
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(14));
         assert_eq!(mock_chip8.cpu_registers[0xF], Wrapping(0));
-        mock_chip8.process_8_command(0x0006, 0, 1);
+        mock_chip8.process_8_command(AluOp::ShiftRight, 0, 1);
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(7));
         assert_eq!(mock_chip8.cpu_registers[0xF], Wrapping(0b0));
     }
@@ -707,7 +1267,7 @@ mod tests {
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(0x01));
         assert_eq!(mock_chip8.cpu_registers[1], Wrapping(0x00));
         assert_eq!(mock_chip8.cpu_registers[0xF], Wrapping(1));
-        mock_chip8.process_8_command(0x0007, 0, 1);
+        mock_chip8.process_8_command(AluOp::SubReverse, 0, 1);
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(0xFF));
         assert_eq!(mock_chip8.cpu_registers[1], Wrapping(0x00));
         assert_eq!(mock_chip8.cpu_registers[0xF], Wrapping(0));
@@ -719,7 +1279,7 @@ mod tests {
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(0x01));
         assert_eq!(mock_chip8.cpu_registers[1], Wrapping(0x0A));
         assert_eq!(mock_chip8.cpu_registers[0xF], Wrapping(0));
-        mock_chip8.process_8_command(0x0007, 0, 1);
+        mock_chip8.process_8_command(AluOp::SubReverse, 0, 1);
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(0x09));
         assert_eq!(mock_chip8.cpu_registers[1], Wrapping(0x0A));
         assert_eq!(mock_chip8.cpu_registers[0xF], Wrapping(1));
@@ -733,7 +1293,7 @@ mod tests {
         mock_chip8.cpu_registers[0] = Wrapping(0xFF);
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(255));
         assert_eq!(mock_chip8.cpu_registers[0xF], Wrapping(0));
-        mock_chip8.process_8_command(0x000E, 0, 1);
+        mock_chip8.process_8_command(AluOp::ShiftLeft, 0, 1);
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(254));
         assert_eq!(mock_chip8.cpu_registers[0xF], Wrapping(0b1));
 
@@ -742,7 +1302,7 @@ mod tests {
         mock_chip8.cpu_registers[0] = Wrapping(0x7F);
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(127));
         assert_eq!(mock_chip8.cpu_registers[0xF], Wrapping(0));
-        mock_chip8.process_8_command(0x000E, 0, 1);
+        mock_chip8.process_8_command(AluOp::ShiftLeft, 0, 1);
         assert_eq!(mock_chip8.cpu_registers[0], Wrapping(254));
         assert_eq!(mock_chip8.cpu_registers[0xF], Wrapping(0b0));
     }
@@ -804,7 +1364,7 @@ mod tests {
     fn test_ex() {
         // Test skip if key is pressed
         let mut mock_chip8 = get_chip_8(None);
-        mock_chip8.set_keys(vec![Keycode::Q]);
+        mock_chip8.set_keys(keys_pressed(&[4]));
         mock_chip8.cpu_registers[0] = Wrapping(4);
         assert_eq!(mock_chip8.keys[4], 1);
         assert_eq!(mock_chip8.program_counter, 0x200);
@@ -820,4 +1380,447 @@ mod tests {
         mock_chip8.process_ex9e_command(1);
         assert_eq!(mock_chip8.program_counter, 0x200 + 2);
     }
+
+    /// Keys set individually are merged with the keyboard state
+    #[test]
+    fn test_set_key() {
+        let mut mock_chip8 = get_chip_8(None);
+        mock_chip8.set_keys(keys_pressed(&[4]));
+        mock_chip8.set_key(0xF, true);
+        assert_eq!(mock_chip8.keys[4], 1);
+        assert_eq!(mock_chip8.keys[0xF], 1);
+        mock_chip8.set_key(4, false);
+        assert_eq!(mock_chip8.keys[4], 0);
+        assert_eq!(mock_chip8.keypad(), keys_pressed(&[0xF]));
+    }
+
+    /// EXF2/EXF5 - CHIP-8X skips on second keypad key pressed/not pressed
+    #[test]
+    fn test_exf2_exf5() {
+        let mut mock_chip8 = Chip8::builder().variant(Variant::Chip8X).build();
+        mock_chip8.set_keys(keys_pressed(&[4]));
+        mock_chip8.set_second_keypad(keys_pressed(&[5]));
+        mock_chip8.cpu_registers[0] = Wrapping(5);
+        mock_chip8.process_exf2_command(0);
+        assert_eq!(mock_chip8.program_counter, 0x200 + 4);
+        mock_chip8.process_exf5_command(0);
+        assert_eq!(mock_chip8.program_counter, 0x200 + 4 + 2);
+
+        // Keys on the first keypad don't count
+        mock_chip8.cpu_registers[0] = Wrapping(4);
+        mock_chip8.program_counter = 0x200;
+        mock_chip8.process_exf2_command(0);
+        assert_eq!(mock_chip8.program_counter, 0x200 + 2);
+    }
+
+    /// CXNN - Seeded machines produce the same random numbers
+    #[test]
+    fn test_cxnn_seeded() {
+        let mut first = Chip8::builder().seed(42).build();
+        let mut second = Chip8::builder().seed(42).build();
+        for _ in 0..8 {
+            first.process_c_command(0, 0xFF);
+            second.process_c_command(0, 0xFF);
+            assert_eq!(first.cpu_registers[0], second.cpu_registers[0]);
+        }
+    }
+
+    /// CXNN - Bytes come from the source given to the builder, masked by NN
+    #[test]
+    fn test_cxnn_random_source() {
+        #[derive(Clone)]
+        struct Counter(u8);
+        impl RandomSource for Counter {
+            fn next_byte(&mut self) -> u8 {
+                self.0 = self.0.wrapping_add(0x11);
+                self.0
+            }
+
+            fn box_clone(&self) -> Box<dyn RandomSource> {
+                Box::new(self.clone())
+            }
+        }
+
+        let mut mock_chip8 = Chip8::builder().random(Box::new(Counter(0))).build();
+        mock_chip8.process_c_command(0, 0xFF);
+        mock_chip8.process_c_command(1, 0x0F);
+        assert_eq!((mock_chip8.cpu_registers[0].0, mock_chip8.cpu_registers[1].0), (0x11, 0x02));
+        let mut copy = mock_chip8.clone();
+        mock_chip8.process_c_command(2, 0xFF);
+        copy.process_c_command(2, 0xFF);
+        assert_eq!((mock_chip8.cpu_registers[2].0, copy.cpu_registers[2].0), (0x33, 0x33));
+    }
+
+    /// Checksum changes with the machine state but not with input
+    #[test]
+    fn test_state_checksum() {
+        let mut mock_chip8 = get_chip_8(Some(0x6012));
+        let initial = mock_chip8.state_checksum();
+        assert_eq!(initial, get_chip_8(Some(0x6012)).state_checksum());
+        mock_chip8.set_keys(keys_pressed(&[1]));
+        assert_eq!(mock_chip8.state_checksum(), initial);
+        mock_chip8.emulate_cycle();
+        assert_ne!(mock_chip8.state_checksum(), initial);
+    }
+
+    /// EXF2 is not an opcode on plain CHIP-8
+    #[test]
+    #[should_panic(expected = "Unknown opcode")]
+    fn test_exf2_requires_chip8x() {
+        let mut mock_chip8 = get_chip_8(Some(0xE0F2));
+        mock_chip8.emulate_cycle();
+    }
+
+    /// Instructions that can't run say why, leaving the machine as it was
+    #[test]
+    fn test_errors() {
+        assert_eq!(get_chip_8(Some(0x6012)).try_emulate_cycle(), Ok(()));
+        assert_eq!(get_chip_8(Some(0xE0F2)).try_emulate_cycle(), Err(Chip8Error::UnknownOpcode { opcode: 0xE0F2, address: 0x200 }));
+        assert_eq!(get_chip_8(Some(0x00EE)).try_emulate_cycle(), Err(Chip8Error::StackUnderflow(0x200)));
+        let mut mock_chip8 = get_chip_8(Some(0x2200));
+        for _ in 0..15 {
+            assert_eq!(mock_chip8.try_emulate_cycle(), Ok(()));
+        }
+        let error = mock_chip8.try_emulate_cycle().unwrap_err();
+        assert_eq!(error, Chip8Error::StackOverflow { address: 0x200, calls: vec![0x200; 15] });
+        assert_eq!(error.to_string(), format!("Call with the stack full at 0x200, 15 calls deep: {}", vec!["0x200"; 15].join(" > ")));
+        assert_eq!((mock_chip8.call_stack().len(), mock_chip8.cycles()), (DEFAULT_STACK_DEPTH as usize, 15));
+
+        // A deeper stack takes more calls before overflowing
+        let mut mock_chip8 = Chip8::builder().stack_depth(40).build();
+        mock_chip8.load_program(&[0x22, 0x00]);
+        for _ in 0..40 {
+            assert_eq!(mock_chip8.try_emulate_cycle(), Ok(()));
+        }
+        assert!(mock_chip8.try_emulate_cycle().unwrap_err().to_string().starts_with("Call with the stack full at 0x200, 40 calls deep"));
+
+        let mut mock_chip8 = get_chip_8(Some(0xF265));
+        mock_chip8.index_register = Wrapping(0xFFE);
+        mock_chip8.memory[0xFFE] = 0xAB;
+        let error = mock_chip8.try_emulate_cycle().unwrap_err();
+        assert_eq!(error.to_string(), "LD V2, [I] at 0x200 accesses memory past the end, I = 0xFFE");
+        assert_eq!((mock_chip8.cpu_registers[0].0, mock_chip8.program_counter, mock_chip8.cycles()), (0, 0x200, 0));
+
+        // With 64K the program counter can run past 0xFFFF, unless it jumps
+        let at_top = |opcode: u16| {
+            let mut mock_chip8 = Chip8::builder().memory_size(0x10000).build();
+            mock_chip8.write_memory(0xFFFE, &opcode.to_be_bytes());
+            mock_chip8.program_counter = 0xFFFE;
+            mock_chip8
+        };
+        assert_eq!(at_top(0x6012).try_emulate_cycle(), Err(Chip8Error::RunsOffEnd { instruction: Instruction::Load(0, 0x12), address: 0xFFFE }));
+        assert_eq!(at_top(0x1200).try_emulate_cycle(), Ok(()));
+        let mut mock_chip8 = at_top(0x2FFC);
+        mock_chip8.write_memory(0xFFC, &[0x00, 0xEE]);
+        mock_chip8.emulate_cycle();
+        assert_eq!(mock_chip8.try_emulate_cycle(), Err(Chip8Error::RunsOffEnd { instruction: Instruction::Return, address: 0xFFC }));
+        assert_eq!(mock_chip8.call_stack(), [0xFFFE]);
+        let mut mock_chip8 = at_top(0x0000);
+        mock_chip8.program_counter = 0xFFFF;
+        assert_eq!(mock_chip8.try_emulate_cycle(), Err(Chip8Error::ProgramCounterPastEnd(0xFFFF)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Return with the stack empty at 0x200")]
+    fn test_emulate_cycle_panics() {
+        get_chip_8(Some(0x00EE)).emulate_cycle();
+    }
+
+    /// DXYN, FX33, FX55 and FX65 at the end of memory fault, or wrap around to the start
+    #[test]
+    fn test_memory_bounds() {
+        let at_end = |opcode: u16, index: u16, memory_bounds: MemoryBounds| {
+            let mut mock_chip8 = Chip8::builder().memory_bounds(memory_bounds).build();
+            mock_chip8.load_program(&opcode.to_be_bytes());
+            mock_chip8.index_register = Wrapping(index);
+            mock_chip8.cpu_registers[0] = Wrapping(254);
+            mock_chip8.cpu_registers[1] = Wrapping(0x11);
+            mock_chip8
+        };
+        // The last byte of each access is the last byte of memory
+        for (opcode, index) in [(0xD005, 0xFFB), (0xF033, 0xFFD), (0xF155, 0xFFE), (0xF165, 0xFFE)] {
+            assert_eq!(at_end(opcode, index, MemoryBounds::Fault).try_emulate_cycle(), Ok(()));
+            assert!(at_end(opcode, index + 1, MemoryBounds::Fault).try_emulate_cycle().is_err());
+        }
+        assert!(at_end(0xF055, 0xFFFF, MemoryBounds::Fault).try_emulate_cycle().is_err());
+
+        let mut mock_chip8 = at_end(0xF033, 0xFFE, MemoryBounds::Wrap);
+        assert_eq!(mock_chip8.try_emulate_cycle(), Ok(()));
+        assert_eq!((mock_chip8.memory[0xFFE], mock_chip8.memory[0xFFF], mock_chip8.memory[0]), (2, 5, 4));
+        let mut mock_chip8 = at_end(0xF165, 0xFFF, MemoryBounds::Wrap);
+        assert_eq!(mock_chip8.try_emulate_cycle(), Ok(()));
+        assert_eq!(mock_chip8.cpu_registers[1].0, 0xF0);
+    }
+
+    /// FX1E wraps I at 12 or 16 bits, and 64K of memory takes accesses past 4K
+    #[test]
+    fn test_memory_size_and_index_width() {
+        let add_index = |builder: crate::chip8::Chip8Builder| {
+            let mut mock_chip8 = builder.build();
+            mock_chip8.load_program(&[0xF0, 0x1E, 0xF0, 0x55]);
+            mock_chip8.index_register = Wrapping(0xFFF);
+            mock_chip8.cpu_registers[0] = Wrapping(2);
+            mock_chip8.emulate_cycle();
+            mock_chip8
+        };
+        let mut mock_chip8 = add_index(Chip8::builder().index_width(IndexWidth::Bits12));
+        assert_eq!(mock_chip8.index_register.0, 0x001);
+        assert_eq!(mock_chip8.try_emulate_cycle(), Ok(()));
+
+        let mut mock_chip8 = add_index(Chip8::builder());
+        assert_eq!(mock_chip8.index_register.0, 0x1001);
+        assert!(mock_chip8.try_emulate_cycle().is_err());
+
+        let mut mock_chip8 = add_index(Chip8::builder().memory_size(0x10000));
+        assert_eq!(mock_chip8.try_emulate_cycle(), Ok(()));
+        assert_eq!((mock_chip8.memory().len(), mock_chip8.memory[0x1001]), (0x10000, 2));
+    }
+
+    /// FX33 and FX55 below 0x200 write, fault or leave memory as it is by the write protection
+    #[test]
+    fn test_write_protection() {
+        let store = |write_protection: WriteProtection| {
+            let mut mock_chip8 = Chip8::builder().write_protection(write_protection).build();
+            mock_chip8.load_program(&[0xF1, 0x55]);
+            mock_chip8.index_register = Wrapping(0x1FF);
+            mock_chip8.cpu_registers[0] = Wrapping(0xAB);
+            mock_chip8.cpu_registers[1] = Wrapping(0xCD);
+            mock_chip8
+        };
+        let mut mock_chip8 = store(WriteProtection::Off);
+        mock_chip8.emulate_cycle();
+        assert_eq!(mock_chip8.memory[0x1FF..0x201], [0xAB, 0xCD]);
+
+        let mut mock_chip8 = store(WriteProtection::Ignore);
+        mock_chip8.emulate_cycle();
+        assert_eq!((mock_chip8.memory[0x1FF], mock_chip8.memory[0x200], mock_chip8.program_counter), (0, 0xCD, 0x202));
+
+        let mut mock_chip8 = store(WriteProtection::Fault);
+        let error = mock_chip8.try_emulate_cycle().unwrap_err();
+        assert_eq!(error.to_string(), "LD [I], V1 at 0x200 writes to the interpreter's memory, I = 0x1FF");
+        assert_eq!((mock_chip8.memory[0x1FF], mock_chip8.memory[0x200]), (0, 0xF1));
+        mock_chip8.index_register = Wrapping(0x200);
+        assert_eq!(mock_chip8.try_emulate_cycle(), Ok(()));
+
+        // Wrapping around the end reaches the interpreter's memory too
+        let mut mock_chip8 = Chip8::builder().memory_bounds(MemoryBounds::Wrap).write_protection(WriteProtection::Fault).build();
+        mock_chip8.load_program(&[0xF1, 0x55]);
+        mock_chip8.index_register = Wrapping(0xFFF);
+        assert!(matches!(mock_chip8.try_emulate_cycle(), Err(Chip8Error::WriteProtected { index: 0xFFF, .. })));
+    }
+
+    /// Instructions are decoded once, and again after memory under them changes
+    #[test]
+    fn test_decoded_cache() {
+        // V1 += 1, jump back
+        let mut mock_chip8 = Chip8::builder().build();
+        mock_chip8.load_program(&[0x71, 0x01, 0x12, 0x00]);
+        for _ in 0..4 {
+            mock_chip8.emulate_cycle();
+        }
+        assert_eq!(mock_chip8.decoded.get(0x200), Some(Instruction::Add(1, 1)));
+
+        // Copies decode again
+        let mut copy = mock_chip8.clone();
+        assert_eq!(copy.decoded.get(0x200), None);
+        copy.emulate_cycle();
+        assert_eq!((copy.decoded.get(0x200), copy.cpu_registers[1]), (Some(Instruction::Add(1, 1)), Wrapping(3)));
+
+        // FX55 over the second byte of the add makes it V1 += 5
+        mock_chip8.index_register = Wrapping(0x201);
+        mock_chip8.cpu_registers[0] = Wrapping(5);
+        mock_chip8.execute_opcode(0xF055).unwrap();
+        assert_eq!(mock_chip8.decoded.get(0x200), None);
+        mock_chip8.emulate_cycle();
+        mock_chip8.emulate_cycle();
+        assert_eq!(mock_chip8.cpu_registers[1], Wrapping(7));
+
+        mock_chip8.write_memory(0x201, &[0x10]);
+        mock_chip8.emulate_cycle();
+        mock_chip8.emulate_cycle();
+        assert_eq!(mock_chip8.cpu_registers[1], Wrapping(0x17));
+    }
+
+    /// Only rows changed since the last draw are drawn again, all of them when forced
+    #[test]
+    fn test_draw_to_buffer_dirty_rows() {
+        // Draw the top row of 0 at (0, 0) and at (0, 2), then clear
+        let mut mock_chip8 = Chip8::builder().build();
+        mock_chip8.load_program(&[0xD0, 0x01, 0x61, 0x02, 0xD0, 0x11, 0x00, 0xE0]);
+        let mut buffer = vec![0xAA; 64 * 32];
+        mock_chip8.emulate_cycle();
+        assert!(mock_chip8.draw_to_buffer(&mut buffer));
+        assert_eq!(&buffer[..5], &[0x0FFF, 0x0FFF, 0x0FFF, 0x0FFF, 0x0000]);
+        assert_eq!(buffer[64 * 31], 0x0000);
+
+        buffer[64 * 5] = 0xAA;
+        mock_chip8.emulate_cycle();
+        mock_chip8.emulate_cycle();
+        assert!(mock_chip8.draw_to_buffer(&mut buffer));
+        assert_eq!((buffer[64 * 2], buffer[64 * 5]), (0x0FFF, 0xAA));
+
+        mock_chip8.emulate_cycle();
+        assert!(mock_chip8.draw_to_buffer(&mut buffer));
+        assert_eq!((buffer[0], buffer[64 * 2], buffer[64 * 5]), (0x0000, 0x0000, 0xAA));
+
+        mock_chip8.force_redraw();
+        assert!(mock_chip8.draw_to_buffer(&mut buffer));
+        assert_eq!(buffer[64 * 5], 0x0000);
+    }
+
+    /// FX55 and FX65 - Bytes at a mapped address go to and come from the device, not memory
+    #[test]
+    fn test_peripheral() {
+        #[derive(Clone)]
+        struct Port(u16, Arc<Mutex<Vec<u8>>>);
+        impl Peripheral for Port {
+            fn range(&self) -> RangeInclusive<u16> {
+                self.0..=self.0
+            }
+
+            fn read(&mut self, _address: u16) -> u8 {
+                0x42
+            }
+
+            fn write(&mut self, _address: u16, value: u8) {
+                self.1.lock().unwrap().push(value);
+            }
+
+            fn box_clone(&self) -> Box<dyn Peripheral> {
+                Box::new(self.clone())
+            }
+        }
+
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut mock_chip8 = Chip8::builder().peripheral(Box::new(Port(0xF00, written.clone()))).build();
+        mock_chip8.load_program(&[0xF1, 0x55, 0xF1, 0x65]);
+        mock_chip8.index_register = Wrapping(0xF00);
+        mock_chip8.cpu_registers[0] = Wrapping(0xAB);
+        mock_chip8.cpu_registers[1] = Wrapping(0xCD);
+        mock_chip8.emulate_cycle();
+        assert_eq!(*written.lock().unwrap(), [0xAB]);
+        assert_eq!(mock_chip8.memory[0xF00..0xF02], [0, 0xCD]);
+
+        mock_chip8.index_register = Wrapping(0xF00);
+        mock_chip8.emulate_cycle();
+        assert_eq!((mock_chip8.cpu_registers[0].0, mock_chip8.cpu_registers[1].0), (0x42, 0xCD));
+
+        // A device in the interpreter's memory takes writes that would fault there
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut mock_chip8 = Chip8::builder().write_protection(WriteProtection::Fault).peripheral(Box::new(Port(0x1FF, written.clone()))).build();
+        mock_chip8.load_program(&[0xF0, 0x55]);
+        mock_chip8.index_register = Wrapping(0x1FF);
+        mock_chip8.cpu_registers[0] = Wrapping(0xAB);
+        assert_eq!(mock_chip8.try_emulate_cycle(), Ok(()));
+        assert_eq!((written.lock().unwrap().clone(), mock_chip8.memory[0x1FF]), (vec![0xAB], 0));
+    }
+
+    /// FX29 - Digits come from the font and address given to the builder, big digits following
+    #[test]
+    fn test_fx29_font() {
+        let mut mock_chip8 = Chip8::builder().font(Font::named("dream6800").unwrap()).font_address(0x50).build();
+        mock_chip8.cpu_registers[0] = Wrapping(1);
+        mock_chip8.load_program(&[0xF0, 0x29]);
+        mock_chip8.emulate_cycle();
+        assert_eq!(mock_chip8.index_register.0, 0x55);
+        assert_eq!(mock_chip8.memory[0x55..0x5A], [0x40; 5]);
+        assert_eq!((mock_chip8.memory[0x4F], mock_chip8.font()), (0, 0x50..0xA0));
+
+        let octo = Chip8::builder().font(Font::named("octo").unwrap()).build();
+        assert_eq!(octo.memory[0x50..0x52], [0xFF, 0xFF]);
+        assert_eq!(octo.memory[0xEF..0xF1], [0xC0, 0x00]);
+        assert!(Font::from_bytes(&octo.memory[..0x50 + 100]).is_ok());
+        assert!(Font::from_bytes(&[0; 81]).is_err());
+    }
+
+    /// FX18 - Setting the sound timer queues a single tone request for the frontend
+    #[test]
+    fn test_fx18_sound_request() {
+        let mut mock_chip8 = get_chip_8(Some(0xF018));
+        mock_chip8.cpu_registers[0] = Wrapping(3);
+        assert_eq!(mock_chip8.take_sound_request(), None);
+        mock_chip8.emulate_cycle();
+        assert_eq!(mock_chip8.sound_timer, 2);
+        assert_eq!(mock_chip8.take_sound_request(), Some(3));
+        assert_eq!(mock_chip8.take_sound_request(), None);
+
+        // Setting it to 0 stops the tone
+        mock_chip8.cpu_registers[0] = Wrapping(0);
+        mock_chip8.program_counter = 0x200;
+        mock_chip8.emulate_cycle();
+        assert_eq!(mock_chip8.take_sound_request(), Some(0));
+    }
+
+    /// Runs a program with the quirks given, from registers already set
+    fn run_quirks(quirks: Quirks, program: &[u8], registers: &[(usize, u8)], cycles: usize) -> Chip8 {
+        let mut chip8 = Chip8::builder().quirks(quirks).build();
+        chip8.load_program(program);
+        for (register, value) in registers {
+            chip8.cpu_registers[*register] = Wrapping(*value);
+        }
+        for _ in 0..cycles {
+            chip8.emulate_cycle();
+        }
+        chip8
+    }
+
+    /// Each quirk changes its instructions the way Octo's does
+    #[test]
+    fn test_quirks() {
+        let (usual, octo) = (Quirks::default(), Quirks::OCTO);
+
+        // 8XY6 shifts VX in place, or VY into VX
+        assert_eq!(run_quirks(usual, &[0x80, 0x16], &[(0, 4), (1, 8)], 1).cpu_registers[0].0, 2);
+        assert_eq!(run_quirks(octo, &[0x80, 0x16], &[(0, 4), (1, 8)], 1).cpu_registers[0].0, 4);
+
+        // FX55 leaves I, or moves it past the registers stored
+        let program = [0xA3, 0x00, 0xF2, 0x55];
+        assert_eq!(run_quirks(usual, &program, &[], 2).index_register.0, 0x300);
+        assert_eq!(run_quirks(octo, &program, &[], 2).index_register.0, 0x303);
+
+        // 8FF4 adds the carry to itself when VF is set first, and keeps the carry otherwise
+        assert_eq!(run_quirks(usual, &[0x8F, 0xF4], &[(0xF, 0x80)], 1).cpu_registers[0xF].0, 2);
+        assert_eq!(run_quirks(octo, &[0x8F, 0xF4], &[(0xF, 0x80)], 1).cpu_registers[0xF].0, 1);
+
+        // 8XY1 resets VF
+        let logic = Quirks { logic: true, ..usual };
+        assert_eq!(run_quirks(usual, &[0x80, 0x11], &[(0xF, 5)], 1).cpu_registers[0xF].0, 5);
+        assert_eq!(run_quirks(logic, &[0x80, 0x11], &[(0xF, 5)], 1).cpu_registers[0xF].0, 0);
+
+        // B210 jumps by V0, or by V2
+        let jump = Quirks { jump: true, ..usual };
+        assert_eq!(run_quirks(usual, &[0xB2, 0x10], &[(0, 1), (2, 4)], 1).program_counter, 0x211);
+        assert_eq!(run_quirks(jump, &[0xB2, 0x10], &[(0, 1), (2, 4)], 1).program_counter, 0x214);
+
+        // A sprite at the right edge wraps around to the left, unless clipped
+        let program = [0xA2, 0x06, 0xD0, 0x11, 0x00, 0x00, 0xFF];
+        let clip = Quirks { clip: true, ..usual };
+        assert_eq!(run_quirks(usual, &program, &[(0, 60)], 2).display()[64], 1);
+        let clipped = run_quirks(clip, &program, &[(0, 60)], 2);
+        assert_eq!((clipped.display()[63], clipped.display()[64]), (1, 0));
+    }
+
+    /// The timers count down once every tick rate instructions, and with the vblank quirk sprites
+    /// wait for the start of a tick
+    #[test]
+    fn test_tickrate() {
+        let mut chip8 = Chip8::builder().tickrate(3).build();
+        chip8.load_program(&[0x12, 0x00]);
+        chip8.delay_timer = 2;
+        chip8.emulate_cycle();
+        chip8.emulate_cycle();
+        assert_eq!(chip8.delay_timer, 2);
+        chip8.emulate_cycle();
+        assert_eq!(chip8.delay_timer, 1);
+
+        let mut chip8 = Chip8::builder().tickrate(3).quirks(Quirks { vblank: true, ..Quirks::default() }).build();
+        chip8.load_program(&[0x60, 0x00, 0xD0, 0x01]);
+        chip8.emulate_cycle();
+        chip8.emulate_cycle();
+        assert_eq!((chip8.program_counter, chip8.cycles), (0x202, 2));
+        chip8.emulate_cycle();
+        assert_eq!(chip8.program_counter, 0x202);
+        chip8.emulate_cycle();
+        assert_eq!(chip8.program_counter, 0x204);
+    }
 }
\ No newline at end of file
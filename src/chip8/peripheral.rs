@@ -0,0 +1,22 @@
+use std::ops::RangeInclusive;
+
+/// A device mapped into memory. Reads and writes of its addresses through I, by DXYN, FX33, FX55
+/// and FX65, go to the device instead of memory. Instructions are still fetched from memory, and
+/// the debugger shows memory as it is underneath.
+pub trait Peripheral: Send {
+    /// Addresses the device answers to
+    fn range(&self) -> RangeInclusive<u16>;
+
+    fn read(&mut self, address: u16) -> u8;
+
+    fn write(&mut self, address: u16, value: u8);
+
+    /// A copy in the same state, for copies of the machine
+    fn box_clone(&self) -> Box<dyn Peripheral>;
+}
+
+impl Clone for Box<dyn Peripheral> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
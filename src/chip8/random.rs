@@ -0,0 +1,37 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// Where CXNN gets its random bytes from. The machine uses a seeded `ChaCha8Rng` unless the
+/// builder is given another source, like a fixed sequence for a test or a TAS. Replays, crash
+/// dumps and TAS movies only keep the seed, so the generator has to be one whose stream is fixed
+/// for a seed, which `StdRng`'s isn't across versions of rand.
+pub trait RandomSource: Send {
+    fn next_byte(&mut self) -> u8;
+
+    /// A copy in the same state, for copies of the machine to draw the same bytes
+    fn box_clone(&self) -> Box<dyn RandomSource>;
+}
+
+impl RandomSource for ChaCha8Rng {
+    fn next_byte(&mut self) -> u8 {
+        self.gen()
+    }
+
+    fn box_clone(&self) -> Box<dyn RandomSource> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn RandomSource> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/// A `ChaCha8Rng` seeded with the seed, or from entropy without one
+pub fn seeded(seed: Option<u64>) -> Box<dyn RandomSource> {
+    Box::new(match seed {
+        Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+        None => ChaCha8Rng::from_entropy(),
+    })
+}
@@ -0,0 +1,147 @@
+//! Runs the opcode test vectors in `tests/vectors/opcodes.json`. Each vector sets up the machine,
+//! runs one instruction and checks the state it leaves:
+//!
+//! ```json
+//! {
+//!     "name": "8XY4 sets VF on carry",
+//!     "opcode": "8124",
+//!     "before": { "V1": 200, "V2": 100 },
+//!     "after": { "V1": 44, "V2": 100, "VF": 1, "PC": 514 }
+//! }
+//! ```
+//!
+//! States name registers `V0` to `VF`, `I`, `PC`, `DT` and `ST`, and can also give `memory` as
+//! bytes from hex addresses, the return addresses on the `stack`, the lit `pixels` as `[x, y]`
+//! and, before the instruction only, the `keys` and `second_keys` held. Anything not given starts
+//! as after power-on and isn't checked after. The instruction is put at `PC`, 0x200 unless given,
+//! and the timers count down once after it like at the end of every cycle. An optional `variant`
+//! picks the instructions, `chip8` if not given.
+
+use crate::chip8::{Chip8, Variant};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::num::Wrapping;
+
+#[derive(Deserialize)]
+struct Vector {
+    name: String,
+    opcode: String,
+    #[serde(default)]
+    variant: Option<String>,
+    #[serde(default)]
+    before: State,
+    after: State,
+}
+
+#[derive(Default, Deserialize)]
+struct State {
+    #[serde(default)]
+    memory: BTreeMap<String, Vec<u8>>,
+    #[serde(default)]
+    stack: Option<Vec<u16>>,
+    #[serde(default)]
+    pixels: Option<Vec<(usize, usize)>>,
+    #[serde(default)]
+    keys: Vec<usize>,
+    #[serde(default)]
+    second_keys: Vec<usize>,
+    #[serde(flatten)]
+    registers: BTreeMap<String, u16>,
+}
+
+fn parse_address(text: &str) -> u16 {
+    u16::from_str_radix(text.trim_start_matches("0x"), 16).unwrap_or_else(|_| panic!("Bad address {}", text))
+}
+
+/// Index of a `V0` to `VF` register name
+fn v_register(name: &str) -> Option<usize> {
+    name.strip_prefix('V').and_then(|digit| usize::from_str_radix(digit, 16).ok()).filter(|x| *x < 16)
+}
+
+fn set_up(chip8: &mut Chip8, state: &State) {
+    for (name, value) in &state.registers {
+        match (name.as_str(), v_register(name)) {
+            (_, Some(x)) => chip8.cpu_registers[x] = Wrapping(*value as u8),
+            ("I", _) => chip8.index_register = Wrapping(*value),
+            ("PC", _) => chip8.program_counter = *value,
+            ("DT", _) => chip8.delay_timer = *value as u8,
+            ("ST", _) => chip8.sound_timer = *value as u8,
+            _ => panic!("Unknown register {}", name),
+        }
+    }
+    for (address, bytes) in &state.memory {
+        chip8.write_memory(parse_address(address), bytes);
+    }
+    if let Some(stack) = &state.stack {
+        chip8.stack[1..=stack.len()].copy_from_slice(stack);
+        chip8.stack_pointer = stack.len() as u16;
+    }
+    for &(x, y) in state.pixels.iter().flatten() {
+        chip8.rows[y] |= 1 << (63 - x);
+    }
+    for &key in &state.keys {
+        chip8.keys[key] = 1;
+    }
+    for &key in &state.second_keys {
+        chip8.keys2[key] = 1;
+    }
+}
+
+/// What differs from the expected state, empty if nothing does
+fn check(chip8: &Chip8, state: &State) -> Vec<String> {
+    let mut differences = Vec::new();
+    let mut compare = |what: &str, actual: String, expected: String| {
+        if actual != expected {
+            differences.push(format!("{} is {}, expected {}", what, actual, expected));
+        }
+    };
+    for (name, value) in &state.registers {
+        let actual = match (name.as_str(), v_register(name)) {
+            (_, Some(x)) => chip8.cpu_registers[x].0 as u16,
+            ("I", _) => chip8.index_register.0,
+            ("PC", _) => chip8.program_counter,
+            ("DT", _) => chip8.delay_timer as u16,
+            ("ST", _) => chip8.sound_timer as u16,
+            _ => panic!("Unknown register {}", name),
+        };
+        compare(name, format!("{:#X}", actual), format!("{:#X}", value));
+    }
+    for (address, bytes) in &state.memory {
+        let start = parse_address(address) as usize;
+        compare(&format!("[{}]", address), format!("{:02X?}", &chip8.memory[start..start + bytes.len()]), format!("{:02X?}", bytes));
+    }
+    if let Some(stack) = &state.stack {
+        compare("stack", format!("{:X?}", chip8.call_stack()), format!("{:X?}", stack));
+    }
+    if let Some(pixels) = &state.pixels {
+        let lit: Vec<(usize, usize)> = chip8.display().iter().enumerate().filter(|(_, pixel)| **pixel != 0).map(|(i, _)| (i % 64, i / 64)).collect();
+        let mut expected = pixels.clone();
+        expected.sort_by_key(|&(x, y)| (y, x));
+        compare("pixels", format!("{:?}", lit), format!("{:?}", expected));
+    }
+    differences
+}
+
+fn run(vector: &Vector) -> Vec<String> {
+    let variant = vector.variant.as_deref().map_or(Variant::Chip8, |name| name.parse().unwrap());
+    let mut chip8 = Chip8::builder().variant(variant).seed(0).build();
+    set_up(&mut chip8, &vector.before);
+    let opcode = u16::from_str_radix(&vector.opcode, 16).unwrap_or_else(|_| panic!("Bad opcode {}", vector.opcode));
+    chip8.write_memory(chip8.program_counter, &opcode.to_be_bytes());
+    chip8.emulate_cycle();
+    check(&chip8, &vector.after)
+}
+
+/// Every vector leaves the state it expects
+#[test]
+fn test_opcode_vectors() {
+    let vectors: Vec<Vector> = serde_json::from_str(include_str!("../../tests/vectors/opcodes.json")).unwrap();
+    let failures: Vec<String> = vectors
+        .iter()
+        .filter_map(|vector| {
+            let differences = run(vector);
+            (!differences.is_empty()).then(|| format!("{} ({}): {}", vector.name, vector.opcode, differences.join(", ")))
+        })
+        .collect();
+    assert!(failures.is_empty(), "{} of {} vectors failed\n{}", failures.len(), vectors.len(), failures.join("\n"));
+}
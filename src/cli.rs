@@ -0,0 +1,600 @@
+use crate::chip8::{Font, IndexWidth, MemoryBounds, Variant, WriteProtection, DEFAULT_MEMORY_SIZE, MAX_MEMORY_SIZE};
+use crate::config::{self, Config};
+use crate::debugger;
+use crate::dev::MemoryRange;
+use crate::fatal;
+use crate::keymap::{Keymap, Layout};
+use crate::sprites::Format;
+use crate::test_suite::Profile;
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+/// Highest address the digits fit below the program at, big ones for 0 to F included
+const MAX_FONT_ADDRESS: u16 = 0x200 - 16 * 5 - 16 * 10;
+
+#[derive(Parser)]
+#[command(name = "chip8", version, about = "CHIP-8 emulator", args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Running a ROM is the default when no subcommand is given
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    /// Messages to show: error, warn, info, debug or trace, or by module like
+    /// `chip_8_emu::run=debug`
+    #[arg(long, global = true, default_value = "info", value_name = "LEVEL")]
+    pub log_level: String,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run a ROM
+    Run(RunArgs),
+    /// Run a ROM from power-on, recording keypad input to a replay file
+    Record {
+        #[command(flatten)]
+        run: RunArgs,
+
+        /// Replay file to write when the emulator exits
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Play a replay back, reporting the frame where the machine stops matching the recording
+    Play {
+        #[command(flatten)]
+        run: RunArgs,
+
+        /// Replay file to play
+        #[arg(short, long)]
+        replay: PathBuf,
+    },
+    /// Run into the failure of a crash dump again, playing the last frames before it in the
+    /// window and pausing there
+    Crash {
+        #[command(flatten)]
+        run: RunArgs,
+
+        /// Crash dump written when the program failed
+        #[arg(short, long)]
+        dump: PathBuf,
+
+        /// Replay the session was recorded to, for dumps without the input
+        #[arg(short, long)]
+        replay: Option<PathBuf>,
+
+        /// Frames before the failure to play in the window
+        #[arg(long, default_value = "120")]
+        frames: u64,
+    },
+    /// Build a replay frame by frame: pause, step, and edit upcoming input in a piano roll
+    Tas {
+        #[command(flatten)]
+        run: RunArgs,
+
+        /// Replay to start editing from, the movie starts empty if not given
+        #[arg(short, long)]
+        replay: Option<PathBuf>,
+
+        /// Replay file to write on F5 and when the emulator exits
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Run a ROM twice without a window, with the same seed and input, reporting the first frame
+    /// where the runs differ from each other or from a replay
+    Verify {
+        rom: PathBuf,
+
+        /// Replay whose input, seed and variant to run with, checked against its recording
+        #[arg(short, long)]
+        replay: Option<PathBuf>,
+
+        /// Seed for the random number generator without a replay, random if not given
+        #[arg(long)]
+        seed: Option<u64>,
+
+        /// Interpreter variant to emulate without a replay: chip8 or chip8x
+        #[arg(long, default_value = "chip8")]
+        variant: Variant,
+
+        /// Frames to run, the length of the replay if one is given
+        #[arg(long)]
+        frames: Option<u64>,
+    },
+    /// Run a ROM, reloading it whenever it or the source it is assembled from is saved
+    Dev {
+        #[command(flatten)]
+        run: RunArgs,
+
+        /// Memory range to carry over on reload, like 0xE00-0xEFF. Reloads are a clean reset
+        /// if none are given
+        #[arg(long)]
+        keep: Vec<MemoryRange>,
+    },
+    /// Show the keypad and light up keys as they are pressed, to check the key mapping
+    Keytest(InputArgs),
+    /// List the instructions of a ROM with their addresses and raw bytes
+    Disasm {
+        rom: PathBuf,
+
+        /// Interpreter variant whose instructions to decode: chip8 or chip8x
+        #[arg(long, default_value = "chip8")]
+        variant: Variant,
+
+        /// Write Octo source instead of a listing
+        #[arg(long)]
+        octo: bool,
+
+        /// Symbol file written by `asm`, for the labels and lines of the source
+        #[arg(long)]
+        symbols: Option<PathBuf>,
+    },
+    /// Describe a ROM without running it: its size, hash, the opcodes it uses, anything
+    /// suspicious in its code and the extensions and quirks it depends on
+    Info {
+        rom: PathBuf,
+
+        /// Interpreter variant whose instructions to decode: chip8 or chip8x
+        #[arg(long, default_value = "chip8")]
+        variant: Variant,
+    },
+    /// Compare two snapshots taken with the debugger, or crash dumps: the registers, runs of
+    /// memory and pixels that differ
+    Diff { before: PathBuf, after: PathBuf },
+    /// Assemble Octo source into a ROM
+    Asm {
+        source: PathBuf,
+
+        /// ROM file to write, the source file with a .ch8 extension if not given
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Also write a symbol file, mapping labels and instructions to the source
+        #[arg(long)]
+        symbols: Option<PathBuf>,
+    },
+    /// Shrink a ROM by removing unreachable code and loads that are overwritten right away
+    Optimize {
+        rom: PathBuf,
+
+        /// Interpreter variant whose instructions to decode: chip8 or chip8x
+        #[arg(long, default_value = "chip8")]
+        variant: Variant,
+
+        /// ROM file to write, the ROM file with a .opt.ch8 extension if not given
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Convert a PNG image into sprite data
+    Sprites {
+        image: PathBuf,
+
+        /// Sprite width: 8, or 16 for 16x16 SCHIP sprites
+        #[arg(long, default_value_t = 8)]
+        width: usize,
+
+        /// Sprite height, up to 15 for 8 pixel wide sprites. The height of the image if not given
+        #[arg(long)]
+        height: Option<usize>,
+
+        /// Treat dark pixels as on, for art drawn on a light background
+        #[arg(long)]
+        invert: bool,
+
+        /// Output format: octo, hex or bin
+        #[arg(long, default_value = "octo")]
+        format: Format,
+
+        /// File to write, standard output if not given
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Draw a sprite, previewing it on a mock screen, and save it as Octo source
+    SpriteEditor {
+        /// PNG image to start from, its top left corner is loaded
+        #[arg(long)]
+        image: Option<PathBuf>,
+
+        /// Sprite width: 8, or 16 for 16x16 SCHIP sprites
+        #[arg(long, default_value_t = 8)]
+        width: usize,
+
+        /// Sprite height, up to 15 for 8 pixel wide sprites
+        #[arg(long, default_value_t = 8)]
+        height: usize,
+
+        /// Octo source file to save to
+        #[arg(short, long, default_value = "sprite.8o")]
+        output: PathBuf,
+    },
+    /// Language server for Octo source, for editors to run over standard input and output
+    Lsp,
+    /// Run Timendus' chip8-test-suite without a window as each variant, reporting the ROMs whose
+    /// marks pass, or that end on the screen recorded for them
+    TestSuite {
+        /// Directory with the suite's ROMs, like 3-corax+.ch8
+        dir: PathBuf,
+
+        /// Profile to run with: chip8 or chip8x. Can be given more than once, both if not given.
+        /// There are no quirk profiles like vip or schip, the ROMs run with the default quirks
+        #[arg(long = "profile", value_name = "PROFILE")]
+        profiles: Vec<Profile>,
+
+        /// Screen hashes to compare ROMs without marks with, expected.toml in the directory if not
+        /// given
+        #[arg(long, value_name = "PATH")]
+        expected: Option<PathBuf>,
+
+        /// Record the screens the ROMs end on as the expected ones, after checking them with --show
+        #[arg(long)]
+        save: bool,
+
+        /// Print the screen each ROM ends on
+        #[arg(long)]
+        show: bool,
+
+        /// Frames to run each ROM for before giving up on it halting
+        #[arg(long, default_value = "10000")]
+        frames: u64,
+    },
+    /// Run a ROM here and in a reference emulator side by side, reporting the first instruction
+    /// after which their states differ
+    Compare {
+        rom: PathBuf,
+
+        /// Command starting the reference, split on spaces, with the ROM's path added at the end
+        #[arg(long, required_unless_present = "serve")]
+        reference: Option<String>,
+
+        /// Instructions to run between comparisons
+        #[arg(long, default_value = "1", value_parser = clap::value_parser!(u64).range(1..))]
+        every: u64,
+
+        /// Instructions to run in all
+        #[arg(long, default_value = "100000")]
+        instructions: u64,
+
+        /// Leave the delay and sound timers out, for references counting them down at 60 Hz
+        #[arg(long)]
+        ignore_timers: bool,
+
+        /// Seed for the random number generator
+        #[arg(long, default_value = "0")]
+        seed: u64,
+
+        /// Interpreter variant to emulate: chip8 or chip8x
+        #[arg(long, default_value = "chip8")]
+        variant: Variant,
+
+        /// Answer the comparison's protocol on standard input and output instead, as the reference
+        /// for another emulator
+        #[arg(long, conflicts_with = "reference")]
+        serve: bool,
+    },
+    /// Run every ROM in a directory headless, in parallel, and write how each one ended to a
+    /// report, listing the ones that end differently from the last report
+    Batch {
+        /// Directory with the ROMs, searched with the ones under it
+        dir: PathBuf,
+
+        /// Frames to run each ROM for
+        #[arg(long, default_value = "600")]
+        frames: u64,
+
+        /// Report to write, batch-report.toml in the directory if not given
+        #[arg(long, value_name = "PATH")]
+        report: Option<PathBuf>,
+
+        /// Seed for the random number generator
+        #[arg(long, default_value = "0")]
+        seed: u64,
+
+        /// Interpreter variant to emulate: chip8 or chip8x
+        #[arg(long, default_value = "chip8")]
+        variant: Variant,
+
+        /// Compile the ROMs to native code where they can be
+        #[cfg(feature = "jit")]
+        #[arg(long)]
+        jit: bool,
+    },
+    /// Run a ROM uncapped for a while, without a window or sound, and report the instructions and
+    /// frames a second
+    Bench {
+        #[command(flatten)]
+        run: RunArgs,
+
+        /// Seconds to run for
+        #[arg(long, default_value = "5")]
+        seconds: f64,
+    },
+    /// Write random ROMs that are still well formed, for stress-testing: jumps stay in the code,
+    /// every call returns and nothing writes over the program
+    Genrom {
+        /// Directory to write the ROMs to, each named by its seed like random-42.ch8
+        #[arg(short, long, default_value = ".")]
+        output: PathBuf,
+
+        /// ROMs to write
+        #[arg(long, default_value = "1")]
+        count: u64,
+
+        /// Seed of the first ROM, counting up for the next ones. Random if not given
+        #[arg(long)]
+        seed: Option<u64>,
+
+        /// Instructions in the main program
+        #[arg(long, default_value = "64", value_parser = clap::value_parser!(u16).range(3..=1024))]
+        length: u16,
+
+        /// Subroutines to call, each only from code before it
+        #[arg(long, default_value = "4", value_parser = clap::value_parser!(u16).range(0..=15))]
+        subroutines: u16,
+
+        /// Instructions in each subroutine, its return included
+        #[arg(long, default_value = "16", value_parser = clap::value_parser!(u16).range(2..=64))]
+        subroutine_length: u16,
+
+        /// Interpreter variant whose instructions to use: chip8 or chip8x
+        #[arg(long, default_value = "chip8")]
+        variant: Variant,
+    },
+}
+
+#[derive(clap::Args)]
+pub struct RunArgs {
+    /// ROM to run, Octo source (.8o) and Octocarts (.gif) are assembled first
+    #[arg(default_value = "roms/pong.rom")]
+    pub rom: PathBuf,
+
+    /// Interpreter variant to emulate: chip8 or chip8x
+    #[arg(long, default_value = "chip8")]
+    pub variant: Variant,
+
+    /// Seed for the random number generator, random if not given
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// What FX33 and FX55 do with the interpreter's memory below 0x200: off, writing it like the
+    /// rest for programs using it on purpose, fault, stopping with a crash dump, or ignore,
+    /// leaving it as it is
+    #[arg(long, default_value = "off")]
+    pub write_protection: WriteProtection,
+
+    /// Bytes of memory, like 4K, the original interpreters', or 64K, XO-CHIP's. In bytes, or
+    /// with K for kilobytes
+    #[arg(long, default_value = "4K", value_parser = parse_memory_size)]
+    pub memory_size: usize,
+
+    /// Bits I is wide, which decides where FX1E wraps it: 12 like interpreters with 4K of memory,
+    /// or 16 like SCHIP and XO-CHIP
+    #[arg(long, default_value = "16")]
+    pub index_bits: IndexWidth,
+
+    /// Calls deep the stack goes before a call overflows it, 15 on the original interpreter
+    #[arg(long, default_value = "15", value_parser = clap::value_parser!(u16).range(1..=1024))]
+    pub stack_depth: u16,
+
+    /// Digits in the interpreter's memory: chip8, vip, dream6800, schip or octo, the last two
+    /// with big digits after the small ones, or a file of the small digits followed by the big ones if any.
+    /// chip8 if not given, or the font style of an Octocart
+    #[arg(long)]
+    pub font: Option<Font>,
+
+    /// Address to load the digits at, like the traditional 0x050, 0 if not given
+    #[arg(long, value_parser = parse_font_address, default_value = "0")]
+    pub font_address: u16,
+
+    /// Print bytes the ROM stores at an address with FX33 or FX55 as text, like `0xFFF`
+    #[arg(long, value_name = "ADDRESS", value_parser = parse_serial_address)]
+    pub serial: Option<u16>,
+
+    /// Load a shared library with a device to map into memory or a filter for the display.
+    /// Can be given more than once
+    #[cfg(feature = "plugins")]
+    #[arg(long = "plugin", value_name = "PATH")]
+    pub plugins: Vec<PathBuf>,
+
+    /// What DXYN, FX33, FX55 and FX65 do with memory past the end: fault, stopping with a
+    /// crash dump, or wrap around to the start
+    #[arg(long, default_value = "fault")]
+    pub memory_bounds: MemoryBounds,
+
+    /// Take debugger commands on standard input while the ROM runs
+    #[arg(long)]
+    pub debug: bool,
+
+    /// Stop before the instruction at an address or label and open the debugger, like `0x2F0`
+    /// or `"draw when V3 == 0x1F"`. Can be given more than once
+    #[arg(long = "break", value_name = "ADDRESS")]
+    pub breakpoints: Vec<String>,
+
+    /// Wait for GDB to connect on a port, to debug with it or an IDE using it. The machine
+    /// stops when GDB connects
+    #[arg(long, value_name = "PORT")]
+    pub gdb: Option<u16>,
+
+    /// Let debug UIs connect over WebSocket on a port, to query the machine, set breakpoints,
+    /// step and watch the screen with JSON messages
+    #[arg(long, value_name = "PORT")]
+    pub remote: Option<u16>,
+
+    /// Stop before instructions that write to memory which already ran as code, and open the
+    /// debugger
+    #[arg(long)]
+    pub break_on_code_writes: bool,
+
+    /// Warn about what the program does that's likely a mistake, like losing a value kept in VF
+    /// to a flag or drawing off the screen. `warnings` in the debugger lists them
+    #[arg(long)]
+    pub warnings: bool,
+
+    /// Warn when the program reads memory or registers nothing wrote yet, which only start at
+    /// zero on some interpreters. `taint on` in the debugger stops before those reads
+    #[arg(long)]
+    pub taint: bool,
+
+    /// Show an expression in the watch panel of the debugger window, like `V3` or `[score]`,
+    /// highlighted when it changes. Can be given more than once
+    #[arg(long = "watch", value_name = "EXPRESSION")]
+    pub watches: Vec<String>,
+
+    /// Plot an expression over the last frames in the graph panel of the debugger window, like
+    /// `V3` or `DT`. Can be given more than once
+    #[arg(long = "graph", value_name = "EXPRESSION")]
+    pub graphs: Vec<String>,
+
+    /// Keep the last instructions run, shown when the debugger stops the machine and with the
+    /// trace command
+    #[arg(long, value_name = "LENGTH")]
+    pub trace: Option<usize>,
+
+    /// Write every instruction run to a file, one line of JSON each
+    #[arg(long, value_name = "PATH")]
+    pub trace_file: Option<PathBuf>,
+
+    /// Write a timing profile of frames, emulation, rendering and audio when the emulator
+    /// exits, for chrome://tracing or Perfetto
+    #[arg(long, value_name = "PATH")]
+    pub profile: Option<PathBuf>,
+
+    /// Start with the graph of where each frame's time goes shown over the game: emulation,
+    /// rendering and audio against the 60 Hz budget. F6 shows and hides it
+    #[arg(long)]
+    pub perf_hud: bool,
+
+    /// Print what happened during the session on exit: instructions, frames, sprite draws,
+    /// states saved and loaded, errors and warnings. `telemetry` in the console shows it too
+    #[arg(long)]
+    pub telemetry: bool,
+
+    /// Write what `--telemetry` prints to a file on exit, as JSON
+    #[arg(long, value_name = "PATH")]
+    pub telemetry_file: Option<PathBuf>,
+
+    /// Count the instructions run and show the most run addresses and opcodes on exit
+    #[arg(long)]
+    pub hotspots: bool,
+
+    /// Write the calls between subroutines as a Graphviz DOT graph on exit
+    #[arg(long, value_name = "PATH")]
+    pub call_graph: Option<PathBuf>,
+
+    /// Write which bytes of the ROM ran and which were read as data on exit
+    #[arg(long, value_name = "PATH")]
+    pub coverage: Option<PathBuf>,
+
+    /// Run a script alongside the ROM, instead of the .c8s file next to it if there is one
+    #[arg(long, value_name = "PATH")]
+    pub script: Option<PathBuf>,
+
+    /// Cheat file to load, instead of the .cht file next to the ROM if there is one
+    #[arg(long, value_name = "PATH")]
+    pub cheats: Option<PathBuf>,
+
+    /// Notes naming memory, instead of the .notes file next to the ROM. The debugger's note
+    /// command saves to it
+    #[arg(long, value_name = "PATH")]
+    pub notes: Option<PathBuf>,
+
+    /// Run without a window or sound, for CI, with every key released and a seed of 0 unless
+    /// one is given. Prints how the run ended and exits with 1 if the program failed
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Frames a headless run stops after, of one instruction each or a tick's worth for an
+    /// Octocart with a tick rate
+    #[arg(long, value_name = "FRAMES", default_value = "10000", requires = "headless")]
+    pub max_frames: u64,
+
+    /// Stop a headless run when the program halts, jumping to the same instruction forever, and
+    /// exit with 2 if it hasn't by the last frame
+    #[arg(long, requires = "headless")]
+    pub exit_on_halt: bool,
+
+    /// Print a hash of the screen at the end of a headless run, to compare with a known good one
+    #[arg(long, requires = "headless")]
+    pub print_hash: bool,
+
+    /// Run the machine on a thread of its own, paced by the clock instead of the window, so a
+    /// slow window drops frames instead of slowing the game down. Only the keypads and the
+    /// quit, pause, reset, save state, speed and screenshot hotkeys work
+    #[arg(long, conflicts_with_all = [
+        "headless", "debug", "breakpoints", "gdb", "remote", "break_on_code_writes", "warnings", "taint", "watches",
+        "graphs", "trace", "trace_file", "profile", "perf_hud", "telemetry", "telemetry_file", "hotspots", "call_graph",
+        "coverage", "script", "cheats",
+    ])]
+    pub threaded: bool,
+
+    /// Compile the program to native code where it can be, for headless runs and benchmarks
+    #[cfg(feature = "jit")]
+    #[arg(long)]
+    pub jit: bool,
+
+    #[command(flatten)]
+    pub input: InputArgs,
+}
+
+impl RunArgs {
+    /// Whether headless runs and benchmarks use the JIT, never without the `jit` feature
+    pub fn jit(&self) -> bool {
+        #[cfg(feature = "jit")]
+        return self.jit;
+        #[cfg(not(feature = "jit"))]
+        false
+    }
+}
+
+/// Config file and keyboard options, shared by everything that reads the keypad
+#[derive(clap::Args)]
+pub struct InputArgs {
+    /// Config file
+    #[arg(long, default_value = config::DEFAULT_PATH)]
+    pub config: PathBuf,
+
+    /// Keyboard layout, used to keep the keypad on the same physical keys. Overrides the config
+    #[arg(long)]
+    pub layout: Option<Layout>,
+
+    /// Map the keypad by the characters 1234/QWER/ASDF/ZXCV instead of their physical position
+    #[arg(long)]
+    pub logical_keys: bool,
+}
+
+impl InputArgs {
+    /// Loads the config file as it is, the keyboard options are only applied by `keymap` so they
+    /// aren't saved back with it
+    pub fn load_config(&self) -> Config {
+        Config::load(&self.config).unwrap_or_else(fatal)
+    }
+
+    /// The config's keypad with the keyboard options applied on top
+    pub fn keymap(&self, config: &Config) -> Keymap {
+        config.keymap.build(self.layout, self.logical_keys).unwrap_or_else(fatal)
+    }
+}
+
+/// A font address in hex or decimal, low enough for the biggest font to fit below the program
+fn parse_font_address(text: &str) -> Result<u16, String> {
+    match debugger::parse_address(text) {
+        Some(address) if address <= MAX_FONT_ADDRESS => Ok(address),
+        _ => Err(format!("Expected an address up to {:#05X}, like 0x050", MAX_FONT_ADDRESS)),
+    }
+}
+
+/// An address in hex or decimal for the serial port
+fn parse_serial_address(text: &str) -> Result<u16, String> {
+    debugger::parse_address(text).ok_or_else(|| String::from("Expected an address like 0xFFF"))
+}
+
+/// A memory size like 4K or 4096, from the 4K programs load into up to all 16 bits address
+fn parse_memory_size(text: &str) -> Result<usize, String> {
+    let size = match text.strip_suffix(['K', 'k']) {
+        Some(kilobytes) => kilobytes.parse::<usize>().map(|kilobytes| kilobytes * 1024),
+        None => text.parse(),
+    };
+    size.ok()
+        .filter(|size| (DEFAULT_MEMORY_SIZE..=MAX_MEMORY_SIZE).contains(size))
+        .ok_or_else(|| format!("Expected a memory size from 4K to 64K, not {}", text))
+}
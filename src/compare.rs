@@ -0,0 +1,304 @@
+//! `chip8 compare`, running a ROM in this core and in a reference emulator side by side and
+//! reporting the first instruction after which their states differ.
+//!
+//! The reference is a separate process, started with the ROM's path as its last argument, that
+//! answers lines on standard input:
+//!
+//! ```text
+//! > step 100
+//! < 0264 02EA 00 1F 05 00 00 00 00 00 00 00 00 00 00 00 00 01 3C 00 0000...0000
+//! > quit
+//! ```
+//!
+//! `step N` runs N instructions, 0 included, and answers with the state after them in hex: PC, I,
+//! V0 to VF, DT, ST and the screen as 512 digits, rows top to bottom with the leftmost pixel in a
+//! byte's high bit. An answer of `error` and a message means it couldn't. `compare --serve`
+//! answers for this core, as a reference for another emulator.
+//!
+//! States are compared every few instructions. When they differ both sides start over, run to the
+//! last matching state and go one instruction at a time, so the reference has to be as
+//! deterministic as this core is: no keys are held and the seed is fixed.
+
+use crate::chip8::{Chip8, Chip8Builder, Instruction, Variant};
+use crate::run::{self, HEIGHT, WIDTH};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+/// What both sides start from
+pub struct Setup<'a> {
+    pub rom: &'a Path,
+    pub program: &'a [u8],
+    /// Command starting the reference, split on spaces
+    pub reference: &'a str,
+    pub variant: Variant,
+    pub seed: u64,
+    /// Leave out the timers, for references counting them down at 60 Hz instead of every
+    /// instruction
+    pub ignore_timers: bool,
+}
+
+impl Setup<'_> {
+    fn builder(&self) -> Chip8Builder {
+        Chip8::builder().variant(self.variant).seed(self.seed)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct State {
+    program_counter: u16,
+    index: u16,
+    registers: [u8; 16],
+    delay: u8,
+    sound: u8,
+    /// A bit per pixel, 8 to a byte
+    screen: Vec<u8>,
+}
+
+impl State {
+    fn of(chip8: &Chip8) -> Self {
+        let (delay, sound) = chip8.timers();
+        let screen = chip8.display().chunks(8).map(|pixels| pixels.iter().fold(0, |byte, pixel| byte << 1 | (*pixel != 0) as u8)).collect();
+        State { program_counter: chip8.program_counter(), index: chip8.index(), registers: chip8.registers(), delay, sound, screen }
+    }
+
+    fn parse(line: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 21 {
+            return Err(format!("Expected 21 fields in a state, got {}: {}", fields.len(), line));
+        }
+        let word = |field: &str| u16::from_str_radix(field, 16).map_err(|_| format!("Bad hex {} in a state", field));
+        let byte = |field: &str| u8::from_str_radix(field, 16).map_err(|_| format!("Bad hex {} in a state", field));
+        let mut registers = [0; 16];
+        for (register, field) in registers.iter_mut().zip(&fields[2..18]) {
+            *register = byte(field)?;
+        }
+        let screen = fields[20];
+        if screen.len() != WIDTH * HEIGHT / 4 {
+            return Err(format!("Expected {} digits of screen, got {}", WIDTH * HEIGHT / 4, screen.len()));
+        }
+        let screen = (0..screen.len()).step_by(2).map(|i| byte(screen.get(i..i + 2).unwrap_or_default())).collect::<Result<_, _>>()?;
+        Ok(State {
+            program_counter: word(fields[0])?,
+            index: word(fields[1])?,
+            registers,
+            delay: byte(fields[18])?,
+            sound: byte(fields[19])?,
+            screen,
+        })
+    }
+
+    fn line(&self) -> String {
+        let registers: Vec<String> = self.registers.iter().map(|value| format!("{:02X}", value)).collect();
+        let screen: String = self.screen.iter().map(|byte| format!("{:02X}", byte)).collect();
+        format!("{:04X} {:04X} {} {:02X} {:02X} {}", self.program_counter, self.index, registers.join(" "), self.delay, self.sound, screen)
+    }
+
+    /// What's different in the reference's state
+    fn differences(&self, reference: &State, ignore_timers: bool) -> Vec<String> {
+        let mut differences = Vec::new();
+        let mut compare = |name: &str, here: u16, there: u16| {
+            if here != there {
+                differences.push(format!("{} is {:#X} here, {:#X} in the reference", name, here, there));
+            }
+        };
+        compare("PC", self.program_counter, reference.program_counter);
+        compare("I", self.index, reference.index);
+        for (x, (here, there)) in self.registers.iter().zip(&reference.registers).enumerate() {
+            compare(&format!("V{:X}", x), *here as u16, *there as u16);
+        }
+        if !ignore_timers {
+            compare("DT", self.delay as u16, reference.delay as u16);
+            compare("ST", self.sound as u16, reference.sound as u16);
+        }
+        let pixels: u32 = self.screen.iter().zip(&reference.screen).map(|(here, there)| (here ^ there).count_ones()).sum();
+        if pixels > 0 {
+            differences.push(format!("{} pixels differ on the screen", pixels));
+        }
+        differences
+    }
+}
+
+/// The first instruction after which the states differed
+#[derive(Debug)]
+pub struct Divergence {
+    /// Instructions run, this one included
+    pub instruction: u64,
+    pub address: u16,
+    pub opcode: u16,
+    pub differences: Vec<String>,
+}
+
+impl Divergence {
+    pub fn describe(&self, variant: Variant) -> String {
+        if self.instruction == 0 {
+            return format!("States differ before the first instruction:\n  {}", self.differences.join("\n  "));
+        }
+        let instruction = Instruction::decode(self.opcode, variant).map_or_else(|| String::from("unknown"), |instruction| instruction.to_string());
+        format!(
+            "States differ after instruction {}, {:04X} ({}) at {:#05X}:\n  {}",
+            self.instruction,
+            self.opcode,
+            instruction,
+            self.address,
+            self.differences.join("\n  ")
+        )
+    }
+}
+
+/// The reference emulator's process
+struct Reference {
+    child: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+}
+
+impl Reference {
+    fn spawn(command: &str, rom: &Path) -> Result<Self, String> {
+        let mut words = command.split_whitespace();
+        let program = words.next().ok_or("The reference command is empty")?;
+        let mut child = Command::new(program)
+            .args(words)
+            .arg(rom)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Could not start the reference {}\n{}", program, e))?;
+        let input = child.stdin.take().ok_or("The reference has no input")?;
+        let output = BufReader::new(child.stdout.take().ok_or("The reference has no output")?);
+        Ok(Reference { child, input, output })
+    }
+
+    fn step(&mut self, count: u64) -> Result<State, String> {
+        writeln!(self.input, "step {}", count).map_err(|e| format!("Could not write to the reference\n{}", e))?;
+        let mut line = String::new();
+        match self.output.read_line(&mut line) {
+            Ok(0) => Err(String::from("The reference stopped answering")),
+            Ok(_) => match line.trim().strip_prefix("error") {
+                Some(message) => Err(format!("The reference failed: {}", message.trim())),
+                None => State::parse(&line),
+            },
+            Err(e) => Err(format!("Could not read from the reference\n{}", e)),
+        }
+    }
+}
+
+impl Drop for Reference {
+    fn drop(&mut self) {
+        let _ = writeln!(self.input, "quit");
+        let _ = self.input.flush();
+        if self.child.try_wait().ok().flatten().is_none() {
+            let _ = self.child.kill();
+        }
+        let _ = self.child.wait();
+    }
+}
+
+/// Both sides, run the same number of instructions
+struct Session {
+    chip8: Chip8,
+    reference: Reference,
+    instructions: u64,
+}
+
+impl Session {
+    fn start(setup: &Setup) -> Result<Self, String> {
+        let chip8 = run::power_on(setup.builder(), setup.program);
+        Ok(Session { chip8, reference: Reference::spawn(setup.reference, setup.rom)?, instructions: 0 })
+    }
+
+    /// Runs both sides a number of instructions, and how they differ after if they do
+    fn step(&mut self, count: u64, ignore_timers: bool) -> Result<Option<Divergence>, String> {
+        let (mut address, mut opcode) = (0, 0);
+        for _ in 0..count {
+            address = self.chip8.program_counter();
+            opcode = self.chip8.memory().get(address as usize..address as usize + 2).map_or(0, |bytes| u16::from_be_bytes([bytes[0], bytes[1]]));
+            self.chip8.try_emulate_cycle().map_err(|reason| format!("This core failed at instruction {}: {}", self.instructions + 1, reason))?;
+            self.instructions += 1;
+        }
+        let reference = self.reference.step(count)?;
+        let differences = State::of(&self.chip8).differences(&reference, ignore_timers);
+        Ok((!differences.is_empty()).then_some(Divergence { instruction: self.instructions, address, opcode, differences }))
+    }
+}
+
+/// Runs up to a number of instructions, comparing every few, and the first one after which the
+/// states differ if they do
+pub fn compare(setup: &Setup, every: u64, instructions: u64) -> Result<Option<Divergence>, String> {
+    let mut session = Session::start(setup)?;
+    if let Some(divergence) = session.step(0, setup.ignore_timers)? {
+        return Ok(Some(divergence));
+    }
+    while session.instructions < instructions {
+        let count = every.min(instructions - session.instructions);
+        let matched = session.instructions;
+        match session.step(count, setup.ignore_timers)? {
+            None => continue,
+            Some(divergence) if count == 1 => return Ok(Some(divergence)),
+            Some(_) => {}
+        }
+        // Start over from the last matching state, one instruction at a time
+        let mut session = Session::start(setup)?;
+        session.step(matched, setup.ignore_timers)?;
+        for _ in 0..count {
+            if let Some(divergence) = session.step(1, setup.ignore_timers)? {
+                return Ok(Some(divergence));
+            }
+        }
+        return Err(format!(
+            "States differed after instruction {} but not when run again one at a time, is the reference deterministic?",
+            matched + count
+        ));
+    }
+    Ok(None)
+}
+
+/// Answers the protocol on standard input and output for this core, until told to quit
+pub fn serve(mut chip8: Chip8) {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        let answer = match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["quit"] => return,
+            ["step", count] => match count.parse::<u64>() {
+                Ok(count) => (0..count).try_for_each(|_| chip8.try_emulate_cycle()).map_or_else(|reason| format!("error {}", reason), |_| State::of(&chip8).line()),
+                Err(_) => format!("error Bad count {}", count),
+            },
+            _ => format!("error Unknown command {}", line),
+        };
+        if writeln!(stdout, "{}", answer).and_then(|_| stdout.flush()).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Chip8;
+    use crate::compare::State;
+    use crate::run::power_on;
+
+    /// States read back from their lines, and name what differs
+    #[test]
+    fn test_state() {
+        // Draw digit 0 with V0 = 5
+        let mut chip8 = power_on(Chip8::builder(), &[0x60, 0x05, 0xF0, 0x29, 0xD0, 0x05]);
+        for _ in 0..3 {
+            chip8.emulate_cycle();
+        }
+        let state = State::of(&chip8);
+        assert_eq!(State::parse(&state.line()), Ok(State::of(&chip8)));
+        assert!(state.differences(&State::of(&chip8), false).is_empty());
+
+        let reference = State::of(&power_on(Chip8::builder(), &[]));
+        assert_eq!(
+            state.differences(&reference, false),
+            vec!["PC is 0x206 here, 0x200 in the reference", "I is 0x19 here, 0x0 in the reference", "V0 is 0x5 here, 0x0 in the reference", "14 pixels differ on the screen"]
+        );
+        assert!(State::parse("0200 0000").is_err());
+    }
+}
@@ -0,0 +1,171 @@
+//! User configuration, read from a TOML file (`chip8.toml` in the working directory by default).
+//!
+//! ```toml
+//! [keymap]
+//! preset = "cosmac"    # or "sequential"
+//! layout = "azerty"    # qwerty, azerty, qwertz or dvorak
+//! logical = false      # match keys by character instead of position
+//!
+//! [keymap.keys]        # rebind individual CHIP-8 keys
+//! A = "Space"
+//!
+//! [second_keymap.keys] # second CHIP-8X keypad, on the numeric keypad by default
+//! 5 = "NumPad5"
+//!
+//! [hotkeys]            # emulator hotkeys, see `hotkeys`
+//! pause = "Space"
+//!
+//! [midi]               # MIDI controller, see `midi`
+//! base_note = 36
+//! ```
+
+#[cfg(feature = "gamepad")]
+use crate::gamepad::GamepadConfig;
+#[cfg(feature = "hid")]
+use crate::hid::HidKeypadConfig;
+use crate::hotkeys::HotkeyConfig;
+use crate::keymap::{key_from_name, Keymap, Layout, Preset};
+use crate::macros::MacroConfig;
+#[cfg(feature = "midi")]
+use crate::midi::MidiConfig;
+use crate::turbo::TurboConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+pub const DEFAULT_PATH: &str = "chip8.toml";
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    pub keymap: KeymapConfig,
+    /// Second keypad for two-player CHIP-8X programs, on the numeric keypad by default
+    pub second_keymap: SecondKeymapConfig,
+    /// Autofire settings per keypad key
+    pub turbo: Vec<TurboConfig>,
+    pub hotkeys: HotkeyConfig,
+    /// Input macros, recorded with the record macro hotkey
+    pub macros: Vec<MacroConfig>,
+    #[cfg(feature = "gamepad")]
+    pub gamepad: GamepadConfig,
+    #[cfg(feature = "hid")]
+    pub hid: Vec<HidKeypadConfig>,
+    #[cfg(feature = "midi")]
+    pub midi: MidiConfig,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct KeymapConfig {
+    pub preset: Preset,
+    pub layout: Layout,
+    pub logical: bool,
+    /// Overrides from CHIP-8 key (hex digit) to key name
+    pub keys: BTreeMap<String, String>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SecondKeymapConfig {
+    /// Overrides from CHIP-8 key (hex digit) to key name
+    pub keys: BTreeMap<String, String>,
+}
+
+impl Config {
+    /// Reads the config file, falling back to the defaults if it doesn't exist
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents)
+                .map_err(|e| format!("Invalid config file {}\n{}", path.display(), e)),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(Config::default()),
+            Err(error) => Err(format!("Could not read config file {}\n{}", path.display(), error)),
+        }
+    }
+
+    /// Writes the settings back over the file's, keeping the tables this build doesn't know, like
+    /// `[gamepad]` in one without the `gamepad` feature
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let mut table = match fs::read_to_string(path) {
+            Ok(contents) => contents.parse::<toml::Table>()
+                .map_err(|e| format!("Invalid config file {}\n{}", path.display(), e))?,
+            Err(error) if error.kind() == ErrorKind::NotFound => toml::Table::new(),
+            Err(error) => return Err(format!("Could not read config file {}\n{}", path.display(), error)),
+        };
+        if let toml::Value::Table(owned) = toml::Value::try_from(self).map_err(|e| e.to_string())? {
+            table.extend(owned);
+        }
+        let contents = toml::to_string_pretty(&table).map_err(|e| e.to_string())?;
+        fs::write(path, contents)
+            .map_err(|e| format!("Could not write config file {}\n{}", path.display(), e))
+    }
+}
+
+impl KeymapConfig {
+    /// Builds the keypad, on the given layout or by character where the command line asks for it
+    /// in place of the config
+    pub fn build(&self, layout: Option<Layout>, logical: bool) -> Result<Keymap, String> {
+        let mut keymap = if self.logical || logical {
+            Keymap::logical(self.preset)
+        } else {
+            Keymap::physical(self.preset, layout.unwrap_or(self.layout))
+        };
+        bind_overrides(&mut keymap, &self.keys)?;
+        Ok(keymap)
+    }
+}
+
+impl SecondKeymapConfig {
+    /// Builds the second keypad, using the same preset as the first one
+    pub fn build(&self, preset: Preset) -> Result<Keymap, String> {
+        let mut keymap = Keymap::numpad(preset);
+        bind_overrides(&mut keymap, &self.keys)?;
+        Ok(keymap)
+    }
+}
+
+fn bind_overrides(keymap: &mut Keymap, keys: &BTreeMap<String, String>) -> Result<(), String> {
+    for (chip8_key, key_name) in keys {
+        let chip8_key = usize::from_str_radix(chip8_key, 16)
+            .ok()
+            .filter(|k| *k < 16)
+            .ok_or_else(|| format!("Invalid CHIP-8 key in keymap: {}", chip8_key))?;
+        let key = key_from_name(key_name)
+            .ok_or_else(|| format!("Unknown key in keymap: {}", key_name))?;
+        keymap.bind(chip8_key, key);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::keymap::Layout;
+    use minifb::Key;
+    use std::fs;
+
+    /// The command line's keyboard options build the keypad without changing the config
+    #[test]
+    fn test_build_overrides() {
+        let config: Config = toml::from_str("[keymap]\nlayout = \"azerty\"\n").unwrap();
+        assert_eq!(config.keymap.build(None, false).unwrap().key(0x4), Key::A);
+        assert_eq!(config.keymap.build(Some(Layout::Qwerty), false).unwrap().key(0x4), Key::Q);
+        assert_eq!(config.keymap.build(None, true).unwrap().key(0x4), Key::Q);
+        assert_eq!((config.keymap.layout, config.keymap.logical), (Layout::Azerty, false));
+    }
+
+    /// Saving keeps the tables of features this build doesn't have and replaces its own
+    #[test]
+    fn test_save_keeps_unknown_tables() {
+        let path = std::env::temp_dir().join(format!("chip8-config-{}.toml", std::process::id()));
+        fs::write(&path, "[unknown_feature]\nsetting = 3\n\n[keymap]\npreset = \"sequential\"\n").unwrap();
+        let mut config = Config::load(&path).unwrap();
+        config.keymap.logical = true;
+        config.save(&path).unwrap();
+        let saved: toml::Table = fs::read_to_string(&path).unwrap().parse().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(saved["unknown_feature"]["setting"].as_integer(), Some(3));
+        assert_eq!(saved["keymap"]["logical"].as_bool(), Some(true));
+    }
+}
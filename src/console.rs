@@ -0,0 +1,282 @@
+//! Drop-down console over the game, opened and closed with the console hotkey, ` by default.
+//!
+//! It takes the commands of the debugger prompt, starting the debugger if it isn't running yet,
+//! and a few of its own:
+//!
+//! | Command                |                                                       |
+//! |------------------------|-------------------------------------------------------|
+//! | `poke address value`   | write a byte to memory, the address a number or label |
+//! | `speed [factor]`       | show or set the frames emulated per update, like `2x` |
+//! | `cheat [number]`       | list the cheats, or turn one on or off                |
+//! | `freeze address value` | keep a byte of memory at a value                      |
+//! | `unfreeze address`     | stop keeping it                                       |
+//! | `search [filter]`      | look for the address of a value, see `search`         |
+//! | `clear`                | empty the console                                     |
+//!
+//! Up and Down go through the commands entered before, Tab completes command names and labels.
+//! The game is paused while the console is open, and Escape closes it.
+
+use crate::cheats::Cheats;
+use crate::chip8::Chip8;
+use crate::debugger::{self, Debugger};
+use crate::run;
+use crate::search::MemorySearch;
+use crate::symbols::Symbols;
+use crate::text::{self, CHAR_WIDTH, LINE_HEIGHT};
+use minifb::{InputCallback, Key, Window};
+use std::sync::{Arc, Mutex};
+
+/// The console is drawn at this many times the resolution of the game
+pub const SCALE: usize = 4;
+pub const WIDTH: usize = run::WIDTH * SCALE;
+pub const HEIGHT: usize = run::HEIGHT * SCALE;
+
+/// Lines of output shown, above the input line
+const SHOWN: usize = 9;
+/// Lines of output kept
+const KEPT: usize = 200;
+
+const BACKGROUND: u32 = 0x101010;
+const TEXT: u32 = 0xC0C0C0;
+const INPUT: u32 = 0xFFFFFF;
+const EDGE: u32 = 0x304060;
+
+/// Commands of the console itself, for completion
+const COMMANDS: [&str; 8] = ["poke", "speed", "cheat", "freeze", "unfreeze", "search", "clear", "telemetry"];
+
+pub struct Console {
+    open: bool,
+    input: String,
+    history: Vec<String>,
+    /// Entry of the history in the input line, while going through it
+    browsing: Option<usize>,
+    /// Output, oldest first
+    lines: Vec<String>,
+    /// Characters typed into the window, passed on by its input callback
+    typed: Arc<Mutex<Vec<char>>>,
+}
+
+struct Typed(Arc<Mutex<Vec<char>>>);
+
+impl InputCallback for Typed {
+    fn add_char(&mut self, uni_char: u32) {
+        if let Some(character) = char::from_u32(uni_char).filter(|character| !character.is_control()) {
+            self.0.lock().unwrap().push(character);
+        }
+    }
+}
+
+impl Console {
+    /// Takes the characters typed into the window
+    pub fn new(window: &mut Window) -> Self {
+        let typed = Arc::new(Mutex::new(Vec::new()));
+        window.set_input_callback(Box::new(Typed(Arc::clone(&typed))));
+        Console { open: false, input: String::new(), history: Vec::new(), browsing: None, lines: Vec::new(), typed }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+        // Whatever was typed at the game, including the key opening the console, isn't input
+        self.typed.lock().unwrap().clear();
+    }
+
+    /// Edits the input line with the keys pressed since the last frame, returning a command
+    /// once Enter is pressed
+    pub fn handle_keys(&mut self, pressed: &[Key], symbols: &Symbols) -> Option<String> {
+        let typed: Vec<char> = self.typed.lock().unwrap().drain(..).collect();
+        self.input.extend(typed);
+        for key in pressed {
+            match key {
+                Key::Escape => self.toggle(),
+                Key::Backspace => {
+                    self.input.pop();
+                }
+                Key::Up | Key::Down if !self.history.is_empty() => {
+                    let last = self.history.len() - 1;
+                    self.browsing = match (self.browsing, key) {
+                        (None, Key::Up) => Some(last),
+                        (Some(entry), Key::Up) => Some(entry.saturating_sub(1)),
+                        (Some(entry), _) if entry < last => Some(entry + 1),
+                        _ => None,
+                    };
+                    self.input = self.browsing.map_or_else(String::new, |entry| self.history[entry].clone());
+                }
+                Key::Tab => self.complete(symbols),
+                Key::Enter | Key::NumPadEnter => {
+                    let line = std::mem::take(&mut self.input);
+                    self.browsing = None;
+                    self.print(&format!("> {}", line));
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    if self.history.last() != Some(&line) {
+                        self.history.push(line.clone());
+                    }
+                    if line.trim() == "clear" {
+                        self.lines.clear();
+                        continue;
+                    }
+                    return Some(line);
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Adds lines of output
+    pub fn print(&mut self, text: &str) {
+        self.lines.extend(text.lines().map(str::to_string));
+        let excess = self.lines.len().saturating_sub(KEPT);
+        self.lines.drain(..excess);
+    }
+
+    /// Completes the last word of the input: a command name, or a label after it
+    fn complete(&mut self, symbols: &Symbols) {
+        let start = self.input.rfind(' ').map_or(0, |space| space + 1);
+        let word = self.input[start..].to_string();
+        let candidates: Vec<&str> = if start == 0 {
+            COMMANDS.iter().chain(debugger::COMMANDS.iter()).copied().collect()
+        } else {
+            symbols.labels.keys().map(String::as_str).collect()
+        };
+        let matches: Vec<&str> = candidates.into_iter().filter(|candidate| candidate.starts_with(&word)).collect();
+        let completed = match matches.split_first() {
+            Some((first, rest)) => {
+                rest.iter().fold(first.to_string(), |prefix, other| prefix.chars().zip(other.chars()).take_while(|(a, b)| a == b).map(|(a, _)| a).collect())
+            }
+            None => return,
+        };
+        let space = if matches.len() == 1 { " " } else { "" };
+        if matches.len() > 1 && completed == word {
+            let mut matches = matches;
+            matches.sort_unstable();
+            self.print(&matches.join(" "));
+        }
+        self.input = format!("{}{}{}", &self.input[..start], completed, space);
+    }
+
+    /// Draws the game scaled up with the console over its top half
+    pub fn draw(&self, frame: &[u32], buffer: &mut [u32]) {
+        for (index, pixel) in buffer.iter_mut().enumerate() {
+            let (x, y) = (index % WIDTH / SCALE, index / WIDTH / SCALE);
+            *pixel = frame[y * run::WIDTH + x];
+        }
+        let height = (SHOWN + 1) * LINE_HEIGHT + 2;
+        buffer[..height * WIDTH].iter_mut().for_each(|pixel| *pixel = BACKGROUND);
+        buffer[height * WIDTH..(height + 1) * WIDTH].iter_mut().for_each(|pixel| *pixel = EDGE);
+
+        let columns = (WIDTH - 2) / CHAR_WIDTH;
+        let shown = &self.lines[self.lines.len().saturating_sub(SHOWN)..];
+        for (row, line) in shown.iter().enumerate() {
+            let fits: String = line.chars().take(columns).collect();
+            text::draw(buffer, WIDTH, (1, 1 + row * LINE_HEIGHT), &fits, TEXT);
+        }
+        // The end of a long input line stays in view
+        let input = format!("> {}_", self.input);
+        let skip = input.chars().count().saturating_sub(columns);
+        let input: String = input.chars().skip(skip).collect();
+        text::draw(buffer, WIDTH, (1, 1 + SHOWN * LINE_HEIGHT), &input, INPUT);
+    }
+}
+
+/// Runs a command typed in the console, passing whatever isn't a console command to the debugger
+pub fn execute(
+    line: &str,
+    chip8: &mut Chip8,
+    debugger: &mut Option<Debugger>,
+    cheats: &mut Cheats,
+    search: &mut MemorySearch,
+    symbols: &Symbols,
+    speed: &mut u32,
+) -> Result<String, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let address = |text: &str| {
+        symbols.labels.get(text).copied().or_else(|| debugger::parse_address(text)).ok_or_else(|| format!("Not an address or label: {}", text))
+    };
+    match words[..] {
+        ["poke", location, value] => {
+            let address = address(location)?;
+            let value = debugger::parse_address(value).filter(|value| *value <= 0xFF).ok_or_else(|| format!("Not a byte: {}", value))?;
+            chip8.write_memory(address, &[value as u8]);
+            chip8.force_redraw();
+            Ok(format!("{:#05X} = {:#04X}", address, value))
+        }
+        ["poke", ..] => Err(String::from("poke address value")),
+        ["speed"] => Ok(format!("Speed: {}x", speed)),
+        ["speed", factor] => {
+            let factor: u32 = factor.trim_end_matches('x').parse().map_err(|_| format!("Not a speed: {}", factor))?;
+            if !(1..=run::MAX_SPEED).contains(&factor) {
+                return Err(format!("Speed goes from 1x to {}x", run::MAX_SPEED));
+            }
+            *speed = factor;
+            Ok(format!("Speed: {}x", speed))
+        }
+        ["cheat"] => Ok(cheats.list()),
+        ["cheat", number] => {
+            let number: usize = number.parse().ok().filter(|number| *number > 0).ok_or_else(|| format!("Not a cheat number: {}", number))?;
+            cheats.toggle(number - 1)
+        }
+        ["freeze", location, value] => cheats.freeze(&format!("{}:{}", location, value), symbols),
+        ["freeze", ..] => Err(String::from("freeze address value")),
+        ["unfreeze", location] => match cheats.unfreeze(address(location)?) {
+            true => Ok(format!("Unfroze {}", location)),
+            false => Err(format!("{} isn't frozen", location)),
+        },
+        ["search"] => Ok(search.start(chip8)),
+        ["search", "list"] => Ok(search.list(chip8)),
+        ["search", "freeze", value] => {
+            let frozen: Vec<String> = search.codes(value)?.iter().map(|code| cheats.freeze(code, symbols)).collect::<Result<_, _>>()?;
+            Ok(frozen.join("\n"))
+        }
+        ["search", ref filter @ ..] => search.filter(filter, chip8),
+        _ => debugger.get_or_insert_with(|| Debugger::new(symbols.clone())).run_command(line, chip8),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cheats::Cheats;
+    use crate::chip8::Chip8;
+    use crate::console::{execute, Console};
+    use crate::search::MemorySearch;
+    use crate::symbols::Symbols;
+    use minifb::Key;
+    use std::sync::{Arc, Mutex};
+
+    /// Typed commands are completed, run, and kept in the history
+    #[test]
+    fn test_console() {
+        let mut symbols = Symbols::default();
+        symbols.labels.insert(String::from("lives"), 0x300);
+        let typed = Arc::new(Mutex::new(Vec::new()));
+        let mut console = Console { open: true, input: String::new(), history: Vec::new(), browsing: None, lines: Vec::new(), typed: Arc::clone(&typed) };
+
+        typed.lock().unwrap().extend("po".chars());
+        assert_eq!(console.handle_keys(&[Key::Tab], &symbols), None);
+        typed.lock().unwrap().extend("li".chars());
+        console.handle_keys(&[Key::Tab], &symbols);
+        typed.lock().unwrap().extend("9".chars());
+        let line = console.handle_keys(&[Key::Enter], &symbols).unwrap();
+        assert_eq!(line, "poke lives 9");
+
+        let mut chip8 = Chip8::new();
+        let mut speed = 1;
+        let mut cheats = Cheats::default();
+        let mut search = MemorySearch::default();
+        assert_eq!(execute(&line, &mut chip8, &mut None, &mut cheats, &mut search, &symbols, &mut speed), Ok(String::from("0x300 = 0x09")));
+        assert_eq!(chip8.memory()[0x300], 9);
+        assert_eq!(execute("speed 4x", &mut chip8, &mut None, &mut cheats, &mut search, &symbols, &mut speed), Ok(String::from("Speed: 4x")));
+        assert_eq!(speed, 4);
+        assert!(execute("speed 100x", &mut chip8, &mut None, &mut cheats, &mut search, &symbols, &mut speed).is_err());
+
+        console.handle_keys(&[Key::Up], &symbols);
+        assert_eq!(console.input, "poke lives 9");
+        console.handle_keys(&[Key::Down], &symbols);
+        assert_eq!(console.input, "");
+    }
+}
@@ -0,0 +1,122 @@
+//! Which bytes of the program ran as instructions and which were read as data through I, to see
+//! how much of a ROM a run went through. The map written out has a character per byte, 16 to a
+//! line:
+//!
+//! ```text
+//! ; x ran, d read as data, b both, . neither
+//! ; 234 of 512 bytes ran (45.7%), 40 read as data (7.8%)
+//! 0x200  xxxxxxxxxxxxxxxx
+//! 0x210  xxxxxx..dddddddd
+//! ```
+
+use crate::chip8::{Access, Chip8, Instruction, MAX_MEMORY_SIZE};
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+const RAN: u8 = 1;
+const READ: u8 = 2;
+
+pub struct Coverage {
+    /// `RAN` and `READ` flags of each byte of memory
+    bytes: Vec<u8>,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Coverage { bytes: vec![0; MAX_MEMORY_SIZE] }
+    }
+}
+
+impl Coverage {
+    /// Marks the instruction at the program counter and the memory it reads, before it runs
+    pub fn record(&mut self, chip8: &Chip8) {
+        let address = chip8.program_counter() as usize;
+        let opcode = match chip8.memory().get(address..address + 2) {
+            Some(opcode) => u16::from_be_bytes([opcode[0], opcode[1]]),
+            None => return,
+        };
+        self.bytes[address] |= RAN;
+        self.bytes[address + 1] |= RAN;
+        let instruction = Instruction::decode(opcode, chip8.variant());
+        if let Some((Access::Read, read)) = instruction.and_then(|instruction| instruction.memory_access(chip8.index())) {
+            for address in read {
+                if let Some(byte) = self.bytes.get_mut(address as usize) {
+                    *byte |= READ;
+                }
+            }
+        }
+    }
+
+    /// Whether an instruction ran from the byte at an address
+    pub fn ran(&self, address: u16) -> bool {
+        self.bytes.get(address as usize).is_some_and(|byte| byte & RAN != 0)
+    }
+
+    /// Bytes that ran and bytes read as data out of the program's
+    pub fn summary(&self, program: Range<usize>) -> String {
+        let length = program.len().max(1);
+        let count = |flag: u8| self.bytes[program.clone()].iter().filter(|byte| **byte & flag != 0).count();
+        let (ran, read) = (count(RAN), count(READ));
+        format!(
+            "{} of {} bytes ran ({:.1}%), {} read as data ({:.1}%)",
+            ran,
+            program.len(),
+            ran as f64 * 100.0 / length as f64,
+            read,
+            read as f64 * 100.0 / length as f64
+        )
+    }
+
+    pub fn to_map(&self, program: Range<usize>) -> String {
+        let mut lines = vec![String::from("; x ran, d read as data, b both, . neither"), format!("; {}", self.summary(program.clone()))];
+        for start in program.clone().step_by(16) {
+            let row: String = self.bytes[start..(start + 16).min(program.end)]
+                .iter()
+                .map(|byte| match *byte {
+                    RAN => 'x',
+                    READ => 'd',
+                    0 => '.',
+                    _ => 'b',
+                })
+                .collect();
+            lines.push(format!("{:#05X}  {}", start, row));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    pub fn save(&self, program: Range<usize>, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_map(program)).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+
+    /// From the start of programs to the last byte that ran or was read, for when the size of
+    /// the program isn't known
+    pub fn touched(&self) -> Range<usize> {
+        let end = self.bytes.iter().rposition(|byte| *byte != 0).map_or(0x200, |last| last + 1);
+        0x200..end.max(0x200)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Chip8;
+    use crate::coverage::Coverage;
+
+    /// Instructions that ran and the sprite drawn are marked, the skipped instruction isn't
+    #[test]
+    fn test_coverage() {
+        let rom = [0xA2, 0x08, 0xD0, 0x01, 0x12, 0x04, 0x00, 0xE0, 0xFF];
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&rom);
+        let mut coverage = Coverage::default();
+        for _ in 0..4 {
+            coverage.record(&chip8);
+            chip8.emulate_cycle();
+        }
+        assert_eq!(
+            coverage.to_map(0x200..0x200 + rom.len()),
+            "; x ran, d read as data, b both, . neither\n; 6 of 9 bytes ran (66.7%), 1 read as data (11.1%)\n0x200  xxxxxx..d\n"
+        );
+        assert_eq!(coverage.touched(), 0x200..0x209);
+    }
+}
@@ -0,0 +1,242 @@
+//! Crash dumps, written next to the ROM when the program runs into something the machine can't
+//! do: an unknown opcode, a return with the stack empty, a call with it full, or memory past the
+//! end accessed through I. Instead of a panic with an opcode, the dump has what's needed to tell
+//! what happened, as JSON:
+//!
+//! - why it stopped, the ROM's path and SHA-1, the variant, the RNG seed and the quirks
+//! - the registers, stack, timers, keypads, memory and screen as the failing instruction found them
+//! - the last instructions run, from the trace ring buffer, ending with the one that failed
+//! - the input since power-on, as a replay file in hex, to run into the failure again
+//!
+//! The input is left out after loading a savestate or reloading the ROM, which it can't replay.
+//! Memory changed by cheats, the console or the debugger isn't part of it either.
+//!
+//! `chip8 crash --dump DUMP ROM` runs into the failure again: the input of the dump, or of a
+//! replay given with `--replay` when it has none, is fed to the machine from power-on without a
+//! window up to the last few frames before the failure, which play in the window. The machine
+//! pauses at the failure, telling whether it's the one of the dump, for the debugger to look
+//! around.
+
+use crate::chip8::{Chip8, IndexWidth, MemoryBounds, Variant, WriteProtection};
+use crate::replay::{FrameInput, Machine, Replay, RomHash};
+use crate::snapshot::Snapshot;
+use crate::symbols::Symbols;
+use crate::trace::Trace;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+/// Instructions kept for the dump
+const TRACE_LENGTH: usize = 32;
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct CrashDump {
+    pub reason: String,
+    pub rom: String,
+    /// SHA-1 of the ROM, in hex
+    pub rom_sha1: String,
+    pub variant: String,
+    pub seed: u64,
+    /// How the emulator behaves where interpreters differ
+    pub quirks: BTreeMap<String, String>,
+    /// The machine as the failing instruction found it, its cycles not counting that instruction
+    #[serde(flatten)]
+    pub machine: Snapshot,
+    /// Keys held, a bit per key
+    pub keypad: u16,
+    pub second_keypad: u16,
+    pub trace: Vec<String>,
+    /// Replay file of the input since power-on, in hex
+    pub input: Option<String>,
+}
+
+/// What the dump needs from before the failure, kept while the program runs
+pub struct CrashLog {
+    rom_hash: RomHash,
+    seed: u64,
+    trace: Trace,
+    input: Option<Replay>,
+}
+
+impl CrashLog {
+    pub fn new(rom_hash: RomHash, seed: u64, variant: Variant, machine: Machine) -> Self {
+        CrashLog { rom_hash, seed, trace: Trace::new(TRACE_LENGTH), input: Some(Replay::new(rom_hash, seed, variant, machine)) }
+    }
+
+    /// Records the instruction at the program counter, before it runs
+    pub fn record(&mut self, chip8: &Chip8) {
+        self.trace.record(chip8);
+    }
+
+    /// Records the keypads the machine runs the next instruction with
+    pub fn push_input(&mut self, chip8: &Chip8) {
+        if let Some(input) = &mut self.input {
+            input.push(FrameInput::new(chip8.keypad(), chip8.second_keypad()), || chip8.state_checksum());
+        }
+    }
+
+    /// Stops recording the input, after the machine changed in a way it can't replay
+    pub fn forget_input(&mut self) {
+        self.input = None;
+    }
+
+    pub fn dump(&self, reason: &str, rom: &Path, chip8: &Chip8, symbols: &Symbols) -> CrashDump {
+        let bits = |keys: [bool; 16]| FrameInput::new(keys, [false; 16]).keypad;
+        CrashDump {
+            reason: reason.to_string(),
+            rom: rom.display().to_string(),
+            rom_sha1: hex(&self.rom_hash),
+            variant: variant_name(chip8.variant()).to_string(),
+            seed: self.seed,
+            quirks: quirks(chip8),
+            machine: Snapshot::new(chip8),
+            keypad: bits(chip8.keypad()),
+            second_keypad: bits(chip8.second_keypad()),
+            trace: self.trace.lines(TRACE_LENGTH, chip8, symbols),
+            input: self.input.as_ref().map(|input| hex(&input.to_bytes())),
+        }
+    }
+}
+
+impl CrashDump {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        serde_json::from_str(&contents).map_err(|e| format!("Invalid crash dump {}\n{}", path.display(), e))
+    }
+
+    /// The input leading to the failure, from the dump or else from a replay file, checked
+    /// against the ROM of the dump
+    pub fn replay(&self, replay: Option<&Path>) -> Result<Replay, String> {
+        let replay = match (&self.input, replay) {
+            (_, Some(path)) => Replay::load(path)?,
+            (Some(input), None) => Replay::from_bytes(&from_hex(input).ok_or("Invalid input in the crash dump")?)?,
+            (None, None) => return Err(String::from("The crash dump has no input, give the replay it was recorded with")),
+        };
+        if hex(&replay.rom_hash) != self.rom_sha1 {
+            return Err(String::from("The replay was recorded with a different ROM than the crash dump"));
+        }
+        if replay.frame_count() < self.machine.cycles {
+            return Err(format!("The replay ends after {} instructions, before the failure at {}", replay.frame_count(), self.machine.cycles));
+        }
+        Ok(replay)
+    }
+
+    /// Whether the machine failed the way the dump says
+    pub fn matches(&self, reason: &str, chip8: &Chip8) -> bool {
+        reason == self.reason && chip8.cycles() == self.machine.cycles
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let contents = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, contents).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+}
+
+fn variant_name(variant: Variant) -> &'static str {
+    match variant {
+        Variant::Chip8 => "chip8",
+        Variant::Chip8X => "chip8x",
+    }
+}
+
+/// The emulator's side of each quirk, what I-relative instructions do past the end of memory,
+/// whether they may write to the interpreter's, the size of the stack, memory and I, and the
+/// instructions to a timer tick
+fn quirks(chip8: &Chip8) -> BTreeMap<String, String> {
+    let q = chip8.quirks();
+    let memory_bounds = match chip8.memory_bounds() {
+        MemoryBounds::Fault => "fault past the end",
+        MemoryBounds::Wrap => "wrap around to the start",
+    };
+    let write_protection = match chip8.write_protection() {
+        WriteProtection::Off => "writes below 0x200 allowed",
+        WriteProtection::Fault => "fault on writes below 0x200",
+        WriteProtection::Ignore => "writes below 0x200 ignored",
+    };
+    [
+        ("shift", if q.shift { "VX shifted in place" } else { "VY shifted into VX" }),
+        ("load_store", if q.load_store { "I left unchanged by FX55 and FX65" } else { "I moved past the registers by FX55 and FX65" }),
+        ("vf_order", if q.vf_order { "VF set before VX" } else { "VF set after VX" }),
+        ("clip", if q.clip { "sprites cut off at the edges" } else { "sprites wrap around" }),
+        ("vblank", if q.vblank { "DXYN waits for a timer tick" } else { "DXYN draws straight away" }),
+        ("jump", if q.jump { "BNNN jumps by VX" } else { "BNNN jumps by V0" }),
+        ("logic", if q.logic { "VF reset by 8XY1 to 8XY3" } else { "VF left by 8XY1 to 8XY3" }),
+        ("memory_bounds", memory_bounds),
+        ("write_protection", write_protection),
+        ("stack_depth", &format!("{} calls", chip8.stack_depth())),
+        ("memory_size", &format!("{} bytes", chip8.memory().len())),
+        ("index_width", if chip8.index_width() == IndexWidth::Bits12 { "I masked to 12 bits" } else { "I 16 bits wide" }),
+        ("tickrate", &format!("{} instructions a timer tick", chip8.tickrate())),
+    ]
+    .iter()
+    .map(|(quirk, behaviour)| (quirk.to_string(), behaviour.to_string()))
+    .collect()
+}
+
+/// Runs the machine from power-on with the input of a replay up to a number of instructions,
+/// failing if the program fails first
+pub fn fast_forward(chip8: &mut Chip8, inputs: &[FrameInput], cycles: u64) -> Result<(), String> {
+    for input in inputs.iter().take(cycles as usize) {
+        let cycles = chip8.cycles();
+        chip8.try_emulate_cycle().map_err(|reason| format!("{} at instruction {}, before the failure of the crash dump", reason, cycles))?;
+        chip8.set_keys(input.keypad());
+        chip8.set_second_keypad(input.second_keypad());
+    }
+    Ok(())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(text, "{:02x}", byte);
+    }
+    text
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|start| u8::from_str_radix(text.get(start..start + 2)?, 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Chip8;
+    use crate::crash::{fast_forward, CrashLog};
+    use crate::replay::{rom_hash, Machine};
+    use crate::symbols::Symbols;
+    use std::path::Path;
+
+    /// The dump has the machine as the failing instruction found it and the instructions up to it
+    #[test]
+    fn test_crash_dump() {
+        let program = [0x60, 0x2A, 0x22, 0x06, 0x00, 0x00, 0x00, 0xEE, 0x00, 0xEE];
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&program);
+        let mut log = CrashLog::new(rom_hash(&program), 7, chip8.variant(), Machine::default());
+        let reason = loop {
+            log.record(&chip8);
+            if let Err(error) = chip8.try_emulate_cycle() {
+                break error.to_string();
+            }
+            log.push_input(&chip8);
+        };
+        assert_eq!(reason, "Unknown opcode 0x0000 at 0x204");
+
+        let dump = log.dump(&reason, Path::new("game.ch8"), &chip8, &Symbols::default());
+        assert_eq!((dump.machine.cycles, dump.machine.pc, dump.machine.registers[0], dump.seed), (3, 0x204, 0x2A, 7));
+        assert_eq!(dump.trace.len(), 4);
+        assert_eq!(dump.machine.memory[0x200..0x20A], program);
+        assert!(dump.input.as_ref().unwrap().starts_with("43385250"));
+
+        let replay = dump.replay(None).unwrap();
+        let inputs: Vec<_> = replay.frames().collect();
+        let mut again = Chip8::new();
+        again.load_program(&program);
+        fast_forward(&mut again, &inputs, dump.machine.cycles).unwrap();
+        assert!(dump.matches(&again.try_emulate_cycle().unwrap_err().to_string(), &again));
+    }
+}
@@ -0,0 +1,778 @@
+//! Debugger window shown next to the game, opened and closed with the debug view hotkey.
+//!
+//! Panels are docked in two columns: the disassembly, following the program counter, on the
+//! left, and the registers and timers, the call stack, the screen and the watch expressions of
+//! the debugger on the right. The stack panel lists the slots of the stack from the stack
+//! pointer down, each with its call, return address and subroutine, then the slots left over
+//! from returned calls, and warns when the instruction at the program counter is a call with the
+//! stack full or a return with it empty. The memory heatmap, hidden at first, shows how often the debugger
+//! saw each address of memory written (red), read (green) and run (blue), a row of 64 bytes to
+//! a line. The hex panel, also hidden at first, shows memory with the instruction at the program
+//! counter, the byte at I, the fontset, the calls on the stack and the memory with notes
+//! highlighted, with the note of the selected byte or of I below, and edits it while the machine
+//! is paused. The sprite panel shows the 15 bytes at I as a sprite, with the rows the
+//! DXYN at the program counter is about to draw lit up. The keypad panel shows the keys the
+//! machine sees held on both keypads, whatever the game does with them, and the key the
+//! instruction at the program counter tests. The graph panel plots the expressions given with
+//! `--graph` or the `graph` command over the last frames, each scaled between its lowest and
+//! highest value. While the machine is stopped, Tab turns the screen panel into the draw
+//! inspector, stepping through the sprites DXYN drew this frame with the screen before and after
+//! each, the pixels drawn in green and the ones erased in red. With the debugger window focused:
+//!
+//! | Key             |                                                     |
+//! |-----------------|-----------------------------------------------------|
+//! | `1` - `9`, `0`  | show or hide a panel, in the order above            |
+//! | Shift + `0-9`   | move a panel to the other column                    |
+//! | Left mouse      | set or clear a breakpoint on an instruction         |
+//! | Left mouse, hex | select a byte, Escape to stop editing               |
+//! | `0` - `F`       | type a new value for the selected byte, when paused |
+//! | Up, Down        | scroll the hex panel a row                          |
+//! | Page Up, Down   | scroll the hex panel a page                         |
+//! | Tab             | inspect the draws of the frame, or the screen again |
+//! | Left, Right     | go to the draw before or after                      |
+
+use crate::chip8::{Chip8, Instruction};
+use crate::debugger::Debugger;
+use crate::disasm;
+use crate::draws::Draw;
+use crate::fatal;
+use crate::graphs::{self, Graph};
+use crate::heatmap;
+use crate::keypad_grid::GRID;
+use crate::run;
+use crate::symbols::Symbols;
+use crate::text::{self, CHAR_WIDTH, LINE_HEIGHT};
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Scale, ScaleMode, Window, WindowOptions};
+
+const WIDTH: usize = 256;
+const HEIGHT: usize = 240;
+const COLUMN_WIDTH: usize = WIDTH / 2;
+
+const BACKGROUND: u32 = 0x101010;
+const TEXT: u32 = 0xC0C0C0;
+const TITLE_BACKGROUND: u32 = 0x304060;
+const TITLE: u32 = 0xFFFFFF;
+/// Background of the instruction at the program counter
+const CURRENT: u32 = 0x404000;
+/// Mark in front of instructions with a breakpoint
+const BREAKPOINT: u32 = 0xCC2020;
+/// Watch expressions whose value just changed
+const CHANGED: u32 = 0xFFFF60;
+/// Background of the byte at I in the hex panel
+const INDEX: u32 = 0x204020;
+/// Background of the calls on the stack in the hex panel
+const CALL: u32 = 0x402040;
+/// Bytes of the fontset in the hex panel
+const FONT: u32 = 0x6080C0;
+/// Background of the memory with notes in the hex panel
+const NOTE: u32 = 0x403018;
+/// Background of the byte being edited in the hex panel
+const SELECTED: u32 = 0x306090;
+/// Stack slots not in use
+const UNUSED: u32 = 0x606060;
+/// A call or return about to overflow or underflow the stack
+const WARNING: u32 = 0xFF4040;
+const PIXEL_ON: u32 = 0x0FFF;
+const PIXEL_OFF: u32 = 0x000000;
+
+/// The screen panel draws the display at twice its size
+const SCREEN_SCALE: usize = 2;
+/// And the memory panel each address as 2x2 pixels
+const HEATMAP_SCALE: usize = 2;
+/// Bytes in a row of the hex panel
+const HEX_ROW: usize = 8;
+/// Rows in the hex panel
+const HEX_ROWS: usize = 16;
+/// The sprite panel draws each pixel of a sprite as 6x6 pixels, a row to a line of text
+const SPRITE_SCALE: usize = LINE_HEIGHT;
+/// Most rows DXYN draws
+const SPRITE_ROWS: usize = 15;
+/// Pixels a draw turned on, in the draw inspector
+const DRAWN: u32 = 0x40FF40;
+/// And the ones it turned off
+const ERASED: u32 = WARNING;
+/// Graphs shown at most in the graph panel
+const GRAPHS: usize = 3;
+/// Height of a plot in the graph panel
+const PLOT_HEIGHT: usize = 2 * LINE_HEIGHT;
+/// Plotted values
+const PLOT: u32 = 0x60C0FF;
+/// Pixels of sprite rows the next DXYN doesn't draw
+const PIXEL_UNUSED: u32 = 0x404040;
+/// Background of the keys held in the keypad panel
+const KEY_HELD: u32 = 0x00A000;
+/// Column of the second keypad in the keypad panel, in characters
+const SECOND_KEYPAD: usize = 12;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Panel {
+    Disassembly,
+    Registers,
+    Stack,
+    Screen,
+    Watch,
+    Memory,
+    Hex,
+    Sprite,
+    Keypad,
+    Graph,
+}
+
+const PANELS: [Panel; 10] = [
+    Panel::Disassembly,
+    Panel::Registers,
+    Panel::Stack,
+    Panel::Screen,
+    Panel::Watch,
+    Panel::Memory,
+    Panel::Hex,
+    Panel::Sprite,
+    Panel::Keypad,
+    Panel::Graph,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Dock {
+    Left,
+    Right,
+}
+
+pub struct DebugView {
+    window: Window,
+    buffer: Vec<u32>,
+    /// Where each panel of `PANELS` is, `None` when hidden
+    docks: [Option<Dock>; 10],
+    /// First address shown in the disassembly, moved when the program counter leaves the view
+    disassembly_start: u16,
+    /// Top left corner of each instruction in the disassembly, as last drawn
+    instructions: Vec<((usize, usize), u16)>,
+    mouse_was_down: bool,
+    /// Instruction clicked since `take_clicked` was last called
+    clicked: Option<u16>,
+    /// First address shown in the hex panel
+    hex_start: u16,
+    /// Top left corner of each byte in the hex panel, as last drawn
+    bytes: Vec<((usize, usize), u16)>,
+    /// Byte being edited in the hex panel, with the first digit typed
+    selected: Option<(u16, Option<u8>)>,
+    /// Byte typed since `take_edit` was last called
+    edit: Option<(u16, u8)>,
+    /// Whether the screen panel shows the draw inspector instead
+    inspecting: bool,
+    /// Draw shown in the draw inspector, counting from 0
+    draw: usize,
+}
+
+impl DebugView {
+    pub fn new() -> Self {
+        let window = Window::new(
+            "Debugger",
+            WIDTH,
+            HEIGHT,
+            WindowOptions {
+                borderless: false,
+                transparency: false,
+                title: true,
+                resize: false,
+                scale: Scale::X2,
+                scale_mode: ScaleMode::Stretch,
+                topmost: false,
+            },
+        )
+            .unwrap_or_else(fatal);
+        DebugView {
+            window,
+            buffer: vec![BACKGROUND; WIDTH * HEIGHT],
+            docks: [Some(Dock::Left), Some(Dock::Right), Some(Dock::Right), Some(Dock::Right), Some(Dock::Right), None, None, None, None, None],
+            disassembly_start: 0x200,
+            instructions: Vec::new(),
+            mouse_was_down: false,
+            clicked: None,
+            hex_start: 0x200,
+            bytes: Vec::new(),
+            selected: None,
+            edit: None,
+            inspecting: false,
+            draw: 0,
+        }
+    }
+
+    /// Handles the keys of the window and draws the machine, with the breakpoints and watches of
+    /// the debugger if there is one. Bytes are only edited while the machine is paused. Returns
+    /// false once the window has been closed.
+    pub fn update(&mut self, chip8: &Chip8, symbols: &Symbols, debugger: Option<&Debugger>, paused: bool) -> bool {
+        if !self.window.is_open() {
+            return false;
+        }
+        let shift = self.window.is_key_down(Key::LeftShift) || self.window.is_key_down(Key::RightShift);
+        for key in self.window.get_keys_pressed(KeyRepeat::No).unwrap_or_default() {
+            if self.edit_key(key, paused) || self.inspect_key(key) {
+                continue;
+            }
+            let index = match key {
+                Key::Key1 => 0,
+                Key::Key2 => 1,
+                Key::Key3 => 2,
+                Key::Key4 => 3,
+                Key::Key5 => 4,
+                Key::Key6 => 5,
+                Key::Key7 => 6,
+                Key::Key8 => 7,
+                Key::Key9 => 8,
+                Key::Key0 => 9,
+                _ => continue,
+            };
+            self.docks[index] = match (self.docks[index], shift) {
+                (Some(Dock::Left), true) => Some(Dock::Right),
+                (Some(Dock::Right), true) => Some(Dock::Left),
+                (None, _) => Some(Dock::Left),
+                (Some(_), false) => None,
+            };
+        }
+
+        let mouse_down = self.window.get_mouse_down(MouseButton::Left);
+        if mouse_down && !self.mouse_was_down {
+            if let Some((x, y)) = self.window.get_mouse_pos(MouseMode::Discard) {
+                let (x, y) = (x as usize, y as usize);
+                let line = self.instructions.iter().find(|((left, top), _)| {
+                    (*left..left + COLUMN_WIDTH).contains(&x) && (*top..top + LINE_HEIGHT).contains(&y)
+                });
+                self.clicked = line.map(|(_, address)| *address).or(self.clicked);
+                let byte = self.bytes.iter().find(|((left, top), _)| {
+                    (*left..left + 2 * CHAR_WIDTH).contains(&x) && (*top..top + LINE_HEIGHT).contains(&y)
+                });
+                self.selected = byte.map(|(_, address)| (*address, None));
+            }
+        }
+        self.mouse_was_down = mouse_down;
+
+        self.buffer.iter_mut().for_each(|pixel| *pixel = BACKGROUND);
+        self.instructions.clear();
+        self.bytes.clear();
+        for (panel, (left, top), lines) in layout(&self.docks) {
+            draw_title(&mut self.buffer, (left, top), panel);
+            let top = top + LINE_HEIGHT + 1;
+            match panel {
+                Panel::Disassembly => self.draw_disassembly(chip8, symbols, debugger, (left, top), lines),
+                Panel::Registers => draw_lines(&mut self.buffer, (left, top), &registers(chip8)),
+                Panel::Stack => {
+                    for (row, (line, color)) in stack(chip8, symbols).iter().enumerate() {
+                        let fits: String = line.chars().take((COLUMN_WIDTH - 2) / CHAR_WIDTH).collect();
+                        text::draw(&mut self.buffer, WIDTH, (left + 1, top + row * LINE_HEIGHT), &fits, *color);
+                    }
+                }
+                Panel::Screen if self.inspecting => self.draw_inspector((left, top), debugger, paused),
+                Panel::Screen => draw_screen(&mut self.buffer, (left, top), &chip8.display()),
+                Panel::Watch => draw_watches(&mut self.buffer, (left, top), debugger, lines),
+                Panel::Memory => draw_heatmap(&mut self.buffer, (left, top), debugger),
+                Panel::Hex => self.draw_hex(chip8, symbols, (left, top)),
+                Panel::Sprite => draw_sprite(&mut self.buffer, (left, top), chip8),
+                Panel::Keypad => draw_keypads(&mut self.buffer, (left, top), chip8),
+                Panel::Graph => draw_graphs(&mut self.buffer, (left, top), debugger),
+            }
+        }
+        self.window.update_with_buffer(&self.buffer, WIDTH, HEIGHT).unwrap();
+        true
+    }
+
+    /// Address of the instruction clicked in the disassembly since the last call
+    pub fn take_clicked(&mut self) -> Option<u16> {
+        self.clicked.take()
+    }
+
+    /// Byte typed in the hex panel since the last call, to write to memory
+    pub fn take_edit(&mut self) -> Option<(u16, u8)> {
+        self.edit.take()
+    }
+
+    /// Scrolls the hex panel and types into the selected byte, returning whether the key was
+    /// taken
+    fn edit_key(&mut self, key: Key, paused: bool) -> bool {
+        let hex_shown = self.docks[6].is_some();
+        let scroll = match key {
+            Key::Up => -(HEX_ROW as i32),
+            Key::Down => HEX_ROW as i32,
+            Key::PageUp => -((HEX_ROW * HEX_ROWS) as i32),
+            Key::PageDown => (HEX_ROW * HEX_ROWS) as i32,
+            _ => 0,
+        };
+        if hex_shown && scroll != 0 {
+            let last = (4096 - HEX_ROW * HEX_ROWS) as i32;
+            self.hex_start = (self.hex_start as i32 + scroll).clamp(0, last) as u16;
+            return true;
+        }
+        let (address, first) = match self.selected {
+            Some(selected) => selected,
+            None => return false,
+        };
+        if key == Key::Escape {
+            self.selected = None;
+            return true;
+        }
+        let digit = match hex_digit(key) {
+            Some(digit) if paused => digit,
+            // Digits still don't toggle panels while a byte is selected
+            Some(_) => return true,
+            None => return false,
+        };
+        self.selected = match first {
+            None => Some((address, Some(digit))),
+            Some(high) => {
+                self.edit = Some((address, high << 4 | digit));
+                Some(((address + 1) % 4096, None))
+            }
+        };
+        true
+    }
+
+    /// Switches between the screen and the draw inspector and steps through the draws, returning
+    /// whether the key was taken
+    fn inspect_key(&mut self, key: Key) -> bool {
+        match key {
+            Key::Tab => self.inspecting = !self.inspecting,
+            Key::Left if self.inspecting => self.draw = self.draw.saturating_sub(1),
+            Key::Right if self.inspecting => self.draw += 1,
+            _ => return false,
+        }
+        true
+    }
+
+    fn draw_inspector(&mut self, (left, top): (usize, usize), debugger: Option<&Debugger>, paused: bool) {
+        let draws = match debugger {
+            Some(_) if !paused => return draw_lines(&mut self.buffer, (left, top), &[String::from("PAUSE TO INSPECT DRAWS")]),
+            Some(debugger) => debugger.draws().draws(),
+            None => return draw_lines(&mut self.buffer, (left, top), &[String::from("RUN WITH --DEBUG")]),
+        };
+        if draws.is_empty() {
+            return draw_lines(&mut self.buffer, (left, top), &[String::from("NO DRAWS THIS FRAME")]);
+        }
+        self.draw = self.draw.min(draws.len() - 1);
+        let draw = &draws[self.draw];
+        let lines = [format!("DRAW {}/{} AT {:03X}", self.draw + 1, draws.len(), draw.address), draw.summary()];
+        draw_lines(&mut self.buffer, (left, top), &lines);
+        let top = top + 2 * LINE_HEIGHT;
+        draw_text(&mut self.buffer, (left + 1, top), "BEFORE");
+        draw_text(&mut self.buffer, (left + run::WIDTH + 1, top), "AFTER");
+        draw_change(&mut self.buffer, (left, top + LINE_HEIGHT), draw);
+    }
+
+    fn draw_hex(&mut self, chip8: &Chip8, symbols: &Symbols, (left, top): (usize, usize)) {
+        let pc = chip8.program_counter();
+        let calls = chip8.call_stack();
+        for row in 0..HEX_ROWS {
+            let start = self.hex_start as usize + row * HEX_ROW;
+            let y = top + row * LINE_HEIGHT;
+            draw_text(&mut self.buffer, (left + 1, y), &format!("{:03X}", start));
+            for column in 0..HEX_ROW {
+                let address = (start + column) as u16;
+                let x = left + 1 + (4 + 3 * column) * CHAR_WIDTH;
+                let background = match self.selected {
+                    Some((selected, _)) if selected == address => Some(SELECTED),
+                    _ if address == pc || address == pc + 1 => Some(CURRENT),
+                    _ if address == chip8.index() => Some(INDEX),
+                    _ if calls.iter().any(|call| address == *call || address == call + 1) => Some(CALL),
+                    _ if symbols.note_at(address).is_some() => Some(NOTE),
+                    _ => None,
+                };
+                if let Some(color) = background {
+                    fill(&mut self.buffer, (x - 1, y), 2 * CHAR_WIDTH + 1, LINE_HEIGHT, color);
+                }
+                let text = match self.selected {
+                    Some((selected, Some(high))) if selected == address => format!("{:X}_", high),
+                    _ => format!("{:02X}", chip8.memory()[address as usize]),
+                };
+                let color = if chip8.font().contains(&(address as usize)) { FONT } else { TEXT };
+                text::draw(&mut self.buffer, WIDTH, (x, y), &text, color);
+                self.bytes.push(((x, y), address));
+            }
+        }
+        let noted = self.selected.map_or(chip8.index(), |(address, _)| address);
+        if let Some(note) = symbols.note_at(noted) {
+            let line = format!("{} {} {}", note.range(), note.name, note.comment);
+            draw_text(&mut self.buffer, (left + 1, top + HEX_ROWS * LINE_HEIGHT), &line);
+        }
+    }
+
+    fn draw_disassembly(&mut self, chip8: &Chip8, symbols: &Symbols, debugger: Option<&Debugger>, (left, top): (usize, usize), lines: usize) {
+        let pc = chip8.program_counter();
+        // Labels take lines too, so leave some room at the bottom
+        let shown = (lines as u16).saturating_sub(lines as u16 / 4) * 2;
+        if pc < self.disassembly_start || pc >= self.disassembly_start + shown {
+            self.disassembly_start = pc.saturating_sub(4);
+        }
+
+        let memory = chip8.memory();
+        let mut listing = Vec::new();
+        let mut address = self.disassembly_start as usize;
+        while listing.len() < lines && address < memory.len() {
+            let line = disasm::line_at(memory, address as u16, chip8.variant());
+            address += line.bytes.len();
+            listing.push(line);
+        }
+        disasm::apply_symbols(&mut listing, symbols);
+
+        let mut row = 0;
+        for line in &listing {
+            if let Some(label) = &line.label {
+                if row < lines {
+                    draw_text(&mut self.buffer, (left, top + row * LINE_HEIGHT), &format!("{}:", label));
+                }
+                row += 1;
+            }
+            if let Some(comment) = &line.comment {
+                if row < lines {
+                    let fits: String = format!("; {}", comment).chars().take((COLUMN_WIDTH - 2) / CHAR_WIDTH).collect();
+                    text::draw(&mut self.buffer, WIDTH, (left, top + row * LINE_HEIGHT), &fits, UNUSED);
+                }
+                row += 1;
+            }
+            if row >= lines {
+                break;
+            }
+            let y = top + row * LINE_HEIGHT;
+            if line.address == pc {
+                fill(&mut self.buffer, (left, y), COLUMN_WIDTH - 1, LINE_HEIGHT, CURRENT);
+            }
+            if debugger.is_some_and(|debugger| debugger.breakpoints().contains(&line.address)) {
+                fill(&mut self.buffer, (left, y), CHAR_WIDTH - 1, LINE_HEIGHT - 1, BREAKPOINT);
+            }
+            self.instructions.push(((left, y), line.address));
+            let code = match &line.instruction {
+                Some(instruction) => instruction.to_string(),
+                None => format!("DB {:#04X}", line.bytes[0]),
+            };
+            draw_text(&mut self.buffer, (left, y), &format!(" {:03X} {}", line.address, code));
+            row += 1;
+        }
+    }
+}
+
+/// Where each shown panel goes, with the number of lines it has room for. Panels are stacked in
+/// their column, and the disassembly gets whatever room the others leave.
+fn layout(docks: &[Option<Dock>; 10]) -> Vec<(Panel, (usize, usize), usize)> {
+    let mut placed = Vec::new();
+    for (column, dock) in [Dock::Left, Dock::Right].iter().enumerate() {
+        let panels: Vec<Panel> = PANELS.iter().zip(docks.iter()).filter(|(_, at)| **at == Some(*dock)).map(|(panel, _)| *panel).collect();
+        let fixed: usize = panels.iter().filter_map(|panel| fixed_lines(*panel)).map(|lines| (lines + 1) * LINE_HEIGHT + 2).sum();
+        let mut top = 0;
+        for panel in panels {
+            let lines = fixed_lines(panel).unwrap_or_else(|| HEIGHT.saturating_sub(fixed + LINE_HEIGHT + 2) / LINE_HEIGHT);
+            placed.push((panel, (column * COLUMN_WIDTH, top), lines));
+            top += (lines + 1) * LINE_HEIGHT + 2;
+        }
+    }
+    placed
+}
+
+/// Lines a panel always takes, `None` for the disassembly which takes the rest
+fn fixed_lines(panel: Panel) -> Option<usize> {
+    match panel {
+        Panel::Disassembly => None,
+        Panel::Registers => Some(6),
+        Panel::Stack => Some(8),
+        Panel::Screen => Some((run::HEIGHT * SCREEN_SCALE).div_ceil(LINE_HEIGHT)),
+        Panel::Watch => Some(6),
+        Panel::Memory => Some((heatmap::SIZE * HEATMAP_SCALE).div_ceil(LINE_HEIGHT)),
+        Panel::Hex => Some(HEX_ROWS + 1),
+        Panel::Sprite => Some(SPRITE_ROWS + 1),
+        Panel::Keypad => Some(7),
+        Panel::Graph => Some(GRAPHS * (1 + PLOT_HEIGHT / LINE_HEIGHT)),
+    }
+}
+
+fn draw_title(buffer: &mut [u32], (left, top): (usize, usize), panel: Panel) {
+    fill(buffer, (left, top), COLUMN_WIDTH - 1, LINE_HEIGHT + 1, TITLE_BACKGROUND);
+    let title = match panel {
+        Panel::Disassembly => "1 DISASSEMBLY",
+        Panel::Registers => "2 REGISTERS",
+        Panel::Stack => "3 STACK",
+        Panel::Screen => "4 SCREEN",
+        Panel::Watch => "5 WATCH",
+        Panel::Memory => "6 MEMORY",
+        Panel::Hex => "7 HEX",
+        Panel::Sprite => "8 SPRITE",
+        Panel::Keypad => "9 KEYPAD",
+        Panel::Graph => "0 GRAPH",
+    };
+    text::draw(buffer, WIDTH, (left + 1, top + 1), title, TITLE);
+}
+
+fn draw_lines(buffer: &mut [u32], (left, top): (usize, usize), lines: &[String]) {
+    for (row, line) in lines.iter().enumerate() {
+        draw_text(buffer, (left + 1, top + row * LINE_HEIGHT), line);
+    }
+}
+
+fn draw_watches(buffer: &mut [u32], (left, top): (usize, usize), debugger: Option<&Debugger>, lines: usize) {
+    let watches = debugger.map(Debugger::watches).filter(|watches| !watches.is_empty());
+    let watches = match watches {
+        Some(watches) => watches.lines(),
+        None => return draw_lines(buffer, (left, top), &[String::from("ADD WITH --WATCH OR DISPLAY")]),
+    };
+    for (row, (line, changed)) in watches.iter().take(lines).enumerate() {
+        let fits: String = line.chars().take((COLUMN_WIDTH - 2) / CHAR_WIDTH).collect();
+        text::draw(buffer, WIDTH, (left + 1, top + row * LINE_HEIGHT), &fits, if *changed { CHANGED } else { TEXT });
+    }
+}
+
+/// Each graph as a line with its expression, latest value and range, then its values as a plot, the latest on
+/// the right
+fn draw_graphs(buffer: &mut [u32], (left, top): (usize, usize), debugger: Option<&Debugger>) {
+    let graphs = debugger.map(|debugger| debugger.graphs().graphs()).filter(|graphs| !graphs.is_empty());
+    let graphs = match graphs {
+        Some(graphs) => graphs,
+        None => return draw_lines(buffer, (left, top), &[String::from("ADD WITH --GRAPH OR GRAPH")]),
+    };
+    for (number, graph) in graphs.iter().take(GRAPHS).enumerate() {
+        let top = top + number * (LINE_HEIGHT + PLOT_HEIGHT);
+        let (low, high) = graph.range();
+        let latest = graph.values().back().copied().unwrap_or(0);
+        let label = format!("{} = {} ({}-{})", graph.expression(), latest, low, high).to_uppercase();
+        let fits: String = label.chars().take((COLUMN_WIDTH - 2) / CHAR_WIDTH).collect();
+        text::draw(buffer, WIDTH, (left + 1, top), &fits, TEXT);
+        draw_plot(buffer, (left + (COLUMN_WIDTH - graphs::FRAMES) / 2, top + LINE_HEIGHT), graph);
+    }
+}
+
+fn draw_plot(buffer: &mut [u32], (left, top): (usize, usize), graph: &Graph) {
+    fill(buffer, (left, top), graphs::FRAMES, PLOT_HEIGHT - 1, PIXEL_OFF);
+    let heights = graph.heights(PLOT_HEIGHT - 1);
+    let start = graphs::FRAMES - heights.len();
+    for (x, height) in heights.iter().enumerate() {
+        let (x, y) = (left + start + x, top + PLOT_HEIGHT - 2 - height);
+        if y < HEIGHT && x < WIDTH {
+            buffer[y * WIDTH + x] = PLOT;
+        }
+    }
+}
+
+fn draw_heatmap(buffer: &mut [u32], (left, top): (usize, usize), debugger: Option<&Debugger>) {
+    let debugger = match debugger {
+        Some(debugger) => debugger,
+        None => return draw_lines(buffer, (left, top), &[String::from("RUN WITH --DEBUG")]),
+    };
+    for (address, color) in debugger.heatmap().pixels().iter().enumerate() {
+        let (x, y) = (address % heatmap::SIZE, address / heatmap::SIZE);
+        fill(buffer, (left + x * HEATMAP_SCALE, top + y * HEATMAP_SCALE), HEATMAP_SCALE, HEATMAP_SCALE, *color);
+    }
+}
+
+/// The bytes at I as a sprite with the bytes next to it, the rows the DXYN at the program
+/// counter doesn't draw dimmed
+fn draw_sprite(buffer: &mut [u32], (left, top): (usize, usize), chip8: &Chip8) {
+    let rows = sprite_rows(chip8);
+    let summary = match rows {
+        Some(rows) => format!("I {:03X}, DRAWS {} ROWS", chip8.index(), rows),
+        None => format!("I {:03X}, NOT DRAWING", chip8.index()),
+    };
+    draw_text(buffer, (left + 1, top), &summary);
+    let memory = chip8.memory();
+    for row in 0..SPRITE_ROWS {
+        let address = (chip8.index() as usize + row) % memory.len();
+        let byte = memory[address];
+        let y = top + (row + 1) * LINE_HEIGHT;
+        let drawn = rows.is_some_and(|rows| row < rows);
+        for bit in 0..8 {
+            let color = match (byte & (0x80 >> bit) != 0, drawn) {
+                (true, true) => PIXEL_ON,
+                (true, false) => PIXEL_UNUSED,
+                (false, _) => PIXEL_OFF,
+            };
+            fill(buffer, (left + 1 + bit * SPRITE_SCALE, y), SPRITE_SCALE, SPRITE_SCALE, color);
+        }
+        draw_text(buffer, (left + 3 + 8 * SPRITE_SCALE, y), &format!("{:03X} {:02X} {:08b}", address, byte, byte));
+    }
+}
+
+/// Rows the instruction at the program counter draws, if it is a DXYN
+fn sprite_rows(chip8: &Chip8) -> Option<usize> {
+    let pc = chip8.program_counter() as usize;
+    let opcode = chip8.memory().get(pc..pc + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))?;
+    match Instruction::decode(opcode, chip8.variant()) {
+        Some(Instruction::Draw(_, _, rows)) => Some(rows as usize),
+        _ => None,
+    }
+}
+
+/// Both keypads laid out like the COSMAC VIP's with the keys held lit up, the first keypad's
+/// state as a row of bits from key 0 to F, and the key tested at the program counter
+fn draw_keypads(buffer: &mut [u32], (left, top): (usize, usize), chip8: &Chip8) {
+    draw_text(buffer, (left + 1, top), "KEYPAD");
+    draw_text(buffer, (left + 1 + SECOND_KEYPAD * CHAR_WIDTH, top), "SECOND");
+    for (column, keys) in [(0, chip8.keypad()), (SECOND_KEYPAD, chip8.second_keypad())] {
+        for (cell, key) in GRID.iter().enumerate() {
+            let x = left + 1 + (column + cell % 4 * 2) * CHAR_WIDTH;
+            let y = top + (1 + cell / 4) * LINE_HEIGHT;
+            if keys[*key] {
+                fill(buffer, (x - 1, y), CHAR_WIDTH + 1, LINE_HEIGHT, KEY_HELD);
+            }
+            draw_text(buffer, (x, y), &format!("{:X}", key));
+        }
+    }
+    draw_text(buffer, (left + 1, top + 5 * LINE_HEIGHT), &format!("HELD {}", held_bits(chip8.keypad())));
+    if let Some(test) = key_test(chip8) {
+        draw_text(buffer, (left + 1, top + 6 * LINE_HEIGHT), &test);
+    }
+}
+
+/// A keypad as a 1 for each key held, from key 0 to F
+fn held_bits(keys: [bool; 16]) -> String {
+    keys.iter().map(|held| if *held { '1' } else { '0' }).collect()
+}
+
+/// The key the instruction at the program counter tests, and whether the machine sees it held
+fn key_test(chip8: &Chip8) -> Option<String> {
+    let pc = chip8.program_counter() as usize;
+    let opcode = chip8.memory().get(pc..pc + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))?;
+    let (register, keys, keypad) = match Instruction::decode(opcode, chip8.variant())? {
+        Instruction::SkipIfKey(x) | Instruction::SkipIfNotKey(x) => (x, chip8.keypad(), "KEY"),
+        Instruction::SkipIfSecondKey(x) | Instruction::SkipIfNotSecondKey(x) => (x, chip8.second_keypad(), "SECOND KEY"),
+        _ => return None,
+    };
+    let key = chip8.registers()[register as usize] as usize & 0xF;
+    Some(format!("TESTS {} {:X} (V{:X}): {}", keypad, key, register, if keys[key] { "HELD" } else { "UP" }))
+}
+
+fn hex_digit(key: Key) -> Option<u8> {
+    const DIGITS: [Key; 16] = [
+        Key::Key0, Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5, Key::Key6, Key::Key7,
+        Key::Key8, Key::Key9, Key::A, Key::B, Key::C, Key::D, Key::E, Key::F,
+    ];
+    DIGITS.iter().position(|digit| *digit == key).map(|digit| digit as u8)
+}
+
+/// Draws text cut off at the edge of its column
+fn draw_text(buffer: &mut [u32], (left, top): (usize, usize), line: &str) {
+    let fits: String = line.chars().take((COLUMN_WIDTH - 2) / CHAR_WIDTH).collect();
+    text::draw(buffer, WIDTH, (left, top), &fits, TEXT);
+}
+
+fn draw_screen(buffer: &mut [u32], (left, top): (usize, usize), display: &[u8]) {
+    for (index, pixel) in display.iter().enumerate() {
+        let (x, y) = (index % run::WIDTH, index / run::WIDTH);
+        let color = if *pixel != 0 { PIXEL_ON } else { PIXEL_OFF };
+        fill(buffer, (left + x * SCREEN_SCALE, top + y * SCREEN_SCALE), SCREEN_SCALE, SCREEN_SCALE, color);
+    }
+}
+
+/// The screen before a draw next to the screen after it, with the pixels the draw turned on and
+/// off highlighted
+fn draw_change(buffer: &mut [u32], (left, top): (usize, usize), draw: &Draw) {
+    for (index, (before, after)) in draw.before.iter().zip(&draw.after).enumerate() {
+        let (x, y) = (index % run::WIDTH, index / run::WIDTH);
+        fill(buffer, (left + x, top + y), 1, 1, if *before != 0 { PIXEL_ON } else { PIXEL_OFF });
+        let color = match (*before != 0, *after != 0) {
+            (false, true) => DRAWN,
+            (true, false) => ERASED,
+            (_, true) => PIXEL_ON,
+            (_, false) => PIXEL_OFF,
+        };
+        fill(buffer, (left + run::WIDTH + x, top + y), 1, 1, color);
+    }
+}
+
+/// Fills a rectangle, clipped to the window
+fn fill(buffer: &mut [u32], (left, top): (usize, usize), width: usize, height: usize, color: u32) {
+    for y in top..(top + height).min(HEIGHT) {
+        for x in left..(left + width).min(WIDTH) {
+            buffer[y * WIDTH + x] = color;
+        }
+    }
+}
+
+fn registers(chip8: &Chip8) -> Vec<String> {
+    let registers = chip8.registers();
+    let mut lines: Vec<String> = registers
+        .chunks(4)
+        .enumerate()
+        .map(|(row, values)| {
+            let cells: Vec<String> = values.iter().enumerate().map(|(column, value)| format!("V{:X} {:02X}", row * 4 + column, value)).collect();
+            cells.join("  ")
+        })
+        .collect();
+    let (delay, sound) = chip8.timers();
+    lines.push(format!("I {:03X}  PC {:03X}  SP {}", chip8.index(), chip8.program_counter(), chip8.call_stack().len()));
+    lines.push(format!("DT {:02X}  ST {:02X}", delay, sound));
+    lines
+}
+
+/// The stack pointer, then the slots of the calls being run, innermost first, with the return
+/// address and the subroutine each one called, then the slots not in use, with their colors
+fn stack(chip8: &Chip8, symbols: &Symbols) -> Vec<(String, u32)> {
+    let memory = chip8.memory();
+    let slots = chip8.stack_slots();
+    let depth = chip8.call_stack().len();
+    let opcode = |address: usize| memory.get(address..address + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]));
+    let next = opcode(chip8.program_counter() as usize).and_then(|opcode| Instruction::decode(opcode, chip8.variant()));
+    let mut lines = vec![match next {
+        Some(Instruction::Call(_)) if depth == chip8.stack_depth() => (format!("SP {}/{} CALL OVERFLOWS", depth, chip8.stack_depth()), WARNING),
+        Some(Instruction::Return) if depth == 0 => (format!("SP {}/{} RET UNDERFLOWS", depth, chip8.stack_depth()), WARNING),
+        _ => (format!("SP {}/{}", depth, chip8.stack_depth()), TEXT),
+    }];
+    for slot in (1..=depth).rev() {
+        let call = slots[slot];
+        let target = opcode(call as usize).map_or(0, |opcode| opcode & 0x0FFF);
+        let name = symbols.label_at(target).map_or_else(|| format!("CALL {:03X}", target), str::to_string);
+        lines.push((format!("{:X} {:03X} RET {:03X} {}", slot, call, call + 2, name), TEXT));
+    }
+    for (slot, value) in slots.iter().enumerate().skip(depth + 1) {
+        lines.push((format!("{:X} {:03X} FREE", slot, value), UNUSED));
+    }
+    lines.truncate(fixed_lines(Panel::Stack).unwrap());
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Chip8;
+    use crate::debug_view::{
+        held_bits, hex_digit, key_test, layout, registers, sprite_rows, stack, Dock, Panel, HEIGHT, TEXT, UNUSED, WARNING,
+    };
+    use crate::symbols::Symbols;
+    use crate::text::LINE_HEIGHT;
+    use minifb::Key;
+
+    /// Panels stack in their column with the disassembly filling the rest. Registers show as text,
+    /// hex digits are typed with their keys, the stack panel sees returns coming with the stack
+    /// empty, the sprite panel knows the rows DXYN draws and the keypad panel the key tested.
+    #[test]
+    fn test_panels() {
+        let docks = [Some(Dock::Left), Some(Dock::Right), None, Some(Dock::Left), None, None, None, None, None, None];
+        let placed = layout(&docks);
+        assert_eq!(placed.iter().map(|(panel, ..)| *panel).collect::<Vec<_>>(), vec![Panel::Disassembly, Panel::Screen, Panel::Registers]);
+        let (_, (_, screen_top), screen_lines) = placed[1];
+        assert!(screen_top + (screen_lines + 1) * LINE_HEIGHT + 2 <= HEIGHT);
+        assert_eq!(placed[2].1, (128, 0));
+
+        let lines = registers(&Chip8::new());
+        assert_eq!(lines[0], "V0 00  V1 00  V2 00  V3 00");
+        assert_eq!(lines[4], "I 000  PC 200  SP 0");
+
+        assert_eq!(hex_digit(Key::Key7), Some(7));
+        assert_eq!(hex_digit(Key::C), Some(0xC));
+        assert_eq!(hex_digit(Key::G), None);
+
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&[0x22, 0x04, 0x00, 0x00, 0x00, 0xEE]);
+        assert_eq!(stack(&chip8, &Symbols::default())[..2], [(String::from("SP 0/15"), TEXT), (String::from("1 000 FREE"), UNUSED)]);
+        chip8.emulate_cycle();
+        assert_eq!(stack(&chip8, &Symbols::default())[1], (String::from("1 200 RET 202 CALL 204"), TEXT));
+        chip8.emulate_cycle();
+        assert_eq!(stack(&chip8, &Symbols::default())[0], (String::from("SP 0/15"), TEXT));
+        chip8.write_memory(0x202, &[0x00, 0xEE]);
+        assert_eq!(stack(&chip8, &Symbols::default())[0], (String::from("SP 0/15 RET UNDERFLOWS"), WARNING));
+
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&[0xE3, 0x9E]);
+        assert_eq!(key_test(&chip8), Some(String::from("TESTS KEY 0 (V3): UP")));
+        chip8.set_key(0x5, true);
+        chip8.set_register(3, 0x5);
+        assert_eq!(held_bits(chip8.keypad()), "0000010000000000");
+        assert_eq!(key_test(&chip8), Some(String::from("TESTS KEY 5 (V3): HELD")));
+
+        let mut chip8 = Chip8::new();
+        assert_eq!(sprite_rows(&chip8), None);
+        chip8.load_program(&[0xD0, 0x15]);
+        assert_eq!(sprite_rows(&chip8), Some(5));
+    }
+}
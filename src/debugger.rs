@@ -0,0 +1,1203 @@
+//! Debugger prompt on standard input, working on the machine while its window keeps running.
+//!
+//! Addresses can be written in hex (`0x2F0`), in decimal, or as labels when the ROM was
+//! assembled from source. Watchpoints also take ranges, like `0xE00-0xE0F`, and catch the memory
+//! accessed through I: sprites read by DXYN, FX33 and FX55 writing, FX65 reading.
+//!
+//! The machine also stops at opcodes it doesn't know, instead of the emulator exiting. When
+//! tracing, the last few instructions run are shown whenever it stops.
+//!
+//! GDB can drive the debugger too, see `gdb`, and so can debug UIs over WebSocket, see `remote`.
+//!
+//! Writes by FX33 and FX55 to memory that already ran as instructions are logged once for each
+//! instruction doing it, as self-modifying code often trips up emulators. `selfmod on` stops the
+//! machine before each of them too.
+//!
+//! With `--warnings`, anything else the program does that's likely a mistake is logged once for
+//! each instruction doing it too, see `diagnostics`.
+//!
+//! With `--taint`, reads of memory and registers nothing wrote yet are logged the same way, see
+//! `taint`, and `taint on` stops the machine before them.
+//!
+//! | Command                            |                                                              |
+//! |------------------------------------|--------------------------------------------------------------|
+//! | `break [address]`, `b`             | stop before the instruction at an address, or list           |
+//! | `break address when condition`     | stop there only when an `expression` holds                   |
+//! | `delete address`                   | remove a breakpoint                                          |
+//! | `watch address`                    | stop before an instruction writes to memory                  |
+//! | `rwatch address`, `awatch address` | same for reads, or for both                                  |
+//! | `unwatch address`                  | remove a watchpoint                                          |
+//! | `display [expression]`             | watch an `expression` in the debugger window, or list        |
+//! | `undisplay number`                 | remove a watch expression                                    |
+//! | `graph [expression]`               | plot an `expression` over the last frames, or list           |
+//! | `ungraph number`                   | remove a graph                                               |
+//! | `step [count]`, `s`                | run one instruction, or `count`, and stop                    |
+//! | `next`, `n`                        | step, running a whole subroutine call as one instruction     |
+//! | `finish`, `out`                    | run until the current subroutine returns                     |
+//! | `rstep [count]`, `rs`              | go back one instruction, or `count`                          |
+//! | `rframe`, `rf`                     | go back to the start of the frame before                     |
+//! | `profile [count]`                  | show the most run addresses, 10 by default, and opcodes      |
+//! | `loops`                            | show the short loops the program ran, and what they wait for |
+//! | `calls [path]`                     | show the subroutines called, or write the call graph as DOT  |
+//! | `coverage [path]`                  | show how much of the program ran, or write the coverage map  |
+//! | `note address name [comment]`      | name an address or range, with a comment, see `notes`        |
+//! | `unnote address`                   | remove the note starting at an address                       |
+//! | `snapshot [path]`                  | keep the machine as it is to compare with, or write it       |
+//! | `diff [path]`                      | show what changed since the snapshot, or since a file's      |
+//! | `selfmod [on/off]`                 | list the writes to code, or stop before them or not          |
+//! | `warnings`                         | list what the program did that's likely a mistake            |
+//! | `taint [on/off]`                   | list the reads of uninitialized state, or stop before them   |
+//! | `draws`                            | list the sprites drawn so far this frame, and where          |
+//! | `trace [count]`                    | show the last instructions run, with `--trace`               |
+//! | `continue`, `c`                    | run until the next breakpoint                                |
+//! | `regs`, `r`                        | show V0 to VF, I, the program counter and timers             |
+//! | `mem address [length]`, `m`        | show memory, 64 bytes if no length is given                  |
+//! | `stack`, `bt`                      | show the subroutine calls being run                          |
+//! | `disasm [address] [count]`, `d`    | list instructions, from the program counter by default       |
+//! | `quit`, `q`                        | close the emulator                                           |
+
+use crate::callgraph::CallGraph;
+use crate::chip8::{Access, Chip8, Instruction};
+use crate::coverage::Coverage;
+use crate::diagnostics::Diagnostics;
+use crate::dev::MemoryRange;
+use crate::disasm;
+use crate::draws::Draws;
+use crate::expression::Expression;
+use crate::graphs::Graphs;
+use crate::gdb::{self, Event, GdbServer, Response, STOPPED_BY_INTERRUPT, STOPPED_BY_TRAP};
+use crate::heatmap::Heatmap;
+use crate::history::History;
+use crate::hotspots::Hotspots;
+use crate::loops::{Kind, Loops};
+use crate::notes::{self, Note};
+use crate::remote::{self, RemoteServer};
+use crate::snapshot::{self, Snapshot};
+use crate::symbols::Symbols;
+use crate::taint::Taint;
+use crate::trace::{JsonTrace, Trace};
+use crate::watches::Watches;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use tracing::{debug, info, warn};
+
+const PROMPT: &str = "(chip8) ";
+/// Instructions of the trace shown when the machine stops
+const STOP_TRACE: usize = 8;
+/// Names of the commands, for completion
+pub const COMMANDS: &[&str] = &[
+    "break", "delete", "watch", "rwatch", "awatch", "unwatch", "display", "undisplay", "graph", "ungraph", "step", "next", "finish", "rstep", "rframe",
+    "profile", "loops", "calls", "coverage", "note", "unnote", "snapshot", "diff", "selfmod", "warnings", "taint", "draws", "trace", "continue", "regs", "mem", "stack", "disasm", "quit", "help",
+];
+
+/// Memory that stops the machine when an instruction accesses it
+struct Watchpoint {
+    range: MemoryRange,
+    /// `None` for both reads and writes
+    access: Option<Access>,
+}
+
+pub struct Debugger {
+    /// Lines typed at the prompt
+    commands: Receiver<String>,
+    symbols: Symbols,
+    breakpoints: BTreeSet<u16>,
+    /// Conditions of the breakpoints that only stop the machine when they hold
+    conditions: HashMap<u16, Expression>,
+    watchpoints: Vec<Watchpoint>,
+    /// Expressions shown in the debugger window
+    watches: Watches,
+    graphs: Graphs,
+    /// Sprites drawn this frame
+    draws: Draws,
+    /// Recent past of the machine, to step backwards
+    history: History,
+    /// Instructions run by address and opcode
+    hotspots: Hotspots,
+    loops: Loops,
+    calls: CallGraph,
+    coverage: Coverage,
+    /// Instructions that wrote to memory which had run as code, and the last memory each wrote
+    code_writes: BTreeMap<u16, (u16, u16)>,
+    /// Whether to stop before writes to code
+    break_on_code_writes: bool,
+    /// Suspicious things the program did, with `--warnings`
+    diagnostics: Option<Diagnostics>,
+    /// Memory and registers written so far, with `--taint`
+    taint: Option<Taint>,
+    /// Whether to stop before reads of uninitialized state
+    break_on_taint: bool,
+    /// Memory accesses by address, for the debugger window
+    heatmap: Heatmap,
+    /// Size of the program loaded, which the coverage is measured against
+    program_length: Option<usize>,
+    /// File the notes of the ROM are saved to when changed
+    notes_path: Option<PathBuf>,
+    /// Machine to compare with, from the last `snapshot`
+    snapshot: Option<Snapshot>,
+    /// Last instructions run, when tracing
+    trace: Option<Trace>,
+    /// File every instruction run is written to
+    json_trace: Option<JsonTrace>,
+    /// Server for GDB to drive the debugger through
+    gdb: Option<GdbServer>,
+    /// Whether GDB is waiting to hear that the machine stopped
+    gdb_waiting: bool,
+    /// Server for debug UIs to connect to over WebSocket
+    remote: Option<RemoteServer>,
+    /// Screen last sent to the debug UIs
+    remote_frame: Vec<u8>,
+    paused: bool,
+    /// Breakpoint being continued from, which mustn't stop the machine again right away
+    resume_from: Option<u16>,
+    /// Call depth to stop at when stepping over or out of a subroutine
+    finish_depth: Option<usize>,
+    quit: bool,
+}
+
+impl Debugger {
+    /// Starts reading commands from standard input. The symbols give labels to addresses.
+    pub fn new(symbols: Symbols) -> Self {
+        let (sender, commands) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        println!("Debugger ready, type help for the commands");
+        prompt();
+        Debugger::with_commands(commands, symbols)
+    }
+
+    fn with_commands(commands: Receiver<String>, symbols: Symbols) -> Self {
+        Debugger {
+            commands,
+            symbols,
+            breakpoints: BTreeSet::new(),
+            conditions: HashMap::new(),
+            watchpoints: Vec::new(),
+            watches: Watches::default(),
+            graphs: Graphs::default(),
+            draws: Draws::default(),
+            history: History::default(),
+            hotspots: Hotspots::default(),
+            loops: Loops::default(),
+            calls: CallGraph::default(),
+            coverage: Coverage::default(),
+            code_writes: BTreeMap::new(),
+            break_on_code_writes: false,
+            diagnostics: None,
+            taint: None,
+            break_on_taint: false,
+            heatmap: Heatmap::default(),
+            program_length: None,
+            notes_path: None,
+            snapshot: None,
+            trace: None,
+            json_trace: None,
+            gdb: None,
+            gdb_waiting: false,
+            remote: None,
+            remote_frame: Vec::new(),
+            paused: false,
+            resume_from: None,
+            finish_depth: None,
+            quit: false,
+        }
+    }
+
+    /// Labels of the program, with the names of the notes
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    /// Where the `note` command saves the notes
+    pub fn set_notes_path(&mut self, path: &Path) {
+        self.notes_path = Some(path.to_path_buf());
+    }
+
+    /// Writes the notes back to their file, if they have one
+    fn save_notes(&self) -> Result<(), String> {
+        match &self.notes_path {
+            Some(path) => notes::save(path, &self.symbols.notes),
+            None => Ok(()),
+        }
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<u16> {
+        &self.breakpoints
+    }
+
+    /// Adds a breakpoint at an address or label, returning its address. A condition can follow,
+    /// as in `0x2F0 when V3 == 0x1F`.
+    pub fn add_breakpoint(&mut self, text: &str) -> Result<u16, String> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let (location, condition) = match words.iter().position(|word| *word == "when" || *word == "if") {
+            Some(at) => (&words[..at], Some(words[at + 1..].join(" "))),
+            None => (&words[..], None),
+        };
+        let address = self.address(&location.join(" "))?;
+        match condition.map(|condition| Expression::parse(&condition, &self.symbols)).transpose()? {
+            Some(condition) => self.conditions.insert(address, condition),
+            None => self.conditions.remove(&address),
+        };
+        self.breakpoints.insert(address);
+        Ok(address)
+    }
+
+    /// Adds a breakpoint, or removes it if there already is one
+    pub fn toggle_breakpoint(&mut self, address: u16) {
+        if self.breakpoints.contains(&address) {
+            println!("\nDeleted breakpoint at {}", self.describe_breakpoint(address));
+            self.remove_breakpoint(address);
+        } else {
+            self.breakpoints.insert(address);
+            println!("\nBreakpoint at {}", self.describe(address));
+        }
+        prompt();
+    }
+
+    pub fn watches(&self) -> &Watches {
+        &self.watches
+    }
+
+    /// Adds an expression to show in the debugger window
+    pub fn add_watch(&mut self, text: &str) -> Result<(), String> {
+        self.watches.add(Expression::parse(text, &self.symbols)?);
+        Ok(())
+    }
+
+    /// Evaluates the watch expressions, once a frame
+    pub fn update_watches(&mut self, chip8: &Chip8) {
+        self.watches.update(chip8);
+    }
+
+    pub fn graphs(&self) -> &Graphs {
+        &self.graphs
+    }
+
+    pub fn draws(&self) -> &Draws {
+        &self.draws
+    }
+
+    /// Adds an expression to plot in the debugger window
+    pub fn add_graph(&mut self, text: &str) -> Result<(), String> {
+        self.graphs.add(Expression::parse(text, &self.symbols)?);
+        Ok(())
+    }
+
+    /// Keeps the last `length` instructions run, to show when the machine stops
+    pub fn start_trace(&mut self, length: usize) {
+        self.trace = Some(Trace::new(length));
+    }
+
+    /// Writes every instruction run to a file as JSON lines
+    pub fn write_trace(&mut self, path: &Path) -> Result<(), String> {
+        self.json_trace = Some(JsonTrace::create(path)?);
+        Ok(())
+    }
+
+    /// Lets GDB connect on a port of this machine and drive the debugger
+    pub fn serve_gdb(&mut self, port: u16) -> Result<(), String> {
+        self.gdb = Some(GdbServer::listen(port)?);
+        Ok(())
+    }
+
+    /// Lets debug UIs connect over WebSocket on a port of this machine
+    pub fn serve_remote(&mut self, port: u16) -> Result<(), String> {
+        self.remote = Some(RemoteServer::listen(port)?);
+        Ok(())
+    }
+
+    /// Records the machine before it runs an instruction, so the debugger can go back to it
+    pub fn record(&mut self, chip8: &Chip8) {
+        self.history.record(chip8);
+        self.hotspots.record(chip8);
+        self.calls.record(chip8);
+        if let Some((start, end)) = self.code_written(chip8) {
+            let address = chip8.program_counter();
+            if self.code_writes.insert(address, (start, end)).is_none() {
+                warn!("Self-modifying code: {} writes to {}, which ran as code", self.describe(address), describe_range(start, end));
+            }
+        }
+        if let Some(warning) = self.diagnostics.as_mut().and_then(|diagnostics| diagnostics.record(chip8)) {
+            warn!("{}: {}", self.describe(chip8.program_counter()), warning);
+        }
+        if let Some(read) = self.taint.as_mut().and_then(|taint| taint.record(chip8)) {
+            warn!("Uninitialized read: {} reads {}, which nothing wrote", self.describe(chip8.program_counter()), read);
+        }
+        self.coverage.record(chip8);
+        self.heatmap.record(chip8);
+        self.draws.record(chip8, instruction(chip8));
+        match self.loops.record(chip8) {
+            Some(Kind::Halt) => info!("Program halted at {}, it jumps to itself", self.describe(chip8.program_counter())),
+            Some(kind) => debug!("Loop ending at {} {}", self.describe(chip8.program_counter()), kind),
+            None => {}
+        }
+        if let Some(trace) = &mut self.trace {
+            trace.record(chip8);
+        }
+        if let Some(Err(error)) = self.json_trace.as_mut().map(|trace| trace.record(chip8)) {
+            warn!("Stopped writing the trace: {}", error);
+            self.json_trace = None;
+        }
+    }
+
+    /// The `count` most run addresses, the opcodes run and the loops the program spun in, most
+    /// first
+    pub fn hotspots(&self, count: usize) -> String {
+        format!("{}\n{}", self.hotspots.report(&self.symbols, count), self.loops.report(&self.symbols))
+    }
+
+    /// Writes the calls between subroutines as a DOT graph
+    pub fn save_call_graph(&self, path: &Path) -> Result<(), String> {
+        self.calls.save(&self.symbols, path)
+    }
+
+    /// Stops the machine before instructions that write to memory which ran as code
+    pub fn set_break_on_code_writes(&mut self, enabled: bool) {
+        self.break_on_code_writes = enabled;
+    }
+
+    /// Warns about suspicious things the program does from now on
+    pub fn enable_warnings(&mut self) {
+        self.diagnostics = Some(Diagnostics::default());
+    }
+
+    /// Tracks the memory and registers written from power-on, to catch reads of the rest
+    pub fn track_taint(&mut self, font: Range<usize>, program_length: usize) {
+        self.taint = Some(Taint::new(font, program_length));
+    }
+
+    /// First and last address the instruction at the program counter writes to that ran as code
+    fn code_written(&self, chip8: &Chip8) -> Option<(u16, u16)> {
+        let (access, written) = instruction(chip8)?.memory_access(chip8.index())?;
+        if access != Access::Write {
+            return None;
+        }
+        let mut code = written.filter(|address| self.coverage.ran(*address));
+        let start = code.next()?;
+        Some((start, code.next_back().unwrap_or(start)))
+    }
+
+    pub fn heatmap(&self) -> &Heatmap {
+        &self.heatmap
+    }
+
+    pub fn set_program_length(&mut self, length: usize) {
+        self.program_length = Some(length);
+    }
+
+    /// Program bytes the coverage covers, up to the last one touched if its size isn't known
+    fn program(&self) -> Range<usize> {
+        self.program_length.map_or_else(|| self.coverage.touched(), |length| 0x200..(0x200 + length).min(4096))
+    }
+
+    /// How much of the program ran and was read as data
+    pub fn coverage(&self) -> String {
+        self.coverage.summary(self.program())
+    }
+
+    /// Writes which bytes of the program ran and which were read as data
+    pub fn save_coverage(&self, path: &Path) -> Result<(), String> {
+        self.coverage.save(self.program(), path)
+    }
+
+    /// Writes out what's left of the trace once the machine stops for good
+    pub fn finish(&mut self, chip8: &Chip8) {
+        if let Some(Err(error)) = self.json_trace.take().map(|mut trace| trace.finish(chip8)) {
+            warn!("Stopped writing the trace: {}", error);
+        }
+    }
+
+    /// Marks the start of a frame, to go back to with `rframe`
+    pub fn start_frame(&mut self, chip8: &Chip8) {
+        self.history.start_frame();
+        self.graphs.record(chip8);
+        self.draws.start_frame();
+    }
+
+    /// Whether the machine is stopped at the prompt
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Runs the commands typed since the last call. Returns false once asked to quit.
+    pub fn poll(&mut self, chip8: &mut Chip8) -> bool {
+        loop {
+            let line = match self.commands.try_recv() {
+                Ok(line) => line,
+                Err(TryRecvError::Empty) => break,
+                // Standard input was closed, the debugger stops but the machine runs on
+                Err(TryRecvError::Disconnected) => {
+                    self.paused = false;
+                    break;
+                }
+            };
+            match self.execute(&line, chip8) {
+                Ok(output) if output.is_empty() => {}
+                Ok(output) => println!("{}", output),
+                Err(error) => eprintln!("{}", error),
+            }
+            if self.quit {
+                return false;
+            }
+            prompt();
+        }
+        self.poll_gdb(chip8);
+        self.poll_remote(chip8);
+        // Quitting can also come from the console or a debug UI
+        !self.quit
+    }
+
+    /// Runs a command of the prompt typed somewhere else, like the console
+    pub fn run_command(&mut self, line: &str, chip8: &mut Chip8) -> Result<String, String> {
+        self.execute(line, chip8)
+    }
+
+    /// Answers the requests of the debug UIs and sends them the screen if it changed
+    fn poll_remote(&mut self, chip8: &mut Chip8) {
+        while let Some((client, message)) = self.remote.as_ref().and_then(RemoteServer::poll) {
+            let request: Value = serde_json::from_str(&message).unwrap_or(Value::Null);
+            let reply = match self.remote_command(client, &request, chip8) {
+                Ok(result) => json!({ "id": request["id"], "result": result }),
+                Err(error) => json!({ "id": request["id"], "error": error }),
+            };
+            if let Some(remote) = &self.remote {
+                remote.reply(client, &reply);
+            }
+        }
+        if let Some(remote) = self.remote.as_ref().filter(|remote| remote.wants_frames()) {
+            if chip8.display()[..] != self.remote_frame[..] {
+                remote.send_frame(chip8);
+                self.remote_frame = chip8.display().to_vec();
+            }
+        }
+    }
+
+    /// Runs a request of a debug UI, see `remote`
+    fn remote_command(&mut self, client: usize, request: &Value, chip8: &mut Chip8) -> Result<Value, String> {
+        let address = || match &request["address"] {
+            Value::Number(number) => number.as_u64().map(|address| format!("{:#X}", address)).ok_or_else(|| format!("Invalid address: {}", number)),
+            Value::String(text) => Ok(text.clone()),
+            _ => Err(String::from("Which address?")),
+        };
+        match request["command"].as_str().ok_or("Which command?")? {
+            "state" => Ok(remote::state(chip8, self.paused)),
+            "memory" => {
+                let start = self.address(&address()?)? as usize;
+                let length = request["length"].as_u64().unwrap_or(64).min(usize::MAX as u64) as usize;
+                let end = chip8.memory().len();
+                Ok(json!(chip8.memory()[start.min(end)..start.saturating_add(length).min(end)]))
+            }
+            "frame" => Ok(json!(remote::frame(chip8))),
+            "subscribe" => {
+                if let Some(remote) = &self.remote {
+                    remote.subscribe(client, request["frames"].as_bool().unwrap_or(true));
+                }
+                // The next poll sends the screen as it is
+                self.remote_frame.clear();
+                Ok(Value::Null)
+            }
+            "break" => self.execute(&format!("break {}", address()?), chip8).map(Value::from),
+            "delete" => self.execute(&format!("delete {}", address()?), chip8).map(Value::from),
+            "step" => self.execute(&format!("step {}", request["count"].as_u64().unwrap_or(1)), chip8).map(Value::from),
+            "continue" => self.execute("continue", chip8).map(Value::from),
+            "pause" => {
+                self.paused = true;
+                self.finish_depth = None;
+                Ok(Value::Null)
+            }
+            "command" => self.execute(request["line"].as_str().ok_or("Which command line?")?, chip8).map(Value::from),
+            command => Err(format!("Unknown command: {}", command)),
+        }
+    }
+
+    /// Answers what GDB sent since the last call
+    fn poll_gdb(&mut self, chip8: &mut Chip8) {
+        while let Some(event) = self.gdb.as_ref().and_then(GdbServer::poll) {
+            match event {
+                Event::Connected => {
+                    info!("GDB connected, stopped at {}", self.describe(chip8.program_counter()));
+                    self.paused = true;
+                    self.finish_depth = None;
+                }
+                Event::Disconnected => {
+                    info!("GDB disconnected");
+                    self.gdb_waiting = false;
+                }
+                Event::Interrupt if self.gdb_waiting => {
+                    self.paused = true;
+                    self.gdb_waiting = false;
+                    self.send_gdb(STOPPED_BY_INTERRUPT);
+                }
+                Event::Interrupt => {}
+                Event::Packet(packet) => {
+                    match gdb::respond(&packet, chip8, &mut self.breakpoints) {
+                        Response::Reply(reply) => self.send_gdb(&reply),
+                        Response::Continue => {
+                            self.resume(chip8, None);
+                            self.gdb_waiting = true;
+                        }
+                        Response::Step => {
+                            if instruction(chip8).is_some() {
+                                self.record(chip8);
+                                chip8.emulate_cycle();
+                            }
+                            self.paused = true;
+                            self.send_gdb(STOPPED_BY_TRAP);
+                        }
+                        Response::Detach => {
+                            self.send_gdb("OK");
+                            self.resume(chip8, None);
+                            self.gdb_waiting = false;
+                        }
+                    }
+                    // Breakpoints GDB removed lose their conditions
+                    let breakpoints = &self.breakpoints;
+                    self.conditions.retain(|address, _| breakpoints.contains(address));
+                }
+            }
+        }
+    }
+
+    fn send_gdb(&self, packet: &str) {
+        if let Some(gdb) = &self.gdb {
+            gdb.send(packet);
+        }
+    }
+
+    /// Whether to stop before the instruction at the program counter, pausing if so
+    pub fn should_break(&mut self, chip8: &Chip8) -> bool {
+        let address = chip8.program_counter();
+        let resuming = self.resume_from.take() == Some(address);
+        let finished = self.finish_depth.is_some_and(|depth| chip8.call_stack().len() <= depth);
+        let reason = match if finished { Some(String::from("Stepped to")) } else { self.stop_reason(chip8) } {
+            Some(reason) if !resuming => reason,
+            _ => return false,
+        };
+        // A breakpoint inside the subroutine being stepped over ends the step
+        self.finish_depth = None;
+        self.paused = true;
+        if let Some(trace) = &self.trace {
+            println!("\n{}", trace.lines(STOP_TRACE, chip8, &self.symbols).join("\n"));
+        }
+        println!("\n{}\n{}", reason, self.disassemble(chip8, address, 1));
+        prompt();
+        if self.gdb_waiting {
+            self.gdb_waiting = false;
+            self.send_gdb(STOPPED_BY_TRAP);
+        }
+        if let Some(remote) = &self.remote {
+            remote.broadcast(&json!({ "event": "stopped", "reason": reason, "pc": address }));
+        }
+        true
+    }
+
+    /// Why the machine should stop before the instruction at the program counter: a breakpoint
+    /// whose condition, if any, holds, an opcode it can't run, a write to code when stopping for
+    /// those, or a watched address it reads or writes
+    fn stop_reason(&self, chip8: &Chip8) -> Option<String> {
+        let address = chip8.program_counter();
+        if self.breakpoints.contains(&address) && self.conditions.get(&address).is_none_or(|condition| condition.is_true(chip8)) {
+            return Some(String::from("Breakpoint at"));
+        }
+        let instruction = match instruction(chip8) {
+            Some(instruction) => instruction,
+            None => return Some(String::from("Unknown opcode at")),
+        };
+        if self.break_on_code_writes {
+            if let Some((start, end)) = self.code_written(chip8) {
+                return Some(format!("Write to code at {} by", describe_range(start, end)));
+            }
+        }
+        if self.break_on_taint {
+            if let Some(read) = self.taint.as_ref().and_then(|taint| taint.uninitialized(chip8)) {
+                return Some(format!("Uninitialized read of {} by", read));
+            }
+        }
+        let (access, accessed) = instruction.memory_access(chip8.index())?;
+        self.watchpoints.iter().find_map(|watchpoint| {
+            let overlap = watchpoint.range.start.max(*accessed.start())..=watchpoint.range.end.min(*accessed.end());
+            if overlap.is_empty() || watchpoint.access.is_some_and(|watched| watched != access) {
+                return None;
+            }
+            let verb = if access == Access::Read { "Read" } else { "Write" };
+            Some(format!("{} of {} by", verb, describe_range(*overlap.start(), *overlap.end())))
+        })
+    }
+
+    /// Lets the machine run on from the program counter, until the call stack is back down to a
+    /// depth if one is given
+    fn resume(&mut self, chip8: &Chip8, finish_depth: Option<usize>) {
+        self.paused = false;
+        self.resume_from = Some(chip8.program_counter());
+        self.finish_depth = finish_depth;
+    }
+
+    fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.conditions.remove(&address);
+        self.breakpoints.remove(&address)
+    }
+
+    /// Runs one command, returning what to print
+    fn execute(&mut self, line: &str, chip8: &mut Chip8) -> Result<String, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, arguments) = match words.split_first() {
+            Some((command, arguments)) => (*command, arguments),
+            None => return Ok(String::new()),
+        };
+        let argument = |index: usize| arguments.get(index).copied();
+        match command {
+            "break" | "b" => match argument(0) {
+                Some(_) => {
+                    let address = self.add_breakpoint(&arguments.join(" "))?;
+                    Ok(format!("Breakpoint at {}", self.describe_breakpoint(address)))
+                }
+                None if self.breakpoints.is_empty() => Ok(String::from("No breakpoints")),
+                None => Ok(self.breakpoints.iter().map(|address| self.describe_breakpoint(*address)).collect::<Vec<_>>().join("\n")),
+            },
+            "delete" => {
+                let address = self.address(argument(0).ok_or("Which breakpoint? delete address")?)?;
+                if self.remove_breakpoint(address) {
+                    Ok(format!("Deleted breakpoint at {}", self.describe(address)))
+                } else {
+                    Err(format!("No breakpoint at {}", self.describe(address)))
+                }
+            }
+            "watch" | "rwatch" | "awatch" => match argument(0) {
+                Some(text) => {
+                    let range = self.range(text)?;
+                    let access = match command {
+                        "watch" => Some(Access::Write),
+                        "rwatch" => Some(Access::Read),
+                        _ => None,
+                    };
+                    self.watchpoints.retain(|watchpoint| watchpoint.range != range);
+                    self.watchpoints.push(Watchpoint { range, access });
+                    Ok(format!("Watching {}", self.describe_watchpoint(self.watchpoints.last().unwrap())))
+                }
+                None if self.watchpoints.is_empty() => Ok(String::from("No watchpoints")),
+                None => Ok(self.watchpoints.iter().map(|watchpoint| self.describe_watchpoint(watchpoint)).collect::<Vec<_>>().join("\n")),
+            },
+            "unwatch" => {
+                let range = self.range(argument(0).ok_or("Which watchpoint? unwatch address")?)?;
+                let count = self.watchpoints.len();
+                self.watchpoints.retain(|watchpoint| watchpoint.range != range);
+                if self.watchpoints.len() < count {
+                    Ok(format!("Stopped watching {}", describe_range(range.start, range.end)))
+                } else {
+                    Err(format!("Not watching {}", describe_range(range.start, range.end)))
+                }
+            }
+            "display" => match argument(0) {
+                Some(_) => {
+                    self.add_watch(&arguments.join(" "))?;
+                    self.watches.update(chip8);
+                    Ok(String::new())
+                }
+                None if self.watches.is_empty() => Ok(String::from("No watch expressions")),
+                None => {
+                    let lines: Vec<String> =
+                        self.watches.lines().iter().enumerate().map(|(index, (line, _))| format!("{}: {}", index + 1, line)).collect();
+                    Ok(lines.join("\n"))
+                }
+            },
+            "undisplay" => {
+                let number = argument(0).and_then(|text| text.parse().ok()).ok_or("Which expression? undisplay number")?;
+                if self.watches.remove(number) {
+                    Ok(String::new())
+                } else {
+                    Err(format!("No watch expression {}", number))
+                }
+            }
+            "graph" => match argument(0) {
+                Some(_) => {
+                    self.add_graph(&arguments.join(" "))?;
+                    Ok(String::new())
+                }
+                None if self.graphs.graphs().is_empty() => Ok(String::from("No graphs")),
+                None => {
+                    let lines: Vec<String> =
+                        self.graphs.graphs().iter().enumerate().map(|(index, graph)| format!("{}: {}", index + 1, graph.expression())).collect();
+                    Ok(lines.join("\n"))
+                }
+            },
+            "ungraph" => {
+                let number = argument(0).and_then(|text| text.parse().ok()).ok_or("Which graph? ungraph number")?;
+                if self.graphs.remove(number) {
+                    Ok(String::new())
+                } else {
+                    Err(format!("No graph {}", number))
+                }
+            }
+            "step" | "s" => {
+                let count = argument(0).map_or(Ok(1), |text| text.parse().map_err(|_| format!("Invalid count: {}", text)))?;
+                self.paused = true;
+                for step in 0..count {
+                    // Stepping starts from a breakpoint, but stops at the next one
+                    if step > 0 && self.stop_reason(chip8).is_some() {
+                        break;
+                    }
+                    if instruction(chip8).is_none() {
+                        return Err(format!("Unknown opcode at {}", self.describe(chip8.program_counter())));
+                    }
+                    self.record(chip8);
+                    chip8.emulate_cycle();
+                }
+                Ok(self.disassemble(chip8, chip8.program_counter(), 1))
+            }
+            "next" | "n" => {
+                if !matches!(instruction(chip8), Some(Instruction::Call(_))) {
+                    return self.execute("step", chip8);
+                }
+                // Runs until the call returns to this depth
+                self.resume(chip8, Some(chip8.call_stack().len()));
+                Ok(String::new())
+            }
+            "finish" | "out" => {
+                let depth = chip8.call_stack().len().checked_sub(1).ok_or("Not in a subroutine")?;
+                self.resume(chip8, Some(depth));
+                Ok(String::new())
+            }
+            "rstep" | "rs" | "rframe" | "rf" => {
+                let position = if command.starts_with("rs") {
+                    let count = argument(0).map_or(Ok(1), |text| text.parse().map_err(|_| format!("Invalid count: {}", text)))?;
+                    self.history.position().checked_sub(count)
+                } else {
+                    self.history.previous_frame()
+                };
+                *chip8 = position.and_then(|position| self.history.rewind(position)).ok_or("Can't go back that far")?;
+                self.paused = true;
+                self.finish_depth = None;
+                Ok(self.disassemble(chip8, chip8.program_counter(), 1))
+            }
+            "profile" => {
+                let count = argument(0).map_or(Ok(10), |text| text.parse().map_err(|_| format!("Invalid count: {}", text)))?;
+                Ok(self.hotspots(count))
+            }
+            "loops" => Ok(self.loops.report(&self.symbols)),
+            "calls" => match argument(0) {
+                Some(path) => {
+                    self.save_call_graph(Path::new(path))?;
+                    Ok(format!("Wrote the call graph to {}", path))
+                }
+                None => Ok(self.calls.report(&self.symbols)),
+            },
+            "coverage" => match argument(0) {
+                Some(path) => {
+                    self.save_coverage(Path::new(path))?;
+                    Ok(format!("Wrote the coverage map to {}", path))
+                }
+                None => Ok(self.coverage()),
+            },
+            "draws" => {
+                if self.draws.draws().is_empty() {
+                    return Ok(String::from("No sprites drawn this frame"));
+                }
+                let lines: Vec<String> =
+                    self.draws.draws().iter().enumerate().map(|(number, draw)| format!("{}: {} {}", number + 1, self.describe(draw.address), draw.summary())).collect();
+                Ok(lines.join("\n"))
+            }
+            "note" => match (argument(0), argument(1)) {
+                (Some(address), Some(name)) => {
+                    let note = Note::new(self.range(address)?, name, &arguments[2..].join(" "))?;
+                    let message = format!("{} is {}", note.range(), note.name);
+                    self.symbols.add_note(note);
+                    self.save_notes()?;
+                    Ok(message)
+                }
+                (Some(_), None) => Err(String::from("Which name? note address name [comment]")),
+                (None, _) if self.symbols.notes.is_empty() => Ok(String::from("No notes")),
+                (None, _) => {
+                    let lines: Vec<String> = self
+                        .symbols
+                        .notes
+                        .iter()
+                        .map(|note| format!("{} {} {}", note.range(), note.name, note.comment).trim_end().to_string())
+                        .collect();
+                    Ok(lines.join("\n"))
+                }
+            },
+            "unnote" => {
+                let address = self.address(argument(0).ok_or("Which note? unnote address")?)?;
+                if !self.symbols.remove_note(address) {
+                    return Err(format!("No note at {:#05X}", address));
+                }
+                self.save_notes()?;
+                Ok(String::new())
+            }
+            "snapshot" => {
+                let snapshot = Snapshot::new(chip8);
+                if let Some(path) = argument(0) {
+                    snapshot.save(Path::new(path))?;
+                }
+                self.snapshot = Some(snapshot);
+                Ok(format!("Snapshot taken at instruction {}", chip8.cycles()))
+            }
+            "diff" => {
+                let before = match argument(0) {
+                    Some(path) => Snapshot::load(Path::new(path))?,
+                    None => self.snapshot.clone().ok_or("No snapshot yet, take one with snapshot")?,
+                };
+                Ok(snapshot::diff(&before, &Snapshot::new(chip8)).join("\n"))
+            }
+            "selfmod" => match argument(0) {
+                Some("on") | Some("off") => {
+                    self.break_on_code_writes = argument(0) == Some("on");
+                    Ok(format!("{} before writes to code", if self.break_on_code_writes { "Stopping" } else { "Not stopping" }))
+                }
+                Some(text) => Err(format!("Expected on or off, not {}", text)),
+                None if self.code_writes.is_empty() => Ok(String::from("No writes to code")),
+                None => Ok(self
+                    .code_writes
+                    .iter()
+                    .map(|(address, (start, end))| format!("{} wrote to {}", self.describe(*address), describe_range(*start, *end)))
+                    .collect::<Vec<_>>()
+                    .join("\n")),
+            },
+            "warnings" => {
+                let diagnostics = self.diagnostics.as_ref().ok_or("Not checking for suspicious behaviour, run with --warnings")?;
+                if diagnostics.warnings().is_empty() {
+                    return Ok(String::from("No warnings"));
+                }
+                Ok(diagnostics.warnings().iter().map(|(address, warning)| format!("{}: {}", self.describe(*address), warning)).collect::<Vec<_>>().join("\n"))
+            }
+            "taint" => {
+                let taint = self.taint.as_ref().ok_or("Not tracking uninitialized reads, run with --taint")?;
+                match argument(0) {
+                    Some("on") | Some("off") => {
+                        self.break_on_taint = argument(0) == Some("on");
+                        Ok(format!("{} before uninitialized reads", if self.break_on_taint { "Stopping" } else { "Not stopping" }))
+                    }
+                    Some(text) => Err(format!("Expected on or off, not {}", text)),
+                    None if taint.reads().is_empty() => Ok(String::from("No uninitialized reads")),
+                    None => Ok(taint.reads().iter().map(|(address, read)| format!("{} read {}", self.describe(*address), read)).collect::<Vec<_>>().join("\n")),
+                }
+            }
+            "trace" => {
+                let trace = self.trace.as_ref().ok_or("Not tracing, run with --trace length")?;
+                let count = argument(0).map_or(Ok(usize::MAX), |text| text.parse().map_err(|_| format!("Invalid count: {}", text)))?;
+                Ok(trace.lines(count, chip8, &self.symbols).join("\n"))
+            }
+            "continue" | "c" => {
+                self.resume(chip8, None);
+                Ok(String::new())
+            }
+            "regs" | "r" => Ok(registers(chip8)),
+            "mem" | "m" => {
+                let address = self.address(argument(0).ok_or("Which address? mem address [length]")?)?;
+                let length = argument(1).map_or(Ok(64), |text| parse_address(text).ok_or(format!("Invalid length: {}", text)))?;
+                Ok(memory(chip8.memory(), address, length))
+            }
+            "stack" | "bt" => {
+                if chip8.call_stack().is_empty() {
+                    return Ok(String::from("Not in a subroutine"));
+                }
+                let frames: Vec<String> =
+                    chip8.call_stack().iter().rev().enumerate().map(|(depth, call)| format!("#{} called from {}", depth, self.describe(*call))).collect();
+                Ok(frames.join("\n"))
+            }
+            "disasm" | "d" => {
+                let address = argument(0).map_or(Ok(chip8.program_counter()), |text| self.address(text))?;
+                let count = argument(1).map_or(Ok(8), |text| text.parse().map_err(|_| format!("Invalid count: {}", text)))?;
+                Ok(self.disassemble(chip8, address, count))
+            }
+            "quit" | "q" => {
+                self.quit = true;
+                Ok(String::new())
+            }
+            "help" | "h" | "?" => Ok(String::from(
+                "break [address] [when condition], delete address, watch/rwatch/awatch [address], unwatch address, display [expression], \
+                 undisplay number, graph [expression], ungraph number, step [count], next, finish, \
+                 rstep [count], rframe, profile [count], loops, calls [path], coverage [path], note address name [comment], unnote address, snapshot [path], diff [path], selfmod [on/off], warnings, taint [on/off], draws, \
+                 trace [count], \
+                 continue, regs, mem address [length], stack, disasm [address] [count], quit",
+            )),
+            _ => Err(format!("Unknown command: {}, type help for the commands", command)),
+        }
+    }
+
+    /// Address written as a number or a label
+    fn address(&self, text: &str) -> Result<u16, String> {
+        self.symbols
+            .labels
+            .get(text)
+            .copied()
+            .or_else(|| parse_address(text))
+            .ok_or_else(|| format!("Not an address or label: {}", text))
+    }
+
+    /// One address, as a number or label, or a range like `0xE00-0xE0F`
+    fn range(&self, text: &str) -> Result<MemoryRange, String> {
+        match self.address(text) {
+            Ok(address) => Ok(MemoryRange { start: address, end: address }),
+            Err(error) => text.parse().map_err(|_| error),
+        }
+    }
+
+    fn describe_watchpoint(&self, watchpoint: &Watchpoint) -> String {
+        let access = match watchpoint.access {
+            Some(Access::Read) => "reads of",
+            Some(Access::Write) => "writes to",
+            None => "reads and writes of",
+        };
+        let range = &watchpoint.range;
+        match self.symbols.label_at(range.start) {
+            Some(label) => format!("{} {} ({})", access, describe_range(range.start, range.end), label),
+            None => format!("{} {}", access, describe_range(range.start, range.end)),
+        }
+    }
+
+    /// Address with its label, if it has one
+    fn describe(&self, address: u16) -> String {
+        self.symbols.describe(address)
+    }
+
+    /// Address of a breakpoint with its condition
+    fn describe_breakpoint(&self, address: u16) -> String {
+        match self.conditions.get(&address) {
+            Some(condition) => format!("{} when {}", self.describe(address), condition),
+            None => self.describe(address),
+        }
+    }
+
+    /// Listing of `count` instructions or data bytes from an address
+    fn disassemble(&self, chip8: &Chip8, address: u16, count: usize) -> String {
+        let memory = chip8.memory();
+        let mut lines = Vec::new();
+        let mut address = address as usize;
+        while lines.len() < count && address < memory.len() {
+            let line = disasm::line_at(memory, address as u16, chip8.variant());
+            address += line.bytes.len();
+            lines.push(line);
+        }
+        disasm::apply_symbols(&mut lines, &self.symbols);
+        lines.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n")
+    }
+}
+
+/// Number written in hex with `0x` in front, or in decimal, that fits in memory
+pub fn parse_address(text: &str) -> Option<u16> {
+    let text = text.trim();
+    let address = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    };
+    address.filter(|address| *address < 0x1000)
+}
+
+fn describe_range(start: u16, end: u16) -> String {
+    if start == end {
+        format!("{:#05X}", start)
+    } else {
+        format!("{:#05X}-{:#05X}", start, end)
+    }
+}
+
+/// The instruction at the program counter
+fn instruction(chip8: &Chip8) -> Option<Instruction> {
+    let address = chip8.program_counter() as usize;
+    let opcode = chip8.memory().get(address..address + 2)?;
+    Instruction::decode(u16::from_be_bytes([opcode[0], opcode[1]]), chip8.variant())
+}
+
+fn prompt() {
+    print!("{}", PROMPT);
+    let _ = io::stdout().flush();
+}
+
+fn registers(chip8: &Chip8) -> String {
+    let registers: Vec<String> =
+        chip8.registers().iter().enumerate().map(|(number, value)| format!("V{:X}={:02X}", number, value)).collect();
+    let (delay, sound) = chip8.timers();
+    format!(
+        "{}\n{}\nI={:#05X} PC={:#05X} DT={:02X} ST={:02X}",
+        registers[..8].join(" "),
+        registers[8..].join(" "),
+        chip8.index(),
+        chip8.program_counter(),
+        delay,
+        sound
+    )
+}
+
+/// Hex dump, 16 bytes to a line
+fn memory(memory: &[u8], address: u16, length: u16) -> String {
+    let start = address as usize;
+    let end = (start + length as usize).min(memory.len());
+    let lines: Vec<String> = memory[start..end]
+        .chunks(16)
+        .enumerate()
+        .map(|(row, bytes)| {
+            let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            format!("{:#05X}  {}", start + row * 16, bytes.join(" "))
+        })
+        .collect();
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assemble_with_symbols;
+    use crate::chip8::Chip8;
+    use crate::debugger::Debugger;
+    use serde_json::json;
+    use std::sync::mpsc;
+
+    /// Breakpoints set by label stop the machine before the instruction, stepping runs single
+    /// instructions
+    #[test]
+    fn test_commands() {
+        let (rom, symbols) =
+            assemble_with_symbols(": main\n\tv0 := 1\n\tdraw\n: spin\n\tjump spin\n: draw\n\tv1 := 2\n\treturn", None).unwrap();
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&rom);
+        let (sender, commands) = mpsc::channel();
+        let mut debugger = Debugger::with_commands(commands, symbols);
+
+        assert_eq!(debugger.execute("b draw", &mut chip8), Ok(String::from("Breakpoint at 0x206 (draw)")));
+        assert!(debugger.execute("b nowhere", &mut chip8).is_err());
+        assert!(!debugger.should_break(&chip8));
+        chip8.emulate_cycle();
+        chip8.emulate_cycle();
+        assert!(debugger.should_break(&chip8));
+        assert!(debugger.is_paused());
+        assert!(debugger.execute("bt", &mut chip8).unwrap().starts_with("#0 called from 0x202"));
+
+        // Continuing runs past the breakpoint it stopped at
+        debugger.execute("c", &mut chip8).unwrap();
+        assert!(!debugger.should_break(&chip8));
+        assert!(debugger.execute("step", &mut chip8).unwrap().contains("RET"));
+        assert!(debugger.execute("regs", &mut chip8).unwrap().starts_with("V0=01 V1=02"));
+        assert_eq!(debugger.execute("mem 0x200 4", &mut chip8), Ok(String::from("0x200  60 01 22 06")));
+
+        // Clicking a breakpoint in the debugger window clears it
+        debugger.toggle_breakpoint(0x206);
+        assert!(debugger.breakpoints().is_empty());
+        assert_eq!(debugger.add_breakpoint("0x2F0"), Ok(0x2F0));
+
+        assert!(debugger.execute("display V0 + 1", &mut chip8).is_ok());
+        assert_eq!(debugger.execute("display", &mut chip8), Ok(String::from("1: V0 + 1 = 0x2")));
+        assert!(debugger.execute("undisplay 2", &mut chip8).is_err());
+
+        // Conditional breakpoints only stop the machine when their condition holds
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&rom);
+        assert_eq!(debugger.execute("b draw when V0 == 2", &mut chip8), Ok(String::from("Breakpoint at 0x206 (draw) when V0 == 2")));
+        chip8.emulate_cycle();
+        chip8.emulate_cycle();
+        assert!(!debugger.should_break(&chip8));
+        debugger.add_breakpoint("draw if V0 == 1").unwrap();
+        assert!(debugger.should_break(&chip8));
+        assert!(debugger.add_breakpoint("draw when V0 ==").is_err());
+
+        // Stepping over the call runs the whole subroutine, stepping out runs to its return
+        debugger.execute("delete draw", &mut chip8).unwrap();
+        for (command, steps) in [("next", 1), ("finish", 2)] {
+            let mut chip8 = Chip8::new();
+            chip8.load_program(&rom);
+            for _ in 0..steps {
+                chip8.emulate_cycle();
+            }
+            debugger.execute(command, &mut chip8).unwrap();
+            while !debugger.should_break(&chip8) {
+                chip8.emulate_cycle();
+            }
+            assert_eq!((chip8.program_counter(), chip8.registers()[1]), (0x204, 2));
+        }
+        assert_eq!(debugger.execute("finish", &mut Chip8::new()), Err(String::from("Not in a subroutine")));
+
+        // The trace has the instructions run before the machine stopped
+        assert!(debugger.execute("trace", &mut chip8).is_err());
+        debugger.start_trace(4);
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&rom);
+        debugger.execute("step 2", &mut chip8).unwrap();
+        assert_eq!(debugger.execute("trace 1", &mut chip8), Ok(String::from("0x202  2206  CALL 0x206        ; I=0x000")));
+
+        // Stepping backwards undoes the steps
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&rom);
+        debugger.execute("step 3", &mut chip8).unwrap();
+        assert!(debugger.execute("rs 2", &mut chip8).unwrap().contains("CALL"));
+        assert_eq!(chip8.call_stack().len(), 0);
+        assert!(debugger.execute("rs 100", &mut chip8).is_err());
+
+        sender.send(String::from("quit")).unwrap();
+        assert!(!debugger.poll(&mut chip8));
+    }
+
+    /// Watchpoints stop the machine before an instruction touches the memory they watch
+    #[test]
+    fn test_watchpoints() {
+        let (rom, symbols) = assemble_with_symbols(": main\n\ti := score\n\tv0 := 5\n\tsave v0\n\tjump main\n: score\n\t0", None).unwrap();
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&rom);
+        let (_sender, commands) = mpsc::channel();
+        let mut debugger = Debugger::with_commands(commands, symbols);
+
+        assert_eq!(debugger.execute("rwatch score", &mut chip8), Ok(String::from("Watching reads of 0x208 (score)")));
+        chip8.emulate_cycle();
+        chip8.emulate_cycle();
+        assert!(!debugger.should_break(&chip8));
+
+        assert_eq!(debugger.execute("awatch 0x200-0x20F", &mut chip8), Ok(String::from("Watching reads and writes of 0x200-0x20F (main)")));
+        assert_eq!(debugger.stop_reason(&chip8), Some(String::from("Write of 0x208 by")));
+        assert!(debugger.execute("unwatch 0x200-0x20F", &mut chip8).is_ok());
+        assert_eq!(debugger.stop_reason(&chip8), None);
+    }
+
+    /// Writes to memory that ran as code are listed, and stop the machine once asked to
+    #[test]
+    fn test_code_writes() {
+        let (rom, symbols) = assemble_with_symbols(": main\n\ti := main\n\tv0 := 0x60\n\tsave v0\n\tjump main", None).unwrap();
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&rom);
+        let (_sender, commands) = mpsc::channel();
+        let mut debugger = Debugger::with_commands(commands, symbols);
+
+        for _ in 0..3 {
+            assert!(!debugger.should_break(&chip8));
+            debugger.record(&chip8);
+            chip8.emulate_cycle();
+        }
+        assert_eq!(debugger.execute("selfmod", &mut chip8), Ok(String::from("0x204 wrote to 0x200")));
+        assert!(debugger.execute("selfmod maybe", &mut chip8).is_err());
+        assert!(debugger.execute("selfmod on", &mut chip8).is_ok());
+        debugger.record(&chip8);
+        chip8.emulate_cycle();
+        debugger.record(&chip8);
+        chip8.emulate_cycle();
+        debugger.record(&chip8);
+        chip8.emulate_cycle();
+        assert_eq!(debugger.stop_reason(&chip8), Some(String::from("Write to code at 0x200 by")));
+    }
+
+    /// Requests of debug UIs run like the commands of the prompt
+    #[test]
+    fn test_remote_commands() {
+        let (rom, symbols) = assemble_with_symbols(": main\n\tv0 := 7\n: spin\n\tjump spin", None).unwrap();
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&rom);
+        let (_sender, commands) = mpsc::channel();
+        let mut debugger = Debugger::with_commands(commands, symbols);
+        let mut request = |text: &str, chip8: &mut Chip8| debugger.remote_command(0, &serde_json::from_str(text).unwrap(), chip8);
+
+        assert_eq!(request(r#"{"command": "break", "address": "spin"}"#, &mut chip8), Ok(json!("Breakpoint at 0x202 (spin)")));
+        assert_eq!(request(r#"{"command": "memory", "address": 512, "length": 2}"#, &mut chip8), Ok(json!([0x60, 0x07])));
+        let rest = request(r#"{"command": "memory", "address": 4094, "length": 18446744073709551615}"#, &mut chip8).unwrap();
+        assert_eq!(rest.as_array().unwrap().len(), 2);
+        assert!(request(r#"{"command": "step"}"#, &mut chip8).is_ok());
+        let state = request(r#"{"command": "state"}"#, &mut chip8).unwrap();
+        assert_eq!((state["pc"].clone(), state["registers"][0].clone(), state["paused"].clone()), (json!(0x202), json!(7), json!(true)));
+        assert!(request(r#"{"command": "fly"}"#, &mut chip8).is_err());
+    }
+}
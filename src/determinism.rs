@@ -0,0 +1,94 @@
+//! `chip8 verify`, checking that a run can be repeated. The ROM is run twice in lockstep from
+//! power-on with the same seed and input, without a window, comparing the machine state after
+//! every frame; the input of a replay is also checked against the checksums it was recorded
+//! with. Anything that makes the emulator depend on more than the ROM, seed and input, like
+//! wall-clock time or unseeded randomness, shows up as the first frame where the runs differ.
+
+use crate::chip8::{Chip8, Chip8Error, Variant};
+use crate::replay::{FrameInput, Replay, Verification};
+use crate::run;
+use crate::snapshot::{self, Snapshot};
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    /// The runs agreed with each other and the recording for every frame
+    Matched { frames: u64 },
+    /// The runs differ after this frame, with what differs between them
+    Diverged { frame: u64, differences: Vec<String> },
+    /// The runs agree with each other but not with the replay, which they last matched at the
+    /// checkpoint
+    Unlike { frame: u64, last_good_frame: u64 },
+    /// The program failed before the last frame, with the runs agreeing up to there
+    Failed { frame: u64, reason: Chip8Error },
+}
+
+/// Runs the program twice for a number of frames, with the input of a replay or none
+pub fn verify(program: &[u8], seed: u64, variant: Variant, replay: Option<&Replay>, frames: u64) -> Outcome {
+    let machine = replay.and_then(|replay| replay.machine.clone()).unwrap_or_default();
+    let power_on = || run::power_on(machine.configure(Chip8::builder().variant(variant)).seed(seed), program);
+    let (mut first, mut second) = (power_on(), power_on());
+    let released = FrameInput { keypad: 0, second_keypad: 0 };
+    let mut inputs = replay.into_iter().flat_map(|replay| replay.frames());
+    for frame in 1..=frames {
+        let input = inputs.next().unwrap_or(released);
+        for chip8 in [&mut first, &mut second] {
+            if let Err(reason) = chip8.try_emulate_cycle() {
+                return Outcome::Failed { frame, reason };
+            }
+            chip8.set_keys(input.keypad());
+            chip8.set_second_keypad(input.second_keypad());
+        }
+        if first.state_checksum() != second.state_checksum() {
+            return Outcome::Diverged { frame, differences: snapshot::diff(&Snapshot::new(&first), &Snapshot::new(&second)) };
+        }
+        if let Some(Verification::Diverged { last_good_frame }) = replay.map(|replay| replay.verify(frame, || first.state_checksum())) {
+            return Outcome::Unlike { frame, last_good_frame };
+        }
+    }
+    Outcome::Matched { frames }
+}
+
+impl Outcome {
+    pub fn is_deterministic(&self) -> bool {
+        !matches!(self, Outcome::Diverged { .. } | Outcome::Unlike { .. })
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Outcome::Matched { frames } => format!("Both runs matched for all {} frames", frames),
+            Outcome::Diverged { frame, differences } => {
+                format!("The runs diverged at frame {}:\n{}", frame, differences.join("\n"))
+            }
+            Outcome::Unlike { frame, last_good_frame } => format!(
+                "Both runs match each other but not the replay at frame {}, the divergence happened after frame {}",
+                frame, last_good_frame
+            ),
+            Outcome::Failed { frame, reason } => format!("Both runs matched until the program failed at frame {}: {}", frame, reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::{Chip8Error, Variant};
+    use crate::determinism::{verify, Outcome};
+    use crate::replay::{rom_hash, FrameInput, Machine, Replay, CHECKSUM_INTERVAL};
+
+    /// Runs with the same seed and input match, and a replay recorded with another seed doesn't
+    #[test]
+    fn test_verify() {
+        // V0 = random, V1 += 1, loop
+        let program = [0xC0, 0xFF, 0x71, 0x01, 0x12, 0x00];
+        assert_eq!(verify(&program, 3, Variant::Chip8, None, 200), Outcome::Matched { frames: 200 });
+        assert_eq!(verify(&[0x00, 0xEE], 3, Variant::Chip8, None, 10), Outcome::Failed { frame: 1, reason: Chip8Error::StackUnderflow(0x200) });
+
+        let mut replay = Replay::new(rom_hash(&program), 4, Variant::Chip8, Machine::default());
+        let mut chip8 = crate::run::power_on(crate::chip8::Chip8::builder().seed(4), &program);
+        for _ in 0..CHECKSUM_INTERVAL * 2 {
+            chip8.emulate_cycle();
+            replay.push(FrameInput { keypad: 0, second_keypad: 0 }, || chip8.state_checksum());
+        }
+        assert_eq!(verify(&program, 4, Variant::Chip8, Some(&replay), 150), Outcome::Matched { frames: 150 });
+        assert!(matches!(verify(&program, 5, Variant::Chip8, Some(&replay), 150), Outcome::Unlike { frame: 60, last_good_frame: 0 }));
+    }
+}
@@ -0,0 +1,108 @@
+//! Development mode: the ROM is reloaded into the running emulator whenever it, or a source file
+//! it includes, is saved.
+//!
+//! A reload starts the new program from power-on. Memory ranges given with `--keep` are carried
+//! over from the machine that was running, so a game keeping its state in memory can pick up
+//! where it was; without any, the reload is a clean reset. Assembly errors are printed and the
+//! old program keeps running until the source is fixed.
+
+use crate::chip8::Chip8;
+use crate::debugger::parse_address;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the files are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Memory addresses from `start` to `end`, both included, written `0xE00-0xEFF`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl FromStr for MemoryRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("Invalid memory range {}, expected something like 0xE00-0xEFF", s);
+        let (start, end) = s.split_once('-').ok_or_else(error)?;
+        match (parse_address(start), parse_address(end)) {
+            (Some(start), Some(end)) if start <= end => Ok(MemoryRange { start, end }),
+            _ => Err(error()),
+        }
+    }
+}
+
+/// Notices when any of a set of files is modified
+pub struct Watcher {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    last_check: Instant,
+}
+
+impl Watcher {
+    pub fn new(files: &[PathBuf]) -> Self {
+        let mut watcher = Watcher { files: Vec::new(), last_check: Instant::now() };
+        watcher.watch(files);
+        watcher
+    }
+
+    /// Replaces the files watched, taking their current state as unchanged
+    pub fn watch(&mut self, files: &[PathBuf]) {
+        self.files = files.iter().map(|file| (file.clone(), modified(file))).collect();
+    }
+
+    /// Whether a file changed since the last call that returned true. Checks at most every
+    /// `POLL_INTERVAL`.
+    pub fn changed(&mut self) -> bool {
+        if self.last_check.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_check = Instant::now();
+        let mut changed = false;
+        for (file, last_modified) in &mut self.files {
+            let now = modified(file);
+            // A file that is missing for a moment while an editor saves it isn't a change yet
+            if now.is_some() && now != *last_modified {
+                *last_modified = now;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+fn modified(file: &Path) -> Option<SystemTime> {
+    fs::metadata(file).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Copies the kept memory ranges from the machine that was running into the reloaded one
+pub fn keep_memory(old: &Chip8, new: &mut Chip8, keep: &[MemoryRange]) {
+    for range in keep {
+        new.write_memory(range.start, &old.memory()[range.start as usize..=range.end as usize]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Chip8;
+    use crate::dev::{keep_memory, MemoryRange};
+
+    /// Ranges parse in hex or decimal, and only they survive a reload
+    #[test]
+    fn test_keep_memory() {
+        assert_eq!("0xE00-0xE0F".parse(), Ok(MemoryRange { start: 0xE00, end: 0xE0F }));
+        assert_eq!("512 - 513".parse(), Ok(MemoryRange { start: 0x200, end: 0x201 }));
+        assert!("0xE0F-0xE00".parse::<MemoryRange>().is_err());
+        assert!("0xE00".parse::<MemoryRange>().is_err());
+        assert!("0xE00-0x1000".parse::<MemoryRange>().is_err());
+
+        let mut old = Chip8::new();
+        old.write_memory(0xE00, &[1, 2, 3]);
+        let mut new = Chip8::new();
+        keep_memory(&old, &mut new, &[MemoryRange { start: 0xE00, end: 0xE01 }]);
+        assert_eq!(&new.memory()[0xE00..0xE03], &[1, 2, 0]);
+    }
+}
@@ -0,0 +1,149 @@
+//! Warnings about what a ROM does at runtime that's likely a mistake, with `--warnings`. Each is
+//! logged once for the instruction doing it:
+//!
+//! - A value kept in VF lost to the flag of 8XY4, 8XY5, 8XY6, 8XY7, 8XYE or DXYN before being
+//!   read, or one of those with VF as VX, whose result and flag interpreters order differently
+//! - FX33 and FX55 writing over memory that ran as code
+//! - DXYN drawing with a coordinate off the screen, which interpreters wrap or clip differently,
+//!   or with a height of 0, which draws nothing on CHIP-8 and a 16x16 sprite on SCHIP
+//! - FX29 for a digit past F, and EX9E and EXA1 for a key past F
+
+use crate::chip8::{Access, AluOp, Chip8, Instruction, MAX_MEMORY_SIZE};
+use crate::run;
+use std::collections::BTreeMap;
+
+pub struct Diagnostics {
+    /// Whether an instruction ran from each byte of memory
+    code: Vec<bool>,
+    /// Instruction that last put a value in VF, while nothing read it yet
+    flag_register: Option<u16>,
+    /// Warnings by the address of the instruction they're about
+    warnings: BTreeMap<u16, String>,
+}
+
+impl Default for Diagnostics {
+    fn default() -> Self {
+        Diagnostics { code: vec![false; MAX_MEMORY_SIZE], flag_register: None, warnings: BTreeMap::new() }
+    }
+}
+
+impl Diagnostics {
+    /// Checks the instruction at the program counter before it runs. Returns a warning the first
+    /// time that instruction does something suspicious.
+    pub fn record(&mut self, chip8: &Chip8) -> Option<String> {
+        let address = chip8.program_counter();
+        let opcode = chip8.memory().get(address as usize..address as usize + 2)?;
+        let instruction = Instruction::decode(u16::from_be_bytes([opcode[0], opcode[1]]), chip8.variant())?;
+        self.code[address as usize] = true;
+        self.code[address as usize + 1] = true;
+        let warning = self.check(chip8, instruction);
+        self.track_flag_register(address, instruction);
+        let warning = warning?;
+        if self.warnings.contains_key(&address) {
+            return None;
+        }
+        self.warnings.insert(address, warning.clone());
+        Some(warning)
+    }
+
+    pub fn warnings(&self) -> &BTreeMap<u16, String> {
+        &self.warnings
+    }
+
+    fn check(&self, chip8: &Chip8, instruction: Instruction) -> Option<String> {
+        let registers = chip8.registers();
+        if sets_flag(instruction) {
+            if let Instruction::Alu(_, 0xF, _) = instruction {
+                return Some(format!("{} puts both its result and its flag in VF, which interpreters order differently", instruction));
+            }
+            if let Some(written) = self.flag_register {
+                return Some(format!("{} overwrites VF with a flag before the value put there at {:#05X} is read", instruction, written));
+            }
+        }
+        match instruction {
+            Instruction::Draw(x, y, rows) => {
+                let (x, y) = (registers[x as usize] as usize, registers[y as usize] as usize);
+                if rows == 0 {
+                    return Some(format!("{} draws nothing here, and a 16x16 sprite on SCHIP", instruction));
+                }
+                if x >= run::WIDTH || y >= run::HEIGHT {
+                    return Some(format!("{} draws at ({}, {}), off the screen, which interpreters wrap or clip", instruction, x, y));
+                }
+            }
+            Instruction::LoadFont(x) if registers[x as usize] > 0xF => {
+                return Some(format!("{} points I at the font for {:#04X}, which isn't a digit", instruction, registers[x as usize]));
+            }
+            Instruction::SkipIfKey(x) | Instruction::SkipIfNotKey(x) if registers[x as usize] > 0xF => {
+                return Some(format!("{} tests key {:#04X}, which isn't a key", instruction, registers[x as usize]));
+            }
+            _ => {}
+        }
+        if let Some((Access::Write, written)) = instruction.memory_access(chip8.index()) {
+            let mut code = written.filter(|address| self.code.get(*address as usize).copied().unwrap_or(false));
+            if let Some(start) = code.next() {
+                let end = code.next_back().unwrap_or(start);
+                let range = if start == end { format!("{:#05X}", start) } else { format!("{:#05X}-{:#05X}", start, end) };
+                return Some(format!("{} writes over code at {}", instruction, range));
+            }
+        }
+        None
+    }
+
+    /// Remembers an instruction putting a value in VF until something reads it
+    fn track_flag_register(&mut self, address: u16, instruction: Instruction) {
+        if instruction.registers().contains(&0xF) {
+            self.flag_register = None;
+        }
+        let written = match instruction {
+            Instruction::Load(0xF, _) | Instruction::Add(0xF, _) | Instruction::Random(0xF, _) | Instruction::GetDelay(0xF) => true,
+            Instruction::Alu(AluOp::Move, 0xF, _) | Instruction::Alu(AluOp::Or, 0xF, _) | Instruction::Alu(AluOp::And, 0xF, _) => true,
+            Instruction::Alu(AluOp::Xor, 0xF, _) => true,
+            Instruction::LoadRegisters(x) => x == 0xF,
+            _ => false,
+        };
+        if written {
+            self.flag_register = Some(address);
+        } else if sets_flag(instruction) {
+            self.flag_register = None;
+        }
+    }
+}
+
+/// Whether the instruction sets VF as a flag
+fn sets_flag(instruction: Instruction) -> bool {
+    match instruction {
+        Instruction::Alu(op, _, _) => !matches!(op, AluOp::Move | AluOp::Or | AluOp::And | AluOp::Xor),
+        Instruction::Draw(..) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Chip8;
+    use crate::diagnostics::Diagnostics;
+
+    /// A value in VF lost to a flag, an off-screen draw and a write over code are warned about
+    #[test]
+    fn test_diagnostics() {
+        // vf := 3, v0 += v1, v2 := 70, sprite v2 v0 1, i := 0x200, bcd v0, jump 0x200
+        let program = [0x6F, 0x03, 0x80, 0x14, 0x62, 0x46, 0xD2, 0x01, 0xA2, 0x00, 0xF0, 0x33, 0x12, 0x00];
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&program);
+        let mut diagnostics = Diagnostics::default();
+        let mut warnings = Vec::new();
+        for _ in 0..7 {
+            warnings.extend(diagnostics.record(&chip8));
+            chip8.emulate_cycle();
+        }
+        assert_eq!(
+            warnings,
+            vec![
+                "ADD V0, V1 overwrites VF with a flag before the value put there at 0x200 is read",
+                "DRW V2, V0, 1 draws at (70, 0), off the screen, which interpreters wrap or clip",
+                "LD B, V0 writes over code at 0x200-0x202",
+            ]
+        );
+        assert_eq!(diagnostics.warnings().len(), 3);
+    }
+}
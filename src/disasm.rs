@@ -0,0 +1,330 @@
+//! ROM disassembler.
+//!
+//! Code is told apart from data by following every path the program can take from the load
+//! address: jumps, subroutine calls and both sides of every skip. Whatever is never reached, like
+//! sprites, is listed one byte per line with its bit pattern instead of as garbage instructions.
+//! Jump and call targets get labels, as do the addresses `LD I` points at outside the code.
+//! Targets of `JP V0` depend on V0 and can't be followed.
+//!
+//! With the symbol file of a ROM built by the assembler, the listing uses the labels of the
+//! source and shows the source line above each instruction. The notes file next to the ROM, see
+//! `notes`, names memory the same way and adds its comments.
+//!
+//! The listing can also be written as Octo source that assembles back into the same ROM, with
+//! the labels above and the data as blocks of binary bytes.
+
+use crate::chip8::{AluOp, Instruction, Variant};
+use crate::symbols::{SourceCache, Symbols};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+/// Address programs are loaded at
+pub const LOAD_ADDRESS: u16 = 0x200;
+
+/// One line of the listing
+pub struct Line {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    /// `None` for data
+    pub instruction: Option<Instruction>,
+    /// Label of this address
+    pub label: Option<String>,
+    /// Label of the address the instruction refers to
+    pub target: Option<String>,
+    /// Source line the instruction was assembled from, when symbols are given
+    pub source: Option<String>,
+    /// Comment of the note starting at this address
+    pub comment: Option<String>,
+}
+
+pub fn disassemble(rom: &[u8], variant: Variant) -> Vec<Line> {
+    let (code, labels) = analyze(rom, variant);
+    let label = |address: u16| labels.get(&address).cloned();
+
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let address = LOAD_ADDRESS + offset as u16;
+        if code[offset] {
+            let instruction = decode_at(rom, offset, variant).unwrap();
+            let target = target_of(instruction).and_then(label);
+            lines.push(Line { address, bytes: rom[offset..offset + 2].to_vec(), instruction: Some(instruction), label: label(address), target, source: None, comment: None });
+            offset += 2;
+        } else {
+            lines.push(Line { address, bytes: vec![rom[offset]], instruction: None, label: label(address), target: None, source: None, comment: None });
+            offset += 1;
+        }
+    }
+    lines
+}
+
+/// Replaces the generated labels with the ones from the source and adds the source lines and
+/// the comments of notes
+pub fn apply_symbols(lines: &mut [Line], symbols: &Symbols) {
+    let mut sources = SourceCache::default();
+    for line in lines.iter_mut() {
+        if let Some(label) = symbols.label_at(line.address) {
+            line.label = Some(label.to_string());
+        }
+        line.comment = symbols.note_at(line.address).filter(|note| note.start == line.address && !note.comment.is_empty()).map(|note| note.comment.clone());
+        if let Some(instruction) = line.instruction {
+            if let Some(label) = target_of(instruction).and_then(|target| symbols.label_at(target)) {
+                line.target = Some(label.to_string());
+            }
+            line.source = symbols.line_at(line.address).map(|source| sources.describe(source));
+        }
+    }
+}
+
+/// Line for whatever is at an address of the machine's memory, decoded as an instruction if it
+/// is one. Labels come from `apply_symbols`.
+pub fn line_at(memory: &[u8], address: u16, variant: Variant) -> Line {
+    let offset = address as usize;
+    match decode_at(memory, offset, variant) {
+        Some(instruction) => Line { address, bytes: memory[offset..offset + 2].to_vec(), instruction: Some(instruction), label: None, target: None, source: None, comment: None },
+        None => Line { address, bytes: vec![memory[offset]], instruction: None, label: None, target: None, source: None, comment: None },
+    }
+}
+
+/// Finds the bytes reachable as instructions and names the addresses referred to
+fn analyze(rom: &[u8], variant: Variant) -> (Vec<bool>, BTreeMap<u16, String>) {
+    let mut code = vec![false; rom.len()];
+    let mut calls = Vec::new();
+    let mut jumps = Vec::new();
+    let mut data = Vec::new();
+
+    let mut pending = vec![LOAD_ADDRESS];
+    while let Some(address) = pending.pop() {
+        let offset = match address.checked_sub(LOAD_ADDRESS) {
+            Some(offset) => offset as usize,
+            None => continue,
+        };
+        if code.get(offset).copied().unwrap_or(true) {
+            // Already visited or outside the ROM
+            continue;
+        }
+        let instruction = match decode_at(rom, offset, variant) {
+            Some(instruction) => instruction,
+            None => continue,
+        };
+        code[offset] = true;
+        code[offset + 1] = true;
+
+        match instruction {
+            Instruction::Jump(target) => jumps.push(target),
+            Instruction::Call(target) => calls.push(target),
+            Instruction::LoadIndex(target) => data.push(target),
+            _ => {}
+        }
+        pending.extend(successors(address, instruction));
+    }
+
+    let in_rom = |address: u16| address >= LOAD_ADDRESS && ((address - LOAD_ADDRESS) as usize) < rom.len();
+    let mut labels = BTreeMap::new();
+    for address in data.into_iter().filter(|address| in_rom(*address)) {
+        if !code[(address - LOAD_ADDRESS) as usize] {
+            labels.insert(address, format!("data_{:03X}", address));
+        }
+    }
+    for address in jumps {
+        labels.insert(address, format!("label_{:03X}", address));
+    }
+    // A call target is a subroutine even if it is also jumped to
+    for address in calls {
+        labels.insert(address, format!("sub_{:03X}", address));
+    }
+    (code, labels)
+}
+
+/// Addresses the program can go on to after the instruction at an address. Returns and jumps
+/// by V0 go where the disassembly can't tell.
+pub fn successors(address: u16, instruction: Instruction) -> Vec<u16> {
+    let next = address + 2;
+    match instruction {
+        Instruction::Return | Instruction::JumpOffset(_) => vec![],
+        Instruction::Jump(target) => vec![target],
+        Instruction::Call(target) => vec![target, next],
+        Instruction::SkipIfEqual(..)
+        | Instruction::SkipIfNotEqual(..)
+        | Instruction::SkipIfRegistersEqual(..)
+        | Instruction::SkipIfRegistersNotEqual(..)
+        | Instruction::SkipIfKey(_)
+        | Instruction::SkipIfNotKey(_)
+        | Instruction::SkipIfSecondKey(_)
+        | Instruction::SkipIfNotSecondKey(_) => vec![next, next + 2],
+        _ => vec![next],
+    }
+}
+
+fn decode_at(rom: &[u8], offset: usize, variant: Variant) -> Option<Instruction> {
+    let bytes = rom.get(offset..offset + 2)?;
+    Instruction::decode(u16::from_be_bytes([bytes[0], bytes[1]]), variant)
+}
+
+/// Address an instruction jumps to, calls or points I at
+fn target_of(instruction: Instruction) -> Option<u16> {
+    match instruction {
+        Instruction::Jump(target) | Instruction::Call(target) | Instruction::LoadIndex(target) => Some(target),
+        _ => None,
+    }
+}
+
+/// Octo source for a listing. The code at the load address is labelled `main` so Octo doesn't
+/// add a jump to it.
+pub fn to_octo(lines: &[Line]) -> String {
+    let labels: HashSet<&str> = lines.iter().filter_map(|line| line.label.as_deref()).collect();
+    let target = |line: &Line, address: u16| match line.target.as_deref() {
+        Some(label) if labels.contains(label) => label.to_string(),
+        _ => format!("{:#05X}", address),
+    };
+
+    let mut source = String::from(": main\n");
+    for line in lines {
+        if let Some(label) = &line.label {
+            source.push_str(&format!(": {}\n", label));
+        }
+        let statement = match line.instruction {
+            // Subroutines with a name are called by writing the name
+            Some(Instruction::Call(address)) if line.target.as_deref().is_some_and(|label| labels.contains(label)) => {
+                target(line, address)
+            }
+            Some(instruction) => match octo_statement(instruction) {
+                Some(statement) => statement.replace("{target}", &target(line, target_of(instruction).unwrap_or(0))),
+                // No Octo syntax for it, emit the raw bytes
+                None => format!("{:#04X} {:#04X}", line.bytes[0], line.bytes[1]),
+            },
+            None => format!("0b{:08b}", line.bytes[0]),
+        };
+        source.push_str(&format!("\t{}\n", statement));
+    }
+    source
+}
+
+/// Octo statement for an instruction, with `{target}` standing for the address or its label
+fn octo_statement(instruction: Instruction) -> Option<String> {
+    let statement = match instruction {
+        Instruction::ClearScreen => String::from("clear"),
+        Instruction::Return => String::from("return"),
+        Instruction::Jump(_) => String::from("jump {target}"),
+        Instruction::Call(_) => String::from(":call {target}"),
+        // Octo conditions say when the next instruction runs, the opposite of when it is skipped
+        Instruction::SkipIfEqual(x, nn) => format!("if v{:x} != {:#04X} then", x, nn),
+        Instruction::SkipIfNotEqual(x, nn) => format!("if v{:x} == {:#04X} then", x, nn),
+        Instruction::SkipIfRegistersEqual(x, y) => format!("if v{:x} != v{:x} then", x, y),
+        Instruction::SkipIfRegistersNotEqual(x, y) => format!("if v{:x} == v{:x} then", x, y),
+        Instruction::Load(x, nn) => format!("v{:x} := {:#04X}", x, nn),
+        Instruction::Add(x, nn) => format!("v{:x} += {:#04X}", x, nn),
+        Instruction::Alu(op, x, y) => {
+            let operator = match op {
+                AluOp::Move => ":=",
+                AluOp::Or => "|=",
+                AluOp::And => "&=",
+                AluOp::Xor => "^=",
+                AluOp::Add => "+=",
+                AluOp::Sub => "-=",
+                AluOp::ShiftRight => ">>=",
+                AluOp::SubReverse => "=-",
+                AluOp::ShiftLeft => "<<=",
+            };
+            format!("v{:x} {} v{:x}", x, operator, y)
+        }
+        Instruction::LoadIndex(_) => String::from("i := {target}"),
+        Instruction::JumpOffset(nnn) => format!("jump0 {:#05X}", nnn),
+        Instruction::Random(x, nn) => format!("v{:x} := random {:#04X}", x, nn),
+        Instruction::Draw(x, y, n) => format!("sprite v{:x} v{:x} {}", x, y, n),
+        Instruction::SkipIfKey(x) => format!("if v{:x} -key then", x),
+        Instruction::SkipIfNotKey(x) => format!("if v{:x} key then", x),
+        Instruction::SkipIfSecondKey(_) | Instruction::SkipIfNotSecondKey(_) => return None,
+        Instruction::GetDelay(x) => format!("v{:x} := delay", x),
+        Instruction::SetDelay(x) => format!("delay := v{:x}", x),
+        Instruction::SetSound(x) => format!("buzzer := v{:x}", x),
+        Instruction::AddIndex(x) => format!("i += v{:x}", x),
+        Instruction::LoadFont(x) => format!("i := hex v{:x}", x),
+        Instruction::StoreBcd(x) => format!("bcd v{:x}", x),
+        Instruction::StoreRegisters(x) => format!("save v{:x}", x),
+        Instruction::LoadRegisters(x) => format!("load v{:x}", x),
+    };
+    Some(statement)
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(label) = &self.label {
+            writeln!(f, "{}:", label)?;
+        }
+        if let Some(source) = &self.source {
+            writeln!(f, "; {}", source)?;
+        }
+        if let Some(comment) = &self.comment {
+            writeln!(f, "; {}", comment)?;
+        }
+        let bytes: Vec<String> = self.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        write!(f, "{:#05X}  {:<5}  ", self.address, bytes.join(" "))?;
+        match &self.instruction {
+            Some(instruction) => {
+                write!(f, "{:<16}  ; {}", instruction.to_string(), instruction.describe())?;
+                if let Some(target) = &self.target {
+                    write!(f, " ({})", target)?;
+                }
+                Ok(())
+            }
+            None => {
+                // Data is most often sprites, so show the bits as pixels
+                let pixels: String = (0..8).map(|bit| if self.bytes[0] & (0x80 >> bit) != 0 { '#' } else { '.' }).collect();
+                write!(f, "{:<16}  ; {}", format!("DB {:#04X}", self.bytes[0]), pixels)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Variant;
+    use crate::assembler::assemble_with_symbols;
+    use crate::disasm::{apply_symbols, disassemble, to_octo};
+
+    /// Listing lines show address, bytes, mnemonic and description
+    #[test]
+    fn test_listing() {
+        let lines = disassemble(&[0x6A, 0x02, 0x12, 0x00], Variant::Chip8);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].to_string(), "label_200:\n0x200  6A 02  LD VA, 0x02       ; VA = 0x02");
+        assert_eq!(lines[1].to_string(), "0x202  12 00  JP 0x200          ; jump to 0x200 (label_200)");
+    }
+
+    /// Sprite data after the code isn't decoded, both sides of a skip are followed
+    #[test]
+    fn test_code_and_data() {
+        let rom = [
+            0xA2, 0x0A, // 0x200: LD I, 0x20A
+            0x30, 0x01, // 0x202: SE V0, 0x01
+            0x22, 0x08, // 0x204: CALL 0x208
+            0x12, 0x06, // 0x206: JP 0x206
+            0x00, 0xEE, // 0x208: RET
+            0xF0, 0x90, // 0x20A: sprite, would decode as garbage
+        ];
+        let lines = disassemble(&rom, Variant::Chip8);
+        let code: Vec<u16> = lines.iter().filter(|line| line.instruction.is_some()).map(|line| line.address).collect();
+        assert_eq!(code, vec![0x200, 0x202, 0x204, 0x206, 0x208]);
+        assert_eq!(lines[4].label.as_deref(), Some("sub_208"));
+        assert_eq!(lines[5].to_string(), "data_20A:\n0x20A  F0     DB 0xF0           ; ####....");
+        assert_eq!(lines[6].address, 0x20B);
+    }
+
+    /// Symbols bring back the source's labels and lines
+    #[test]
+    fn test_symbols() {
+        let (rom, symbols) = assemble_with_symbols(": main\n: spin\n\tjump spin", None).unwrap();
+        let mut lines = disassemble(&rom, Variant::Chip8);
+        apply_symbols(&mut lines, &symbols);
+        assert_eq!(lines[0].to_string(), "main:\n; line 3\n0x200  12 00  JP 0x200          ; jump to 0x200 (main)");
+    }
+
+    /// Octo output uses labels where they exist and binary for data
+    #[test]
+    fn test_octo() {
+        let rom = [0xA2, 0x06, 0x3A, 0x01, 0x12, 0x00, 0xF0];
+        let source = to_octo(&disassemble(&rom, Variant::Chip8));
+        assert_eq!(source, ": main\n: label_200\n\ti := data_206\n\tif va != 0x01 then\n\tjump label_200\n: data_206\n\t0b11110000\n");
+    }
+}
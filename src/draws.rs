@@ -0,0 +1,96 @@
+//! Sprites drawn by DXYN in the current frame, with the screen before and after each, for the
+//! draw inspector of the debugger window and the `draws` command. Collision logic is easier to
+//! follow one draw at a time than from the finished frame.
+
+use crate::chip8::{Chip8, Instruction};
+
+/// Draws kept at most in a frame, a program drawing more is likely stuck in a loop
+const MAX_DRAWS: usize = 256;
+
+pub struct Draw {
+    /// Address of the DXYN
+    pub address: u16,
+    pub x: u8,
+    pub y: u8,
+    pub rows: u8,
+    /// Where the sprite was read from
+    pub index: u16,
+    /// Whether the draw turned a pixel off, what VF was set to
+    pub collision: bool,
+    pub before: Vec<u8>,
+    pub after: Vec<u8>,
+}
+
+#[derive(Default)]
+pub struct Draws {
+    frame: Vec<Draw>,
+}
+
+impl Draws {
+    /// Records the instruction about to run if it's a DXYN, running it on a copy of the machine
+    /// to see what it draws
+    pub fn record(&mut self, chip8: &Chip8, instruction: Option<Instruction>) {
+        let (x, y, rows) = match instruction {
+            Some(Instruction::Draw(x, y, rows)) if self.frame.len() < MAX_DRAWS => (x, y, rows),
+            _ => return,
+        };
+        let mut after = chip8.clone();
+        after.emulate_cycle();
+        let registers = chip8.registers();
+        self.frame.push(Draw {
+            address: chip8.program_counter(),
+            x: registers[x as usize],
+            y: registers[y as usize],
+            rows,
+            index: chip8.index(),
+            collision: after.registers()[0xF] == 1,
+            before: chip8.display().to_vec(),
+            after: after.display().to_vec(),
+        });
+    }
+
+    /// Forgets the draws of the frame before
+    pub fn start_frame(&mut self) {
+        self.frame.clear();
+    }
+
+    pub fn draws(&self) -> &[Draw] {
+        &self.frame
+    }
+}
+
+impl Draw {
+    /// One line describing the draw, without its address
+    pub fn summary(&self) -> String {
+        format!("X {} Y {} H {} I {:03X} {}", self.x, self.y, self.rows, self.index, if self.collision { "HIT" } else { "NO HIT" })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::{Chip8, Instruction};
+    use crate::draws::Draws;
+
+    /// Drawing the same sprite twice erases it, which the second draw records as a collision
+    #[test]
+    fn test_draws() {
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&[0x60, 0x05, 0xD0, 0x05, 0xD0, 0x05]);
+        let mut draws = Draws::default();
+        chip8.emulate_cycle();
+        for _ in 0..2 {
+            draws.record(&chip8, Some(Instruction::Draw(0, 0, 5)));
+            chip8.emulate_cycle();
+        }
+        draws.record(&chip8, None);
+
+        let frame = draws.draws();
+        assert_eq!(frame.len(), 2);
+        assert_eq!((frame[0].address, frame[0].summary()), (0x202, String::from("X 5 Y 5 H 5 I 000 NO HIT")));
+        assert_eq!(frame[1].summary(), "X 5 Y 5 H 5 I 000 HIT");
+        assert_eq!(frame[0].after, frame[1].before);
+        assert_eq!(frame[1].after, frame[0].before);
+        draws.start_frame();
+        assert!(draws.draws().is_empty());
+    }
+}
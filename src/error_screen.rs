@@ -0,0 +1,68 @@
+//! Screen shown instead of the game when a source ROM fails to assemble.
+//!
+//! The display is too small for the message, so it goes in the window title while the screen
+//! shows the line number of the error in large digits. Escape or closing the window quits.
+
+use crate::assembler::Error;
+use crate::chip8::CHIP8_FONTSET;
+use crate::fatal;
+use crate::run::{HEIGHT, WIDTH};
+use minifb::{Key, Scale, ScaleMode, Window, WindowOptions};
+
+const BACKGROUND: u32 = 0xAA0000;
+const FOREGROUND: u32 = 0xFFFFFF;
+
+/// Digits are drawn from the font at twice its size
+const SCALE: usize = 2;
+
+pub fn show(error: &Error) {
+    let mut window = Window::new(
+        &error.to_string(),
+        WIDTH,
+        HEIGHT,
+        WindowOptions {
+            borderless: false,
+            transparency: false,
+            title: true,
+            resize: false,
+            scale: Scale::X16,
+            scale_mode: ScaleMode::Stretch,
+            topmost: false,
+        },
+    )
+        .unwrap_or_else(fatal);
+
+    let mut buffer: Vec<u32> = vec![BACKGROUND; WIDTH * HEIGHT];
+    if error.line > 0 {
+        draw_number(&mut buffer, error.line);
+    }
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        window.update_with_buffer(&buffer, WIDTH, HEIGHT).unwrap();
+    }
+}
+
+/// Draws a decimal number centered on the screen
+fn draw_number(buffer: &mut [u32], number: usize) {
+    let digits = number.to_string();
+    let pitch = 5 * SCALE;
+    let left = WIDTH.saturating_sub(digits.len() * pitch - SCALE) / 2;
+    let top = (HEIGHT - 5 * SCALE) / 2;
+    for (position, digit) in digits.chars().enumerate() {
+        let glyph = digit.to_digit(10).unwrap() as usize * 5;
+        for (y, bits) in CHIP8_FONTSET[glyph..glyph + 5].iter().enumerate() {
+            for x in 0..4 {
+                if bits & (0x80 >> x) == 0 {
+                    continue;
+                }
+                for dy in 0..SCALE {
+                    for dx in 0..SCALE {
+                        let column = left + position * pitch + x * SCALE + dx;
+                        if column < WIDTH {
+                            buffer[(top + y * SCALE + dy) * WIDTH + column] = FOREGROUND;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! Expressions over the machine state, for breakpoint conditions and the like.
+//!
+//! They are written much like in C: `V3 == 0x1F`, `I > 0xE00 && [I] != 0`. Names are the
+//! registers `V0` to `VF`, `I`, `PC`, `SP` (the call depth), `DT` and `ST`, and labels, which
+//! stand for their address. `[address]` reads the byte of memory at an address. Comparisons and
+//! `&&`, `||` and `!` give 1 for true and 0 for false, and any value but 0 counts as true.
+//!
+//! From loosest to tightest, the operators are `||`, `&&`, `== !=`, `< <= > >=`, `|`, `^`, `&`,
+//! `<< >>`, `+ -`, `* / %`, and the prefix operators `! - ~`.
+
+use crate::chip8::Chip8;
+use crate::symbols::Symbols;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expression {
+    /// As written, to show it back
+    text: String,
+    node: Node,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Number(i64),
+    Register(usize),
+    Index,
+    ProgramCounter,
+    StackPointer,
+    Delay,
+    Sound,
+    Memory(Box<Node>),
+    Unary(&'static str, Box<Node>),
+    Binary(&'static str, Box<Node>, Box<Node>),
+}
+
+/// Binary operators by precedence, loosest first
+const BINARY: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["==", "!="],
+    &["<=", ">=", "<", ">"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+/// Operator spellings, longest first so `<=` isn't read as `<`
+const OPERATORS: [&str; 24] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>", "<", ">", "|", "^", "&", "+", "-", "*", "/", "%", "!", "~", "(", ")",
+    "[", "]",
+];
+
+impl Expression {
+    /// Parses an expression, looking labels up in the symbols
+    pub fn parse(text: &str, symbols: &Symbols) -> Result<Self, String> {
+        let tokens = tokenize(text, symbols)?;
+        let mut parser = Parser { tokens, position: 0, symbols };
+        let node = parser.binary(0)?;
+        if let Some(token) = parser.tokens.get(parser.position) {
+            return Err(format!("Unexpected {} in {}", token, text));
+        }
+        Ok(Expression { text: text.trim().to_string(), node })
+    }
+
+    pub fn evaluate(&self, chip8: &Chip8) -> i64 {
+        evaluate(&self.node, chip8)
+    }
+
+    pub fn is_true(&self, chip8: &Chip8) -> bool {
+        self.evaluate(chip8) != 0
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+/// Splits an expression into operators and words. Labels can have `-` in them like in Octo
+/// source, so a `-` is only part of a word when that makes it a label.
+fn tokenize(text: &str, symbols: &Symbols) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let length = match OPERATORS.iter().find(|operator| rest.starts_with(**operator)) {
+            Some(operator) => operator.len(),
+            None => {
+                let word = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-')).unwrap_or(rest.len());
+                // The longest label the word starts with, or the word up to its first `-`
+                let ends = std::iter::once(word).chain(rest[..word].rmatch_indices('-').map(|(end, _)| end));
+                ends.clone()
+                    .find(|end| symbols.labels.contains_key(&rest[..*end]))
+                    .unwrap_or_else(|| ends.last().unwrap())
+            }
+        };
+        if length == 0 {
+            return Err(format!("Unexpected {} in {}", rest.chars().next().unwrap(), text));
+        }
+        tokens.push(rest[..length].to_string());
+        rest = rest[length..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<String>,
+    position: usize,
+    symbols: &'a Symbols,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Result<String, String> {
+        let token = self.tokens.get(self.position).cloned().ok_or("Unfinished expression")?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.next()? {
+            token if token == expected => Ok(()),
+            token => Err(format!("Expected {} but found {}", expected, token)),
+        }
+    }
+
+    /// Operators of a precedence level and tighter
+    fn binary(&mut self, level: usize) -> Result<Node, String> {
+        if level == BINARY.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some(operator) = self.tokens.get(self.position).and_then(|token| BINARY[level].iter().find(|operator| **operator == token)) {
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            left = Node::Binary(operator, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        let token = self.next()?;
+        match token.as_str() {
+            "!" => Ok(Node::Unary("!", Box::new(self.unary()?))),
+            "-" => Ok(Node::Unary("-", Box::new(self.unary()?))),
+            "~" => Ok(Node::Unary("~", Box::new(self.unary()?))),
+            "(" => {
+                let node = self.binary(0)?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            "[" => {
+                let node = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Node::Memory(Box::new(node)))
+            }
+            _ => self.name(&token),
+        }
+    }
+
+    fn name(&self, token: &str) -> Result<Node, String> {
+        let upper = token.to_ascii_uppercase();
+        let register = upper.strip_prefix('V').filter(|digit| digit.len() == 1).and_then(|digit| usize::from_str_radix(digit, 16).ok());
+        if let Some(register) = register {
+            return Ok(Node::Register(register));
+        }
+        match upper.as_str() {
+            "I" => return Ok(Node::Index),
+            "PC" => return Ok(Node::ProgramCounter),
+            "SP" => return Ok(Node::StackPointer),
+            "DT" => return Ok(Node::Delay),
+            "ST" => return Ok(Node::Sound),
+            _ => {}
+        }
+        if let Some(address) = self.symbols.labels.get(token) {
+            return Ok(Node::Number(*address as i64));
+        }
+        let number = match upper.strip_prefix("0X") {
+            Some(hex) => i64::from_str_radix(hex, 16).ok(),
+            None => match upper.strip_prefix("0B") {
+                Some(binary) => i64::from_str_radix(binary, 2).ok(),
+                None => token.parse().ok(),
+            },
+        };
+        number.map(Node::Number).ok_or_else(|| format!("Unknown name {}", token))
+    }
+}
+
+fn evaluate(node: &Node, chip8: &Chip8) -> i64 {
+    match node {
+        Node::Number(value) => *value,
+        Node::Register(register) => chip8.registers()[*register] as i64,
+        Node::Index => chip8.index() as i64,
+        Node::ProgramCounter => chip8.program_counter() as i64,
+        Node::StackPointer => chip8.call_stack().len() as i64,
+        Node::Delay => chip8.timers().0 as i64,
+        Node::Sound => chip8.timers().1 as i64,
+        Node::Memory(address) => {
+            let memory = chip8.memory();
+            memory[evaluate(address, chip8).rem_euclid(memory.len() as i64) as usize] as i64
+        }
+        Node::Unary(operator, operand) => {
+            let value = evaluate(operand, chip8);
+            match *operator {
+                "!" => (value == 0) as i64,
+                "-" => value.wrapping_neg(),
+                _ => !value,
+            }
+        }
+        Node::Binary(operator, left, right) => {
+            let left = evaluate(left, chip8);
+            // Both sides are always evaluated, reading the machine has no side effects
+            let right = evaluate(right, chip8);
+            match *operator {
+                "||" => (left != 0 || right != 0) as i64,
+                "&&" => (left != 0 && right != 0) as i64,
+                "==" => (left == right) as i64,
+                "!=" => (left != right) as i64,
+                "<" => (left < right) as i64,
+                "<=" => (left <= right) as i64,
+                ">" => (left > right) as i64,
+                ">=" => (left >= right) as i64,
+                "|" => left | right,
+                "^" => left ^ right,
+                "&" => left & right,
+                "<<" => left.wrapping_shl(right as u32),
+                ">>" => left.wrapping_shr(right as u32),
+                "+" => left.wrapping_add(right),
+                "-" => left.wrapping_sub(right),
+                "*" => left.wrapping_mul(right),
+                // Dividing by zero gives 0 rather than stopping the emulator
+                "/" => left.checked_div(right).unwrap_or(0),
+                _ => left.checked_rem(right).unwrap_or(0),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Chip8;
+    use crate::expression::Expression;
+    use crate::symbols::Symbols;
+
+    /// Precedence, names, labels and memory reads
+    #[test]
+    fn test_evaluate() {
+        let mut symbols = Symbols::default();
+        symbols.labels.insert(String::from("score"), 0xE00);
+        symbols.labels.insert(String::from("high-score"), 0xE01);
+        let mut chip8 = Chip8::new();
+        chip8.write_memory(0xE00, &[7]);
+
+        let value = |text: &str| Expression::parse(text, &symbols).unwrap().evaluate(&chip8);
+        assert_eq!(value("1 + 2 * 3"), 7);
+        assert_eq!(value("(1 + 2) * 3"), 9);
+        assert_eq!(value("PC == 0x200 && v3 == 0"), 1);
+        assert_eq!(value("I > 0xE00 || !0"), 1);
+        assert_eq!(value("[score] + [score + 1]"), 7);
+        assert_eq!(value("high-score-score"), 1);
+        assert_eq!(value("-1 < 0b10 >> 1"), 1);
+        assert_eq!(value("5 / 0"), 0);
+
+        assert!(Expression::parse("V3 ==", &symbols).is_err());
+        assert!(Expression::parse("V3 == 1)", &symbols).is_err());
+        assert!(Expression::parse("lives > 0", &symbols).err().unwrap().contains("lives"));
+        assert_eq!(Expression::parse(" V3 == 0x1F ", &symbols).unwrap().to_string(), "V3 == 0x1F");
+    }
+}
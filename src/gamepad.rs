@@ -0,0 +1,177 @@
+//! Gamepad input through gilrs.
+//!
+//! Buttons are bound to keypad keys in the `[gamepad]` section of the config file, with optional
+//! per-ROM layouts keyed by ROM file name:
+//!
+//! ```toml
+//! [gamepad.buttons]
+//! DPadUp = "2"
+//! South = "5"
+//!
+//! [gamepad.roms."brix.ch8".buttons]
+//! DPadLeft = "4"
+//! DPadRight = "6"
+//! ```
+//!
+//! Controllers can be plugged in and out while the emulator runs. With `rumble = true` under
+//! `[gamepad]`, controllers that support force feedback rumble while the sound timer is active.
+
+use gilrs::ff::{BaseEffect, BaseEffectType, Effect, EffectBuilder, Replay, Ticks};
+use gilrs::{Button, EventType, Gilrs};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use tracing::{info, warn};
+
+const BUTTONS: [Button; 19] = [
+    Button::South, Button::East, Button::North, Button::West, Button::C, Button::Z,
+    Button::LeftTrigger, Button::LeftTrigger2, Button::RightTrigger, Button::RightTrigger2,
+    Button::Select, Button::Start, Button::Mode, Button::LeftThumb, Button::RightThumb,
+    Button::DPadUp, Button::DPadDown, Button::DPadLeft, Button::DPadRight,
+];
+
+/// Bindings used when the config file doesn't give any: the D-pad on the 2/4/6/8 keys most games
+/// use for movement, and the face buttons on the common action keys
+const DEFAULT_BINDINGS: [(Button, usize); 8] = [
+    (Button::DPadUp, 0x2),
+    (Button::DPadDown, 0x8),
+    (Button::DPadLeft, 0x4),
+    (Button::DPadRight, 0x6),
+    (Button::South, 0x5),
+    (Button::East, 0xF),
+    (Button::Start, 0x1),
+    (Button::Select, 0x0),
+];
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct GamepadConfig {
+    /// Button name to CHIP-8 key (hex digit)
+    pub buttons: BTreeMap<String, String>,
+    /// Layouts replacing `buttons` for specific ROMs, keyed by ROM file name
+    pub roms: BTreeMap<String, RomGamepadConfig>,
+    /// Rumble in place of the buzzer
+    pub rumble: bool,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RomGamepadConfig {
+    pub buttons: BTreeMap<String, String>,
+}
+
+const RUMBLE_MAGNITUDE: u16 = 40_000;
+
+pub struct Gamepads {
+    gilrs: Gilrs,
+    bindings: Vec<(Button, usize)>,
+    rumble_enabled: bool,
+    // Effect currently playing, it stops when dropped
+    rumble: Option<Effect>,
+}
+
+impl Gamepads {
+    pub fn new(config: &GamepadConfig, rom: &Path) -> Result<Self, String> {
+        let gilrs = Gilrs::new().map_err(|e| format!("Could not initialize gamepad support\n{}", e))?;
+        for (_, gamepad) in gilrs.gamepads() {
+            info!("Gamepad connected: {}", gamepad.name());
+        }
+
+        let rom_buttons = rom
+            .file_name()
+            .and_then(|name| config.roms.get(name.to_string_lossy().as_ref()))
+            .map(|rom_config| &rom_config.buttons);
+        let buttons = rom_buttons.unwrap_or(&config.buttons);
+        let bindings = if buttons.is_empty() {
+            DEFAULT_BINDINGS.to_vec()
+        } else {
+            parse_bindings(buttons)?
+        };
+
+        Ok(Gamepads { gilrs, bindings, rumble_enabled: config.rumble, rumble: None })
+    }
+
+    /// Rumbles every connected gamepad that supports it for `ticks` 60Hz timer ticks, or stops
+    /// for 0
+    pub fn rumble(&mut self, ticks: u8) {
+        if !self.rumble_enabled {
+            return;
+        }
+        if ticks == 0 {
+            if let Some(effect) = self.rumble.take() {
+                let _ = effect.stop();
+            }
+            return;
+        }
+
+        let ids: Vec<_> = self
+            .gilrs
+            .gamepads()
+            .filter(|(_, gamepad)| gamepad.is_ff_supported())
+            .map(|(id, _)| id)
+            .collect();
+        if ids.is_empty() {
+            return;
+        }
+
+        let effect = EffectBuilder::new()
+            .add_effect(BaseEffect {
+                kind: BaseEffectType::Strong { magnitude: RUMBLE_MAGNITUDE },
+                scheduling: Replay {
+                    play_for: Ticks::from_ms(ticks.max(1) as u32 * 1000 / 60),
+                    ..Default::default()
+                },
+                envelope: Default::default(),
+            })
+            .gamepads(&ids)
+            .finish(&mut self.gilrs);
+        match effect.and_then(|effect| effect.play().map(|_| effect)) {
+            Ok(effect) => self.rumble = Some(effect),
+            Err(error) => warn!("Could not rumble gamepad\n{}", error),
+        }
+    }
+
+    /// Handles pending gamepad events (including hotplugging) and returns the keypad keys held
+    /// down on any connected gamepad
+    pub fn pressed_keys(&mut self) -> [bool; 16] {
+        while let Some(event) = self.gilrs.next_event() {
+            match event.event {
+                EventType::Connected => {
+                    info!("Gamepad connected: {}", self.gilrs.gamepad(event.id).name())
+                }
+                EventType::Disconnected => {
+                    info!("Gamepad disconnected: {}", self.gilrs.gamepad(event.id).name())
+                }
+                _ => {}
+            }
+        }
+
+        let mut keys = [false; 16];
+        for (_, gamepad) in self.gilrs.gamepads() {
+            for (button, chip8_key) in &self.bindings {
+                if gamepad.is_pressed(*button) {
+                    keys[*chip8_key] = true;
+                }
+            }
+        }
+        keys
+    }
+}
+
+fn parse_bindings(buttons: &BTreeMap<String, String>) -> Result<Vec<(Button, usize)>, String> {
+    buttons
+        .iter()
+        .map(|(button_name, chip8_key)| {
+            let button = BUTTONS
+                .iter()
+                .copied()
+                .find(|button| format!("{:?}", button).eq_ignore_ascii_case(button_name))
+                .ok_or_else(|| format!("Unknown gamepad button: {}", button_name))?;
+            let chip8_key = usize::from_str_radix(chip8_key, 16)
+                .ok()
+                .filter(|k| *k < 16)
+                .ok_or_else(|| format!("Invalid CHIP-8 key for {}: {}", button_name, chip8_key))?;
+            Ok((button, chip8_key))
+        })
+        .collect()
+}
@@ -0,0 +1,373 @@
+//! GDB remote serial protocol server, so GDB and the IDEs built on it can drive the debugger:
+//!
+//! ```text
+//! chip8 --gdb 1234 game.ch8
+//! gdb -ex "target remote :1234"
+//! ```
+//!
+//! The machine is described to GDB as an architecture of its own, with the registers in this
+//! order, 16-bit ones little-endian:
+//!
+//! | Register   | Bits |                                 |
+//! |------------|------|---------------------------------|
+//! | `v0`-`vf`  | 8    |                                 |
+//! | `i`        | 16   |                                 |
+//! | `pc`       | 16   |                                 |
+//! | `sp`       | 8    | number of subroutine calls deep |
+//! | `dt`, `st` | 8    | delay and sound timers          |
+//!
+//! Registers, memory, software and hardware breakpoints, stepping, continuing and interrupting
+//! are supported. Memory can be written, registers only read. Breakpoints are the debugger's own,
+//! so they show up at its prompt too.
+
+use crate::chip8::Chip8;
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tracing::{info, warn};
+
+/// Described in `qXfer:features:read`, so GDB knows the registers without knowing the machine
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+/// Stop replies, by the signal GDB is told stopped the machine
+pub const STOPPED_BY_TRAP: &str = "S05";
+pub const STOPPED_BY_INTERRUPT: &str = "S02";
+
+/// What came in from GDB
+pub enum Event {
+    Connected,
+    Packet(String),
+    /// Ctrl-C, to stop the running machine
+    Interrupt,
+    Disconnected,
+}
+
+/// What the next bytes from GDB were
+enum Received {
+    Event(Event),
+    /// A packet whose checksum is off, for GDB to send again
+    Corrupt,
+    /// Acknowledgements, and bytes between packets
+    Skipped,
+}
+
+/// What to do about a packet
+#[derive(Debug, PartialEq)]
+pub enum Response {
+    Reply(String),
+    /// Run until a breakpoint, replying then
+    Continue,
+    /// Run one instruction and reply
+    Step,
+    /// Let the machine run on without GDB
+    Detach,
+}
+
+/// Listens for one GDB connection at a time
+pub struct GdbServer {
+    events: Receiver<Event>,
+    /// Where replies go, `None` between connections
+    connection: Arc<Mutex<Option<TcpStream>>>,
+}
+
+impl GdbServer {
+    pub fn listen(port: u16) -> Result<Self, String> {
+        let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| format!("Could not listen for GDB on port {}: {}", port, e))?;
+        info!("Waiting for GDB on port {}", port);
+        let (sender, events) = mpsc::channel();
+        let connection = Arc::new(Mutex::new(None));
+        let shared = Arc::clone(&connection);
+        thread::spawn(move || {
+            for stream in listener.incoming().map_while(Result::ok) {
+                match stream.try_clone() {
+                    Ok(writer) => *shared.lock().unwrap() = Some(writer),
+                    Err(_) => continue,
+                }
+                if sender.send(Event::Connected).is_err() || !read_packets(stream, |event| sender.send(event).is_ok()) {
+                    break;
+                }
+                *shared.lock().unwrap() = None;
+                if sender.send(Event::Disconnected).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(GdbServer { events, connection })
+    }
+
+    /// The next event from GDB, if any came in
+    pub fn poll(&self) -> Option<Event> {
+        match self.events.try_recv() {
+            Ok(event) => Some(event),
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => None,
+        }
+    }
+
+    pub fn send(&self, packet: &str) {
+        if let Some(stream) = self.connection.lock().unwrap().as_mut() {
+            if let Err(error) = stream.write_all(frame(packet).as_bytes()) {
+                warn!("Could not reply to GDB: {}", error);
+            }
+        }
+    }
+}
+
+/// Reads packets off a connection, acknowledging each, until it closes. Returns false if the
+/// events stopped being taken.
+fn read_packets(mut stream: TcpStream, mut handle: impl FnMut(Event) -> bool) -> bool {
+    let mut received = Vec::new();
+    let mut buffer = [0; 1024];
+    while let Ok(length) = stream.read(&mut buffer) {
+        if length == 0 {
+            break;
+        }
+        received.extend_from_slice(&buffer[..length]);
+        while let Some((event, used)) = next_event(&received) {
+            received.drain(..used);
+            let packet = match event {
+                Received::Event(Event::Packet(packet)) => packet,
+                Received::Event(event) => {
+                    if !handle(event) {
+                        return false;
+                    }
+                    continue;
+                }
+                Received::Corrupt => {
+                    if stream.write_all(b"-").is_err() {
+                        return false;
+                    }
+                    continue;
+                }
+                Received::Skipped => continue,
+            };
+            if stream.write_all(b"+").is_err() || !handle(Event::Packet(packet)) {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// The first thing in what was received and the bytes it took, or nothing if a packet hasn't all
+/// arrived yet
+fn next_event(received: &[u8]) -> Option<(Received, usize)> {
+    match received.first()? {
+        0x03 => Some((Received::Event(Event::Interrupt), 1)),
+        b'$' => {
+            let end = received.iter().position(|byte| *byte == b'#')?;
+            let checksum = received.get(end + 1..end + 3)?;
+            let data = &received[1..end];
+            let sum = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+            let valid = std::str::from_utf8(checksum).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok()) == Some(sum);
+            if !valid {
+                return Some((Received::Corrupt, end + 3));
+            }
+            Some((Received::Event(Event::Packet(String::from_utf8_lossy(data).into_owned())), end + 3))
+        }
+        _ => Some((Received::Skipped, 1)),
+    }
+}
+
+/// A packet as sent, with its checksum
+fn frame(packet: &str) -> String {
+    let sum = packet.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+    format!("${}#{:02x}", packet, sum)
+}
+
+/// Answers a packet from GDB, setting and clearing breakpoints as asked
+pub fn respond(packet: &str, chip8: &mut Chip8, breakpoints: &mut BTreeSet<u16>) -> Response {
+    let reply = |text: &str| Response::Reply(text.to_string());
+    let (command, arguments) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+    match command {
+        "?" => reply(STOPPED_BY_TRAP),
+        "g" => Response::Reply(hex(&registers(chip8))),
+        "p" => match usize::from_str_radix(arguments, 16).ok().and_then(|register| register_bytes(chip8, register)) {
+            Some(bytes) => Response::Reply(hex(&bytes)),
+            None => reply("E01"),
+        },
+        "m" => match parse_range(arguments).and_then(|(address, length)| Some(address..address.checked_add(length)?)) {
+            Some(range) if range.end <= chip8.memory().len() => Response::Reply(hex(&chip8.memory()[range])),
+            _ => reply("E01"),
+        },
+        "M" => {
+            let written = arguments.split_once(':').and_then(|(range, data)| {
+                let (address, length) = parse_range(range)?;
+                let bytes = unhex(data)?;
+                let end = address.checked_add(length)?;
+                (bytes.len() == length && end <= chip8.memory().len()).then_some((address, bytes))
+            });
+            match written {
+                Some((address, bytes)) => {
+                    chip8.write_memory(address as u16, &bytes);
+                    reply("OK")
+                }
+                None => reply("E01"),
+            }
+        }
+        "Z" | "z" => {
+            let mut fields = arguments.split(',');
+            let kind = fields.next();
+            let address = fields.next().and_then(|text| u16::from_str_radix(text, 16).ok());
+            match (kind, address) {
+                // Software and hardware breakpoints are the same thing here
+                (Some("0"), Some(address)) | (Some("1"), Some(address)) => {
+                    if command == "Z" {
+                        breakpoints.insert(address);
+                    } else {
+                        breakpoints.remove(&address);
+                    }
+                    reply("OK")
+                }
+                _ => reply(""),
+            }
+        }
+        "c" => Response::Continue,
+        "s" => Response::Step,
+        "D" | "k" => Response::Detach,
+        "H" => reply("OK"),
+        "T" => reply("OK"),
+        "q" => reply(&query(arguments)),
+        _ => reply(""),
+    }
+}
+
+/// Answers to the general queries GDB asks when connecting
+fn query(query: &str) -> String {
+    if query.starts_with("Supported") {
+        String::from("PacketSize=1000;qXfer:features:read+")
+    } else if let Some(read) = query.strip_prefix("Xfer:features:read:target.xml:") {
+        let (offset, length) = match parse_range(read) {
+            Some(range) => range,
+            None => return String::from("E01"),
+        };
+        let rest = TARGET_XML.get(offset.min(TARGET_XML.len())..).unwrap_or("");
+        if rest.len() > length {
+            format!("m{}", &rest[..length])
+        } else {
+            format!("l{}", rest)
+        }
+    } else {
+        match query {
+            "Attached" => String::from("1"),
+            "C" => String::from("QC1"),
+            "fThreadInfo" => String::from("m1"),
+            "sThreadInfo" => String::from("l"),
+            _ => String::new(),
+        }
+    }
+}
+
+/// Every register, in the order of the target description
+fn registers(chip8: &Chip8) -> Vec<u8> {
+    (0..21).flat_map(|register| register_bytes(chip8, register).unwrap_or_default()).collect()
+}
+
+fn register_bytes(chip8: &Chip8, register: usize) -> Option<Vec<u8>> {
+    let (delay, sound) = chip8.timers();
+    match register {
+        0..=15 => Some(vec![chip8.registers()[register]]),
+        16 => Some(chip8.index().to_le_bytes().to_vec()),
+        17 => Some(chip8.program_counter().to_le_bytes().to_vec()),
+        18 => Some(vec![chip8.call_stack().len() as u8]),
+        19 => Some(vec![delay]),
+        20 => Some(vec![sound]),
+        _ => None,
+    }
+}
+
+/// Address and length, as `addr,length` in hex
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (address, length) = text.split_once(',')?;
+    Some((usize::from_str_radix(address, 16).ok()?, usize::from_str_radix(length, 16).ok()?))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut text, byte| {
+        let _ = write!(text, "{:02x}", byte);
+        text
+    })
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|at| u8::from_str_radix(text.get(at..at + 2)?, 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Chip8;
+    use crate::gdb::{frame, next_event, respond, Event, Received, Response};
+    use std::collections::BTreeSet;
+
+    /// Packets are framed with their checksum, and read registers, memory and breakpoints
+    #[test]
+    fn test_respond() {
+        assert_eq!(frame("OK"), "$OK#9a");
+        match next_event(b"$m200,2#5d+") {
+            Some((Received::Event(Event::Packet(packet)), 10)) => assert_eq!(packet, "m200,2"),
+            _ => panic!("Expected a packet"),
+        }
+        assert!(matches!(next_event(b"$m200,2#00"), Some((Received::Corrupt, 10))));
+        assert!(matches!(next_event(b"+$m200,2#5d"), Some((Received::Skipped, 1))));
+        assert!(next_event(b"$m200").is_none());
+
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&[0x60, 0x2A, 0xA3, 0x45, 0x12, 0x04]);
+        chip8.emulate_cycle();
+        chip8.emulate_cycle();
+        let mut breakpoints = BTreeSet::new();
+        let reply = |text: &str| Response::Reply(text.to_string());
+
+        assert_eq!(respond("g", &mut chip8, &mut breakpoints), reply(&format!("2a{}45030402000000", "00".repeat(15))));
+        assert_eq!(respond("p11", &mut chip8, &mut breakpoints), reply("0402"));
+        assert_eq!(respond("m200,4", &mut chip8, &mut breakpoints), reply("602aa345"));
+        assert_eq!(respond("mfff,2", &mut chip8, &mut breakpoints), reply("E01"));
+        assert_eq!(respond("mffffffffffffffff,1", &mut chip8, &mut breakpoints), reply("E01"));
+        assert_eq!(respond("Mffffffffffffffff,1:00", &mut chip8, &mut breakpoints), reply("E01"));
+        assert_eq!(respond("M300,2:beef", &mut chip8, &mut breakpoints), reply("OK"));
+        assert_eq!(&chip8.memory()[0x300..0x302], [0xBE, 0xEF]);
+        assert_eq!(respond("Z0,204,2", &mut chip8, &mut breakpoints), reply("OK"));
+        assert!(breakpoints.contains(&0x204));
+        assert_eq!(respond("z0,204,2", &mut chip8, &mut breakpoints), reply("OK"));
+        assert!(breakpoints.is_empty());
+        assert_eq!(respond("c", &mut chip8, &mut breakpoints), Response::Continue);
+        assert_eq!(respond("vMustReplyEmpty", &mut chip8, &mut breakpoints), reply(""));
+        match respond("qXfer:features:read:target.xml:0,20", &mut chip8, &mut breakpoints) {
+            Response::Reply(text) => assert_eq!(text, "m<?xml version=\"1.0\"?>\n<!DOCTYPE "),
+            response => panic!("Unexpected {:?}", response),
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! `chip8 genrom`, writing random ROMs that are still well formed, for stress-testing the
+//! emulator, the disassembler and tools built on them.
+//!
+//! A ROM is a main program ending in a jump to itself, then subroutines ending in a return, then
+//! random bytes for I to point into. Every instruction is one the variant knows. Jumps stay within
+//! the program or subroutine they're in, and calls only go to later subroutines, so calls nest
+//! no deeper than there are subroutines and every call returns. I is only ever pointed into the
+//! data, with room after it for all 16 registers, so nothing writes over the code. Without BNNN,
+//! FX1E and FX29 nothing can take it elsewhere.
+
+use crate::chip8::{AluOp, Instruction, Variant};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const PROGRAM_START: u16 = 0x200;
+/// Bytes of data ANNN points into
+const DATA_SIZE: u16 = 0x100;
+/// Bytes after those, for FX55 and DXYN to reach 15 past I
+const DATA_PADDING: u16 = 0x10;
+
+const ALU_OPS: [AluOp; 9] = [
+    AluOp::Move,
+    AluOp::Or,
+    AluOp::And,
+    AluOp::Xor,
+    AluOp::Add,
+    AluOp::Sub,
+    AluOp::ShiftRight,
+    AluOp::SubReverse,
+    AluOp::ShiftLeft,
+];
+
+/// How big a ROM to make
+pub struct Shape {
+    /// Instructions in the main program, the jump ending it included
+    pub length: usize,
+    pub subroutines: usize,
+    /// Instructions in each subroutine, the return ending it included
+    pub subroutine_length: usize,
+    pub variant: Variant,
+}
+
+/// The ROM for a seed, the same one every time
+pub fn generate(seed: u64, shape: &Shape) -> Result<Vec<u8>, String> {
+    let instructions = shape.length + shape.subroutines * shape.subroutine_length;
+    let data = PROGRAM_START as usize + instructions * 2;
+    if data + (DATA_SIZE + DATA_PADDING) as usize > 0x1000 {
+        return Err(format!("{} instructions don't fit in memory with the data after them", instructions));
+    }
+    let data = data as u16;
+    let subroutine_start = |index: usize| PROGRAM_START + (shape.length + index * shape.subroutine_length) as u16 * 2;
+    let subroutines: Vec<u16> = (0..shape.subroutines).map(subroutine_start).collect();
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut code = vec![Instruction::LoadIndex(data)];
+    code.extend(block(&mut rng, PROGRAM_START + 2, shape.length - 1, &subroutines, data, shape.variant));
+    for (index, start) in subroutines.iter().enumerate() {
+        code.extend(block(&mut rng, *start, shape.subroutine_length, &subroutines[index + 1..], data, shape.variant));
+    }
+    let mut rom: Vec<u8> = code.iter().flat_map(|instruction| instruction.encode().to_be_bytes()).collect();
+    rom.extend((0..DATA_SIZE + DATA_PADDING).map(|_| rng.gen::<u8>()));
+    Ok(rom)
+}
+
+/// Instructions from an address on, ending in a return if there's nothing to call back from,
+/// the main program's jump to itself otherwise. `callees` are the subroutines it may call.
+fn block(rng: &mut StdRng, start: u16, length: usize, callees: &[u16], data: u16, variant: Variant) -> Vec<Instruction> {
+    let last = start + (length as u16 - 1) * 2;
+    let is_main = start == PROGRAM_START + 2;
+    let mut code = Vec::with_capacity(length);
+    for address in (start..last).step_by(2) {
+        let mut instruction = random_instruction(rng, start..=last, callees, data, variant);
+        // A skip just before the end would skip it, running off into what comes next
+        while address == last - 2 && skips(&instruction) {
+            instruction = random_instruction(rng, start..=last, callees, data, variant);
+        }
+        code.push(instruction);
+    }
+    code.push(if is_main { Instruction::Jump(last) } else { Instruction::Return });
+    code
+}
+
+fn random_instruction(
+    rng: &mut StdRng,
+    block: std::ops::RangeInclusive<u16>,
+    callees: &[u16],
+    data: u16,
+    variant: Variant,
+) -> Instruction {
+    let x = rng.gen_range(0, 16);
+    let y = rng.gen_range(0, 16);
+    let nn = rng.gen::<u8>();
+    loop {
+        return match rng.gen_range(0, 23) {
+            0 => Instruction::ClearScreen,
+            1 => Instruction::Jump(block.start() + rng.gen_range(0, (block.end() - block.start()) / 2 + 1) * 2),
+            2 if !callees.is_empty() => Instruction::Call(callees[rng.gen_range(0, callees.len())]),
+            3 => Instruction::SkipIfEqual(x, nn),
+            4 => Instruction::SkipIfNotEqual(x, nn),
+            5 => Instruction::SkipIfRegistersEqual(x, y),
+            6 => Instruction::Load(x, nn),
+            7 => Instruction::Add(x, nn),
+            8 => Instruction::Alu(ALU_OPS[rng.gen_range(0, ALU_OPS.len())], x, y),
+            9 => Instruction::SkipIfRegistersNotEqual(x, y),
+            10 => Instruction::LoadIndex(data + rng.gen_range(0, DATA_SIZE)),
+            11 => Instruction::Random(x, nn),
+            12 => Instruction::Draw(x, y, rng.gen_range(0, 16)),
+            13 => Instruction::SkipIfKey(x),
+            14 => Instruction::SkipIfNotKey(x),
+            15 if variant == Variant::Chip8X => Instruction::SkipIfSecondKey(x),
+            16 if variant == Variant::Chip8X => Instruction::SkipIfNotSecondKey(x),
+            17 => Instruction::GetDelay(x),
+            18 => Instruction::SetDelay(x),
+            19 => Instruction::SetSound(x),
+            20 => Instruction::StoreBcd(x),
+            21 => Instruction::StoreRegisters(x),
+            22 => Instruction::LoadRegisters(x),
+            _ => continue,
+        };
+    }
+}
+
+fn skips(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::SkipIfEqual(..)
+            | Instruction::SkipIfNotEqual(..)
+            | Instruction::SkipIfRegistersEqual(..)
+            | Instruction::SkipIfRegistersNotEqual(..)
+            | Instruction::SkipIfKey(_)
+            | Instruction::SkipIfNotKey(_)
+            | Instruction::SkipIfSecondKey(_)
+            | Instruction::SkipIfNotSecondKey(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::{Chip8, Instruction, Variant, WriteProtection};
+    use crate::genrom::{generate, Shape};
+    use crate::headless::{run_frames, Outcome};
+    use crate::run::power_on;
+
+    /// ROMs decode, are the same for a seed, and run without faulting even with the interpreter's
+    /// memory protected
+    #[test]
+    fn test_generate() {
+        for variant in [Variant::Chip8, Variant::Chip8X] {
+            let shape = Shape { length: 64, subroutines: 4, subroutine_length: 16, variant };
+            for seed in 0..50 {
+                let rom = generate(seed, &shape).unwrap();
+                assert_eq!(rom, generate(seed, &shape).unwrap());
+                let code = &rom[..(64 + 4 * 16) * 2];
+                assert!(code.chunks(2).all(|opcode| Instruction::decode(u16::from_be_bytes([opcode[0], opcode[1]]), variant).is_some()));
+                let mut chip8 = power_on(Chip8::builder().variant(variant).write_protection(WriteProtection::Fault), &rom);
+                assert!(!matches!(run_frames(&mut chip8, 1, 5000, false), Outcome::Failed { .. }), "seed {}", seed);
+            }
+        }
+        let too_big = Shape { length: 1024, subroutines: 15, subroutine_length: 64, variant: Variant::Chip8 };
+        assert!(generate(0, &too_big).is_err());
+    }
+}
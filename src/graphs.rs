@@ -0,0 +1,103 @@
+//! Values of expressions over the last frames, plotted in the graph panel of the debugger window
+//! to spot patterns like a register holding a Y position that jitters every few frames. Added
+//! with `--graph` or the `graph` command.
+
+use crate::chip8::Chip8;
+use crate::expression::Expression;
+use std::collections::VecDeque;
+
+/// Frames kept for each graph, a pixel each in the panel
+pub const FRAMES: usize = 120;
+
+#[derive(Default)]
+pub struct Graphs {
+    graphs: Vec<Graph>,
+}
+
+pub struct Graph {
+    expression: Expression,
+    /// Oldest first
+    values: VecDeque<i64>,
+}
+
+impl Graphs {
+    pub fn add(&mut self, expression: Expression) {
+        self.graphs.push(Graph { expression, values: VecDeque::with_capacity(FRAMES) });
+    }
+
+    /// Removes a graph by its number, counting from 1
+    pub fn remove(&mut self, number: usize) -> bool {
+        if (1..=self.graphs.len()).contains(&number) {
+            self.graphs.remove(number - 1);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn graphs(&self) -> &[Graph] {
+        &self.graphs
+    }
+
+    /// Evaluates every expression, once an emulated frame
+    pub fn record(&mut self, chip8: &Chip8) {
+        for graph in &mut self.graphs {
+            if graph.values.len() == FRAMES {
+                graph.values.pop_front();
+            }
+            graph.values.push_back(graph.expression.evaluate(chip8));
+        }
+    }
+}
+
+impl Graph {
+    pub fn expression(&self) -> &Expression {
+        &self.expression
+    }
+
+    pub fn values(&self) -> &VecDeque<i64> {
+        &self.values
+    }
+
+    /// Lowest and highest value kept, the scale of the plot
+    pub fn range(&self) -> (i64, i64) {
+        let low = self.values.iter().copied().min().unwrap_or(0);
+        let high = self.values.iter().copied().max().unwrap_or(0);
+        (low, high)
+    }
+
+    /// Height of each value in a plot of a given height, 0 for the lowest value and one less
+    /// than the height for the highest
+    pub fn heights(&self, height: usize) -> Vec<usize> {
+        let (low, high) = self.range();
+        let span = (high - low).max(1) as f64;
+        self.values.iter().map(|value| ((value - low) as f64 / span * (height - 1) as f64).round() as usize).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Chip8;
+    use crate::expression::Expression;
+    use crate::graphs::{Graphs, FRAMES};
+    use crate::symbols::Symbols;
+
+    /// Values are kept for the last frames and scaled to the height of the plot
+    #[test]
+    fn test_graphs() {
+        let mut graphs = Graphs::default();
+        graphs.add(Expression::parse("V1 * 2", &Symbols::default()).unwrap());
+        let mut chip8 = Chip8::new();
+        for value in 0..FRAMES as u8 + 10 {
+            chip8.set_register(1, value % 5);
+            graphs.record(&chip8);
+        }
+        let graph = &graphs.graphs()[0];
+        assert_eq!(graph.values().len(), FRAMES);
+        assert_eq!(graph.values()[0], 0);
+        assert_eq!(graph.range(), (0, 8));
+        assert_eq!(graph.heights(5)[..5], [0, 1, 2, 3, 4]);
+        assert!(graphs.remove(1));
+        assert!(!graphs.remove(1));
+    }
+}
@@ -0,0 +1,164 @@
+//! `chip8 run --headless`, running a ROM without a window or sound, for CI. Nothing depends on
+//! the wall clock or the keyboard: every key stays released and the seed is 0 unless given, so
+//! the same ROM and options always end the same way, down to the hash of the screen.
+
+#[cfg(feature = "jit")]
+use crate::chip8::jit::Jit;
+use crate::chip8::{Chip8, Instruction};
+use crate::cli::RunArgs;
+use crate::fatal;
+use crate::run;
+use sha1::{Digest, Sha1};
+use std::process;
+use tracing::debug_span;
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    /// The program jumped to the instruction it was at during this frame
+    Halted { frame: u64 },
+    /// The program was still running after the last frame
+    Ran { frames: u64 },
+    /// The program failed during this frame
+    Failed { frame: u64, reason: String },
+}
+
+/// Runs up to a number of instructions at once, returning how many ran
+pub type Step = Box<dyn FnMut(&mut Chip8, u64) -> Result<u64, String>>;
+
+/// Runs the next instruction with the interpreter
+pub fn interpreter() -> Step {
+    Box::new(|chip8, _| chip8.try_emulate_cycle().map(|_| 1).map_err(|error| error.to_string()))
+}
+
+/// Runs blocks of instructions compiled to native code if asked, the interpreter otherwise
+#[cfg(feature = "jit")]
+pub fn stepper(jit: bool) -> Result<Step, String> {
+    if !jit {
+        return Ok(interpreter());
+    }
+    let mut jit = Jit::new()?;
+    Ok(Box::new(move |chip8, budget| jit.step(chip8, budget)))
+}
+
+/// Runs the interpreter, there being no JIT without the `jit` feature
+#[cfg(not(feature = "jit"))]
+pub fn stepper(_jit: bool) -> Result<Step, String> {
+    Ok(interpreter())
+}
+
+/// Runs the ROM, printing how the run ended and the screen's hash if asked, and exits with 1 if
+/// the program failed or 2 if it was to halt and didn't
+pub fn run(args: &RunArgs) {
+    let program = run::load_program(&args.rom).unwrap_or_else(fatal);
+    let font = run::machine_font(args, &program.options);
+    let builder = run::configure(args, &program.options, args.variant, font.as_ref()).seed(args.seed.unwrap_or(0));
+    let mut chip8 = run::power_on(builder, &program.bytes);
+    let step = stepper(args.jit()).unwrap_or_else(fatal);
+
+    let outcome = run_frames_with(&mut chip8, program.options.speed(), args.max_frames, args.exit_on_halt, step);
+    println!("{}", outcome.describe());
+    if args.print_hash {
+        println!("{}", screen_hash(&chip8));
+    }
+    match outcome {
+        Outcome::Failed { .. } => process::exit(1),
+        Outcome::Ran { .. } if args.exit_on_halt => process::exit(2),
+        _ => {}
+    }
+}
+
+/// Runs a number of frames of `speed` instructions each, stopping early if the program fails or,
+/// when asked, halts
+#[cfg(test)]
+pub fn run_frames(chip8: &mut Chip8, speed: u32, max_frames: u64, exit_on_halt: bool) -> Outcome {
+    run_frames_with(chip8, speed, max_frames, exit_on_halt, interpreter())
+}
+
+/// Runs frames like `run_frames`, each step running as many of the instructions left as it can.
+/// Nothing happens between frames, so a step can run on into the next one
+pub fn run_frames_with(chip8: &mut Chip8, speed: u32, max_frames: u64, exit_on_halt: bool, mut step: Step) -> Outcome {
+    let total = speed as u64 * max_frames;
+    let mut done = 0;
+    while done < total {
+        let frame = done / speed as u64 + 1;
+        // Entered for each step, a step starting in the frame
+        let _span = debug_span!("frame", number = frame).entered();
+        let program_counter = chip8.program_counter();
+        let ran = match step(chip8, total - done) {
+            Ok(ran) => ran,
+            Err(reason) => return Outcome::Failed { frame, reason },
+        };
+        // A block ending where it started is a loop, only a single jump can halt. Other
+        // instructions stay put too while a sprite waits for the next tick
+        if exit_on_halt && ran == 1 && chip8.program_counter() == program_counter && jumps_to_itself(chip8) {
+            return Outcome::Halted { frame };
+        }
+        done += ran;
+    }
+    Outcome::Ran { frames: max_frames }
+}
+
+/// Whether the instruction at the program counter is a jump to itself
+fn jumps_to_itself(chip8: &Chip8) -> bool {
+    let address = chip8.program_counter();
+    let opcode = chip8.memory().get(address as usize..address as usize + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]));
+    opcode.and_then(|opcode| Instruction::decode(opcode, chip8.variant())) == Some(Instruction::Jump(address))
+}
+
+/// SHA-1 of the screen's pixels, on or off, in hex
+pub fn screen_hash(chip8: &Chip8) -> String {
+    let pixels: Vec<u8> = chip8.display().iter().map(|pixel| (*pixel != 0) as u8).collect();
+    Sha1::digest(&pixels).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+impl Outcome {
+    pub fn describe(&self) -> String {
+        match self {
+            Outcome::Halted { frame } => format!("Halted at frame {}", frame),
+            Outcome::Ran { frames } => format!("Still running after {} frames", frames),
+            Outcome::Failed { frame, reason } => format!("Failed at frame {}: {}", frame, reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::{Chip8, Quirks};
+    use crate::headless::{run_frames, screen_hash, Outcome};
+    use crate::run::power_on;
+
+    /// Runs stop at a jump to itself only when asked, and at a failure
+    #[test]
+    fn test_run_frames() {
+        // V0 = 3, V0 -= 1 until 0, then loop forever
+        let program = [0x60, 0x03, 0x70, 0xFF, 0x30, 0x00, 0x12, 0x02, 0x12, 0x08];
+        let mut chip8 = power_on(Chip8::builder(), &program);
+        assert_eq!(run_frames(&mut chip8, 1, 100, false), Outcome::Ran { frames: 100 });
+        let mut chip8 = power_on(Chip8::builder(), &program);
+        assert_eq!(run_frames(&mut chip8, 1, 100, true), Outcome::Halted { frame: 10 });
+        let mut chip8 = power_on(Chip8::builder(), &program);
+        assert_eq!(run_frames(&mut chip8, 4, 100, true), Outcome::Halted { frame: 3 });
+
+        let mut chip8 = power_on(Chip8::builder(), &[0x00, 0xEE]);
+        assert_eq!(run_frames(&mut chip8, 1, 100, true), Outcome::Failed { frame: 1, reason: String::from("Return with the stack empty at 0x200") });
+    }
+
+    /// A sprite waiting for the next tick isn't a halt
+    #[test]
+    fn test_vblank_isnt_halt() {
+        let builder = Chip8::builder().tickrate(4).quirks(Quirks { vblank: true, ..Quirks::default() });
+        let mut chip8 = power_on(builder, &[0x60, 0x00, 0xD0, 0x01, 0x12, 0x04]);
+        assert_eq!(run_frames(&mut chip8, 4, 100, true), Outcome::Halted { frame: 2 });
+    }
+
+    /// The hash follows what's on the screen
+    #[test]
+    fn test_screen_hash() {
+        let blank = screen_hash(&Chip8::new());
+        assert_eq!(blank, screen_hash(&Chip8::new()));
+        // Draw digit 0
+        let mut chip8 = power_on(Chip8::builder(), &[0xF0, 0x29, 0xD0, 0x05]);
+        run_frames(&mut chip8, 1, 2, false);
+        assert_ne!(screen_hash(&chip8), blank);
+    }
+}
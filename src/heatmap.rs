@@ -0,0 +1,89 @@
+//! Reads and writes of each memory address, drawn as a 64 by 64 image of the 4KB of memory with
+//! a pixel per address, a row of 64 bytes at a time. Writes show red, data read through I green
+//! and instructions run blue, brighter the more often, so variables, sprites and code stand
+//! apart.
+
+use crate::chip8::{Access, Chip8, Instruction, MAX_MEMORY_SIZE};
+
+/// Addresses on a row of the image
+pub const SIZE: usize = 64;
+
+pub struct Heatmap {
+    writes: Vec<u32>,
+    reads: Vec<u32>,
+    runs: Vec<u32>,
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Heatmap { writes: vec![0; MAX_MEMORY_SIZE], reads: vec![0; MAX_MEMORY_SIZE], runs: vec![0; MAX_MEMORY_SIZE] }
+    }
+}
+
+impl Heatmap {
+    /// Counts the instruction at the program counter and the memory it accesses, before it runs
+    pub fn record(&mut self, chip8: &Chip8) {
+        let address = chip8.program_counter() as usize;
+        let opcode = match chip8.memory().get(address..address + 2) {
+            Some(opcode) => u16::from_be_bytes([opcode[0], opcode[1]]),
+            None => return,
+        };
+        self.runs[address] += 1;
+        self.runs[address + 1] += 1;
+        if let Some((access, range)) = Instruction::decode(opcode, chip8.variant()).and_then(|instruction| instruction.memory_access(chip8.index())) {
+            let counts = match access {
+                Access::Read => &mut self.reads,
+                Access::Write => &mut self.writes,
+            };
+            for address in range {
+                if let Some(count) = counts.get_mut(address as usize) {
+                    *count += 1;
+                }
+            }
+        }
+    }
+
+    /// Color of each address, scaled so the busiest address of each kind is at full brightness
+    pub fn pixels(&self) -> Vec<u32> {
+        let (writes, reads, runs) = (brightness(&self.writes), brightness(&self.reads), brightness(&self.runs));
+        (0..4096).map(|address| (writes[address] << 16) | (reads[address] << 8) | runs[address]).collect()
+    }
+}
+
+/// Counts scaled logarithmically from 0 to 255, anything counted at all being visible
+fn brightness(counts: &[u32]) -> Vec<u32> {
+    let most = counts.iter().copied().max().unwrap_or(0);
+    counts
+        .iter()
+        .map(|count| match *count {
+            0 => 0,
+            _ if most <= 1 => 255,
+            count => 64 + (191.0 * (count as f64).ln() / (most as f64).ln()) as u32,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Chip8;
+    use crate::heatmap::Heatmap;
+
+    /// A loop storing registers shows its code blue and the memory it writes red
+    #[test]
+    fn test_heatmap() {
+        // I := 0x300, loop: save v1, jump loop
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&[0xA3, 0x00, 0xF1, 0x55, 0x12, 0x02]);
+        let mut heatmap = Heatmap::default();
+        for _ in 0..9 {
+            heatmap.record(&chip8);
+            chip8.emulate_cycle();
+        }
+        let pixels = heatmap.pixels();
+        assert_eq!(pixels[0x200], 0x000040);
+        assert_eq!(pixels[0x202], 0x0000FF);
+        assert_eq!(pixels[0x300], 0xFF0000);
+        assert_eq!(pixels[0x301], 0xFF0000);
+        assert_eq!(pixels[0x302], 0);
+    }
+}
@@ -0,0 +1,113 @@
+//! Raw HID keypad input through hidapi, for real hex keypads and other custom hardware.
+//!
+//! Devices are listed in the config file by vendor/product ID, with a table from HID usage IDs
+//! (as sent in the device's input reports) to CHIP-8 keys:
+//!
+//! ```toml
+//! [[hid]]
+//! vendor_id = 0x1209
+//! product_id = 0x0001
+//! report_offset = 2    # byte where usage IDs start, 2 for boot keyboard reports
+//!
+//! [hid.usages]
+//! "0x59" = "1"         # keypad 1
+//! "0x5A" = "2"         # keypad 2
+//! ```
+
+use hidapi::{HidApi, HidDevice};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tracing::warn;
+
+const REPORT_SIZE: usize = 64;
+
+#[derive(Deserialize, Serialize)]
+pub struct HidKeypadConfig {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    #[serde(default = "default_report_offset")]
+    pub report_offset: usize,
+    /// HID usage ID (decimal or 0x-prefixed hex) to CHIP-8 key (hex digit)
+    pub usages: BTreeMap<String, String>,
+}
+
+fn default_report_offset() -> usize {
+    2
+}
+
+struct HidKeypad {
+    device: HidDevice,
+    report_offset: usize,
+    usages: Vec<(u8, usize)>,
+    // Usage IDs in the last report received
+    held: Vec<u8>,
+}
+
+pub struct HidKeypads {
+    keypads: Vec<HidKeypad>,
+}
+
+impl HidKeypads {
+    /// Opens every configured device that is attached, skipping the others
+    pub fn open(configs: &[HidKeypadConfig]) -> Result<Self, String> {
+        let api = HidApi::new().map_err(|e| format!("Could not initialize HID support\n{}", e))?;
+        let mut keypads = Vec::new();
+        for config in configs {
+            let usages = parse_usages(&config.usages)?;
+            match api.open(config.vendor_id, config.product_id) {
+                Ok(device) => {
+                    device.set_blocking_mode(false).map_err(|e| e.to_string())?;
+                    keypads.push(HidKeypad { device, report_offset: config.report_offset, usages, held: Vec::new() });
+                }
+                Err(error) => warn!(
+                    "Could not open HID device {:04x}:{:04x}\n{}",
+                    config.vendor_id, config.product_id, error
+                ),
+            }
+        }
+        Ok(HidKeypads { keypads })
+    }
+
+    /// Reads pending input reports and returns the keypad keys held down on any device
+    pub fn pressed_keys(&mut self) -> [bool; 16] {
+        let mut keys = [false; 16];
+        for keypad in self.keypads.iter_mut() {
+            let mut report = [0u8; REPORT_SIZE];
+            while let Ok(size) = keypad.device.read(&mut report) {
+                if size == 0 {
+                    break;
+                }
+                keypad.held = report[keypad.report_offset.min(size)..size]
+                    .iter()
+                    .copied()
+                    .filter(|usage| *usage != 0)
+                    .collect();
+            }
+
+            for (usage, chip8_key) in &keypad.usages {
+                if keypad.held.contains(usage) {
+                    keys[*chip8_key] = true;
+                }
+            }
+        }
+        keys
+    }
+}
+
+fn parse_usages(usages: &BTreeMap<String, String>) -> Result<Vec<(u8, usize)>, String> {
+    usages
+        .iter()
+        .map(|(usage, chip8_key)| {
+            let parsed_usage = match usage.strip_prefix("0x").or_else(|| usage.strip_prefix("0X")) {
+                Some(hex) => u8::from_str_radix(hex, 16),
+                None => usage.parse(),
+            };
+            let parsed_usage = parsed_usage.map_err(|_| format!("Invalid HID usage: {}", usage))?;
+            let chip8_key = usize::from_str_radix(chip8_key, 16)
+                .ok()
+                .filter(|k| *k < 16)
+                .ok_or_else(|| format!("Invalid CHIP-8 key for HID usage {}: {}", usage, chip8_key))?;
+            Ok((parsed_usage, chip8_key))
+        })
+        .collect()
+}
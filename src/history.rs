@@ -0,0 +1,141 @@
+//! Recent past of the machine, for stepping backwards in the debugger.
+//!
+//! A snapshot of the machine is kept every few hundred instructions, along with the keypads
+//! before every instruction since the oldest snapshot. Going back re-executes from the nearest
+//! snapshot with the same input, which gives the same result since the random number generator
+//! is part of the machine.
+
+use crate::chip8::Chip8;
+use std::collections::VecDeque;
+
+/// Instructions between snapshots
+const SNAPSHOT_INTERVAL: u64 = 256;
+/// Snapshots kept, going back 65536 instructions
+const MAX_SNAPSHOTS: usize = 256;
+
+#[derive(Default)]
+pub struct History {
+    /// Machine before the instruction at a position, oldest first
+    snapshots: VecDeque<(u64, Chip8)>,
+    /// Keypads before each instruction, from the oldest snapshot on
+    inputs: VecDeque<([bool; 16], [bool; 16])>,
+    /// Positions where frames started
+    frames: VecDeque<u64>,
+    /// Instructions recorded
+    position: u64,
+    /// Cycle count the machine has if it just ran on, to notice resets and loaded savestates
+    next_cycle: Option<u64>,
+}
+
+impl History {
+    /// Records the machine before it runs an instruction
+    pub fn record(&mut self, chip8: &Chip8) {
+        // Re-executing can't go from one machine to another, so a new one gets a snapshot
+        let continued = self.next_cycle == Some(chip8.cycles());
+        let since_snapshot = self.snapshots.back().map_or(u64::MAX, |(start, _)| self.position - start);
+        if !continued || since_snapshot >= SNAPSHOT_INTERVAL {
+            self.snapshots.push_back((self.position, chip8.clone()));
+            if self.snapshots.len() > MAX_SNAPSHOTS {
+                let (forgotten, _) = self.snapshots.pop_front().unwrap();
+                self.inputs.drain(..(self.oldest() - forgotten) as usize);
+                let oldest = self.oldest();
+                self.frames.retain(|frame| *frame >= oldest);
+            }
+        }
+        self.inputs.push_back((chip8.keypad(), chip8.second_keypad()));
+        self.position += 1;
+        self.next_cycle = Some(chip8.cycles() + 1);
+    }
+
+    /// Marks the next instruction recorded as the start of a frame
+    pub fn start_frame(&mut self) {
+        if self.frames.back() != Some(&self.position) {
+            self.frames.push_back(self.position);
+        }
+    }
+
+    /// Instructions recorded so far
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Start of the last frame before the current position
+    pub fn previous_frame(&self) -> Option<u64> {
+        self.frames.iter().rev().find(|frame| **frame < self.position).copied()
+    }
+
+    /// The machine as it was before the instruction at a position, if that's still remembered.
+    /// What was recorded after it is forgotten, the machine runs on from there.
+    pub fn rewind(&mut self, position: u64) -> Option<Chip8> {
+        if position > self.position {
+            return None;
+        }
+        let (start, snapshot) = self.snapshots.iter().rev().find(|(start, _)| *start <= position)?;
+        let oldest = self.oldest();
+        let mut chip8 = snapshot.clone();
+        let end = (position - oldest) as usize;
+        for (keypad, second_keypad) in self.inputs.range((start - oldest) as usize..end) {
+            chip8.set_keys(*keypad);
+            chip8.set_second_keypad(*second_keypad);
+            chip8.emulate_cycle();
+        }
+        // The keys held at the position itself
+        if let Some((keypad, second_keypad)) = self.inputs.get(end) {
+            chip8.set_keys(*keypad);
+            chip8.set_second_keypad(*second_keypad);
+        }
+
+        self.snapshots.retain(|(start, _)| *start <= position);
+        self.inputs.truncate(end);
+        self.frames.retain(|frame| *frame <= position);
+        self.position = position;
+        self.next_cycle = Some(chip8.cycles());
+        chip8.force_redraw();
+        Some(chip8)
+    }
+
+    /// Position of the oldest snapshot
+    fn oldest(&self) -> u64 {
+        self.snapshots.front().map_or(self.position, |(start, _)| *start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Chip8;
+    use crate::history::History;
+
+    /// Going back re-executes to the same state, with the keys that were held then
+    #[test]
+    fn test_rewind() {
+        // Adds 1 to V0 while key 5 is held, forever
+        let rom = [0x61, 0x05, 0xE1, 0xA1, 0x70, 0x01, 0x12, 0x02];
+        let mut chip8 = Chip8::builder().seed(1).build();
+        chip8.load_program(&rom);
+        let mut history = History::default();
+        let mut states = Vec::new();
+        for cycle in 0..1000 {
+            if cycle % 10 == 0 {
+                history.start_frame();
+            }
+            chip8.set_key(5, cycle % 7 < 3);
+            history.record(&chip8);
+            states.push(chip8.state_checksum());
+            chip8.emulate_cycle();
+        }
+
+        let rewound = history.rewind(600).unwrap();
+        assert_eq!(rewound.state_checksum(), states[600]);
+        assert_eq!(rewound.keypad()[5], 600 % 7 < 3);
+        assert_eq!(history.previous_frame(), Some(590));
+        assert!(history.rewind(601).is_none());
+
+        // A machine that was reset can't be reached by re-executing the one before it
+        let mut reset = Chip8::new();
+        reset.load_program(&rom);
+        history.record(&reset);
+        reset.emulate_cycle();
+        history.record(&reset);
+        assert_eq!(history.rewind(601).unwrap().cycles(), 1);
+    }
+}
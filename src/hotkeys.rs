@@ -0,0 +1,200 @@
+//! Emulator hotkeys, as opposed to the keys of the CHIP-8 keypad.
+//!
+//! Every hotkey can be rebound in the `[hotkeys]` section of the config file. A hotkey can't share
+//! a key with the keypad, another hotkey or a turbo toggle, so conflicts are reported when the
+//! emulator starts instead of one of them silently not working.
+//!
+//! ```toml
+//! [hotkeys]
+//! pause = "Space"
+//! screenshot = "F11"
+//! ```
+
+use crate::keymap::{key_from_name, key_name, Keymap};
+use minifb::Key;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Quit,
+    Pause,
+    Reset,
+    SaveState,
+    LoadState,
+    Screenshot,
+    SpeedUp,
+    SpeedDown,
+    /// Start or stop recording a replay
+    Record,
+    /// Open or close the key remapping screen
+    Remap,
+    /// Show or hide the keypad state over the game
+    InputDisplay,
+    /// Start or stop recording an input macro
+    RecordMacro,
+    /// Open or close the debugger window
+    DebugView,
+    /// Open or close the command console
+    Console,
+    /// Open or close the cheat menu
+    Cheats,
+    /// Show or hide the graph of frame times over the game
+    PerfHud,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub struct HotkeyConfig {
+    pub quit: String,
+    pub pause: String,
+    pub reset: String,
+    pub save_state: String,
+    pub load_state: String,
+    pub screenshot: String,
+    pub speed_up: String,
+    pub speed_down: String,
+    pub record: String,
+    pub remap: String,
+    pub input_display: String,
+    pub record_macro: String,
+    pub debug_view: String,
+    pub console: String,
+    pub cheats: String,
+    pub perf_hud: String,
+}
+
+impl Default for HotkeyConfig {
+    fn default() -> Self {
+        HotkeyConfig {
+            quit: String::from("Escape"),
+            pause: String::from("F3"),
+            reset: String::from("F4"),
+            save_state: String::from("F5"),
+            load_state: String::from("F7"),
+            screenshot: String::from("F12"),
+            speed_up: String::from("Equal"),
+            speed_down: String::from("Minus"),
+            record: String::from("F2"),
+            remap: String::from("F1"),
+            input_display: String::from("F8"),
+            record_macro: String::from("F10"),
+            debug_view: String::from("F9"),
+            console: String::from("Backquote"),
+            cheats: String::from("Insert"),
+            perf_hud: String::from("F6"),
+        }
+    }
+}
+
+impl HotkeyConfig {
+    fn bindings(&self) -> [(Action, &str, &String); 16] {
+        [
+            (Action::Quit, "quit", &self.quit),
+            (Action::Pause, "pause", &self.pause),
+            (Action::Reset, "reset", &self.reset),
+            (Action::SaveState, "save_state", &self.save_state),
+            (Action::LoadState, "load_state", &self.load_state),
+            (Action::Screenshot, "screenshot", &self.screenshot),
+            (Action::SpeedUp, "speed_up", &self.speed_up),
+            (Action::SpeedDown, "speed_down", &self.speed_down),
+            (Action::Record, "record", &self.record),
+            (Action::Remap, "remap", &self.remap),
+            (Action::InputDisplay, "input_display", &self.input_display),
+            (Action::RecordMacro, "record_macro", &self.record_macro),
+            (Action::DebugView, "debug_view", &self.debug_view),
+            (Action::Console, "console", &self.console),
+            (Action::Cheats, "cheats", &self.cheats),
+            (Action::PerfHud, "perf_hud", &self.perf_hud),
+        ]
+    }
+}
+
+pub struct Hotkeys {
+    bindings: Vec<(Action, Key)>,
+    // Keys switching autofire, taken like hotkeys
+    turbo_toggles: Vec<Key>,
+}
+
+impl Hotkeys {
+    /// Resolves the configured hotkeys, failing if one of them is also bound on a keypad or toggles
+    /// turbo, or if a turbo toggle is bound on a keypad
+    pub fn new(config: &HotkeyConfig, keymaps: &[&Keymap], turbo_toggles: &[Key]) -> Result<Self, String> {
+        let mut bindings: Vec<(Action, Key)> = Vec::new();
+        let mut names: Vec<&str> = Vec::new();
+        for (action, name, bound_name) in config.bindings().iter() {
+            let key = key_from_name(bound_name)
+                .ok_or_else(|| format!("Unknown key for hotkey {}: {}", name, bound_name))?;
+            if let Some(other) = bindings.iter().position(|(_, bound)| *bound == key) {
+                return Err(format!("Hotkeys {} and {} are both bound to {}", names[other], name, key_name(key)));
+            }
+            for keymap in keymaps {
+                if let Some(chip8_key) = keymap.chip8_key(key) {
+                    return Err(format!(
+                        "Hotkey {} is bound to {}, which is already CHIP-8 key {:X}",
+                        name, key_name(key), chip8_key
+                    ));
+                }
+            }
+            if turbo_toggles.contains(&key) {
+                return Err(format!("Hotkey {} is bound to {}, which already toggles turbo", name, key_name(key)));
+            }
+            bindings.push((*action, key));
+            names.push(name);
+        }
+        for toggle in turbo_toggles {
+            for keymap in keymaps {
+                if let Some(chip8_key) = keymap.chip8_key(*toggle) {
+                    return Err(format!("Turbo toggle {} is already CHIP-8 key {:X}", key_name(*toggle), chip8_key));
+                }
+            }
+        }
+        Ok(Hotkeys { bindings, turbo_toggles: turbo_toggles.to_vec() })
+    }
+
+    /// Actions triggered by the keys pressed since the last frame
+    pub fn triggered(&self, pressed: &[Key]) -> Vec<Action> {
+        self.bindings
+            .iter()
+            .filter(|(_, key)| pressed.contains(key))
+            .map(|(action, _)| *action)
+            .collect()
+    }
+
+    /// Whether a key is bound to a hotkey or toggles turbo
+    pub fn is_hotkey(&self, key: Key) -> bool {
+        self.bindings.iter().any(|(_, bound)| *bound == key) || self.turbo_toggles.contains(&key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hotkeys::{Action, HotkeyConfig, Hotkeys};
+    use crate::keymap::{Keymap, Preset};
+    use minifb::Key;
+
+    /// Default hotkeys don't overlap the default keypad
+    #[test]
+    fn test_defaults() {
+        let keymap = Keymap::logical(Preset::Sequential);
+        let hotkeys = Hotkeys::new(&HotkeyConfig::default(), &[&keymap], &[]).unwrap();
+        assert_eq!(hotkeys.triggered(&[Key::Escape]), vec![Action::Quit]);
+        assert_eq!(hotkeys.triggered(&[Key::F5, Key::Q]), vec![Action::SaveState]);
+    }
+
+    /// Hotkeys on keypad keys or on each other's keys are rejected
+    #[test]
+    fn test_conflicts() {
+        let keymap = Keymap::logical(Preset::Sequential);
+        let config = HotkeyConfig { pause: String::from("Q"), ..HotkeyConfig::default() };
+        assert!(Hotkeys::new(&config, &[&keymap], &[]).err().unwrap().contains("CHIP-8 key 4"));
+
+        let config = HotkeyConfig { reset: String::from("F3"), ..HotkeyConfig::default() };
+        assert!(Hotkeys::new(&config, &[&keymap], &[]).err().unwrap().contains("pause and reset"));
+
+        // F6 shows the frame times by default
+        assert!(Hotkeys::new(&HotkeyConfig::default(), &[&keymap], &[Key::F6]).err().unwrap().contains("toggles turbo"));
+        assert!(Hotkeys::new(&HotkeyConfig::default(), &[&keymap], &[Key::Q]).err().unwrap().contains("CHIP-8 key 4"));
+        let hotkeys = Hotkeys::new(&HotkeyConfig::default(), &[&keymap], &[Key::F11]).unwrap();
+        assert!(hotkeys.is_hotkey(Key::F11));
+    }
+}
@@ -0,0 +1,92 @@
+//! Counts of the instructions run, by address and by opcode, to see where a ROM spends its time.
+
+use crate::chip8::{Chip8, Instruction};
+use crate::symbols::Symbols;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+pub struct Hotspots {
+    /// Runs of the instruction at each address
+    by_address: Vec<u64>,
+    by_pattern: BTreeMap<&'static str, u64>,
+    total: u64,
+}
+
+impl Default for Hotspots {
+    fn default() -> Self {
+        Hotspots { by_address: vec![0; 4096], by_pattern: BTreeMap::new(), total: 0 }
+    }
+}
+
+impl Hotspots {
+    /// Counts the instruction at the program counter, before it runs
+    pub fn record(&mut self, chip8: &Chip8) {
+        let address = chip8.program_counter() as usize;
+        if let Some(runs) = self.by_address.get_mut(address) {
+            *runs += 1;
+        }
+        let memory = chip8.memory();
+        if let Some(opcode) = memory.get(address..address + 2) {
+            if let Some(instruction) = Instruction::decode(u16::from_be_bytes([opcode[0], opcode[1]]), chip8.variant()) {
+                *self.by_pattern.entry(instruction.pattern()).or_default() += 1;
+            }
+        }
+        self.total += 1;
+    }
+
+    /// The `count` most run addresses and how often each opcode ran, most first
+    pub fn report(&self, symbols: &Symbols, count: usize) -> String {
+        if self.total == 0 {
+            return String::from("No instructions run yet");
+        }
+        let mut addresses: Vec<(u16, u64)> =
+            self.by_address.iter().enumerate().filter(|(_, runs)| **runs > 0).map(|(address, runs)| (address as u16, *runs)).collect();
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        addresses.truncate(count);
+        let addresses: Vec<(String, u64)> = addresses.iter().map(|(address, runs)| (symbols.describe(*address), *runs)).collect();
+        let mut patterns: Vec<(String, u64)> = self.by_pattern.iter().map(|(pattern, runs)| (pattern.to_string(), *runs)).collect();
+        patterns.sort_by_key(|(_, runs)| Reverse(*runs));
+
+        let width = addresses.iter().chain(&patterns).map(|(name, _)| name.len()).max().unwrap_or(0);
+        let row = |(name, runs): &(String, u64)| format!("  {:<width$}  {:>10}  {:5.1}%", name, runs, *runs as f64 * 100.0 / self.total as f64);
+        let mut lines = vec![format!("{} instructions run", self.total), String::from("Hot addresses:")];
+        lines.extend(addresses.iter().map(row));
+        lines.push(String::from("Opcodes:"));
+        lines.extend(patterns.iter().map(row));
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Chip8;
+    use crate::hotspots::Hotspots;
+    use crate::symbols::Symbols;
+
+    /// The busiest address comes first, with its label
+    #[test]
+    fn test_report() {
+        let mut symbols = Symbols::default();
+        symbols.labels.insert(String::from("loop"), 0x202);
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&[0x60, 0x00, 0x70, 0x01, 0x12, 0x02]);
+        let mut hotspots = Hotspots::default();
+        for _ in 0..7 {
+            hotspots.record(&chip8);
+            chip8.emulate_cycle();
+        }
+        assert_eq!(
+            hotspots.report(&symbols, 1),
+            [
+                "7 instructions run",
+                "Hot addresses:",
+                "  0x202 (loop)           3   42.9%",
+                "Opcodes:",
+                "  1NNN                   3   42.9%",
+                "  7XNN                   3   42.9%",
+                "  6XNN                   1   14.3%",
+            ]
+            .join("\n")
+        );
+    }
+}
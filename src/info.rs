@@ -0,0 +1,297 @@
+//! What can be told about a ROM without running it, for the `info` subcommand.
+//!
+//! Opcodes are counted over the code the disassembler reaches from the load address, so sprites
+//! and other data don't count as instructions. Suspicious constructs are places on those paths
+//! where a ROM is likely to misbehave or to need something of the interpreter: going outside
+//! the ROM, into the middle of an instruction or onto an opcode the variant doesn't know,
+//! jumps by V0 that can't be followed, and I pointed into the interpreter's memory or at code.
+//!
+//! Opcodes of the extensions, SCHIP, XO-CHIP and CHIP-8X, are listed with the first place each
+//! is reached, though the code after them isn't followed. Instructions whose effect changed
+//! between interpreters are listed too when used in a way that tells them apart:
+//!
+//! - Shifts: 8XY6 and 8XYE with VY not VX, which the original interpreter shifts into VX where
+//!   SCHIP and this emulator shift VX in place.
+//! - Loads and stores: I used by the instructions right after FX55 or FX65, before being set
+//!   again. The original interpreter moves I past the registers, SCHIP and this emulator don't.
+//! - Jumps: BNNN, where SCHIP adds VX, X being the top digit of the address, instead of V0.
+
+use crate::chip8::{AluOp, Instruction, Variant};
+use crate::disasm::{self, LOAD_ADDRESS};
+use sha1::{Digest, Sha1};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Instructions followed after FX55 and FX65 looking for uses of I
+const LOAD_STORE_LOOKAHEAD: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Extension {
+    Chip8X,
+    Schip,
+    XoChip,
+}
+
+impl fmt::Display for Extension {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Extension::Chip8X => write!(f, "CHIP-8X"),
+            Extension::Schip => write!(f, "SCHIP"),
+            Extension::XoChip => write!(f, "XO-CHIP"),
+        }
+    }
+}
+
+/// Behaviour that differs between interpreters
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Quirk {
+    Shift,
+    LoadStore,
+    Jump,
+}
+
+impl fmt::Display for Quirk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Quirk::Shift => write!(f, "shifts VY into VX on the original interpreter, VX in place on SCHIP and here"),
+            Quirk::LoadStore => write!(f, "uses I after FX55 or FX65, which moved it on the original interpreter and not on SCHIP or here"),
+            Quirk::Jump => write!(f, "jumps by V0 here and on the original interpreter, by VX on SCHIP"),
+        }
+    }
+}
+
+/// Memory the ROM is loaded into, from the load address to the end of the 4KB
+pub const MAX_SIZE: usize = 0x1000 - LOAD_ADDRESS as usize;
+
+#[derive(Debug, PartialEq)]
+pub struct Report {
+    pub size: usize,
+    /// SHA-1 of the ROM, in hex
+    pub sha1: String,
+    /// Where the program goes when it starts with a jump, as most do to skip their data
+    pub entry_jump: Option<u16>,
+    /// Times each opcode pattern appears in the code, most first
+    pub opcodes: Vec<(&'static str, usize)>,
+    /// Addresses of the suspicious constructs and what they do
+    pub suspicious: Vec<(u16, String)>,
+    /// Extensions whose opcodes the code reaches, with the first address of one
+    pub extensions: BTreeMap<Extension, u16>,
+    /// Addresses of the instructions depending on each quirk
+    pub quirks: BTreeMap<Quirk, Vec<u16>>,
+}
+
+pub fn analyze(rom: &[u8], variant: Variant) -> Report {
+    let lines = disasm::disassemble(rom, variant);
+    let instructions: Vec<(u16, Instruction)> =
+        lines.iter().filter_map(|line| line.instruction.map(|instruction| (line.address, instruction))).collect();
+    let starts: BTreeSet<u16> = instructions.iter().map(|(address, _)| *address).collect();
+    let end = LOAD_ADDRESS as usize + rom.len();
+    let in_rom = |address: u16| (LOAD_ADDRESS as usize..end).contains(&(address as usize));
+
+    let mut opcodes = BTreeMap::new();
+    let mut suspicious = Vec::new();
+    let mut extensions = BTreeMap::new();
+    let mut quirks: BTreeMap<Quirk, Vec<u16>> = BTreeMap::new();
+    let code: BTreeMap<u16, Instruction> = instructions.iter().copied().collect();
+    for (address, instruction) in &instructions {
+        *opcodes.entry(instruction.pattern()).or_insert(0) += 1;
+        let quirk = match instruction {
+            Instruction::Alu(AluOp::ShiftRight, x, y) | Instruction::Alu(AluOp::ShiftLeft, x, y) if x != y => Some(Quirk::Shift),
+            Instruction::StoreRegisters(_) | Instruction::LoadRegisters(_) if uses_index_next(&code, *address) => Some(Quirk::LoadStore),
+            Instruction::JumpOffset(_) => Some(Quirk::Jump),
+            _ => None,
+        };
+        if let Some(quirk) = quirk {
+            quirks.entry(quirk).or_default().push(*address);
+        }
+        match instruction {
+            Instruction::Draw(_, _, 0) => {
+                extensions.entry(Extension::Schip).or_insert(*address);
+            }
+            Instruction::SkipIfSecondKey(_) | Instruction::SkipIfNotSecondKey(_) => {
+                extensions.entry(Extension::Chip8X).or_insert(*address);
+            }
+            Instruction::JumpOffset(target) => suspicious.push((*address, format!("jumps to {:#05X} + V0, which can't be followed", target))),
+            Instruction::LoadIndex(target) if *target < LOAD_ADDRESS => {
+                suspicious.push((*address, format!("points I at {:#05X}, in the interpreter's memory", target)))
+            }
+            Instruction::LoadIndex(target) if starts.contains(target) => {
+                suspicious.push((*address, format!("points I at code at {:#05X}", target)))
+            }
+            _ => {}
+        }
+        for next in disasm::successors(*address, *instruction) {
+            let problem = if !in_rom(next) {
+                format!("goes to {:#05X}, outside the ROM", next)
+            } else if starts.contains(&next) {
+                continue;
+            } else if starts.contains(&(next - 1)) {
+                format!("goes to {:#05X}, in the middle of an instruction", next)
+            } else {
+                let offset = (next - LOAD_ADDRESS) as usize;
+                match rom.get(offset..offset + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]])) {
+                    Some(opcode) => match extension(opcode) {
+                        Some(extension) => {
+                            let first = extensions.entry(extension).or_insert(next);
+                            *first = next.min(*first);
+                            continue;
+                        }
+                        None => format!("goes to {:#05X}, where opcode {:04X} is unknown", next, opcode),
+                    },
+                    None => format!("goes to {:#05X}, the last byte of the ROM", next),
+                }
+            };
+            suspicious.push((*address, problem));
+        }
+    }
+    let mut opcodes: Vec<(&'static str, usize)> = opcodes.into_iter().collect();
+    opcodes.sort_by_key(|(_, count)| Reverse(*count));
+
+    let entry_jump = match instructions.first() {
+        Some((LOAD_ADDRESS, Instruction::Jump(target))) => Some(*target),
+        _ => None,
+    };
+    let sha1 = Sha1::digest(rom).iter().map(|byte| format!("{:02x}", byte)).collect();
+    Report { size: rom.len(), sha1, entry_jump, opcodes, suspicious, extensions, quirks }
+}
+
+/// Extension an opcode belongs to, if it isn't plain CHIP-8
+fn extension(opcode: u16) -> Option<Extension> {
+    match (opcode >> 12, opcode & 0x0FFF) {
+        (0x0, 0x0C0..=0x0CF) | (0x0, 0x0FB..=0x0FF) => Some(Extension::Schip),
+        (0x0, 0x0D0..=0x0DF) | (0xF, 0x000) => Some(Extension::XoChip),
+        _ => match (opcode >> 12, opcode & 0x000F, opcode & 0x00FF) {
+            (0x5, 0x2, _) | (0x5, 0x3, _) | (0xF, _, 0x01) | (0xF, _, 0x02) | (0xF, _, 0x3A) => Some(Extension::XoChip),
+            (0xF, _, 0x30) | (0xF, _, 0x75) | (0xF, _, 0x85) => Some(Extension::Schip),
+            (0xE, _, 0xF2) | (0xE, _, 0xF5) => Some(Extension::Chip8X),
+            _ => None,
+        },
+    }
+}
+
+/// Whether the straight-line code after FX55 or FX65 at an address uses I before setting it
+fn uses_index_next(code: &BTreeMap<u16, Instruction>, address: u16) -> bool {
+    let mut current = (address, code[&address]);
+    for _ in 0..LOAD_STORE_LOOKAHEAD {
+        let next = match disasm::successors(current.0, current.1)[..] {
+            [next] => next,
+            _ => return false,
+        };
+        let instruction = match code.get(&next) {
+            Some(instruction) => *instruction,
+            None => return false,
+        };
+        match instruction {
+            Instruction::LoadIndex(_) => return false,
+            Instruction::Draw(..) | Instruction::AddIndex(_) | Instruction::StoreBcd(_) | Instruction::StoreRegisters(_) | Instruction::LoadRegisters(_) => {
+                return true
+            }
+            _ => current = (next, instruction),
+        }
+    }
+    false
+}
+
+impl Report {
+    /// Variant to run the ROM with, or the extension it needs that this emulator doesn't run
+    pub fn profile(&self) -> String {
+        match self.extensions.keys().next_back() {
+            Some(Extension::XoChip) => String::from("XO-CHIP, which this emulator doesn't run"),
+            Some(Extension::Schip) => String::from("SCHIP, which this emulator doesn't run"),
+            Some(Extension::Chip8X) => String::from("chip8x, with --variant chip8x"),
+            None if self.quirks.contains_key(&Quirk::Shift) || self.quirks.contains_key(&Quirk::LoadStore) => {
+                String::from("chip8, though it may have been written for the original interpreter's quirks")
+            }
+            None => String::from("chip8"),
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Size: {} bytes", self.size)?;
+        writeln!(f, "SHA-1: {}", self.sha1)?;
+        match self.entry_jump {
+            Some(target) => writeln!(f, "Entry point: {:#05X}, jumping to {:#05X}", LOAD_ADDRESS, target)?,
+            None => writeln!(f, "Entry point: {:#05X}", LOAD_ADDRESS)?,
+        }
+        if self.size <= MAX_SIZE {
+            writeln!(f, "Fits in 0x200-0xFFF, with {} bytes to spare", MAX_SIZE - self.size)?;
+        } else {
+            writeln!(f, "Doesn't fit in 0x200-0xFFF, {} bytes too long", self.size - MAX_SIZE)?;
+        }
+        writeln!(f, "Opcodes:")?;
+        for (pattern, count) in &self.opcodes {
+            writeln!(f, "  {}  {:>5}", pattern, count)?;
+        }
+        if self.suspicious.is_empty() {
+            writeln!(f, "Nothing suspicious found")?;
+        } else {
+            writeln!(f, "Suspicious:")?;
+            for (address, problem) in &self.suspicious {
+                writeln!(f, "  {:#05X}: {}", address, problem)?;
+            }
+        }
+        for (extension, address) in &self.extensions {
+            writeln!(f, "Uses {}, first at {:#05X}", extension, address)?;
+        }
+        for (quirk, addresses) in &self.quirks {
+            let addresses: Vec<String> = addresses.iter().map(|address| format!("{:#05X}", address)).collect();
+            writeln!(f, "{} {}", addresses.join(", "), quirk)?;
+        }
+        write!(f, "Suggested profile: {}", self.profile())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Variant;
+    use crate::info::{analyze, Extension, Quirk};
+
+    /// Code past the data is counted, and the ways it goes wrong, extensions and quirks are found
+    #[test]
+    fn test_analyze() {
+        let rom = [
+            0x12, 0x04, // 0x200: JP 0x204
+            0xF0, 0x90, // 0x202: sprite
+            0xA2, 0x02, // 0x204: LD I, 0x202
+            0xA2, 0x04, // 0x206: LD I, 0x204, at code
+            0x3F, 0x00, // 0x208: SE VF, 0x00
+            0x13, 0x00, // 0x20A: JP 0x300, outside the ROM
+            0xB2, 0x00, // 0x20C: JP V0, 0x200
+        ];
+        let report = analyze(&rom, Variant::Chip8);
+        assert_eq!(report.entry_jump, Some(0x204));
+        assert_eq!(report.opcodes, vec![("1NNN", 2), ("ANNN", 2), ("3XNN", 1), ("BNNN", 1)]);
+        assert_eq!(
+            report.to_string().lines().skip(2).collect::<Vec<_>>(),
+            [
+                "Entry point: 0x200, jumping to 0x204",
+                "Fits in 0x200-0xFFF, with 3570 bytes to spare",
+                "Opcodes:",
+                "  1NNN      2",
+                "  ANNN      2",
+                "  3XNN      1",
+                "  BNNN      1",
+                "Suspicious:",
+                "  0x206: points I at code at 0x204",
+                "  0x20A: goes to 0x300, outside the ROM",
+                "  0x20C: jumps to 0x200 + V0, which can't be followed",
+                "0x20C jumps by V0 here and on the original interpreter, by VX on SCHIP",
+                "Suggested profile: chip8",
+            ]
+        );
+
+        let schip = [
+            0x80, 0x16, // 0x200: SHR V0, V1
+            0xF1, 0x65, // 0x202: LD V1, [I]
+            0xD0, 0x15, // 0x204: DRW V0, V1, 5, from I moved past the registers or not
+            0x00, 0xFF, // 0x206: SCHIP high resolution
+        ];
+        let report = analyze(&schip, Variant::Chip8);
+        assert_eq!(report.extensions.into_iter().collect::<Vec<_>>(), [(Extension::Schip, 0x206)]);
+        assert_eq!(report.quirks.into_iter().collect::<Vec<_>>(), [(Quirk::Shift, vec![0x200]), (Quirk::LoadStore, vec![0x202])]);
+        assert!(report.suspicious.is_empty());
+    }
+}
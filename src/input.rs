@@ -0,0 +1,72 @@
+//! Global keyboard input through device_query.
+//!
+//! Gameplay input normally comes from the window's own key events, so keys are only seen while
+//! the emulator is focused. This reads the keyboard system-wide instead (e.g. to drive the
+//! emulator from another window), which needs extra permissions on macOS.
+
+use device_query::{DeviceQuery, DeviceState, Keycode};
+use minifb::Key;
+
+pub struct GlobalKeyboard {
+    device_state: DeviceState,
+}
+
+impl GlobalKeyboard {
+    pub fn new() -> Self {
+        GlobalKeyboard { device_state: DeviceState::new() }
+    }
+
+    /// Keys currently held down anywhere on the system, as window key codes
+    pub fn get_keys(&self) -> Vec<Key> {
+        self.device_state.get_keys().into_iter().filter_map(to_window_key).collect()
+    }
+}
+
+fn to_window_key(keycode: Keycode) -> Option<Key> {
+    Some(match keycode {
+        Keycode::Key0 => Key::Key0,
+        Keycode::Key1 => Key::Key1,
+        Keycode::Key2 => Key::Key2,
+        Keycode::Key3 => Key::Key3,
+        Keycode::Key4 => Key::Key4,
+        Keycode::Key5 => Key::Key5,
+        Keycode::Key6 => Key::Key6,
+        Keycode::Key7 => Key::Key7,
+        Keycode::Key8 => Key::Key8,
+        Keycode::Key9 => Key::Key9,
+        Keycode::A => Key::A,
+        Keycode::B => Key::B,
+        Keycode::C => Key::C,
+        Keycode::D => Key::D,
+        Keycode::E => Key::E,
+        Keycode::F => Key::F,
+        Keycode::G => Key::G,
+        Keycode::H => Key::H,
+        Keycode::I => Key::I,
+        Keycode::J => Key::J,
+        Keycode::K => Key::K,
+        Keycode::L => Key::L,
+        Keycode::M => Key::M,
+        Keycode::N => Key::N,
+        Keycode::O => Key::O,
+        Keycode::P => Key::P,
+        Keycode::Q => Key::Q,
+        Keycode::R => Key::R,
+        Keycode::S => Key::S,
+        Keycode::T => Key::T,
+        Keycode::U => Key::U,
+        Keycode::V => Key::V,
+        Keycode::W => Key::W,
+        Keycode::X => Key::X,
+        Keycode::Y => Key::Y,
+        Keycode::Z => Key::Z,
+        Keycode::Up => Key::Up,
+        Keycode::Down => Key::Down,
+        Keycode::Left => Key::Left,
+        Keycode::Right => Key::Right,
+        Keycode::Space => Key::Space,
+        Keycode::Enter => Key::Enter,
+        Keycode::Escape => Key::Escape,
+        _ => return None,
+    })
+}
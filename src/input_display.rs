@@ -0,0 +1,57 @@
+//! On-screen display of the keypad state.
+//!
+//! A miniature 4x4 keypad, laid out like the COSMAC VIP hex pad, is drawn in the bottom right
+//! corner over the game with the keys currently held down lit up. It shows what the game actually
+//! receives, after every input device, autofire and replay playback has been applied, so it's
+//! handy both on streams and to find out why a key doesn't reach the game.
+
+use crate::keypad_grid::GRID;
+
+/// Side of a key in display pixels, keys are one pixel apart
+const KEY_SIZE: usize = 3;
+const PITCH: usize = KEY_SIZE + 1;
+
+const PRESSED: u32 = 0x00FF_C000;
+const RELEASED: u32 = 0x0040_4040;
+
+pub struct InputDisplay {
+    visible: bool,
+    keys: [bool; 16],
+}
+
+impl InputDisplay {
+    pub fn new() -> Self {
+        InputDisplay { visible: false, keys: [false; 16] }
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// Takes the keypad state for this frame, returning true when the display needs to be redrawn
+    pub fn update(&mut self, keys: [bool; 16]) -> bool {
+        let changed = self.visible && keys != self.keys;
+        self.keys = keys;
+        changed
+    }
+
+    /// Draws the keypad over a game frame
+    pub fn draw(&self, buffer: &mut [u32], width: usize, height: usize) {
+        let left = width - 4 * PITCH;
+        let top = height - 4 * PITCH;
+        for (cell, chip8_key) in GRID.iter().enumerate() {
+            let color = if self.keys[*chip8_key] { PRESSED } else { RELEASED };
+            let cell_x = left + 1 + (cell % 4) * PITCH;
+            let cell_y = top + 1 + (cell / 4) * PITCH;
+            for y in 0..KEY_SIZE {
+                for x in 0..KEY_SIZE {
+                    buffer[(cell_y + y) * width + cell_x + x] = color;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//! Mapping from keyboard keys to the 16 CHIP-8 keypad keys.
+//!
+//! The keypad is laid out on the 4x4 block of keys that sits under 1234/QWER/ASDF/ZXCV on a US
+//! keyboard. In physical mode (the default) the block stays in the same place whatever the
+//! keyboard layout, so on AZERTY it's 1234/AZER/QSDF/WXCV and on Dvorak 1234/',.P/AOEU/;QJK.
+//! In logical mode the keys are matched by the character printed on them instead.
+//!
+//! Which CHIP-8 key each position of the block produces is chosen by a preset, and individual
+//! keys can be rebound to any key in the config file.
+
+use minifb::Key;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Keys at each position of the 4x4 block on a US QWERTY keyboard, row by row
+const QWERTY_BLOCK: [Key; 16] = [
+    Key::Key1, Key::Key2, Key::Key3, Key::Key4,
+    Key::Q, Key::W, Key::E, Key::R,
+    Key::A, Key::S, Key::D, Key::F,
+    Key::Z, Key::X, Key::C, Key::V,
+];
+
+/// Numeric keypad block used for the second CHIP-8X keypad, row by row
+const NUMPAD_BLOCK: [Key; 16] = [
+    Key::NumPad7, Key::NumPad8, Key::NumPad9, Key::NumPadSlash,
+    Key::NumPad4, Key::NumPad5, Key::NumPad6, Key::NumPadAsterisk,
+    Key::NumPad1, Key::NumPad2, Key::NumPad3, Key::NumPadMinus,
+    Key::NumPad0, Key::NumPadDot, Key::NumPadEnter, Key::NumPadPlus,
+];
+
+/// Every key the window can report, used to look keys up by name
+const ALL_KEYS: [Key; 106] = [
+    Key::Key0, Key::Key1, Key::Key2, Key::Key3, Key::Key4,
+    Key::Key5, Key::Key6, Key::Key7, Key::Key8, Key::Key9,
+    Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I, Key::J, Key::K, Key::L,
+    Key::M, Key::N, Key::O, Key::P, Key::Q, Key::R, Key::S, Key::T, Key::U, Key::V, Key::W, Key::X,
+    Key::Y, Key::Z,
+    Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8,
+    Key::F9, Key::F10, Key::F11, Key::F12, Key::F13, Key::F14, Key::F15,
+    Key::Down, Key::Left, Key::Right, Key::Up,
+    Key::Apostrophe, Key::Backquote, Key::Backslash, Key::Comma, Key::Equal, Key::LeftBracket,
+    Key::Minus, Key::Period, Key::RightBracket, Key::Semicolon, Key::Slash,
+    Key::Backspace, Key::Delete, Key::End, Key::Enter, Key::Escape, Key::Home, Key::Insert,
+    Key::Menu, Key::PageDown, Key::PageUp, Key::Pause, Key::Space, Key::Tab,
+    Key::NumLock, Key::CapsLock, Key::ScrollLock,
+    Key::LeftShift, Key::RightShift, Key::LeftCtrl, Key::RightCtrl,
+    Key::NumPad0, Key::NumPad1, Key::NumPad2, Key::NumPad3, Key::NumPad4,
+    Key::NumPad5, Key::NumPad6, Key::NumPad7, Key::NumPad8, Key::NumPad9,
+    Key::NumPadDot, Key::NumPadSlash, Key::NumPadAsterisk, Key::NumPadMinus, Key::NumPadPlus,
+    Key::NumPadEnter,
+    Key::LeftAlt, Key::RightAlt, Key::LeftSuper, Key::RightSuper,
+];
+
+/// Looks a key up by its name as written in the config file, e.g. `Q`, `Space` or `NumPad7`.
+/// Single digits are accepted for the number row.
+pub fn key_from_name(name: &str) -> Option<Key> {
+    ALL_KEYS.iter().copied().find(|key| {
+        let key_name = key_name(*key);
+        key_name.eq_ignore_ascii_case(name) || key_name.eq_ignore_ascii_case(&format!("Key{}", name))
+    })
+}
+
+pub fn key_name(key: Key) -> String {
+    format!("{:?}", key)
+}
+
+/// Which CHIP-8 key each position of the 4x4 block produces
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    /// Keys 0-F in order: 1234 is 0123, QWER is 4567 and so on
+    #[default]
+    Sequential,
+    /// Laid out like the COSMAC VIP hex pad: 123C/456D/789E/A0BF
+    Cosmac,
+}
+
+impl Preset {
+    fn chip8_keys(self) -> [usize; 16] {
+        match self {
+            Preset::Sequential => [
+                0x0, 0x1, 0x2, 0x3,
+                0x4, 0x5, 0x6, 0x7,
+                0x8, 0x9, 0xA, 0xB,
+                0xC, 0xD, 0xE, 0xF,
+            ],
+            Preset::Cosmac => [
+                0x1, 0x2, 0x3, 0xC,
+                0x4, 0x5, 0x6, 0xD,
+                0x7, 0x8, 0x9, 0xE,
+                0xA, 0x0, 0xB, 0xF,
+            ],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    #[default]
+    Qwerty,
+    Azerty,
+    Qwertz,
+    Dvorak,
+}
+
+impl Layout {
+    /// Key reported by the window when pressing the key at the given QWERTY position
+    fn key_at(self, qwerty_key: Key) -> Key {
+        match (self, qwerty_key) {
+            (Layout::Azerty, Key::Q) => Key::A,
+            (Layout::Azerty, Key::W) => Key::Z,
+            (Layout::Azerty, Key::A) => Key::Q,
+            (Layout::Azerty, Key::Z) => Key::W,
+            (Layout::Qwertz, Key::Z) => Key::Y,
+            (Layout::Dvorak, Key::Q) => Key::Apostrophe,
+            (Layout::Dvorak, Key::W) => Key::Comma,
+            (Layout::Dvorak, Key::E) => Key::Period,
+            (Layout::Dvorak, Key::R) => Key::P,
+            (Layout::Dvorak, Key::S) => Key::O,
+            (Layout::Dvorak, Key::D) => Key::E,
+            (Layout::Dvorak, Key::F) => Key::U,
+            (Layout::Dvorak, Key::Z) => Key::Semicolon,
+            (Layout::Dvorak, Key::X) => Key::Q,
+            (Layout::Dvorak, Key::C) => Key::J,
+            (Layout::Dvorak, Key::V) => Key::K,
+            (_, key) => key,
+        }
+    }
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "qwerty" => Ok(Layout::Qwerty),
+            "azerty" => Ok(Layout::Azerty),
+            "qwertz" => Ok(Layout::Qwertz),
+            "dvorak" => Ok(Layout::Dvorak),
+            _ => Err(format!("Unknown keyboard layout: {}", s)),
+        }
+    }
+}
+
+pub struct Keymap {
+    // Window key bound to each CHIP-8 key
+    keys: [Key; 16],
+}
+
+impl Keymap {
+    /// Keypad bound to the same physical keys on any layout
+    pub fn physical(preset: Preset, layout: Layout) -> Self {
+        let mut keymap = Keymap::logical(preset);
+        for key in keymap.keys.iter_mut() {
+            *key = layout.key_at(*key);
+        }
+        keymap
+    }
+
+    /// Keypad bound to the 1234/QWER/ASDF/ZXCV characters wherever they are on the keyboard
+    pub fn logical(preset: Preset) -> Self {
+        Keymap::from_block(preset, QWERTY_BLOCK)
+    }
+
+    /// Keypad on the numeric keypad, used for the second CHIP-8X keypad. The numeric keypad
+    /// doesn't change between layouts.
+    pub fn numpad(preset: Preset) -> Self {
+        Keymap::from_block(preset, NUMPAD_BLOCK)
+    }
+
+    fn from_block(preset: Preset, block: [Key; 16]) -> Self {
+        let mut keys = [Key::Unknown; 16];
+        for (position, chip8_key) in preset.chip8_keys().iter().enumerate() {
+            keys[*chip8_key] = block[position];
+        }
+        Keymap { keys }
+    }
+
+    /// Rebinds a single CHIP-8 key. A CHIP-8 key the window key was bound to takes the old key
+    /// in its place, and is returned
+    pub fn bind(&mut self, chip8_key: usize, key: Key) -> Option<usize> {
+        let previous = self.chip8_key(key).filter(|previous| *previous != chip8_key);
+        if let Some(previous) = previous {
+            self.keys[previous] = self.keys[chip8_key];
+        }
+        self.keys[chip8_key] = key;
+        previous
+    }
+
+    /// Window key bound to a CHIP-8 key
+    pub fn key(&self, chip8_key: usize) -> Key {
+        self.keys[chip8_key]
+    }
+
+    /// CHIP-8 key bound to a window key
+    pub fn chip8_key(&self, key: Key) -> Option<usize> {
+        self.keys.iter().position(|bound| *bound == key)
+    }
+
+    /// Translates the window keys currently held down into keypad state
+    pub fn keypad_state(&self, pressed: &[Key]) -> [bool; 16] {
+        let mut state = [false; 16];
+        for (chip8_key, key) in self.keys.iter().enumerate() {
+            state[chip8_key] = pressed.contains(key);
+        }
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::keymap::{key_from_name, Keymap, Layout, Preset};
+    use minifb::Key;
+
+    /// Physical mapping follows the key position, not the character
+    #[test]
+    fn test_physical_azerty() {
+        let keymap = Keymap::physical(Preset::Sequential, Layout::Azerty);
+        let state = keymap.keypad_state(&[Key::A, Key::W]);
+        assert!(state[4]);
+        assert!(state[12]);
+        assert!(!state[8]);
+    }
+
+    /// Logical mapping follows the character whatever the layout
+    #[test]
+    fn test_logical() {
+        let keymap = Keymap::logical(Preset::Sequential);
+        let state = keymap.keypad_state(&[Key::A, Key::Key1]);
+        assert!(state[8]);
+        assert!(state[0]);
+        assert_eq!(state.iter().filter(|pressed| **pressed).count(), 2);
+    }
+
+    /// COSMAC preset puts the hex pad layout on the block
+    #[test]
+    fn test_cosmac_preset() {
+        let keymap = Keymap::logical(Preset::Cosmac);
+        assert!(keymap.keypad_state(&[Key::Key4])[0xC]);
+        assert!(keymap.keypad_state(&[Key::X])[0x0]);
+        assert!(keymap.keypad_state(&[Key::V])[0xF]);
+    }
+
+    /// Binding a key taken by another CHIP-8 key swaps the two
+    #[test]
+    fn test_bind_swaps() {
+        let mut keymap = Keymap::logical(Preset::Sequential);
+        assert_eq!(keymap.bind(0x0, Key::W), Some(0x5));
+        assert_eq!((keymap.key(0x0), keymap.key(0x5)), (Key::W, Key::Key1));
+        assert_eq!(keymap.keypad_state(&[Key::W]).iter().filter(|pressed| **pressed).count(), 1);
+        assert_eq!(keymap.bind(0x0, Key::W), None);
+        assert_eq!(keymap.bind(0x1, Key::Space), None);
+        assert_eq!(keymap.key(0x1), Key::Space);
+    }
+
+    /// Keys can be named as in the config file
+    #[test]
+    fn test_key_from_name() {
+        assert_eq!(key_from_name("space"), Some(Key::Space));
+        assert_eq!(key_from_name("7"), Some(Key::Key7));
+        assert_eq!(key_from_name("NumPad7"), Some(Key::NumPad7));
+        assert_eq!(key_from_name("nope"), None);
+    }
+}
@@ -0,0 +1,64 @@
+//! Drawing of the 4x4 hex keypad, shared by the screens and overlays that show it.
+//!
+//! The grid covers the whole 64x32 display in 16x8 cells, laid out like the COSMAC VIP hex pad,
+//! with each key's digit drawn from the built-in font.
+
+use crate::chip8::CHIP8_FONTSET;
+
+pub const CELL_WIDTH: usize = 16;
+pub const CELL_HEIGHT: usize = 8;
+
+/// CHIP-8 key shown in each cell of the grid
+pub const GRID: [usize; 16] = [
+    0x1, 0x2, 0x3, 0xC,
+    0x4, 0x5, 0x6, 0xD,
+    0x7, 0x8, 0x9, 0xE,
+    0xA, 0x0, 0xB, 0xF,
+];
+
+/// Colors of a single cell. `None` leaves the pixels underneath untouched.
+pub struct CellStyle {
+    pub background: Option<u32>,
+    pub foreground: u32,
+}
+
+/// Draws every cell with the style returned for its grid index
+pub fn draw(buffer: &mut [u32], width: usize, style: impl Fn(usize) -> CellStyle) {
+    for (cell, chip8_key) in GRID.iter().enumerate() {
+        let style = style(cell);
+        let cell_x = (cell % 4) * CELL_WIDTH;
+        let cell_y = (cell / 4) * CELL_HEIGHT;
+
+        if let Some(background) = style.background {
+            for y in 0..CELL_HEIGHT {
+                for x in 0..CELL_WIDTH {
+                    buffer[(cell_y + y) * width + cell_x + x] = background;
+                }
+            }
+        }
+
+        // Font glyphs are 4x5, stored in the high nibble of 5 consecutive bytes
+        let glyph = &CHIP8_FONTSET[chip8_key * 5..chip8_key * 5 + 5];
+        for (row, bits) in glyph.iter().enumerate() {
+            for column in 0..4 {
+                if bits & (0x80 >> column) != 0 {
+                    buffer[(cell_y + 1 + row) * width + cell_x + 6 + column] = style.foreground;
+                }
+            }
+        }
+    }
+}
+
+/// Grid index of the cell under a display coordinate
+pub fn cell_at(x: f32, y: f32) -> Option<usize> {
+    if x < 0.0 || y < 0.0 {
+        return None;
+    }
+    let column = x as usize / CELL_WIDTH;
+    let row = y as usize / CELL_HEIGHT;
+    if column < 4 && row < 4 {
+        Some(row * 4 + column)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,92 @@
+//! Key mapping test screen.
+//!
+//! Shows the keypad grid without loading a game and lights up each cell while the keys mapped to
+//! it are held, keeping it lit briefly after release so quick taps are visible too. The window
+//! title lists the keys held and what they are mapped to, including keys that aren't mapped at
+//! all. Tab switches between the first and second (CHIP-8X) keypad.
+
+use crate::cli::InputArgs;
+use crate::fatal;
+use crate::keymap::key_name;
+use crate::keypad_grid::{self, CellStyle, GRID};
+use crate::run::{HEIGHT, WIDTH};
+use minifb::{Key, KeyRepeat, Scale, ScaleMode, Window, WindowOptions};
+use std::time::{Duration, Instant};
+
+/// How long a cell stays lit after its key is released
+const FLASH: Duration = Duration::from_millis(150);
+
+const ON: u32 = 0x0FFF;
+const OFF: u32 = 0x0000;
+
+pub fn run(args: &InputArgs) {
+    let config = args.load_config();
+    let keymaps = [
+        args.keymap(&config),
+        config.second_keymap.build(config.keymap.preset).unwrap_or_else(fatal),
+    ];
+
+    let mut window = Window::new(
+        "Keytest",
+        WIDTH,
+        HEIGHT,
+        WindowOptions {
+            borderless: false,
+            transparency: false,
+            title: true,
+            resize: false,
+            scale: Scale::X16,
+            scale_mode: ScaleMode::Stretch,
+            topmost: false,
+        },
+    )
+        .unwrap_or_else(fatal);
+    let mut buffer: Vec<u32> = vec![0; WIDTH * HEIGHT];
+
+    let mut keypad = 0;
+    let mut last_held: [Option<Instant>; 16] = [None; 16];
+    let mut title = String::new();
+
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        if window.is_key_pressed(Key::Tab, KeyRepeat::No) {
+            keypad = 1 - keypad;
+            last_held = [None; 16];
+        }
+
+        let held = window.get_keys().unwrap_or_default();
+        let now = Instant::now();
+        for (chip8_key, pressed) in keymaps[keypad].keypad_state(&held).iter().enumerate() {
+            if *pressed {
+                last_held[chip8_key] = Some(now);
+            }
+        }
+
+        keypad_grid::draw(&mut buffer, WIDTH, |cell| {
+            let lit = last_held[GRID[cell]].is_some_and(|at| now.duration_since(at) < FLASH);
+            if lit {
+                CellStyle { background: Some(ON), foreground: OFF }
+            } else {
+                CellStyle { background: Some(OFF), foreground: ON }
+            }
+        });
+
+        let new_title = describe(&held, keypad, |key| keymaps[keypad].chip8_key(key));
+        if new_title != title {
+            window.set_title(&new_title);
+            title = new_title;
+        }
+        window.update_with_buffer(&buffer, WIDTH, HEIGHT).unwrap();
+    }
+}
+
+/// Window title listing each held key with the CHIP-8 key it is mapped to
+fn describe(held: &[Key], keypad: usize, chip8_key: impl Fn(Key) -> Option<usize>) -> String {
+    let mut title = format!("Keytest - keypad {}", keypad + 1);
+    for key in held.iter().filter(|key| **key != Key::Tab) {
+        match chip8_key(*key) {
+            Some(chip8_key) => title.push_str(&format!(" | {} = {:X}", key_name(*key), chip8_key)),
+            None => title.push_str(&format!(" | {} not mapped", key_name(*key))),
+        }
+    }
+    title
+}
@@ -0,0 +1,3 @@
+//! The CHIP-8 core, shared by the emulator with its tests, benchmarks and fuzz targets
+
+pub mod chip8;
@@ -0,0 +1,131 @@
+//! Short loops the program spins in, found as jumps back a few instructions. Games wait on the
+//! delay timer or the keypad this way, and a program that looks frozen is usually stuck in one.
+
+use crate::chip8::{Chip8, Instruction};
+use crate::symbols::Symbols;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Longest loop body counted, in bytes
+const MAX_LENGTH: u16 = 16;
+
+#[derive(Default)]
+pub struct Loops {
+    /// By first and last address
+    loops: BTreeMap<(u16, u16), Loop>,
+}
+
+struct Loop {
+    kind: Kind,
+    iterations: u64,
+}
+
+/// What a loop waits for, judging by its instructions
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    /// A jump to itself, which never ends
+    Halt,
+    DelayTimer,
+    Keypad,
+    /// Neither, so only memory it reads can end it
+    Spin,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kind::Halt => write!(f, "jumps to itself"),
+            Kind::DelayTimer => write!(f, "waits for the delay timer"),
+            Kind::Keypad => write!(f, "waits for a key"),
+            Kind::Spin => write!(f, "spins"),
+        }
+    }
+}
+
+impl Loops {
+    /// Looks at the instruction at the program counter before it runs, returning the kind of
+    /// loop it closes the first time it's found
+    pub fn record(&mut self, chip8: &Chip8) -> Option<Kind> {
+        let end = chip8.program_counter();
+        let start = match decode(chip8, end)? {
+            Instruction::Jump(target) if target <= end && end - target <= MAX_LENGTH => target,
+            _ => return None,
+        };
+        let mut found = None;
+        let the_loop = self.loops.entry((start, end)).or_insert_with(|| {
+            let kind = classify(chip8, start, end);
+            found = Some(kind);
+            Loop { kind, iterations: 0 }
+        });
+        the_loop.iterations += 1;
+        found
+    }
+
+    /// Loops found, most run first
+    pub fn report(&self, symbols: &Symbols) -> String {
+        if self.loops.is_empty() {
+            return String::from("No loops found");
+        }
+        let mut loops: Vec<(&(u16, u16), &Loop)> = self.loops.iter().collect();
+        loops.sort_by_key(|(_, the_loop)| Reverse(the_loop.iterations));
+        let lines: Vec<String> = loops
+            .iter()
+            .map(|((start, end), the_loop)| {
+                format!("  {} to {:#05X}, {} times: {}", symbols.describe(*start), end, the_loop.iterations, the_loop.kind)
+            })
+            .collect();
+        format!("Loops:\n{}", lines.join("\n"))
+    }
+}
+
+fn decode(chip8: &Chip8, address: u16) -> Option<Instruction> {
+    let opcode = chip8.memory().get(address as usize..address as usize + 2)?;
+    Instruction::decode(u16::from_be_bytes([opcode[0], opcode[1]]), chip8.variant())
+}
+
+fn classify(chip8: &Chip8, start: u16, end: u16) -> Kind {
+    if start == end {
+        return Kind::Halt;
+    }
+    let body: Vec<Instruction> = (start..end).step_by(2).filter_map(|address| decode(chip8, address)).collect();
+    if body.iter().any(|instruction| matches!(instruction, Instruction::GetDelay(_))) {
+        Kind::DelayTimer
+    } else if body.iter().any(|instruction| {
+        matches!(
+            instruction,
+            Instruction::SkipIfKey(_) | Instruction::SkipIfNotKey(_) | Instruction::SkipIfSecondKey(_) | Instruction::SkipIfNotSecondKey(_)
+        )
+    }) {
+        Kind::Keypad
+    } else {
+        Kind::Spin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assemble_with_symbols;
+    use crate::chip8::Chip8;
+    use crate::loops::{Kind, Loops};
+
+    /// A wait on the delay timer is found once and counted every time round
+    #[test]
+    fn test_loops() {
+        let source = ": main\n\tv0 := 12\n\tdelay := v0\n: wait\n\tv0 := delay\n\tif v0 != 0 then jump wait\n: halt\n\tjump halt";
+        let (rom, symbols) = assemble_with_symbols(source, None).unwrap();
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&rom);
+        let mut loops = Loops::default();
+        let mut found = Vec::new();
+        for _ in 0..30 {
+            found.extend(loops.record(&chip8));
+            chip8.emulate_cycle();
+        }
+        assert_eq!(found, [Kind::DelayTimer, Kind::Halt]);
+        assert_eq!(
+            loops.report(&symbols),
+            "Loops:\n  0x20A (halt) to 0x20A, 14 times: jumps to itself\n  0x204 (wait) to 0x208, 4 times: waits for the delay timer"
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! Language server for Octo source, spoken over standard input and output.
+//!
+//! Editors start it with `chip8 lsp`. Open documents are assembled on every change and the first
+//! error is reported as a diagnostic; go to definition jumps to where a label, constant, alias or
+//! macro is defined; hovering an instruction shows what it does and the opcode it assembles to,
+//! hovering a label shows its address.
+
+use crate::assembler::{assemble, assemble_with_symbols, Error};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// Directives that define a name, which comes right after them
+const DEFINITIONS: [&str; 5] = [":", ":const", ":alias", ":macro", ":sprite"];
+
+/// Hover text for the words of the language
+const DOCS: [(&str, &str); 25] = [
+    ("clear", "`clear`: clear the screen (00E0)"),
+    ("return", "`return`: return from a subroutine (00EE)"),
+    (";", "`;`: return from a subroutine (00EE)"),
+    ("jump", "`jump label`: continue at the label (1NNN)"),
+    ("jump0", "`jump0 label`: continue at the label plus v0 (BNNN)"),
+    (":call", "`:call label`: call the subroutine at the label (2NNN). Writing the label alone does the same"),
+    ("sprite", "`sprite vx vy n`: draw n rows of the sprite at i at (vx, vy), vf = 1 on collision (DXYN)"),
+    ("bcd", "`bcd vx`: store the decimal digits of vx at i, i+1 and i+2 (FX33)"),
+    ("save", "`save vx`: store v0 to vx at i (FX55)"),
+    ("load", "`load vx`: load v0 to vx from i (FX65)"),
+    ("delay", "`delay := vx` sets the delay timer (FX15), `vx := delay` reads it (FX07)"),
+    ("buzzer", "`buzzer := vx`: sound the buzzer for vx ticks (FX18)"),
+    ("i", "`i := label` (ANNN), `i := hex vx` for the font digit of vx (FX29), `i += vx` (FX1E)"),
+    ("hex", "`i := hex vx`: point i at the font digit of vx (FX29)"),
+    ("random", "`vx := random mask`: random byte ANDed with mask (CXNN)"),
+    ("key", "`if vx key then`: run the next statement if the key in vx is held (EXA1)"),
+    ("-key", "`if vx -key then`: run the next statement if the key in vx is not held (EX9E)"),
+    ("if", "`if condition then statement`, or `if condition begin ... else ... end`"),
+    ("loop", "`loop ... again` repeats forever, `while condition` inside leaves the loop when false"),
+    ("while", "`while condition`: leave the enclosing loop unless the condition holds"),
+    ("again", "`again`: jump back to the matching `loop`"),
+    (":const", "`:const name value`: give a number a name"),
+    (":alias", "`:alias name vx`: give a register a name"),
+    (":macro", "`:macro name arguments { body }`: writing the name expands the body"),
+    (":include", "`:include \"file.8o\"`: assemble another file here"),
+];
+
+/// Runs the server until the client asks it to exit
+pub fn run() {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut documents: HashMap<String, String> = HashMap::new();
+
+    while let Some(message) = read_message(&mut input) {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+        let result = match method {
+            "initialize" => Some(json!({
+                "capabilities": { "textDocumentSync": 1, "definitionProvider": true, "hoverProvider": true }
+            })),
+            "shutdown" => Some(Value::Null),
+            "exit" => return,
+            "textDocument/didOpen" => {
+                documents.insert(uri.clone(), params["textDocument"]["text"].as_str().unwrap_or_default().to_string());
+                publish_diagnostics(&uri, &documents[&uri]);
+                None
+            }
+            "textDocument/didChange" => {
+                // Full sync, so the last change is the whole document
+                if let Some(text) = params["contentChanges"].as_array().and_then(|changes| changes.last()) {
+                    documents.insert(uri.clone(), text["text"].as_str().unwrap_or_default().to_string());
+                    publish_diagnostics(&uri, &documents[&uri]);
+                }
+                None
+            }
+            "textDocument/didClose" => {
+                documents.remove(&uri);
+                None
+            }
+            "textDocument/definition" | "textDocument/hover" => {
+                let text = documents.get(&uri).map_or("", String::as_str);
+                let line = params["position"]["line"].as_u64().unwrap_or(0) as usize;
+                let character = params["position"]["character"].as_u64().unwrap_or(0) as usize;
+                let word = word_at(text, line, character);
+                Some(match (method, word) {
+                    ("textDocument/definition", Some(word)) => match definition(text, word) {
+                        Some((line, character)) => json!({ "uri": uri, "range": range(line, character, word.len()) }),
+                        None => Value::Null,
+                    },
+                    (_, Some(word)) => match hover(text, word, &path_of(&uri)) {
+                        Some(contents) => json!({ "contents": { "kind": "markdown", "value": contents } }),
+                        None => Value::Null,
+                    },
+                    _ => Value::Null,
+                })
+            }
+            _ => None,
+        };
+        // Requests have an id and get a response, notifications don't
+        if let (Some(id), Some(result)) = (message.get("id"), result) {
+            write_message(&json!({ "jsonrpc": "2.0", "id": id, "result": result }));
+        }
+    }
+}
+
+/// Reads one message, `None` at the end of the input
+fn read_message(input: &mut impl BufRead) -> Option<Value> {
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).ok()? == 0 {
+            return None;
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok()?;
+        }
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body).ok()?;
+    serde_json::from_slice(&body).ok()
+}
+
+fn write_message(message: &Value) {
+    let body = message.to_string();
+    let mut output = io::stdout().lock();
+    let _ = write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+    let _ = output.flush();
+}
+
+fn publish_diagnostics(uri: &str, text: &str) {
+    let path = path_of(uri);
+    let diagnostics: Vec<Value> = match assemble(text, Some(&path)) {
+        Ok(_) => Vec::new(),
+        Err(error) => vec![diagnostic(&error, &path, text)],
+    };
+    write_message(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    }));
+}
+
+/// Diagnostic covering the line of the error. Errors in included files are shown on the first
+/// line, naming the file.
+fn diagnostic(error: &Error, path: &Path, text: &str) -> Value {
+    let in_document = error.file.as_ref().is_none_or(|file| file == path);
+    let (line, message) = if in_document {
+        (error.line.saturating_sub(1), error.message.clone())
+    } else {
+        (0, error.to_string())
+    };
+    let length = text.lines().nth(line).map_or(0, str::len);
+    json!({ "range": range(line, 0, length), "severity": 1, "source": "chip8", "message": message })
+}
+
+fn range(line: usize, character: usize, length: usize) -> Value {
+    json!({
+        "start": { "line": line, "character": character },
+        "end": { "line": line, "character": character + length },
+    })
+}
+
+/// File path of a `file://` URI
+fn path_of(uri: &str) -> PathBuf {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut bytes = path.bytes();
+    while let Some(byte) = bytes.next() {
+        let escaped = if byte == b'%' {
+            let digits: String = bytes.by_ref().take(2).map(char::from).collect();
+            u8::from_str_radix(&digits, 16).ok()
+        } else {
+            None
+        };
+        decoded.push(escaped.unwrap_or(byte));
+    }
+    PathBuf::from(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+/// Word under a position, words being separated by whitespace as in the assembler
+fn word_at(text: &str, line: usize, character: usize) -> Option<&str> {
+    let line = text.lines().nth(line)?;
+    let code = line.split('#').next().unwrap();
+    let mut start = 0;
+    for word in code.split(char::is_whitespace) {
+        let end = start + word.len();
+        if !word.is_empty() && (start..=end).contains(&character) {
+            return Some(word);
+        }
+        start = end + 1;
+    }
+    None
+}
+
+/// Line and column where a name is defined
+fn definition(text: &str, name: &str) -> Option<(usize, usize)> {
+    for (number, line) in text.lines().enumerate() {
+        let code = line.split('#').next().unwrap();
+        let words: Vec<&str> = code.split_whitespace().collect();
+        for pair in words.windows(2) {
+            if DEFINITIONS.contains(&pair[0]) && pair[1] == name {
+                // Both words are whole words of the line, so the name can be found after the directive
+                let directive = code.find(pair[0]).unwrap_or(0);
+                return code[directive..].find(name).map(|column| (number, directive + column));
+            }
+        }
+    }
+    None
+}
+
+fn hover(text: &str, word: &str, path: &Path) -> Option<String> {
+    if let Some((_, doc)) = DOCS.iter().find(|(keyword, _)| *keyword == word) {
+        return Some(doc.to_string());
+    }
+    let (_, symbols) = assemble_with_symbols(text, Some(path)).ok()?;
+    symbols.labels.get(word).map(|address| format!("`{}`: label at {:#05X}", word, address))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lsp::{definition, hover, path_of, word_at};
+    use std::path::PathBuf;
+
+    /// Words are found under the cursor and names where they are defined
+    #[test]
+    fn test_navigation() {
+        let text = ": main\n\tloop\n\t\tdraw-ball  # comment\n\tagain\n:  draw-ball\n\tsprite v0 v1 4";
+        assert_eq!(word_at(text, 2, 4), Some("draw-ball"));
+        assert_eq!(word_at(text, 2, 2), Some("draw-ball"));
+        assert_eq!(word_at(text, 2, 15), None);
+        assert_eq!(definition(text, "draw-ball"), Some((4, 3)));
+        assert_eq!(definition(text, "nothing"), None);
+
+        let path = PathBuf::from("game.8o");
+        assert!(hover(text, "sprite", &path).unwrap().contains("DXYN"));
+        assert_eq!(hover(text, "draw-ball", &path).unwrap(), "`draw-ball`: label at 0x204");
+        assert_eq!(path_of("file:///home/me/my%20game.8o"), PathBuf::from("/home/me/my game.8o"));
+    }
+}
@@ -0,0 +1,219 @@
+//! Input macros: short keypad sequences played back by pressing a single key.
+//!
+//! Pressing the record macro hotkey starts recording the keypad input frame by frame, pressing it
+//! again stops, and the next key pressed (other than a hotkey or a keypad key) is bound to the
+//! macro and saved in the config file. Pressing that key later plays the sequence back one frame
+//! at a time, on top of whatever is held at the same time, so timing is exactly as recorded.
+//! Idle frames before the first and after the last key press are left out.
+//!
+//! Each step holds the listed keypad keys (hex digits, empty for none) for a number of frames:
+//!
+//! ```toml
+//! [[macros]]
+//! key = "F11"
+//! steps = [{ keys = "5", frames = 3 }, { keys = "", frames = 40 }, { keys = "5", frames = 3 }]
+//! ```
+
+use crate::config::Config;
+use crate::hotkeys::Hotkeys;
+use crate::keymap::{key_from_name, key_name, Keymap};
+use minifb::Key;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tracing::{error, info};
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct MacroConfig {
+    pub key: String,
+    pub steps: Vec<MacroStep>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct MacroStep {
+    pub keys: String,
+    pub frames: u32,
+}
+
+struct Macro {
+    key: Key,
+    // Keypad state for every frame
+    frames: Vec<[bool; 16]>,
+}
+
+pub struct Macros {
+    macros: Vec<Macro>,
+    // Macro being played and the next frame of it
+    playing: Option<(usize, usize)>,
+    recording: Option<Vec<[bool; 16]>>,
+    // Recorded sequence waiting for a key to be bound to
+    unbound: Option<Vec<[bool; 16]>>,
+}
+
+impl Macros {
+    /// Loads the macros from the config, failing if a macro key is also a hotkey or a keypad key
+    pub fn new(configs: &[MacroConfig], hotkeys: &Hotkeys, keymaps: &[&Keymap]) -> Result<Self, String> {
+        let macros = configs
+            .iter()
+            .map(|config| {
+                let key = key_from_name(&config.key)
+                    .ok_or_else(|| format!("Unknown macro key: {}", config.key))?;
+                if is_taken(key, hotkeys, keymaps) {
+                    return Err(format!("Macro key {} is already a hotkey or a keypad key", config.key));
+                }
+                Ok(Macro { key, frames: from_steps(&config.steps)? })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Macros { macros, playing: None, recording: None, unbound: None })
+    }
+
+    /// Starts recording, or stops and waits for a key to bind the recording to
+    pub fn toggle_recording(&mut self) {
+        match self.recording.take() {
+            Some(frames) => {
+                let first = frames.iter().position(|keys| keys.contains(&true));
+                let last = frames.iter().rposition(|keys| keys.contains(&true));
+                match (first, last) {
+                    (Some(first), Some(last)) => {
+                        self.unbound = Some(frames[first..=last].to_vec());
+                        info!("Macro recorded, press a key to bind it to");
+                    }
+                    _ => info!("Macro recording stopped, no keys were pressed"),
+                }
+            }
+            None => {
+                self.unbound = None;
+                self.recording = Some(Vec::new());
+                info!("Recording macro");
+            }
+        }
+    }
+
+    /// Handles the keys pressed since the last frame: binds a new recording to the first key that
+    /// isn't already in use and saves it to the config file, or starts playing a macro
+    pub fn handle_keys(
+        &mut self,
+        pressed: &[Key],
+        hotkeys: &Hotkeys,
+        keymaps: &[&Keymap],
+        config: &mut Config,
+        config_path: &Path,
+    ) {
+        for key in pressed {
+            if is_taken(*key, hotkeys, keymaps) {
+                continue;
+            }
+
+            if let Some(frames) = self.unbound.take() {
+                config.macros.retain(|existing| key_from_name(&existing.key) != Some(*key));
+                config.macros.push(MacroConfig { key: key_name(*key), steps: to_steps(&frames) });
+                if let Err(error) = config.save(config_path) {
+                    error!("{}", error);
+                }
+                self.macros.retain(|existing| existing.key != *key);
+                self.macros.push(Macro { key: *key, frames });
+                info!("Macro bound to {}", key_name(*key));
+                continue;
+            }
+
+            if let Some(index) = self.macros.iter().position(|existing| existing.key == *key) {
+                self.playing = Some((index, 0));
+            }
+        }
+    }
+
+    /// Records the keypad state of this frame, then adds the keys of the macro being played
+    pub fn apply(&mut self, keypad: &mut [bool; 16]) {
+        if let Some(frames) = &mut self.recording {
+            frames.push(*keypad);
+        }
+        if let Some((index, frame)) = self.playing {
+            let frames = &self.macros[index].frames;
+            for (key, pressed) in keypad.iter_mut().zip(frames[frame].iter()) {
+                *key |= *pressed;
+            }
+            self.playing = if frame + 1 < frames.len() { Some((index, frame + 1)) } else { None };
+        }
+    }
+}
+
+fn is_taken(key: Key, hotkeys: &Hotkeys, keymaps: &[&Keymap]) -> bool {
+    hotkeys.is_hotkey(key) || keymaps.iter().any(|keymap| keymap.chip8_key(key).is_some())
+}
+
+fn from_steps(steps: &[MacroStep]) -> Result<Vec<[bool; 16]>, String> {
+    let mut frames = Vec::new();
+    for step in steps {
+        let mut keys = [false; 16];
+        for digit in step.keys.chars() {
+            let chip8_key = digit
+                .to_digit(16)
+                .ok_or_else(|| format!("Invalid CHIP-8 key in macro: {}", digit))?;
+            keys[chip8_key as usize] = true;
+        }
+        frames.extend(std::iter::repeat_n(keys, step.frames as usize));
+    }
+    Ok(frames)
+}
+
+fn to_steps(frames: &[[bool; 16]]) -> Vec<MacroStep> {
+    let mut steps: Vec<MacroStep> = Vec::new();
+    for keys in frames {
+        let keys: String = keys
+            .iter()
+            .enumerate()
+            .filter(|(_, pressed)| **pressed)
+            .map(|(key, _)| format!("{:X}", key))
+            .collect();
+        match steps.last_mut() {
+            Some(step) if step.keys == keys => step.frames += 1,
+            _ => steps.push(MacroStep { keys, frames: 1 }),
+        }
+    }
+    steps
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hotkeys::{HotkeyConfig, Hotkeys};
+    use crate::keymap::{Keymap, Preset};
+    use crate::macros::{from_steps, to_steps, MacroConfig, MacroStep, Macros};
+
+    /// Steps survive a round trip through the per-frame representation
+    #[test]
+    fn test_steps() {
+        let steps = vec![
+            MacroStep { keys: String::from("5"), frames: 2 },
+            MacroStep { keys: String::new(), frames: 3 },
+            MacroStep { keys: String::from("5A"), frames: 1 },
+        ];
+        let frames = from_steps(&steps).unwrap();
+        assert_eq!(frames.len(), 6);
+        assert!(frames[5][0x5] && frames[5][0xA]);
+        assert_eq!(to_steps(&frames), steps);
+        assert!(from_steps(&[MacroStep { keys: String::from("G"), frames: 1 }]).is_err());
+    }
+
+    /// A macro adds one frame of input per call, then stops
+    #[test]
+    fn test_playback() {
+        let config = MacroConfig {
+            key: String::from("F11"),
+            steps: vec![MacroStep { keys: String::from("1"), frames: 1 }, MacroStep { keys: String::from("2"), frames: 1 }],
+        };
+        let keymap = Keymap::logical(Preset::Sequential);
+        let hotkeys = Hotkeys::new(&HotkeyConfig::default(), &[&keymap], &[]).unwrap();
+        let mut macros = Macros::new(&[config], &hotkeys, &[&keymap]).unwrap();
+        macros.playing = Some((0, 0));
+
+        let mut keypad = [false; 16];
+        keypad[0xF] = true;
+        macros.apply(&mut keypad);
+        assert!(keypad[0x1] && keypad[0xF]);
+        let mut keypad = [false; 16];
+        macros.apply(&mut keypad);
+        assert!(keypad[0x2] && !keypad[0x1]);
+        let mut keypad = [false; 16];
+        macros.apply(&mut keypad);
+        assert_eq!(keypad, [false; 16]);
+    }
+}
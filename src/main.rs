@@ -1,63 +1,332 @@
-mod chip8;
+mod assembler;
+mod audio;
+mod batch;
+mod bench;
+mod callgraph;
+mod cheats;
+mod cli;
+mod compare;
+mod config;
+mod console;
+mod coverage;
+mod crash;
+mod debug_view;
+mod debugger;
+mod determinism;
+mod dev;
+mod diagnostics;
+mod disasm;
+mod draws;
+mod error_screen;
+mod expression;
+#[cfg(feature = "gamepad")]
+mod gamepad;
+mod gdb;
+mod genrom;
+mod graphs;
+mod headless;
+mod heatmap;
+#[cfg(feature = "hid")]
+mod hid;
+mod history;
+mod hotkeys;
+mod hotspots;
+mod info;
+#[cfg(feature = "global-input")]
+mod input;
+mod input_display;
+mod keymap;
+mod keytest;
+mod loops;
+mod lsp;
+mod keypad_grid;
+mod macros;
+#[cfg(feature = "midi")]
+mod midi;
+mod notes;
+mod octocart;
+mod optimize;
+mod perf_hud;
+#[cfg(feature = "plugins")]
+mod plugin;
+mod profile;
+mod remap;
+mod remote;
+mod replay;
+mod run;
+mod savetree;
+mod screenshot;
+mod script;
+mod search;
+mod serial;
+mod snapshot;
+mod sprite_editor;
+mod sprites;
+mod symbols;
+mod taint;
+mod tas;
+mod telemetry;
+mod test_suite;
+mod text;
+mod threaded;
+mod touchpad;
+mod trace;
+mod turbo;
+mod watches;
 
-use chip8::Chip8;
-use std::fs;
-use device_query::{DeviceState, DeviceQuery};
-use minifb::{Window, WindowOptions, Key, Scale, ScaleMode};
+use chip_8_emu::chip8;
+use clap::Parser;
+use crash::CrashDump;
+use cli::{Cli, Command};
+use replay::Replay;
+use run::Session;
+use snapshot::Snapshot;
+use std::fmt;
+use std::io::{self, Write};
+use std::process;
+use symbols::Symbols;
+use tracing::{error, info, Event, Level, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::EnvFilter;
 
-const WIDTH: usize = 64;
-const HEIGHT: usize = 32;
+/// Frames `verify` runs without a replay
+const DEFAULT_VERIFY_FRAMES: u64 = 10_000;
 
 fn main() {
-    // Set up window
-    let mut buffer: Vec<u32> = vec![0; WIDTH * HEIGHT];
-    let mut window = Window::new(
-        "Chip8 Emulator",
-        WIDTH,
-        HEIGHT,
-        WindowOptions {
-            borderless: false,
-            transparency: false,
-            title: true,
-            resize: false,
-            scale: Scale::X16,
-            scale_mode: ScaleMode::Stretch,
-            topmost: false,
+    let cli = Cli::parse();
+    init_logging(&cli.log_level);
+    match cli.command.unwrap_or(Command::Run(cli.run)) {
+        Command::Run(args) if args.headless => headless::run(&args),
+        Command::Run(args) if args.threaded => threaded::run(&args),
+        Command::Run(args) => run::run(&args, Session::Live),
+        Command::Record { run, output } => run::run(&run, Session::Record(&output)),
+        Command::Play { run, replay } => match Replay::load(&replay) {
+            Ok(replay) => run::run(&run, Session::Play(replay)),
+            Err(error) => error!("{}", error),
         },
-    )
-        .unwrap_or_else(|e| {
-            panic!("{}", e);
-        });
-
-    // Set up keyboard
-    let device_state = DeviceState::new();
-
-    // Set up render system and register input callbacks
-    let mut chip8 = Chip8::new();
-
-    // Initialize the Chip8 system and load the game into memory
-    let program = load_program();
-    chip8.load_program(&program);
+        Command::Crash { run, dump, replay, frames } => {
+            match CrashDump::load(&dump).and_then(|dump| dump.replay(replay.as_deref()).map(|replay| (dump, replay))) {
+                Ok((dump, replay)) => run::run(&run, Session::Crash(Box::new(dump), replay, frames)),
+                Err(error) => error!("{}", error),
+            }
+        }
+        Command::Tas { run, replay, output } => match replay.map(|path| Replay::load(&path)).transpose() {
+            Ok(replay) => tas::run(&run, replay, &output),
+            Err(error) => error!("{}", error),
+        },
+        Command::Verify { rom, replay, seed, variant, frames } => {
+            let program = run::load_program(&rom).unwrap_or_else(fatal).bytes;
+            let replay = replay.map(|path| Replay::load(&path)).transpose().unwrap_or_else(fatal);
+            if replay.as_ref().is_some_and(|replay| replay.rom_hash != replay::rom_hash(&program)) {
+                fatal::<(), _>(format!("Replay was recorded with a different ROM than {}", rom.display()));
+            }
+            let seed = replay.as_ref().map_or_else(|| seed.unwrap_or_else(rand::random), |replay| replay.seed);
+            let variant = replay.as_ref().map_or(variant, |replay| replay.variant);
+            let frames = frames.or_else(|| replay.as_ref().map(Replay::frame_count)).unwrap_or(DEFAULT_VERIFY_FRAMES);
+            info!("Running {} frames twice with seed {}", frames, seed);
+            let outcome = determinism::verify(&program, seed, variant, replay.as_ref(), frames);
+            println!("{}", outcome.describe());
+            if !outcome.is_deterministic() {
+                process::exit(1);
+            }
+        }
+        Command::Dev { run, keep } => run::run(&run, Session::Dev(&keep)),
+        Command::Keytest(args) => keytest::run(&args),
+        Command::Disasm { rom, variant, octo, symbols } => match std::fs::read(&rom) {
+            Ok(program) => {
+                let mut lines = disasm::disassemble(&program, variant);
+                let mut symbols = match symbols.map(|path| Symbols::load(&path)) {
+                    Some(Ok(symbols)) => Some(symbols),
+                    Some(Err(error)) => {
+                        error!("{}", error);
+                        None
+                    }
+                    None => None,
+                };
+                let notes_path = rom.with_extension("notes");
+                if notes_path.exists() {
+                    match notes::load(&notes_path) {
+                        Ok(notes) => notes.into_iter().for_each(|note| symbols.get_or_insert_with(Symbols::default).add_note(note)),
+                        Err(error) => error!("{}", error),
+                    }
+                }
+                if let Some(symbols) = &symbols {
+                    disasm::apply_symbols(&mut lines, symbols);
+                }
+                if octo {
+                    print!("{}", disasm::to_octo(&lines));
+                } else {
+                    for line in lines {
+                        println!("{}", line);
+                    }
+                }
+            }
+            Err(error) => error!("Could not read {}\n{}", rom.display(), error),
+        },
+        Command::Info { rom, variant } => match std::fs::read(&rom) {
+            Ok(program) => println!("{}", info::analyze(&program, variant)),
+            Err(error) => error!("Could not read {}\n{}", rom.display(), error),
+        },
+        Command::Diff { before, after } => match Snapshot::load(&before).and_then(|before| Snapshot::load(&after).map(|after| (before, after))) {
+            Ok((before, after)) => println!("{}", snapshot::diff(&before, &after).join("\n")),
+            Err(error) => error!("{}", error),
+        },
+        Command::Asm { source, output, symbols } => match std::fs::read_to_string(&source) {
+            Ok(text) => match assembler::assemble_with_symbols(&text, Some(&source)) {
+                Ok((program, symbol_table)) => {
+                    let output = output.unwrap_or_else(|| source.with_extension("ch8"));
+                    if let Err(error) = std::fs::write(&output, program) {
+                        error!("Could not write {}\n{}", output.display(), error);
+                    }
+                    if let Some(path) = symbols {
+                        if let Err(error) = symbol_table.save(&path) {
+                            error!("{}", error);
+                        }
+                    }
+                }
+                Err(error) => error!("{}", error),
+            },
+            Err(error) => error!("Could not read {}\n{}", source.display(), error),
+        },
+        Command::Optimize { rom, variant, output } => match std::fs::read(&rom) {
+            Ok(program) => {
+                let (optimized, report) = optimize::optimize(&program, variant);
+                let output = output.unwrap_or_else(|| rom.with_extension("opt.ch8"));
+                match std::fs::write(&output, optimized) {
+                    Ok(()) => println!("{}", report),
+                    Err(error) => error!("Could not write {}\n{}", output.display(), error),
+                }
+            }
+            Err(error) => error!("Could not read {}\n{}", rom.display(), error),
+        },
+        Command::Sprites { image, width, height, invert, format, output } => {
+            match sprites::convert(&image, width, height, invert, format) {
+                Ok(data) => {
+                    let written = match &output {
+                        Some(path) => std::fs::write(path, data),
+                        None => std::io::stdout().write_all(&data),
+                    };
+                    if let Err(error) = written {
+                        error!("Could not write sprites\n{}", error);
+                    }
+                }
+                Err(error) => error!("{}", error),
+            }
+        }
+        Command::SpriteEditor { image, width, height, output } => sprite_editor::run(image.as_deref(), width, height, &output),
+        Command::Lsp => lsp::run(),
+        Command::TestSuite { dir, profiles, expected, save, show, frames } => {
+            let profiles = if profiles.is_empty() { test_suite::PROFILES.to_vec() } else { profiles };
+            let expected_path = test_suite::expected_path(&dir, expected);
+            let mut expected = test_suite::load_expected(&expected_path).unwrap_or_else(fatal);
+            let mut failed = false;
+            for profile in profiles {
+                let results = test_suite::run(&dir, profile, frames).unwrap_or_else(fatal);
+                println!("{}", test_suite::report(profile, &results, &expected, show));
+                failed |= results.iter().any(|result| test_suite::passed(result, profile, &expected) == Some(false));
+                if save {
+                    let hashes = expected.entry(String::from(profile.name())).or_default();
+                    hashes.extend(results.into_iter().map(|result| (result.rom, result.hash)));
+                }
+            }
+            if save {
+                test_suite::save_expected(&expected, &expected_path).unwrap_or_else(fatal);
+                info!("Saved the screens to {}", expected_path.display());
+            } else if failed {
+                process::exit(1);
+            }
+        }
+        Command::Compare { rom, reference, every, instructions, ignore_timers, seed, variant, .. } => {
+            let program = std::fs::read(&rom).map_err(|e| format!("Could not read {}\n{}", rom.display(), e)).unwrap_or_else(fatal);
+            match reference {
+                Some(reference) => {
+                    let setup = compare::Setup { rom: &rom, program: &program, reference: &reference, variant, seed, ignore_timers };
+                    match compare::compare(&setup, every, instructions).unwrap_or_else(fatal) {
+                        Some(divergence) => {
+                            println!("{}", divergence.describe(variant));
+                            process::exit(1);
+                        }
+                        None => println!("States match after {} instructions", instructions),
+                    }
+                }
+                None => compare::serve(run::power_on(chip8::Chip8::builder().variant(variant).seed(seed), &program)),
+            }
+        }
+        Command::Batch { dir, frames, report, seed, variant, #[cfg(feature = "jit")] jit } => {
+            #[cfg(not(feature = "jit"))]
+            let jit = false;
+            let report_path = batch::report_path(&dir, report);
+            let before = batch::load_report(&report_path).unwrap_or_else(fatal);
+            let report = batch::run(&dir, variant, seed, frames, jit).unwrap_or_else(fatal);
+            println!("{}", batch::summary(&report));
+            if !before.rom.is_empty() {
+                let changes = batch::changes(&before, &report);
+                println!("{} ROMs end differently from {}", changes.len(), report_path.display());
+                for change in changes {
+                    println!("  {}", change);
+                }
+            }
+            batch::save_report(&report, &report_path).unwrap_or_else(fatal);
+            if report.rom.iter().any(|rom| rom.status == batch::Status::Panic) {
+                process::exit(1);
+            }
+        }
+        Command::Bench { run, seconds } => bench::run(&run, std::time::Duration::from_secs_f64(seconds)),
+        Command::Genrom { output, count, seed, length, subroutines, subroutine_length, variant } => {
+            let shape = genrom::Shape { length: length as usize, subroutines: subroutines as usize, subroutine_length: subroutine_length as usize, variant };
+            let first = seed.unwrap_or_else(rand::random);
+            for seed in (0..count).map(|i| first.wrapping_add(i)) {
+                let rom = genrom::generate(seed, &shape).unwrap_or_else(fatal);
+                let path = output.join(format!("random-{}.ch8", seed));
+                std::fs::write(&path, rom).map_err(|e| format!("Could not write {}\n{}", path.display(), e)).unwrap_or_else(fatal);
+            }
+            info!("Wrote {} ROMs to {}", count, output.display());
+        }
+    }
+}
 
-    // Emulation loop
-    while window.is_open() && !window.is_key_down(Key::Escape) {
-        // Emulate one cycle
-        chip8.emulate_cycle();
+/// Sends log messages to standard error, tagged with their level and module below the info level
+fn init_logging(filters: &str) {
+    // Cranelift logs every block the JIT compiles at the info level
+    #[cfg(feature = "jit")]
+    let filters = format!("cranelift=warn,{}", filters);
+    tracing_subscriber::fmt().with_env_filter(EnvFilter::new(filters)).event_format(LogFormat).with_writer(io::stderr).init();
+}
 
-        // Store key press state (Press and Release)
-        chip8.set_keys(device_state.get_keys());
+/// Messages as they are up to info, and with their level, target and spans, like the frame they
+/// were logged in, below it
+struct LogFormat;
 
-        // Draw screen if necessary
-        if chip8.draw_to_buffer(&mut buffer) {
-            window.update_with_buffer(&buffer, WIDTH, HEIGHT).unwrap();
+impl<S, N> FormatEvent<S, N> for LogFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(&self, context: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        let metadata = event.metadata();
+        telemetry::count_log(*metadata.level());
+        match *metadata.level() {
+            Level::ERROR | Level::WARN | Level::INFO => {}
+            level => {
+                write!(writer, "[{} {}", level, metadata.target())?;
+                for span in context.event_scope().into_iter().flat_map(|scope| scope.from_root()) {
+                    match span.extensions().get::<FormattedFields<N>>().filter(|fields| !fields.is_empty()) {
+                        Some(fields) => write!(writer, " {}{{{}}}", span.name(), fields)?,
+                        None => write!(writer, " {}", span.name())?,
+                    }
+                }
+                write!(writer, "] ")?;
+            }
         }
-    };
+        context.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
 }
 
-fn load_program() -> Vec<u8> {
-    let program = fs::read("roms/pong.rom");
-    match program {
-        Ok(program_loaded) => program_loaded,
-        Err(error) => panic!("Could not load program!\n{}", error)
-    }
+/// Logs an error the emulator can't carry on from, and exits
+pub(crate) fn fatal<T, E: fmt::Display>(error: E) -> T {
+    error!("{}", error);
+    process::exit(1)
 }
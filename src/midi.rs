@@ -0,0 +1,157 @@
+//! MIDI controller input.
+//!
+//! Note-on/off messages on any channel of a MIDI input port are mapped onto the 16 keypad keys:
+//! `base_note` drives key 0x0, `base_note + 1` key 0x1 and so on up to key 0xF. Both are set in the
+//! config file:
+//!
+//! ```toml
+//! [midi]
+//! base_note = 36       # most pad controllers start their first bank at C1, the default
+//! port = "Launchpad"   # first port whose name contains this, the first port at all if unset
+//! ```
+
+use midir::{Ignore, MidiInput, MidiInputConnection};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+pub const DEFAULT_BASE_NOTE: u8 = 36;
+
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub struct MidiConfig {
+    /// Note that drives key 0x0
+    pub base_note: u8,
+    /// Part of the name of the port to use
+    pub port: Option<String>,
+}
+
+impl Default for MidiConfig {
+    fn default() -> Self {
+        MidiConfig { base_note: DEFAULT_BASE_NOTE, port: None }
+    }
+}
+
+const NOTE_OFF: u8 = 0x80;
+const NOTE_ON: u8 = 0x90;
+
+pub struct MidiKeypad {
+    keys: Arc<Mutex<[bool; 16]>>,
+    // Kept alive for as long as the keypad exists, the port closes when dropped
+    _connection: MidiInputConnection<()>,
+}
+
+impl MidiKeypad {
+    /// Connects to the configured MIDI input port, returning `None` if there is no such controller
+    /// attached
+    pub fn connect(config: &MidiConfig) -> Option<Self> {
+        let mut input = match MidiInput::new("chip8-emulator") {
+            Ok(input) => input,
+            Err(error) => {
+                warn!("Could not initialize MIDI input\n{}", error);
+                return None;
+            }
+        };
+        input.ignore(Ignore::All);
+
+        let port = input.ports().into_iter().find(|port| match &config.port {
+            Some(wanted) => input.port_name(port).is_ok_and(|name| name.contains(wanted.as_str())),
+            None => true,
+        });
+        let port = match (port, &config.port) {
+            (Some(port), _) => port,
+            (None, Some(wanted)) => {
+                warn!("No MIDI input port named like \"{}\"", wanted);
+                return None;
+            }
+            (None, None) => return None,
+        };
+        if let Ok(name) = input.port_name(&port) {
+            info!("Using MIDI controller: {}", name);
+        }
+
+        let base_note = config.base_note;
+        let keys = Arc::new(Mutex::new([false; 16]));
+        let callback_keys = Arc::clone(&keys);
+        let connection = input
+            .connect(&port, "chip8-keypad", move |_, message, _| {
+                if let Some((key, pressed)) = map_message(message, base_note) {
+                    callback_keys.lock().unwrap()[key] = pressed;
+                }
+            }, ())
+            .map_err(|error| warn!("Could not connect to MIDI controller\n{}", error))
+            .ok()?;
+
+        Some(MidiKeypad { keys, _connection: connection })
+    }
+
+    /// Keypad keys currently held down on the controller
+    pub fn pressed_keys(&self) -> [bool; 16] {
+        *self.keys.lock().unwrap()
+    }
+}
+
+/// Translates a raw MIDI message into a (keypad key, pressed) pair.
+/// Note-on with zero velocity is treated as note-off, as sent by many controllers.
+fn map_message(message: &[u8], base_note: u8) -> Option<(usize, bool)> {
+    if message.len() < 3 {
+        return None;
+    }
+    let status = message[0] & 0xF0;
+    let note = message[1].checked_sub(base_note)? as usize;
+    if note >= 16 {
+        return None;
+    }
+    match status {
+        NOTE_ON => Some((note, message[2] > 0)),
+        NOTE_OFF => Some((note, false)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::midi::{map_message, MidiConfig, DEFAULT_BASE_NOTE};
+
+    /// A `[midi]` table without a base note keeps the default one
+    #[test]
+    fn test_config() {
+        let config: MidiConfig = toml::from_str("port = \"Pads\"").unwrap();
+        assert_eq!(config.base_note, DEFAULT_BASE_NOTE);
+        assert_eq!(config.port.as_deref(), Some("Pads"));
+        assert_eq!(toml::from_str::<MidiConfig>("base_note = 48").unwrap().base_note, 48);
+    }
+
+    /// Notes from the base note up press and release keys 0x0 to 0xF
+    #[test]
+    fn test_note_on_off() {
+        assert_eq!(map_message(&[0x90, DEFAULT_BASE_NOTE, 100], DEFAULT_BASE_NOTE), Some((0x0, true)));
+        assert_eq!(map_message(&[0x90, DEFAULT_BASE_NOTE + 15, 1], DEFAULT_BASE_NOTE), Some((0xF, true)));
+        assert_eq!(map_message(&[0x80, DEFAULT_BASE_NOTE + 3, 64], DEFAULT_BASE_NOTE), Some((0x3, false)));
+    }
+
+    /// Note-on with zero velocity is a release
+    #[test]
+    fn test_note_on_zero_velocity() {
+        assert_eq!(map_message(&[0x90, DEFAULT_BASE_NOTE + 5, 0], DEFAULT_BASE_NOTE), Some((0x5, false)));
+    }
+
+    /// Notes below the base note or past the 16th are ignored
+    #[test]
+    fn test_out_of_range_notes() {
+        assert_eq!(map_message(&[0x90, DEFAULT_BASE_NOTE - 1, 100], DEFAULT_BASE_NOTE), None);
+        assert_eq!(map_message(&[0x90, DEFAULT_BASE_NOTE + 16, 100], DEFAULT_BASE_NOTE), None);
+        assert_eq!(map_message(&[0x90, 0, 100], 1), None);
+    }
+
+    /// Notes count on every channel, other messages and short ones don't
+    #[test]
+    fn test_channels_and_other_messages() {
+        assert_eq!(map_message(&[0x99, DEFAULT_BASE_NOTE + 2, 100], DEFAULT_BASE_NOTE), Some((0x2, true)));
+        assert_eq!(map_message(&[0x8F, DEFAULT_BASE_NOTE + 2, 0], DEFAULT_BASE_NOTE), Some((0x2, false)));
+        // Control change and polyphonic aftertouch
+        assert_eq!(map_message(&[0xB0, DEFAULT_BASE_NOTE, 127], DEFAULT_BASE_NOTE), None);
+        assert_eq!(map_message(&[0xA0, DEFAULT_BASE_NOTE, 127], DEFAULT_BASE_NOTE), None);
+        assert_eq!(map_message(&[0x90, DEFAULT_BASE_NOTE], DEFAULT_BASE_NOTE), None);
+    }
+}
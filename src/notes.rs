@@ -0,0 +1,128 @@
+//! Names and comments for addresses and ranges of memory, kept in a file next to the ROM with the
+//! `.notes` extension, or given with `--notes`:
+//!
+//! ```toml
+//! [[note]]
+//! address = "0x300"
+//! name = "player_y"
+//!
+//! [[note]]
+//! address = "0x310-0x312"
+//! name = "score"
+//! comment = "score BCD buffer"
+//! ```
+//!
+//! Names become labels of the first address, so they work wherever labels do. The hex panel of
+//! the debugger window tints the memory with notes, the disassembly shows the comments, and the
+//! trace names the memory I points into. The `note` and `unnote` debugger commands change the
+//! notes and save them back to the file.
+
+use crate::debugger;
+use crate::dev::MemoryRange;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Note {
+    pub start: u16,
+    /// Last address of the range, the same as the start for one address
+    pub end: u16,
+    pub name: String,
+    /// Empty when there's no comment
+    pub comment: String,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct NoteFile {
+    #[serde(default, rename = "note")]
+    notes: Vec<NoteConfig>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+struct NoteConfig {
+    address: String,
+    name: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    comment: String,
+}
+
+impl Note {
+    pub fn new(range: MemoryRange, name: &str, comment: &str) -> Result<Self, String> {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(format!("Note names are one word, not \"{}\"", name));
+        }
+        Ok(Note { start: range.start, end: range.end, name: name.to_string(), comment: comment.to_string() })
+    }
+
+    pub fn contains(&self, address: u16) -> bool {
+        (self.start..=self.end).contains(&address)
+    }
+
+    /// The address or range, like `0x310-0x312`
+    pub fn range(&self) -> String {
+        if self.start == self.end {
+            format!("{:#05X}", self.start)
+        } else {
+            format!("{:#05X}-{:#05X}", self.start, self.end)
+        }
+    }
+}
+
+pub fn load(path: &Path) -> Result<Vec<Note>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    parse(&contents).map_err(|e| format!("Invalid notes file {}\n{}", path.display(), e))
+}
+
+fn parse(contents: &str) -> Result<Vec<Note>, String> {
+    let file: NoteFile = toml::from_str(contents).map_err(|e| e.to_string())?;
+    file.notes.iter().map(|note| Note::new(parse_range(&note.address)?, &note.name, &note.comment)).collect()
+}
+
+/// An address or a range like `0x310-0x312`
+fn parse_range(text: &str) -> Result<MemoryRange, String> {
+    match debugger::parse_address(text) {
+        Some(address) => Ok(MemoryRange { start: address, end: address }),
+        None => text.parse(),
+    }
+}
+
+pub fn save(path: &Path, notes: &[Note]) -> Result<(), String> {
+    let file = NoteFile {
+        notes: notes.iter().map(|note| NoteConfig { address: note.range(), name: note.name.clone(), comment: note.comment.clone() }).collect(),
+    };
+    let contents = toml::to_string_pretty(&file).map_err(|e| e.to_string())?;
+    fs::write(path, contents).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dev::MemoryRange;
+    use crate::notes::{parse, Note};
+    use crate::symbols::Symbols;
+
+    /// Notes parse from the file, name their memory and survive being written back
+    #[test]
+    fn test_notes() {
+        let notes = parse("[[note]]\naddress = \"0x300\"\nname = \"player_y\"\n\n[[note]]\naddress = \"0x310-0x312\"\nname = \"score\"\ncomment = \"score BCD buffer\"\n").unwrap();
+        assert_eq!(notes[1], Note { start: 0x310, end: 0x312, name: String::from("score"), comment: String::from("score BCD buffer") });
+        assert!(Note::new(MemoryRange { start: 0x300, end: 0x300 }, "player y", "").is_err());
+        assert!(parse("[[note]]\naddress = \"0x312-0x310\"\nname = \"score\"\n").is_err());
+
+        let mut symbols = Symbols::default();
+        for note in notes {
+            symbols.add_note(note);
+        }
+        assert_eq!(symbols.labels.get("player_y"), Some(&0x300));
+        assert_eq!(symbols.name_of(0x311).as_deref(), Some("score+1"));
+        assert_eq!(symbols.name_of(0x313), None);
+        assert!(symbols.remove_note(0x310));
+        assert_eq!(symbols.labels.get("score"), None);
+
+        let path = std::env::temp_dir().join(format!("chip8-notes-{}.notes", std::process::id()));
+        crate::notes::save(&path, &symbols.notes).unwrap();
+        assert_eq!(crate::notes::load(&path).unwrap(), symbols.notes);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,166 @@
+//! Octocarts: GIF images carrying an Octo program along with the settings to run it with.
+//!
+//! The cartridge data hides in the low two bits of the color index of each pixel of the first
+//! frame, four pixels to a byte with the most significant bits first. It starts with its length
+//! as a 32-bit big endian number, followed by JSON holding the program source and Octo's options:
+//!
+//! ```json
+//! {"program": ": main ...", "options": {"tickrate": 20, "fillColor": "#FFCC00", ...}}
+//! ```
+//!
+//! The colors, quirks and tick rate are applied when the cartridge is run. The tick rate is
+//! instructions per 60 Hz frame: the program runs that many times 60 instructions a second, with
+//! the timers counting down once every that many instructions. Quirks the cartridge leaves out
+//! are off, as they are in Octo.
+
+use crate::chip8::Quirks;
+use serde_json::{Map, Value};
+use std::fs::File;
+use std::path::Path;
+
+pub struct Cartridge {
+    /// Octo source
+    pub program: String,
+    pub options: Options,
+}
+
+/// Settings a program wants to run with, all left as they are when not given
+#[derive(Debug, Default, PartialEq)]
+pub struct Options {
+    /// Instructions per 60 Hz frame
+    pub tickrate: Option<u32>,
+    /// Color of pixels that are on
+    pub foreground: Option<u32>,
+    /// Color of pixels that are off
+    pub background: Option<u32>,
+    /// Name of the font, like `vip`
+    pub font: Option<String>,
+    /// Quirks for a cartridge, with the ones it leaves out off
+    pub quirks: Option<Quirks>,
+}
+
+impl Options {
+    /// Instructions per frame for runs without a window, where a frame is 60 Hz with a tick rate
+    /// and a single instruction without one
+    pub fn speed(&self) -> u32 {
+        self.tickrate.unwrap_or(1)
+    }
+}
+
+pub fn load(path: &Path) -> Result<Cartridge, String> {
+    let file = File::open(path).map_err(|e| format!("Could not read {}\n{}", path.display(), e))?;
+    read(file).map_err(|e| format!("{} is not an Octocart: {}", path.display(), e))
+}
+
+fn read(reader: impl std::io::Read) -> Result<Cartridge, String> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(reader).map_err(|e| e.to_string())?;
+    let frame = decoder.read_next_frame().map_err(|e| e.to_string())?.ok_or("the image has no frames")?;
+    let payload = payload(&frame.buffer)?;
+
+    let json: Value = serde_json::from_slice(&payload).map_err(|e| e.to_string())?;
+    let program = json
+        .get("program")
+        .and_then(Value::as_str)
+        .ok_or("the cartridge has no program")?
+        .to_string();
+    let empty = Map::new();
+    let options = json.get("options").and_then(Value::as_object).unwrap_or(&empty);
+    Ok(Cartridge { program, options: parse_options(options) })
+}
+
+/// Bytes hidden in the color indices of the pixels, without the length in front of them
+fn payload(pixels: &[u8]) -> Result<Vec<u8>, String> {
+    let mut bytes = pixels
+        .chunks_exact(4)
+        .map(|chunk| chunk.iter().fold(0, |byte, pixel| (byte << 2) | (pixel & 3)));
+    let mut length = 0usize;
+    for _ in 0..4 {
+        length = (length << 8) | bytes.next().ok_or("the image is too small")? as usize;
+    }
+    let payload: Vec<u8> = bytes.take(length).collect();
+    if payload.len() < length {
+        return Err(format!("the image holds {} of the {} bytes of data", payload.len(), length));
+    }
+    Ok(payload)
+}
+
+fn parse_options(options: &Map<String, Value>) -> Options {
+    // Octo has saved numbers both as numbers and as strings
+    let tickrate = options.get("tickrate").and_then(|value| match value {
+        Value::Number(number) => number.as_u64().map(|tickrate| tickrate as u32),
+        Value::String(text) => text.parse().ok(),
+        _ => None,
+    });
+    let color = |name: &str| options.get(name).and_then(Value::as_str).and_then(parse_color);
+    let mut quirks = Quirks::OCTO;
+    let names = [
+        ("shiftQuirks", &mut quirks.shift),
+        ("loadStoreQuirks", &mut quirks.load_store),
+        ("vfOrderQuirks", &mut quirks.vf_order),
+        ("clipQuirks", &mut quirks.clip),
+        ("vBlankQuirks", &mut quirks.vblank),
+        ("jumpQuirks", &mut quirks.jump),
+        ("logicQuirks", &mut quirks.logic),
+    ];
+    for (name, quirk) in names {
+        *quirk = options.get(name).is_some_and(|value| value == &Value::Bool(true) || value == "true");
+    }
+    let font = options.get("fontStyle").and_then(Value::as_str).map(str::to_string);
+    Options { tickrate, foreground: color("fillColor"), background: color("backgroundColor"), font, quirks: Some(quirks) }
+}
+
+/// Color written as `#RRGGBB`
+fn parse_color(text: &str) -> Option<u32> {
+    let hex = text.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Quirks;
+    use crate::octocart::{read, Options};
+    use std::borrow::Cow;
+
+    /// A cartridge written the way Octo writes them reads back with its program and options
+    #[test]
+    fn test_read() {
+        let json = r##"{"program": ": main\njump main", "options": {"tickrate": "15", "fillColor": "#FFAA00", "fontStyle": "vip", "clipQuirks": true, "shiftQuirks": false}}"##;
+        let mut data = (json.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(json.as_bytes());
+
+        // Two bits per pixel on top of arbitrary artwork in the higher bits
+        let (width, height) = (64u16, 32u16);
+        let mut pixels: Vec<u8> = (0..width as usize * height as usize).map(|i| (i % 3) as u8 * 4).collect();
+        for (i, byte) in data.iter().enumerate() {
+            for bit in 0..4 {
+                pixels[i * 4 + bit] |= (byte >> (6 - bit * 2)) & 3;
+            }
+        }
+
+        let palette: Vec<u8> = (0..16u8).flat_map(|i| [i * 16, i * 16, i * 16]).collect();
+        let mut gif = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut gif, width, height, &palette).unwrap();
+            let frame = gif::Frame { width, height, buffer: Cow::Borrowed(&pixels), ..gif::Frame::default() };
+            encoder.write_frame(&frame).unwrap();
+        }
+
+        let cartridge = read(&gif[..]).unwrap();
+        assert_eq!(cartridge.program, ": main\njump main");
+        assert_eq!(
+            cartridge.options,
+            Options {
+                tickrate: Some(15),
+                foreground: Some(0xFFAA00),
+                background: None,
+                font: Some(String::from("vip")),
+                quirks: Some(Quirks { clip: true, ..Quirks::OCTO })
+            }
+        );
+    }
+}
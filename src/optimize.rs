@@ -0,0 +1,192 @@
+//! Size optimizations for assembled ROMs.
+//!
+//! Two things are removed, and every jump, call and `LD I` into the ROM is moved to follow:
+//!
+//! - Dead code: bytes the disassembler's analysis never reaches and that no `LD I` points into.
+//!   Sprites and other data are reached through I, so they stay. A ROM with `JP V0` jumps to
+//!   places the analysis can't follow, so its unreached bytes are all kept.
+//! - Redundant loads: a register or I loaded and then overwritten by the next instruction
+//!   without being read in between. A load right after a skip is kept, since taking it out would
+//!   change what the skip skips.
+//!
+//! Code is assumed not to be read or written as data, which holds for programs that don't
+//! modify themselves.
+
+use crate::chip8::{AluOp, Instruction, Variant};
+use crate::disasm::{self, LOAD_ADDRESS};
+use std::fmt;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    /// Bytes of unreachable code removed
+    pub dead_code: usize,
+    /// Instructions removed for being overwritten right away
+    pub redundant_loads: usize,
+    /// Whether dead code was left in because the ROM has computed jumps
+    pub computed_jumps: bool,
+    pub original_size: usize,
+    pub optimized_size: usize,
+}
+
+pub fn optimize(rom: &[u8], variant: Variant) -> (Vec<u8>, Report) {
+    let lines = disasm::disassemble(rom, variant);
+    let offset_of = |address: u16| (address - LOAD_ADDRESS) as usize;
+    let mut report = Report { original_size: rom.len(), ..Report::default() };
+    let mut removed = vec![false; rom.len()];
+
+    let instructions: Vec<(u16, Instruction)> =
+        lines.iter().filter_map(|line| line.instruction.map(|instruction| (line.address, instruction))).collect();
+    report.computed_jumps = instructions.iter().any(|(_, instruction)| matches!(instruction, Instruction::JumpOffset(_)));
+
+    if !report.computed_jumps {
+        let data: Vec<u16> = instructions
+            .iter()
+            .filter_map(|(_, instruction)| match instruction {
+                Instruction::LoadIndex(target) => Some(*target),
+                _ => None,
+            })
+            .collect();
+        // Runs of unreached bytes, kept whole when I points anywhere into them
+        let mut start = 0;
+        while start < lines.len() {
+            if lines[start].instruction.is_some() {
+                start += 1;
+                continue;
+            }
+            let end = lines[start..].iter().position(|line| line.instruction.is_some()).map_or(lines.len(), |length| start + length);
+            let (first, last) = (lines[start].address, lines[end - 1].address);
+            if !data.iter().any(|target| (first..=last).contains(target)) {
+                removed[offset_of(first)..=offset_of(last)].iter_mut().for_each(|byte| *byte = true);
+                report.dead_code += (last - first) as usize + 1;
+            }
+            start = end;
+        }
+    }
+
+    for pair in instructions.windows(2) {
+        let ((address, instruction), (next_address, next)) = (pair[0], pair[1]);
+        let after_skip = address >= LOAD_ADDRESS + 2
+            && instructions.iter().any(|(other, instruction)| *other == address - 2 && is_skip(*instruction));
+        if next_address == address + 2 && !after_skip && overwrites(next, instruction) {
+            removed[offset_of(address)] = true;
+            removed[offset_of(address) + 1] = true;
+            report.redundant_loads += 1;
+        }
+    }
+
+    // Addresses move down by the bytes removed before them. A removed address ends up at the
+    // next byte kept, which for a redundant load is the instruction overwriting it.
+    let mut moved = Vec::with_capacity(rom.len() + 1);
+    let mut removed_before = 0;
+    for is_removed in removed.iter().chain(std::iter::once(&false)) {
+        moved.push(removed_before);
+        removed_before += *is_removed as u16;
+    }
+    // Targets up to the end of the ROM move, the end being where Octo programs put buffers
+    let relocate = |target: u16| match target.checked_sub(LOAD_ADDRESS) {
+        Some(offset) if (offset as usize) <= rom.len() => target - moved[offset as usize],
+        _ => target,
+    };
+
+    let mut optimized = Vec::with_capacity(rom.len());
+    for line in &lines {
+        let offset = offset_of(line.address);
+        if removed[offset] {
+            continue;
+        }
+        match line.instruction {
+            Some(instruction) => {
+                let instruction = match instruction {
+                    Instruction::Jump(target) => Instruction::Jump(relocate(target)),
+                    Instruction::Call(target) => Instruction::Call(relocate(target)),
+                    Instruction::LoadIndex(target) => Instruction::LoadIndex(relocate(target)),
+                    other => other,
+                };
+                optimized.extend_from_slice(&instruction.encode().to_be_bytes());
+            }
+            None => optimized.extend_from_slice(&line.bytes),
+        }
+    }
+    report.optimized_size = optimized.len();
+    (optimized, report)
+}
+
+fn is_skip(instruction: Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::SkipIfEqual(..)
+            | Instruction::SkipIfNotEqual(..)
+            | Instruction::SkipIfRegistersEqual(..)
+            | Instruction::SkipIfRegistersNotEqual(..)
+            | Instruction::SkipIfKey(_)
+            | Instruction::SkipIfNotKey(_)
+            | Instruction::SkipIfSecondKey(_)
+            | Instruction::SkipIfNotSecondKey(_)
+    )
+}
+
+/// Whether `next` overwrites everything `load` does, without reading it first. Random numbers
+/// aren't counted as loads, since dropping one would change the numbers that follow.
+fn overwrites(next: Instruction, load: Instruction) -> bool {
+    let written = match load {
+        Instruction::Load(x, _) => x,
+        Instruction::Alu(AluOp::Move, x, y) if x != y => x,
+        Instruction::LoadIndex(_) => return matches!(next, Instruction::LoadIndex(_)),
+        _ => return false,
+    };
+    match next {
+        Instruction::Load(x, _) | Instruction::Random(x, _) | Instruction::GetDelay(x) => x == written,
+        Instruction::Alu(AluOp::Move, x, y) => x == written && y != written,
+        _ => false,
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Removed {} bytes of unreachable code and {} redundant loads", self.dead_code, self.redundant_loads)?;
+        if self.computed_jumps {
+            writeln!(f, "Unreachable code was kept, the ROM jumps to computed addresses")?;
+        }
+        write!(
+            f,
+            "{} -> {} bytes, saved {}",
+            self.original_size,
+            self.optimized_size,
+            self.original_size - self.optimized_size
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Variant;
+    use crate::optimize::optimize;
+
+    /// Dead code and overwritten loads go, targets after them move down, data stays
+    #[test]
+    fn test_optimize() {
+        let rom = [
+            0x60, 0x01, // 0x200: LD V0, 0x01, overwritten
+            0x60, 0x02, // 0x202: LD V0, 0x02
+            0xA2, 0x0C, // 0x204: LD I, 0x20C
+            0x12, 0x0A, // 0x206: JP 0x20A
+            0x00, 0xE0, // 0x208: CLS, never reached
+            0x12, 0x0A, // 0x20A: JP 0x20A
+            0xF0, // 0x20C: sprite
+        ];
+        let (optimized, report) = optimize(&rom, Variant::Chip8);
+        assert_eq!(optimized, vec![0x60, 0x02, 0xA2, 0x08, 0x12, 0x06, 0x12, 0x06, 0xF0]);
+        assert_eq!((report.dead_code, report.redundant_loads), (2, 1));
+        assert_eq!(report.to_string().lines().last(), Some("13 -> 9 bytes, saved 4"));
+
+        // The load after a skip is what the skip skips
+        let guarded = [0x30, 0x01, 0x60, 0x01, 0x60, 0x02, 0x12, 0x06];
+        assert_eq!(optimize(&guarded, Variant::Chip8).0, guarded.to_vec());
+
+        // Computed jumps might land anywhere
+        let computed = [0xB2, 0x04, 0x00, 0xE0, 0x12, 0x04];
+        let (optimized, report) = optimize(&computed, Variant::Chip8);
+        assert_eq!(optimized, computed.to_vec());
+        assert!(report.computed_jumps);
+    }
+}
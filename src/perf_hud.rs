@@ -0,0 +1,104 @@
+//! On-screen graph of where each frame's time goes.
+//!
+//! Drawn over the top of the game, a column per frame for the last 64 frames, newest on the right.
+//! Each column stacks the time spent emulating the frame's instructions (green), rendering it
+//! (blue) and in the audio callbacks meanwhile (orange), against a dotted line at the 60 Hz frame
+//! budget halfway up. Rendering counts everything from converting the screen to the window update
+//! returning, so it includes minifb's wait when updates come faster than its rate limit.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Frames shown, a column each
+const FRAMES: usize = 64;
+/// Rows of the graph, twice the frame budget
+const ROWS: usize = 16;
+const BUDGET: Duration = Duration::from_micros(16_667);
+
+const EMULATION: u32 = 0x0030_C030;
+const RENDER: u32 = 0x0030_60FF;
+const AUDIO: u32 = 0x00FF_9020;
+const BUDGET_LINE: u32 = 0x00C0_C0C0;
+
+/// Time a frame took, by what took it
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameTime {
+    pub emulation: Duration,
+    pub render: Duration,
+    pub audio: Duration,
+}
+
+pub struct PerfHud {
+    visible: bool,
+    /// Oldest first
+    frames: VecDeque<FrameTime>,
+}
+
+impl PerfHud {
+    pub fn new(visible: bool) -> Self {
+        PerfHud { visible, frames: VecDeque::with_capacity(FRAMES) }
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn record(&mut self, time: FrameTime) {
+        if self.frames.len() == FRAMES {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(time);
+    }
+
+    /// Draws the graph over a game frame at least as wide as the frames shown
+    pub fn draw(&self, buffer: &mut [u32], width: usize) {
+        let left = width - FRAMES;
+        for x in (0..FRAMES).step_by(2) {
+            buffer[(ROWS - ROWS / 2) * width + left + x] = BUDGET_LINE;
+        }
+        for (column, time) in self.frames.iter().enumerate() {
+            let x = left + FRAMES - self.frames.len() + column;
+            let parts = [(time.emulation, EMULATION), (time.render, RENDER), (time.audio, AUDIO)];
+            let mut bottom = 0;
+            for (duration, color) in parts {
+                let top = (bottom + rows(duration)).min(ROWS);
+                for row in bottom..top {
+                    buffer[(ROWS - 1 - row) * width + x] = color;
+                }
+                bottom = top;
+            }
+        }
+    }
+}
+
+/// Rows a duration takes in the graph, rounded up so anything measurable shows
+fn rows(duration: Duration) -> usize {
+    let row = BUDGET.as_nanos() * 2 / ROWS as u128;
+    duration.as_nanos().div_ceil(row) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::perf_hud::{FrameTime, PerfHud, AUDIO, EMULATION, RENDER};
+    use std::time::Duration;
+
+    /// Columns stack the parts of each frame from the bottom, the newest on the right
+    #[test]
+    fn test_draw() {
+        let mut hud = PerfHud::new(true);
+        let ms = Duration::from_millis;
+        hud.record(FrameTime { emulation: ms(40), render: ms(0), audio: ms(0) });
+        hud.record(FrameTime { emulation: ms(2), render: ms(3), audio: ms(1) });
+        let mut buffer = vec![0; 64 * 32];
+        hud.draw(&mut buffer, 64);
+        let column = |x: usize| (0..16).rev().map(|y| buffer[y * 64 + x]).take_while(|color| *color != 0).collect::<Vec<_>>();
+        assert_eq!(column(63), vec![EMULATION, RENDER, RENDER, AUDIO]);
+        // Past the top of the graph, over the budget line
+        assert_eq!(column(62), vec![EMULATION; 16]);
+        assert_eq!(buffer[16 * 64 + 62], 0);
+    }
+}
@@ -0,0 +1,108 @@
+//! Plugins loaded from shared libraries with `--plugin PATH`, for devices and display filters
+//! shipped apart from the emulator. A plugin is a library with a C ABI exporting either or both
+//! of:
+//!
+//! ```c
+//! // A device to map into memory, made again for every power-on and reset
+//! struct Chip8Peripheral chip8_peripheral(void);
+//! // Changes a frame before it's shown, pixels as 0x00RRGGBB row by row
+//! void chip8_filter(uint32_t *pixels, size_t width, size_t height);
+//! ```
+//!
+//! where `Chip8Peripheral` is laid out as `RawPeripheral`. The state pointer belongs to the
+//! emulator until it passes it to `free`, and may be used from another thread than the one that
+//! made it.
+
+use crate::chip8::Peripheral;
+use libloading::Library;
+use std::ffi::c_void;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::Arc;
+
+type PeripheralFn = extern "C" fn() -> RawPeripheral;
+type FilterFn = extern "C" fn(*mut u32, usize, usize);
+
+/// A device as a plugin hands it over: its state and the functions taking it
+#[repr(C)]
+pub struct RawPeripheral {
+    state: *mut c_void,
+    // First and last address the device answers to
+    start: u16,
+    end: u16,
+    read: extern "C" fn(*mut c_void, u16) -> u8,
+    write: extern "C" fn(*mut c_void, u16, u8),
+    // A copy of the state, for copies of the machine
+    clone: extern "C" fn(*mut c_void) -> *mut c_void,
+    free: extern "C" fn(*mut c_void),
+}
+
+/// A plugin's device, keeping the library loaded for as long as it's mapped
+struct PluginPeripheral {
+    raw: RawPeripheral,
+    _library: Arc<Library>,
+}
+
+// Plugins promise their state can move between threads
+unsafe impl Send for PluginPeripheral {}
+
+impl Peripheral for PluginPeripheral {
+    fn range(&self) -> RangeInclusive<u16> {
+        self.raw.start..=self.raw.end
+    }
+
+    fn read(&mut self, address: u16) -> u8 {
+        (self.raw.read)(self.raw.state, address)
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        (self.raw.write)(self.raw.state, address, value)
+    }
+
+    fn box_clone(&self) -> Box<dyn Peripheral> {
+        let raw = RawPeripheral { state: (self.raw.clone)(self.raw.state), ..self.raw };
+        Box::new(PluginPeripheral { raw, _library: self._library.clone() })
+    }
+}
+
+impl Drop for PluginPeripheral {
+    fn drop(&mut self) {
+        (self.raw.free)(self.raw.state)
+    }
+}
+
+pub struct Plugin {
+    library: Arc<Library>,
+    peripheral: Option<PeripheralFn>,
+    filter: Option<FilterFn>,
+}
+
+impl Plugin {
+    /// Loads a library, failing if it exports neither a device nor a filter
+    pub fn load(path: &Path) -> Result<Self, String> {
+        // Loading runs the library's initializers, trusted like the emulator itself
+        let library = unsafe { Library::new(path) }
+            .map_err(|e| format!("Could not load plugin {}\n{}", path.display(), e))?;
+        let peripheral = unsafe { library.get::<PeripheralFn>(b"chip8_peripheral\0") }.ok().map(|symbol| *symbol);
+        let filter = unsafe { library.get::<FilterFn>(b"chip8_filter\0") }.ok().map(|symbol| *symbol);
+        if peripheral.is_none() && filter.is_none() {
+            return Err(format!("Plugin {} exports neither chip8_peripheral nor chip8_filter", path.display()));
+        }
+        Ok(Plugin { library: Arc::new(library), peripheral, filter })
+    }
+
+    /// A new device from the plugin, if it has one
+    pub fn peripheral(&self) -> Option<Box<dyn Peripheral>> {
+        self.peripheral.map(|make| {
+            Box::new(PluginPeripheral { raw: make(), _library: self.library.clone() }) as Box<dyn Peripheral>
+        })
+    }
+
+    /// Runs the plugin's filter over a frame, if it has one
+    pub fn filter(&self, pixels: &mut [u32], width: usize, height: usize) {
+        assert_eq!(pixels.len(), width * height);
+        if let Some(filter) = self.filter {
+            filter(pixels.as_mut_ptr(), width, height);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! Timing profile in the Chrome trace format, to open in chrome://tracing or Perfetto.
+//!
+//! Frames, the emulation and rendering within them and the audio callbacks are recorded as
+//! complete events on their own tracks, timed in microseconds from when profiling started.
+
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Track an event is shown on
+#[derive(Clone, Copy)]
+pub enum Thread {
+    Emulation = 1,
+    Audio = 2,
+}
+
+/// Cheap to clone, clones record into the same profile
+#[derive(Clone)]
+pub struct Profiler {
+    start: Instant,
+    events: Arc<Mutex<Vec<Value>>>,
+}
+
+/// Times what happens until it's dropped
+pub struct Span<'a> {
+    profiler: &'a Profiler,
+    name: &'static str,
+    thread: Thread,
+    start: Instant,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler { start: Instant::now(), events: Arc::new(Mutex::new(Vec::new())) }
+    }
+
+    pub fn span(&self, name: &'static str, thread: Thread) -> Span<'_> {
+        Span { profiler: self, name, thread, start: Instant::now() }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let names = [(Thread::Emulation, "emulation"), (Thread::Audio, "audio")];
+        let mut events: Vec<Value> = names
+            .iter()
+            .map(|(thread, name)| json!({ "name": "thread_name", "ph": "M", "pid": 1, "tid": *thread as u8, "args": { "name": name } }))
+            .collect();
+        events.extend(self.events.lock().unwrap().iter().cloned());
+        let text = json!({ "traceEvents": events, "displayTimeUnit": "ms" }).to_string();
+        fs::write(path, text).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+}
+
+impl Drop for Span<'_> {
+    fn drop(&mut self) {
+        let event = json!({
+            "name": self.name,
+            "ph": "X",
+            "pid": 1,
+            "tid": self.thread as u8,
+            "ts": self.start.duration_since(self.profiler.start).as_micros() as u64,
+            "dur": self.start.elapsed().as_micros() as u64,
+        });
+        self.profiler.events.lock().unwrap().push(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::{Profiler, Thread};
+    use serde_json::Value;
+    use std::fs;
+
+    /// Spans nest on their track and the file loads as a trace
+    #[test]
+    fn test_profile() {
+        let profiler = Profiler::new();
+        {
+            let _frame = profiler.span("frame", Thread::Emulation);
+            let _render = profiler.span("render", Thread::Emulation);
+        }
+        let path = std::env::temp_dir().join("chip8-profile-test.json");
+        profiler.save(&path).unwrap();
+        let trace: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let events = trace["traceEvents"].as_array().unwrap();
+        let spans: Vec<&str> = events.iter().filter(|event| event["ph"] == "X").map(|event| event["name"].as_str().unwrap()).collect();
+        assert_eq!(spans, ["render", "frame"]);
+        assert!(events[3]["ts"].as_u64() >= events[2]["ts"].as_u64());
+        assert!(events[3]["dur"].as_u64() >= events[2]["dur"].as_u64());
+    }
+}
@@ -0,0 +1,93 @@
+//! In-emulator key remapping screen.
+//!
+//! Shows the 16 CHIP-8 keys as a 4x4 grid drawn with the built-in font. The arrow keys move the
+//! selection, Enter waits for the next key press and binds it to the selected CHIP-8 key. A key
+//! already on the keypad swaps places with the selected one, and keys of the second keypad, hotkeys
+//! and turbo toggles are refused. Every new binding is written to the config file straight away.
+
+use crate::config::Config;
+use crate::hotkeys::Hotkeys;
+use crate::keypad_grid::{self, CellStyle, GRID};
+use crate::keymap::{key_name, Keymap};
+use minifb::Key;
+use std::path::Path;
+use std::time::Instant;
+use tracing::{error, info, warn};
+
+const ON: u32 = 0x0FFF;
+const OFF: u32 = 0x0000;
+
+pub struct RemapScreen {
+    // Index into GRID
+    selected: usize,
+    waiting_since: Option<Instant>,
+}
+
+impl RemapScreen {
+    pub fn new() -> Self {
+        RemapScreen { selected: 0, waiting_since: None }
+    }
+
+    /// Handles the keys pressed since the last frame, saving any new binding to the config file.
+    /// Hotkeys, turbo toggles and the second keypad's keys can't be bound to the keypad.
+    pub fn handle_keys(
+        &mut self,
+        pressed: &[Key],
+        hotkeys: &Hotkeys,
+        keymap: &mut Keymap,
+        second_keymap: &Keymap,
+        config: &mut Config,
+        config_path: &Path,
+    ) {
+        for key in pressed {
+            if hotkeys.is_hotkey(*key) {
+                if self.waiting_since.is_some() {
+                    warn!("{} is a hotkey or turbo toggle, pick another key", key_name(*key));
+                }
+                continue;
+            }
+
+            if self.waiting_since.take().is_some() {
+                if let Some(taken) = second_keymap.chip8_key(*key) {
+                    warn!("{} is key {:X} of the second keypad, pick another key", key_name(*key), taken);
+                    self.waiting_since = Some(Instant::now());
+                    continue;
+                }
+                let chip8_key = GRID[self.selected];
+                config.keymap.keys.insert(format!("{:X}", chip8_key), key_name(*key));
+                if let Some(swapped) = keymap.bind(chip8_key, *key) {
+                    config.keymap.keys.insert(format!("{:X}", swapped), key_name(keymap.key(swapped)));
+                    info!("Key {:X} moved to {}", swapped, key_name(keymap.key(swapped)));
+                }
+                if let Err(error) = config.save(config_path) {
+                    error!("{}", error);
+                }
+                continue;
+            }
+
+            match key {
+                Key::Left => self.selected = (self.selected + 15) % 16,
+                Key::Right => self.selected = (self.selected + 1) % 16,
+                Key::Up => self.selected = (self.selected + 12) % 16,
+                Key::Down => self.selected = (self.selected + 4) % 16,
+                Key::Enter => self.waiting_since = Some(Instant::now()),
+                _ => {}
+            }
+        }
+    }
+
+    /// Draws the grid, inverting the selected cell. The selected cell blinks while waiting for a key.
+    pub fn draw(&self, buffer: &mut [u32], width: usize) {
+        let blink_off = self
+            .waiting_since
+            .is_some_and(|since| since.elapsed().as_millis() / 250 % 2 == 1);
+
+        keypad_grid::draw(buffer, width, |cell| {
+            if cell == self.selected && !blink_off {
+                CellStyle { background: Some(ON), foreground: OFF }
+            } else {
+                CellStyle { background: Some(OFF), foreground: ON }
+            }
+        });
+    }
+}
@@ -0,0 +1,308 @@
+//! Debugger over WebSocket, for debug UIs in a browser or an editor to attach to the running
+//! emulator. Requests and replies are JSON text messages:
+//!
+//! ```text
+//! -> {"id": 1, "command": "break", "address": "draw"}
+//! <- {"id": 1, "result": "Breakpoint at 0x206 (draw)"}
+//! -> {"id": 2, "command": "state"}
+//! <- {"id": 2, "result": {"pc": 518, "i": 768, "registers": [...], "stack": [514], ...}}
+//! <- {"event": "stopped", "reason": "Breakpoint at", "pc": 518}
+//! ```
+//!
+//! | Command     | Fields              |                                                          |
+//! |-------------|---------------------|----------------------------------------------------------|
+//! | `state`     |                     | registers, I, program counter, stack, timers, paused     |
+//! | `memory`    | `address`, `length` | bytes of memory                                          |
+//! | `frame`     |                     | the screen, a string of 0 and 1 per row                  |
+//! | `subscribe` | `frames`            | send a `frame` event whenever the screen changes, or not |
+//! | `break`     | `address`           | set a breakpoint, the address a number or a label        |
+//! | `delete`    | `address`           | remove a breakpoint                                      |
+//! | `step`      | `count`             | run instructions, one if no count is given               |
+//! | `continue`  |                     | run until a breakpoint                                   |
+//! | `pause`     |                     | stop the machine                                         |
+//! | `command`   | `line`              | run any command of the debugger prompt                   |
+//!
+//! Errors come back as `{"id": 1, "error": "..."}`. Every client connected gets the events.
+
+use crate::chip8::Chip8;
+use crate::run;
+use serde_json::{json, Value};
+use sha1::{Digest, Sha1};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tracing::{info, warn};
+
+/// Appended to the client's key to accept the handshake
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const TEXT: u8 = 0x1;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+/// Longest message taken from a client, whole or in frames. Anything longer closes the
+/// connection instead of being read into memory
+const MAX_MESSAGE: usize = 1 << 20;
+/// Close status for a message too big to take
+const MESSAGE_TOO_BIG: u16 = 1009;
+
+/// Clients connected and what they are sent
+struct Client {
+    id: usize,
+    stream: TcpStream,
+    frames: bool,
+}
+
+/// Accepts any number of clients
+pub struct RemoteServer {
+    /// Messages from the clients, by client
+    messages: Receiver<(usize, String)>,
+    clients: Arc<Mutex<Vec<Client>>>,
+}
+
+impl RemoteServer {
+    pub fn listen(port: u16) -> Result<Self, String> {
+        let listener =
+            TcpListener::bind(("127.0.0.1", port)).map_err(|e| format!("Could not listen for remote debuggers on port {}: {}", port, e))?;
+        info!("Remote debugging on ws://127.0.0.1:{}", port);
+        let (sender, messages) = mpsc::channel();
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let shared = Arc::clone(&clients);
+        thread::spawn(move || {
+            for (id, stream) in listener.incoming().map_while(Result::ok).enumerate() {
+                let (sender, clients) = (sender.clone(), Arc::clone(&shared));
+                thread::spawn(move || {
+                    if let Err(error) = serve(id, stream, &sender, &clients) {
+                        warn!("Remote debugger {}: {}", id, error);
+                    }
+                    clients.lock().unwrap().retain(|client| client.id != id);
+                });
+            }
+        });
+        Ok(RemoteServer { messages, clients })
+    }
+
+    /// The next message from a client, with the client to reply to
+    pub fn poll(&self) -> Option<(usize, String)> {
+        self.messages.try_recv().ok()
+    }
+
+    pub fn reply(&self, client: usize, message: &Value) {
+        self.send(|each| each.id == client, message);
+    }
+
+    /// Sends an event to every client
+    pub fn broadcast(&self, event: &Value) {
+        self.send(|_| true, event);
+    }
+
+    /// Sends the screen to the clients that asked for it
+    pub fn send_frame(&self, chip8: &Chip8) {
+        self.send(|client| client.frames, &json!({ "event": "frame", "rows": frame(chip8) }));
+    }
+
+    pub fn wants_frames(&self) -> bool {
+        self.clients.lock().unwrap().iter().any(|client| client.frames)
+    }
+
+    pub fn subscribe(&self, client: usize, frames: bool) {
+        if let Some(client) = self.clients.lock().unwrap().iter_mut().find(|each| each.id == client) {
+            client.frames = frames;
+        }
+    }
+
+    fn send(&self, to: impl Fn(&Client) -> bool, message: &Value) {
+        let data = encode(TEXT, message.to_string().as_bytes());
+        for client in self.clients.lock().unwrap().iter_mut().filter(|client| to(client)) {
+            // A client that went away is dropped by its own thread
+            let _ = client.stream.write_all(&data);
+        }
+    }
+}
+
+/// Shakes hands with a client and passes its messages on until it closes
+fn serve(id: usize, stream: TcpStream, sender: &mpsc::Sender<(usize, String)>, clients: &Mutex<Vec<Client>>) -> Result<(), String> {
+    let mut reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+    let mut key = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+            return Err(String::from("closed during the handshake"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Sec-WebSocket-Key") {
+                key = Some(value.trim().to_string());
+            }
+        }
+    }
+    let key = key.ok_or("not a WebSocket request")?;
+    let mut writer = stream.try_clone().map_err(|e| e.to_string())?;
+    write!(
+        writer,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(&key)
+    )
+    .map_err(|e| e.to_string())?;
+    clients.lock().unwrap().push(Client { id, stream, frames: false });
+    info!("Remote debugger {} connected", id);
+
+    let mut message = Vec::new();
+    loop {
+        let (opcode, last, payload) = match read_frame(&mut reader) {
+            Ok(frame) if message.len() + frame.2.len() <= MAX_MESSAGE => frame,
+            Ok(_) => return Err(too_big(&mut writer, "Message")),
+            Err(error) if error.kind() == io::ErrorKind::InvalidData => return Err(too_big(&mut writer, "Frame")),
+            Err(_) => break,
+        };
+        match opcode {
+            CLOSE => {
+                let _ = writer.write_all(&encode(CLOSE, &[]));
+                break;
+            }
+            PING => {
+                let _ = writer.write_all(&encode(PONG, &payload));
+            }
+            PONG => {}
+            _ => {
+                message.extend_from_slice(&payload);
+                if last {
+                    if sender.send((id, String::from_utf8_lossy(&message).into_owned())).is_err() {
+                        break;
+                    }
+                    message.clear();
+                }
+            }
+        }
+    }
+    info!("Remote debugger {} disconnected", id);
+    Ok(())
+}
+
+/// Closes the connection to a client that sent too much, saying why
+fn too_big(writer: &mut impl Write, what: &str) -> String {
+    let _ = writer.write_all(&encode(CLOSE, &MESSAGE_TOO_BIG.to_be_bytes()));
+    format!("{} longer than {} bytes, disconnected", what, MAX_MESSAGE)
+}
+
+/// `Sec-WebSocket-Accept` for a client's key
+fn accept_key(key: &str) -> String {
+    base64(&Sha1::digest(format!("{}{}", key, HANDSHAKE_GUID).as_bytes()))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (at, byte)| bits | (*byte as u32) << (16 - 8 * at));
+        for at in 0..4 {
+            if at <= chunk.len() {
+                text.push(ALPHABET[(bits >> (18 - 6 * at) & 0x3F) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+/// Reads a frame from a client, returning its opcode, whether it ends a message and its
+/// unmasked payload. A payload longer than `MAX_MESSAGE` is an `InvalidData` error, unread
+fn read_frame(reader: &mut impl Read) -> io::Result<(u8, bool, Vec<u8>)> {
+    let mut header = [0; 2];
+    reader.read_exact(&mut header)?;
+    let length = match header[1] & 0x7F {
+        126 => {
+            let mut length = [0; 2];
+            reader.read_exact(&mut length)?;
+            u16::from_be_bytes(length) as usize
+        }
+        127 => {
+            let mut length = [0; 8];
+            reader.read_exact(&mut length)?;
+            u64::from_be_bytes(length).min(usize::MAX as u64) as usize
+        }
+        length => length as usize,
+    };
+    if length > MAX_MESSAGE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Frame of {} bytes", length)));
+    }
+    let mut mask = [0; 4];
+    if header[1] & 0x80 != 0 {
+        reader.read_exact(&mut mask)?;
+    }
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload)?;
+    payload.iter_mut().enumerate().for_each(|(at, byte)| *byte ^= mask[at % 4]);
+    Ok((header[0] & 0x0F, header[0] & 0x80 != 0, payload))
+}
+
+/// A whole unmasked frame, as servers send them
+fn encode(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        length if length < 126 => frame.push(length as u8),
+        length if length <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Registers, I, program counter, stack, timers and instruction count
+pub fn state(chip8: &Chip8, paused: bool) -> Value {
+    let (delay, sound) = chip8.timers();
+    json!({
+        "pc": chip8.program_counter(),
+        "i": chip8.index(),
+        "registers": chip8.registers(),
+        "stack": chip8.call_stack(),
+        "delay": delay,
+        "sound": sound,
+        "cycles": chip8.cycles(),
+        "paused": paused,
+    })
+}
+
+/// The screen, a string of 0 and 1 per row
+pub fn frame(chip8: &Chip8) -> Vec<String> {
+    chip8.display().chunks(run::WIDTH).map(|row| row.iter().map(|pixel| if *pixel != 0 { '1' } else { '0' }).collect()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::remote::{accept_key, encode, read_frame, MAX_MESSAGE, TEXT};
+    use std::io::ErrorKind;
+
+    /// The handshake key is the one of RFC 6455, frames read back masked as clients send them
+    #[test]
+    fn test_websocket() {
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+
+        assert_eq!(encode(TEXT, b"Hello"), b"\x81\x05Hello");
+        let masked = b"\x81\x85\x37\xfa\x21\x3d\x7f\x9f\x4d\x51\x58";
+        assert_eq!(read_frame(&mut &masked[..]).unwrap(), (TEXT, true, b"Hello".to_vec()));
+        let long = encode(TEXT, &[b'x'; 300]);
+        assert_eq!(&long[..4], [0x81, 126, 0x01, 0x2C]);
+        assert_eq!(read_frame(&mut &long[..]).unwrap().2.len(), 300);
+
+        // Lengths past the limit aren't trusted with an allocation
+        let huge = [0x81, 0xFF, 0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0];
+        assert_eq!(read_frame(&mut &huge[..]).unwrap_err().kind(), ErrorKind::InvalidData);
+        let over = encode(TEXT, &vec![b'x'; MAX_MESSAGE + 1]);
+        assert_eq!(read_frame(&mut &over[..]).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert!(read_frame(&mut &encode(TEXT, &vec![b'x'; MAX_MESSAGE])[..]).is_ok());
+    }
+}
@@ -0,0 +1,457 @@
+//! Replay files: keypad input recorded frame by frame from power-on.
+//!
+//! Together with the ROM, the RNG seed, the variant and the machine settings stored in the header,
+//! the input is all that's needed to re-run a session exactly. Input rarely changes between frames, so it's
+//! stored run-length encoded. A checksum of the machine state is stored every
+//! `CHECKSUM_INTERVAL` frames so playback can tell when it no longer matches the recording.
+//!
+//! Layout (little endian):
+//!
+//! | Field          | Size   |                                                   |
+//! |----------------|--------|---------------------------------------------------|
+//! | magic          | 4      | `C8RP`                                            |
+//! | version        | 1      |                                                   |
+//! | ROM SHA-1      | 20     |                                                   |
+//! | seed           | 8      |                                                   |
+//! | variant        | 1      | 0 = CHIP-8, 1 = CHIP-8X                           |
+//! | memory bounds  | 1      | from version 3, 0 = fault, 1 = wrap               |
+//! | write protect  | 1      | from version 3, 0 = off, 1 = fault, 2 = ignore    |
+//! | index width    | 1      | from version 3, 12 or 16                          |
+//! | stack depth    | 2      | from version 3                                    |
+//! | memory size    | 4      | from version 3                                    |
+//! | font address   | 2      | from version 3                                    |
+//! | tick rate      | 4      | from version 3, instructions per timer tick       |
+//! | quirks         | 1      | from version 3, one bit each in `Quirks` order    |
+//! | font length    | 2      | from version 3, 0 for the usual digits            |
+//! | font           | varies | from version 3                                    |
+//! | run count      | 4      |                                                   |
+//! | runs           | 8 each | frame count (u32), keypad 1 (u16), keypad 2 (u16) |
+//! | checksum count | 4      | from version 2                                    |
+//! | checksums      | 8 each | state after every `CHECKSUM_INTERVAL`th frame     |
+
+use crate::chip8::{Chip8Builder, Font, IndexWidth, MemoryBounds, Quirks, Variant, WriteProtection, DEFAULT_MEMORY_SIZE, DEFAULT_STACK_DEPTH};
+use sha1::{Digest, Sha1};
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"C8RP";
+const VERSION: u8 = 3;
+/// Header up to the variant, the part every version has
+const HEADER_SIZE: usize = 4 + 1 + 20 + 8 + 1;
+/// Machine settings before the font, which is as long as the last two of them say
+const FIXED_SIZE: usize = 18;
+
+/// Frames between two state checksums
+pub const CHECKSUM_INTERVAL: u64 = 60;
+
+pub type RomHash = [u8; 20];
+
+pub fn rom_hash(rom: &[u8]) -> RomHash {
+    Sha1::digest(rom).into()
+}
+
+/// Both keypads for one frame, one bit per key
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameInput {
+    pub keypad: u16,
+    pub second_keypad: u16,
+}
+
+impl FrameInput {
+    pub fn new(keypad: [bool; 16], second_keypad: [bool; 16]) -> Self {
+        FrameInput { keypad: to_bits(keypad), second_keypad: to_bits(second_keypad) }
+    }
+
+    pub fn keypad(&self) -> [bool; 16] {
+        from_bits(self.keypad)
+    }
+
+    pub fn second_keypad(&self) -> [bool; 16] {
+        from_bits(self.second_keypad)
+    }
+}
+
+fn to_bits(keys: [bool; 16]) -> u16 {
+    keys.iter().enumerate().fold(0, |bits, (key, pressed)| bits | ((*pressed as u16) << key))
+}
+
+fn from_bits(bits: u16) -> [bool; 16] {
+    let mut keys = [false; 16];
+    for (key, pressed) in keys.iter_mut().enumerate() {
+        *pressed = bits & (1 << key) != 0;
+    }
+    keys
+}
+
+/// Result of checking the state after a frame against the recording
+#[derive(Debug, PartialEq)]
+pub enum Verification {
+    /// No checksum was recorded for this frame
+    Unchecked,
+    Matched,
+    /// The state differs, the divergence happened after the previous checkpoint
+    Diverged { last_good_frame: u64 },
+}
+
+/// How the machine was set up besides its variant, which the input only replays on the same
+#[derive(Clone, Debug, PartialEq)]
+pub struct Machine {
+    pub memory_bounds: MemoryBounds,
+    pub write_protection: WriteProtection,
+    pub index_width: IndexWidth,
+    pub stack_depth: u16,
+    pub memory_size: usize,
+    /// Digits loaded in place of the usual ones
+    pub font: Option<Font>,
+    pub font_address: u16,
+    /// Instructions per timer tick
+    pub tickrate: u32,
+    pub quirks: Quirks,
+}
+
+impl Default for Machine {
+    /// The machine `Chip8::builder()` makes
+    fn default() -> Self {
+        Machine {
+            memory_bounds: MemoryBounds::default(),
+            write_protection: WriteProtection::default(),
+            index_width: IndexWidth::default(),
+            stack_depth: DEFAULT_STACK_DEPTH,
+            memory_size: DEFAULT_MEMORY_SIZE,
+            font: None,
+            font_address: 0,
+            tickrate: 1,
+            quirks: Quirks::default(),
+        }
+    }
+}
+
+impl Machine {
+    /// Sets the builder up like the recorded machine
+    pub fn configure(&self, builder: Chip8Builder) -> Chip8Builder {
+        let builder = builder
+            .memory_bounds(self.memory_bounds)
+            .write_protection(self.write_protection)
+            .index_width(self.index_width)
+            .stack_depth(self.stack_depth)
+            .memory_size(self.memory_size)
+            .font_address(self.font_address)
+            .tickrate(self.tickrate)
+            .quirks(self.quirks);
+        match &self.font {
+            Some(font) => builder.font(font.clone()),
+            None => builder,
+        }
+    }
+
+    fn to_bytes(&self, bytes: &mut Vec<u8>) {
+        let q = &self.quirks;
+        bytes.push(match self.memory_bounds {
+            MemoryBounds::Fault => 0,
+            MemoryBounds::Wrap => 1,
+        });
+        bytes.push(match self.write_protection {
+            WriteProtection::Off => 0,
+            WriteProtection::Fault => 1,
+            WriteProtection::Ignore => 2,
+        });
+        bytes.push(match self.index_width {
+            IndexWidth::Bits12 => 12,
+            IndexWidth::Bits16 => 16,
+        });
+        bytes.extend_from_slice(&self.stack_depth.to_le_bytes());
+        bytes.extend_from_slice(&(self.memory_size as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.font_address.to_le_bytes());
+        bytes.extend_from_slice(&self.tickrate.to_le_bytes());
+        let quirks = [q.shift, q.load_store, q.vf_order, q.clip, q.vblank, q.jump, q.logic];
+        bytes.push(quirks.iter().enumerate().fold(0, |bits, (i, on)| bits | (*on as u8) << i));
+        let font = self.font.as_ref().map(Font::bytes).unwrap_or_default();
+        bytes.extend_from_slice(&(font.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&font);
+    }
+
+    /// Reads the settings from the start of `bytes`, returning them with the bytes they took
+    fn from_bytes(bytes: &[u8]) -> Result<(Self, usize), String> {
+        let truncated = || String::from("Replay file is truncated");
+        let fixed = bytes.get(..FIXED_SIZE).ok_or_else(truncated)?;
+        let memory_bounds = match fixed[0] {
+            0 => MemoryBounds::Fault,
+            1 => MemoryBounds::Wrap,
+            other => return Err(format!("Unknown memory bounds in replay: {}", other)),
+        };
+        let write_protection = match fixed[1] {
+            0 => WriteProtection::Off,
+            1 => WriteProtection::Fault,
+            2 => WriteProtection::Ignore,
+            other => return Err(format!("Unknown write protection in replay: {}", other)),
+        };
+        let index_width = match fixed[2] {
+            12 => IndexWidth::Bits12,
+            16 => IndexWidth::Bits16,
+            other => return Err(format!("Unknown index width in replay: {}", other)),
+        };
+        let stack_depth = u16::from_le_bytes(fixed[3..5].try_into().unwrap());
+        let memory_size = u32::from_le_bytes(fixed[5..9].try_into().unwrap()) as usize;
+        let font_address = u16::from_le_bytes(fixed[9..11].try_into().unwrap());
+        let tickrate = u32::from_le_bytes(fixed[11..15].try_into().unwrap()).max(1);
+        let quirk = |i: u8| fixed[15] & (1 << i) != 0;
+        let quirks = Quirks {
+            shift: quirk(0),
+            load_store: quirk(1),
+            vf_order: quirk(2),
+            clip: quirk(3),
+            vblank: quirk(4),
+            jump: quirk(5),
+            logic: quirk(6),
+        };
+        let font_length = u16::from_le_bytes(fixed[16..18].try_into().unwrap()) as usize;
+        let font = match bytes.get(FIXED_SIZE..FIXED_SIZE + font_length).ok_or_else(truncated)? {
+            [] => None,
+            font => Some(Font::from_bytes(font)?),
+        };
+        let machine = Machine { memory_bounds, write_protection, index_width, stack_depth, memory_size, font, font_address, tickrate, quirks };
+        Ok((machine, FIXED_SIZE + font_length))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Replay {
+    pub rom_hash: RomHash,
+    pub seed: u64,
+    pub variant: Variant,
+    /// `None` for files from before version 3, which are played on the machine the command line
+    /// asks for
+    pub machine: Option<Machine>,
+    // (frame count, input) runs
+    runs: Vec<(u32, FrameInput)>,
+    checksums: Vec<u64>,
+}
+
+impl Replay {
+    pub fn new(rom_hash: RomHash, seed: u64, variant: Variant, machine: Machine) -> Self {
+        Replay { rom_hash, seed, variant, machine: Some(machine), runs: Vec::new(), checksums: Vec::new() }
+    }
+
+    /// Appends the input for the next frame, along with the state checksum after it. The checksum
+    /// is only computed on checkpoint frames.
+    pub fn push(&mut self, input: FrameInput, checksum: impl FnOnce() -> u64) {
+        match self.runs.last_mut() {
+            Some((count, last)) if *last == input && *count < u32::MAX => *count += 1,
+            _ => self.runs.push((1, input)),
+        }
+        if self.frame_count().is_multiple_of(CHECKSUM_INTERVAL) {
+            self.checksums.push(checksum());
+        }
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.runs.iter().map(|(count, _)| *count as u64).sum()
+    }
+
+    /// Input for every frame in order
+    pub fn frames(&self) -> impl Iterator<Item = FrameInput> + '_ {
+        self.runs.iter().flat_map(|(count, input)| std::iter::repeat_n(*input, *count as usize))
+    }
+
+    /// Checks the state after frame number `frame` (counting from 1) against the recording
+    pub fn verify(&self, frame: u64, checksum: impl FnOnce() -> u64) -> Verification {
+        if !frame.is_multiple_of(CHECKSUM_INTERVAL) {
+            return Verification::Unchecked;
+        }
+        match self.checksums.get((frame / CHECKSUM_INTERVAL - 1) as usize) {
+            None => Verification::Unchecked,
+            Some(expected) if *expected == checksum() => Verification::Matched,
+            Some(_) => Verification::Diverged { last_good_frame: frame - CHECKSUM_INTERVAL },
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + FIXED_SIZE + 4 + self.runs.len() * 8 + 4 + self.checksums.len() * 8);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&self.rom_hash);
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        bytes.push(match self.variant {
+            Variant::Chip8 => 0,
+            Variant::Chip8X => 1,
+        });
+        self.machine.clone().unwrap_or_default().to_bytes(&mut bytes);
+        bytes.extend_from_slice(&(self.runs.len() as u32).to_le_bytes());
+        for (count, input) in &self.runs {
+            bytes.extend_from_slice(&count.to_le_bytes());
+            bytes.extend_from_slice(&input.keypad.to_le_bytes());
+            bytes.extend_from_slice(&input.second_keypad.to_le_bytes());
+        }
+        bytes.extend_from_slice(&(self.checksums.len() as u32).to_le_bytes());
+        for checksum in &self.checksums {
+            bytes.extend_from_slice(&checksum.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < HEADER_SIZE || &bytes[0..4] != MAGIC {
+            return Err(String::from("Not a replay file"));
+        }
+        let version = bytes[4];
+        if version == 0 || version > VERSION {
+            return Err(format!("Unsupported replay version {}", version));
+        }
+        let mut rom_hash = [0; 20];
+        rom_hash.copy_from_slice(&bytes[5..25]);
+        let seed = u64::from_le_bytes(bytes[25..33].try_into().unwrap());
+        let variant = match bytes[33] {
+            0 => Variant::Chip8,
+            1 => Variant::Chip8X,
+            other => return Err(format!("Unknown variant in replay: {}", other)),
+        };
+
+        let truncated = || String::from("Replay file is truncated");
+        let mut machine = None;
+        let mut runs_start = HEADER_SIZE;
+        if version >= 3 {
+            let (settings, length) = Machine::from_bytes(&bytes[HEADER_SIZE..])?;
+            machine = Some(settings);
+            runs_start += length;
+        }
+        let count_bytes = bytes.get(runs_start..runs_start + 4).ok_or_else(truncated)?;
+        let run_count = u32::from_le_bytes(count_bytes.try_into().unwrap()) as usize;
+        runs_start += 4;
+        let runs_end = runs_start + run_count * 8;
+        let runs = bytes
+            .get(runs_start..runs_end)
+            .ok_or_else(truncated)?
+            .chunks(8)
+            .map(|run| {
+                let count = u32::from_le_bytes(run[0..4].try_into().unwrap());
+                let keypad = u16::from_le_bytes(run[4..6].try_into().unwrap());
+                let second_keypad = u16::from_le_bytes(run[6..8].try_into().unwrap());
+                (count, FrameInput { keypad, second_keypad })
+            })
+            .collect();
+
+        // Version 1 files have no checksums
+        let mut checksums = Vec::new();
+        if version >= 2 {
+            let count_bytes = bytes.get(runs_end..runs_end + 4).ok_or_else(truncated)?;
+            let checksum_count = u32::from_le_bytes(count_bytes.try_into().unwrap()) as usize;
+            let checksums_start = runs_end + 4;
+            checksums = bytes
+                .get(checksums_start..checksums_start + checksum_count * 8)
+                .ok_or_else(truncated)?
+                .chunks(8)
+                .map(|checksum| u64::from_le_bytes(checksum.try_into().unwrap()))
+                .collect();
+        }
+
+        Ok(Replay { rom_hash, seed, variant, machine, runs, checksums })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_bytes())
+            .map_err(|e| format!("Could not write replay {}\n{}", path.display(), e))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|e| format!("Could not read replay {}\n{}", path.display(), e))?;
+        Replay::from_bytes(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::{Chip8, Font, IndexWidth, MemoryBounds, Quirks, Variant, WriteProtection};
+    use crate::replay::{FrameInput, Machine, Replay, Verification, CHECKSUM_INTERVAL};
+
+    /// Repeated input is merged into runs
+    #[test]
+    fn test_run_length_encoding() {
+        let mut replay = Replay::new([7; 20], 0xDEADBEEF, Variant::Chip8X, Machine::default());
+        let mut held = [false; 16];
+        held[5] = true;
+        replay.push(FrameInput::new([false; 16], [false; 16]), || 0);
+        replay.push(FrameInput::new(held, [false; 16]), || 0);
+        replay.push(FrameInput::new(held, [false; 16]), || 0);
+        replay.push(FrameInput::new([false; 16], held), || 0);
+        assert_eq!(replay.frame_count(), 4);
+        assert_eq!(replay.runs[1], (2, FrameInput { keypad: 1 << 5, second_keypad: 0 }));
+    }
+
+    /// Replays survive a round trip through the file format
+    #[test]
+    fn test_round_trip() {
+        let mut replay = Replay::new([7; 20], 0xDEADBEEF, Variant::Chip8X, Machine::default());
+        let mut held = [false; 16];
+        held[5] = true;
+        for frame in 0..CHECKSUM_INTERVAL * 2 + 1 {
+            let input = if frame % 7 == 0 { held } else { [false; 16] };
+            replay.push(FrameInput::new(input, [false; 16]), || frame);
+        }
+        assert_eq!(replay.checksums.len(), 2);
+
+        let loaded = Replay::from_bytes(&replay.to_bytes()).unwrap();
+        assert_eq!(loaded, replay);
+        let frames: Vec<FrameInput> = loaded.frames().collect();
+        assert_eq!(frames.len() as u64, CHECKSUM_INTERVAL * 2 + 1);
+        assert_eq!(frames[7].keypad(), held);
+        assert_eq!(frames[8].keypad(), [false; 16]);
+    }
+
+    /// The machine settings are kept in the header and build the machine they describe
+    #[test]
+    fn test_machine() {
+        let machine = Machine {
+            memory_bounds: MemoryBounds::Wrap,
+            write_protection: WriteProtection::Ignore,
+            index_width: IndexWidth::Bits12,
+            stack_depth: 31,
+            memory_size: 0x10000,
+            font: Font::named("octo"),
+            font_address: 0x50,
+            tickrate: 15,
+            quirks: Quirks { clip: true, jump: true, ..Quirks::OCTO },
+        };
+        let mut replay = Replay::new([7; 20], 3, Variant::Chip8, machine.clone());
+        replay.push(FrameInput::new([true; 16], [false; 16]), || 0);
+        let loaded = Replay::from_bytes(&replay.to_bytes()).unwrap();
+        assert_eq!(loaded, replay);
+
+        let chip8 = loaded.machine.unwrap().configure(Chip8::builder()).build();
+        assert_eq!((chip8.memory_bounds(), chip8.write_protection(), chip8.index_width()), (MemoryBounds::Wrap, WriteProtection::Ignore, IndexWidth::Bits12));
+        assert_eq!((chip8.stack_depth(), chip8.memory().len()), (31, 0x10000));
+        assert_eq!((chip8.tickrate(), chip8.quirks()), (15, machine.quirks));
+        assert_eq!(chip8.memory()[0x50..0x55], machine.font.unwrap().bytes()[..5]);
+    }
+
+    /// Files from before version 3 have no settings, and still load
+    #[test]
+    fn test_version_2() {
+        let mut bytes = b"C8RP\x02".to_vec();
+        bytes.extend_from_slice(&[7; 20]);
+        bytes.extend_from_slice(&3u64.to_le_bytes());
+        bytes.push(1);
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(&[5, 0, 0, 0, 0x20, 0, 0, 0]);
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        let replay = Replay::from_bytes(&bytes).unwrap();
+        assert_eq!((replay.seed, replay.variant, replay.machine.is_none()), (3, Variant::Chip8X, true));
+        assert_eq!(replay.frames().collect::<Vec<_>>(), vec![FrameInput { keypad: 0x20, second_keypad: 0 }; 5]);
+    }
+
+    /// Checksums are compared on checkpoint frames only
+    #[test]
+    fn test_verify() {
+        let mut replay = Replay::new([0; 20], 0, Variant::Chip8, Machine::default());
+        for frame in 1..=CHECKSUM_INTERVAL * 2 {
+            replay.push(FrameInput::new([false; 16], [false; 16]), || frame);
+        }
+        assert_eq!(replay.verify(1, || 1), Verification::Unchecked);
+        assert_eq!(replay.verify(CHECKSUM_INTERVAL, || CHECKSUM_INTERVAL), Verification::Matched);
+        assert_eq!(
+            replay.verify(CHECKSUM_INTERVAL * 2, || 0),
+            Verification::Diverged { last_good_frame: CHECKSUM_INTERVAL }
+        );
+        assert_eq!(replay.verify(CHECKSUM_INTERVAL * 3, || 0), Verification::Unchecked);
+    }
+}
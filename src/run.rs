@@ -0,0 +1,908 @@
+//! Windowed emulation loop.
+
+use crate::assembler;
+use crate::audio::Beeper;
+use crate::cheats::{self, CheatMenu, Cheats};
+use crate::chip8::{Chip8, Chip8Builder, Font, Variant};
+use crate::cli::RunArgs;
+use crate::console::{self, Console};
+use crate::crash::{self, CrashDump, CrashLog};
+use crate::debug_view::DebugView;
+use crate::debugger::Debugger;
+use crate::dev::{self, MemoryRange, Watcher};
+use crate::error_screen;
+use crate::fatal;
+use crate::hotkeys::{Action, Hotkeys};
+use crate::input_display::InputDisplay;
+use crate::macros::Macros;
+use crate::octocart;
+use crate::perf_hud::{FrameTime, PerfHud};
+use crate::profile::{Profiler, Thread};
+use crate::remap::RemapScreen;
+use crate::replay::{self, FrameInput, Machine, Replay, Verification};
+use crate::screenshot;
+use crate::search::MemorySearch;
+use crate::serial::SerialOut;
+use crate::script::Script;
+use crate::notes;
+use crate::symbols::Symbols;
+use crate::telemetry::Telemetry;
+use crate::touchpad::TouchKeypad;
+use crate::turbo::Turbo;
+use minifb::{KeyRepeat, Scale, ScaleMode, Window, WindowOptions};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, debug_span, error, info, trace, warn};
+
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+
+/// Highest number of frames emulated per window update
+pub const MAX_SPEED: u32 = 16;
+/// Window updates a second, minifb's default limit on them, which paces the emulation
+pub const FRAME_RATE: u32 = 250;
+/// Addresses shown by --hotspots on exit
+const HOTSPOTS: usize = 20;
+
+pub enum Session<'a> {
+    Live,
+    /// Record input from power-on and write it to this file on exit
+    Record(&'a Path),
+    /// Feed input from a replay, checking the machine state against it along the way
+    Play(Replay),
+    /// Reload the program whenever its files are saved, keeping these memory ranges
+    Dev(&'a [MemoryRange]),
+    /// Feed input from a replay up to the failure of a crash dump, the last frames in the window
+    Crash(Box<CrashDump>, Replay, u64),
+}
+
+/// Playback progress through a replay
+struct Playback {
+    replay: Replay,
+    frames: Vec<FrameInput>,
+    // Frames emulated so far
+    position: u64,
+    diverged: bool,
+}
+
+/// Runs a ROM in a window until it is closed
+pub fn run(args: &RunArgs, session: Session) {
+    let mut config = args.input.load_config();
+
+    // Set up window
+    let mut buffer: Vec<u32> = vec![0; WIDTH * HEIGHT];
+    let mut window = Window::new(
+        "Chip8 Emulator",
+        WIDTH,
+        HEIGHT,
+        WindowOptions {
+            borderless: false,
+            transparency: false,
+            title: true,
+            resize: false,
+            scale: Scale::X16,
+            scale_mode: ScaleMode::Stretch,
+            topmost: false,
+        },
+    )
+        .unwrap_or_else(fatal);
+
+    // Set up keypad mapping
+    let mut keymap = args.input.keymap(&config);
+    let second_keymap = config.second_keymap.build(config.keymap.preset).unwrap_or_else(fatal);
+
+    // Set up autofire
+    let mut turbo = Turbo::new(&config.turbo).unwrap_or_else(fatal);
+
+    // Set up emulator hotkeys, which must stay clear of both keypads and the turbo toggles
+    let hotkeys = Hotkeys::new(&config.hotkeys, &[&keymap, &second_keymap], &turbo.toggles()).unwrap_or_else(fatal);
+
+    // Set up input macros
+    let mut macros = Macros::new(&config.macros, &hotkeys, &[&keymap, &second_keymap]).unwrap_or_else(fatal);
+
+    // Set up system-wide keyboard, only used instead of the window's key events when requested
+    #[cfg(feature = "global-input")]
+    let global_keyboard = crate::input::GlobalKeyboard::new();
+
+    // Set up MIDI controller, if one is attached
+    #[cfg(feature = "midi")]
+    let midi_keypad = crate::midi::MidiKeypad::connect(&config.midi);
+
+    // Set up gamepads, more can be connected later
+    #[cfg(feature = "gamepad")]
+    let mut gamepads = crate::gamepad::Gamepads::new(&config.gamepad, &args.rom)
+        .map_err(|e| error!("{}", e))
+        .ok();
+
+    // Set up raw HID keypads
+    #[cfg(feature = "hid")]
+    let mut hid_keypads = crate::hid::HidKeypads::open(&config.hid)
+        .map_err(|e| error!("{}", e))
+        .ok();
+
+    // Set up profiling, which the audio callbacks also record into
+    let profiler = args.profile.as_ref().map(|_| Profiler::new());
+
+    // Set up sound
+    let beeper = Beeper::new(profiler.clone());
+
+    // Initialize the Chip8 system and load the game into memory
+    let Program { bytes: mut program, options, files, mut symbols } = match load_program(&args.rom) {
+        Ok(program) => program,
+        Err(error) => {
+            error!("{}", error);
+            error_screen::show(&error);
+            return;
+        }
+    };
+    // Names and comments given to memory, which the debugger's note command saves back
+    let notes_path = args.notes.clone().unwrap_or_else(|| args.rom.with_extension("notes"));
+    if notes_path.exists() {
+        for note in notes::load(&notes_path).unwrap_or_else(fatal) {
+            symbols.add_note(note);
+        }
+    }
+    let mut rom_hash = replay::rom_hash(&program);
+    let mut seed = args.seed.unwrap_or_else(rand::random);
+    let mut variant = args.variant;
+    let font = machine_font(args, &options);
+    let mut machine = machine(args, &options, font.as_ref());
+    let mut record_to = None;
+    let mut playback = None;
+    let mut dev = None;
+    let mut crash_replay = None;
+    match session {
+        Session::Live => {}
+        Session::Dev(keep) => dev = Some((Watcher::new(&files), keep)),
+        Session::Record(path) => record_to = Some(path),
+        Session::Play(replay) => {
+            if replay.rom_hash != rom_hash {
+                error!("Replay was recorded with a different ROM than {}", args.rom.display());
+                return;
+            }
+            seed = replay.seed;
+            variant = replay.variant;
+            machine = replay.machine.clone().unwrap_or(machine);
+            let frames = replay.frames().collect();
+            playback = Some(Playback { replay, frames, position: 0, diverged: false });
+        }
+        Session::Crash(dump, replay, frames) => {
+            if replay.rom_hash != rom_hash {
+                error!("Crash dump was written for a different ROM than {}", args.rom.display());
+                return;
+            }
+            seed = replay.seed;
+            variant = replay.variant;
+            machine = replay.machine.clone().unwrap_or(machine);
+            let inputs = replay.frames().collect();
+            playback = Some(Playback { replay, frames: inputs, position: 0, diverged: false });
+            crash_replay = Some((dump, frames));
+        }
+    }
+    // Load plugins, kept for the whole run as their devices and filters call into them
+    #[cfg(feature = "plugins")]
+    let plugins: Vec<crate::plugin::Plugin> = args.plugins.iter()
+        .map(|path| crate::plugin::Plugin::load(path).unwrap_or_else(fatal))
+        .collect();
+    let builder = || {
+        let builder = configure_machine(args, &options, variant, &machine);
+        #[cfg(feature = "plugins")]
+        let builder = plugins.iter().filter_map(crate::plugin::Plugin::peripheral).fold(builder, Chip8Builder::peripheral);
+        builder
+    };
+    let mut chip8 = power_on(builder().seed(seed), &program);
+
+    // Input recording, from power-on
+    let mut recording = record_to.map(|_| Replay::new(rom_hash, seed, variant, machine.clone()));
+    // Trace and input for a crash dump, should the program fail
+    let mut crash_log = CrashLog::new(rom_hash, seed, variant, machine.clone());
+
+    // On-screen keypad for touchscreens, drawn over the game into its own frame
+    let mut touch_keypad = TouchKeypad::new();
+    let mut frame: Vec<u32> = vec![0; WIDTH * HEIGHT];
+
+    // Keypad state shown over the game
+    let mut input_display = InputDisplay::new();
+
+    // Frame times shown over the game
+    let mut perf_hud = PerfHud::new(args.perf_hud);
+
+    // Counts of what happens during the session
+    let mut telemetry = Telemetry::new();
+
+    // Key remapping screen, replaces the game while open
+    let mut remap_screen: Option<RemapScreen> = None;
+    let mut remap_buffer: Vec<u32> = vec![0; WIDTH * HEIGHT];
+
+    // Command console, pauses the game while open
+    let mut console = Console::new(&mut window);
+    let mut search = MemorySearch::default();
+    let mut console_buffer: Vec<u32> = vec![0; console::WIDTH * console::HEIGHT];
+
+    // Emulator state driven by hotkeys
+    let mut paused = false;
+    let mut speed = 1;
+    let mut pace = Pace::new(options.tickrate);
+    let mut savestate: Option<Chip8> = None;
+
+    // Crash dumps run without a window up to the last frames before the failure
+    if let (Some((dump, frames)), Some(active)) = (&crash_replay, &mut playback) {
+        let start = dump.machine.cycles.saturating_sub(frames * pace.per_second(speed) as u64 / FRAME_RATE as u64);
+        if let Err(error) = crash::fast_forward(&mut chip8, &active.frames, start) {
+            error!("{}", error);
+            return;
+        }
+        active.position = start;
+        info!("Ran {} instructions, {} frames before the failure: {}", start, frames, dump.reason);
+    }
+
+    // Debugger prompt on standard input
+    let debugging = args.debug || !args.breakpoints.is_empty() || !args.watches.is_empty() || !args.graphs.is_empty() || args.break_on_code_writes || args.gdb.is_some() || args.remote.is_some();
+    let tracing = args.trace.is_some() || args.trace_file.is_some() || args.hotspots || args.call_graph.is_some() || args.coverage.is_some() || args.warnings || args.taint;
+    let new_debugger = || {
+        let mut debugger = Debugger::new(symbols.clone());
+        debugger.set_notes_path(&notes_path);
+        debugger
+    };
+    let mut debugger = (debugging || tracing).then(new_debugger);
+    if let Some(debugger) = &mut debugger {
+        debugger.set_program_length(program.len());
+        debugger.set_break_on_code_writes(args.break_on_code_writes);
+        if args.warnings {
+            debugger.enable_warnings();
+        }
+        if args.taint {
+            debugger.track_taint(chip8.font(), program.len());
+        }
+        if let Some(port) = args.gdb {
+            if let Err(error) = debugger.serve_gdb(port) {
+                error!("{}", error);
+            }
+        }
+        if let Some(port) = args.remote {
+            if let Err(error) = debugger.serve_remote(port) {
+                error!("{}", error);
+            }
+        }
+        if let Some(length) = args.trace {
+            debugger.start_trace(length);
+        }
+        if let Some(path) = &args.trace_file {
+            if let Err(error) = debugger.write_trace(path) {
+                error!("{}", error);
+            }
+        }
+        for breakpoint in &args.breakpoints {
+            if let Err(error) = debugger.add_breakpoint(breakpoint) {
+                error!("{}", error);
+            }
+        }
+        for watch in &args.watches {
+            if let Err(error) = debugger.add_watch(watch) {
+                error!("{}", error);
+            }
+        }
+        for graph in &args.graphs {
+            if let Err(error) = debugger.add_graph(graph) {
+                error!("{}", error);
+            }
+        }
+    }
+    let mut debug_view: Option<DebugView> = None;
+
+    // Script run alongside the ROM, its failed assertions make the emulator exit with an error
+    let script_path = args.script.clone().or_else(|| Some(args.rom.with_extension("c8s")).filter(|path| path.exists()));
+    let mut script = script_path.map(|path| Script::load(&path, &symbols).unwrap_or_else(fatal));
+    let mut failed = false;
+
+    // Cheats from the file next to the ROM and the console, toggled in a menu replacing the game
+    let cheats_path = args.cheats.clone().or_else(|| Some(args.rom.with_extension("cht")).filter(|path| path.exists()));
+    let mut cheats = cheats_path.map_or_else(Cheats::default, |path| Cheats::load(&path, &symbols).unwrap_or_else(fatal));
+    let mut cheat_menu: Option<CheatMenu> = None;
+    let mut cheat_buffer: Vec<u32> = vec![0; cheats::WIDTH * cheats::HEIGHT];
+
+    // Emulation loop
+    let mut frame_number: u64 = 0;
+    'emulation: while window.is_open() {
+        frame_number += 1;
+        let _span = debug_span!("frame", number = frame_number).entered();
+        let _frame = profiler.as_ref().map(|profiler| profiler.span("frame", Thread::Emulation));
+
+        // Reload the program when its files are saved
+        if let Some((watcher, keep)) = &mut dev {
+            if watcher.changed() {
+                if recording.is_some() {
+                    warn!("Not reloading while recording");
+                } else {
+                    match load_program(&args.rom) {
+                        Ok(reloaded) => {
+                            program = reloaded.bytes;
+                            rom_hash = replay::rom_hash(&program);
+                            if let Some(debugger) = &mut debugger {
+                                debugger.set_program_length(program.len());
+                            }
+                            watcher.watch(&reloaded.files);
+                            let mut reloaded_chip8 = power_on(builder().seed(seed), &program);
+                            dev::keep_memory(&chip8, &mut reloaded_chip8, keep);
+                            chip8 = reloaded_chip8;
+                            chip8.force_redraw();
+                            crash_log.forget_input();
+                            info!("Reloaded {}", args.rom.display());
+                        }
+                        Err(error) => error!("{}", error),
+                    }
+                }
+            }
+        }
+
+        let pressed = window.get_keys_pressed(KeyRepeat::No).unwrap_or_default();
+        for action in hotkeys.triggered(&pressed) {
+            // Keys typed in the console are input, except the one closing it
+            if console.is_open() && action != Action::Console {
+                continue;
+            }
+            match action {
+                Action::Quit => break 'emulation,
+                Action::Pause => {
+                    paused = !paused;
+                    info!("{}", if paused { "Paused" } else { "Resumed" });
+                }
+                // Anything that changes the machine state behind the replay's back would desync it
+                Action::Reset | Action::LoadState if recording.is_some() || playback.is_some() => {
+                    warn!("Not available while recording or playing a replay");
+                }
+                Action::Reset => {
+                    chip8 = power_on(builder().seed(seed), &program);
+                    crash_log = CrashLog::new(rom_hash, seed, variant, machine.clone());
+                    debug!("Reset");
+                }
+                Action::SaveState => {
+                    savestate = Some(chip8.clone());
+                    telemetry.state_saved();
+                    info!("State saved");
+                }
+                Action::LoadState => match &savestate {
+                    Some(state) => {
+                        chip8 = state.clone();
+                        chip8.force_redraw();
+                        crash_log.forget_input();
+                        telemetry.state_loaded();
+                        debug!("State loaded");
+                    }
+                    None => warn!("No state saved yet"),
+                },
+                Action::Screenshot => {
+                    let path = timestamped_path(&args.rom, "png");
+                    match screenshot::save(&frame, WIDTH, HEIGHT, &path) {
+                        Ok(()) => info!("Saved screenshot to {}", path.display()),
+                        Err(error) => error!("{}", error),
+                    }
+                }
+                Action::SpeedUp | Action::SpeedDown => {
+                    speed = if action == Action::SpeedUp { (speed * 2).min(MAX_SPEED) } else { (speed / 2).max(1) };
+                    info!("Speed: {}x", speed);
+                }
+                Action::Record if playback.is_none() => match recording.take() {
+                    Some(replay) => save_replay(&replay, &timestamped_path(&args.rom, "c8r")),
+                    None => {
+                        // Replays always start from power-on
+                        seed = rand::random();
+                        chip8 = power_on(builder().seed(seed), &program);
+                        recording = Some(Replay::new(rom_hash, seed, variant, machine.clone()));
+                        info!("Recording started");
+                    }
+                },
+                Action::Record => {}
+                Action::RecordMacro => macros.toggle_recording(),
+                Action::InputDisplay => {
+                    input_display.toggle();
+                    chip8.force_redraw();
+                }
+                Action::PerfHud => {
+                    perf_hud.toggle();
+                    chip8.force_redraw();
+                }
+                Action::DebugView => {
+                    debug_view = match debug_view {
+                        Some(_) => None,
+                        None => Some(DebugView::new()),
+                    };
+                }
+                Action::Remap => {
+                    remap_screen = match remap_screen {
+                        Some(_) => {
+                            window.update_with_buffer(&frame, WIDTH, HEIGHT).unwrap();
+                            None
+                        }
+                        None => Some(RemapScreen::new()),
+                    };
+                }
+                Action::Console => {
+                    console.toggle();
+                    chip8.force_redraw();
+                }
+                Action::Cheats => {
+                    cheat_menu = match cheat_menu {
+                        Some(_) => {
+                            window.update_with_buffer(&frame, WIDTH, HEIGHT).unwrap();
+                            None
+                        }
+                        None => Some(CheatMenu::new()),
+                    };
+                }
+            }
+        }
+
+        if let Some(debugger) = &mut debugger {
+            debugger.update_watches(&chip8);
+        }
+        if let Some(view) = &mut debug_view {
+            let stopped = paused || debugger.as_ref().is_some_and(Debugger::is_paused);
+            // Notes added in the debugger show up in the window right away
+            let view_symbols = debugger.as_ref().map_or(&symbols, Debugger::symbols);
+            if view.update(&chip8, view_symbols, debugger.as_ref(), stopped) {
+                // Clicking an instruction sets a breakpoint, starting the debugger if needed
+                if let Some(address) = view.take_clicked() {
+                    debugger.get_or_insert_with(new_debugger).toggle_breakpoint(address);
+                }
+                if let Some((address, value)) = view.take_edit() {
+                    chip8.write_memory(address, &[value]);
+                    chip8.force_redraw();
+                }
+            } else {
+                debug_view = None;
+            }
+        }
+
+        if let Some(remap_screen) = &mut remap_screen {
+            remap_screen.handle_keys(&pressed, &hotkeys, &mut keymap, &second_keymap, &mut config, &args.input.config);
+            remap_screen.draw(&mut remap_buffer, WIDTH);
+            window.update_with_buffer(&remap_buffer, WIDTH, HEIGHT).unwrap();
+            continue;
+        }
+
+        if let Some(cheat_menu) = &mut cheat_menu {
+            cheat_menu.handle_keys(&pressed, &hotkeys, &mut cheats);
+            cheat_menu.draw(&cheats, &mut cheat_buffer);
+            window.update_with_buffer(&cheat_buffer, cheats::WIDTH, cheats::HEIGHT).unwrap();
+            continue;
+        }
+
+        if !console.is_open() {
+            macros.handle_keys(&pressed, &hotkeys, &[&keymap, &second_keymap], &mut config, &args.input.config);
+        }
+
+        if let Some(debugger) = &mut debugger {
+            if !debugger.poll(&mut chip8) {
+                break 'emulation;
+            }
+        }
+
+        if console.is_open() {
+            if let Some(line) = console.handle_keys(&pressed, &symbols) {
+                let output = match line.trim() {
+                    "telemetry" => Ok(telemetry.summary().describe()),
+                    _ => console::execute(&line, &mut chip8, &mut debugger, &mut cheats, &mut search, &symbols, &mut speed),
+                };
+                match output {
+                    Ok(output) => console.print(&output),
+                    Err(error) => console.print(&error),
+                }
+                if debugger.as_mut().is_some_and(|debugger| !debugger.poll(&mut chip8)) {
+                    break 'emulation;
+                }
+            }
+            if chip8.draw_to_buffer(&mut buffer) {
+                frame.copy_from_slice(&buffer);
+                #[cfg(feature = "plugins")]
+                for plugin in &plugins {
+                    plugin.filter(&mut frame, WIDTH, HEIGHT);
+                }
+            }
+            if console.is_open() {
+                console.draw(&frame, &mut console_buffer);
+                window.update_with_buffer(&console_buffer, console::WIDTH, console::HEIGHT).unwrap();
+            } else {
+                window.update_with_buffer(&frame, WIDTH, HEIGHT).unwrap();
+            }
+            continue;
+        }
+
+        if paused || debugger.as_ref().is_some_and(Debugger::is_paused) {
+            // Instructions stepped in the debugger still show up
+            if chip8.draw_to_buffer(&mut buffer) {
+                frame.copy_from_slice(&buffer);
+                #[cfg(feature = "plugins")]
+                for plugin in &plugins {
+                    plugin.filter(&mut frame, WIDTH, HEIGHT);
+                }
+                window.update_with_buffer(&frame, WIDTH, HEIGHT).unwrap();
+            } else {
+                window.update();
+            }
+            continue;
+        }
+
+        turbo.handle_toggles(&pressed);
+        let mut overlay_changed = false;
+        if let Some(debugger) = &mut debugger {
+            debugger.start_frame(&chip8);
+        }
+        if let Some(script) = &mut script {
+            let hud = script.hud();
+            match script.run_frame(&mut chip8) {
+                Ok(true) => overlay_changed |= script.hud() != hud,
+                Ok(false) => break 'emulation,
+                Err(error) => {
+                    error!("{}", error);
+                    failed = true;
+                    break 'emulation;
+                }
+            }
+        }
+        cheats.apply(&mut chip8);
+        let emulation = profiler.as_ref().map(|profiler| profiler.span("emulation", Thread::Emulation));
+        let emulation_start = Instant::now();
+        for _ in 0..pace.frame(speed) {
+            if let Some(debugger) = &mut debugger {
+                if debugger.should_break(&chip8) {
+                    break;
+                }
+                debugger.record(&chip8);
+            }
+            crash_log.record(&chip8);
+
+            // Emulate one cycle
+            telemetry.instruction(&chip8);
+            if let Err(error) = chip8.try_emulate_cycle() {
+                let reason = error.to_string();
+                // Running into the failure again pauses there instead
+                if let Some((dump, _)) = &crash_replay {
+                    if dump.matches(&reason, &chip8) {
+                        info!("Reproduced the failure at instruction {}: {}", chip8.cycles(), reason);
+                    } else {
+                        warn!("Failed differently from the crash dump at instruction {}: {}", chip8.cycles(), reason);
+                    }
+                    paused = true;
+                    break;
+                }
+                let path = timestamped_path(&args.rom, "crash.json");
+                match crash_log.dump(&reason, &args.rom, &chip8, &symbols).save(&path) {
+                    Ok(()) => error!("{}, crash dump written to {}", reason, path.display()),
+                    Err(error) => error!("{}\n{}", reason, error),
+                }
+                failed = true;
+                break 'emulation;
+            }
+
+            // Schedule the whole tone as soon as the sound timer is set
+            if let Some(ticks) = chip8.take_sound_request() {
+                debug!("Tone of {} ticks at cycle {}", ticks, chip8.cycles());
+                beeper.beep(ticks);
+                #[cfg(feature = "gamepad")]
+                if let Some(gamepads) = &mut gamepads {
+                    gamepads.rumble(ticks);
+                }
+            }
+
+            // Store key press state (Press and Release)
+            #[cfg(not(feature = "global-input"))]
+            let pressed_keys = window.get_keys().unwrap_or_default();
+            #[cfg(feature = "global-input")]
+            let pressed_keys = global_keyboard.get_keys();
+            chip8.set_keys(keymap.keypad_state(&pressed_keys));
+            chip8.set_second_keypad(second_keymap.keypad_state(&pressed_keys));
+            let (touched_keys, changed) = touch_keypad.update(&window, !pressed_keys.is_empty());
+            overlay_changed |= changed;
+            for (key, pressed) in touched_keys.iter().enumerate() {
+                if *pressed {
+                    chip8.set_key(key, true);
+                }
+            }
+            #[cfg(feature = "midi")]
+            if let Some(midi_keypad) = &midi_keypad {
+                for (key, pressed) in midi_keypad.pressed_keys().iter().enumerate() {
+                    if *pressed {
+                        chip8.set_key(key, true);
+                    }
+                }
+            }
+            #[cfg(feature = "gamepad")]
+            if let Some(gamepads) = &mut gamepads {
+                for (key, pressed) in gamepads.pressed_keys().iter().enumerate() {
+                    if *pressed {
+                        chip8.set_key(key, true);
+                    }
+                }
+            }
+            #[cfg(feature = "hid")]
+            if let Some(hid_keypads) = &mut hid_keypads {
+                for (key, pressed) in hid_keypads.pressed_keys().iter().enumerate() {
+                    if *pressed {
+                        chip8.set_key(key, true);
+                    }
+                }
+            }
+            let mut keypad = chip8.keypad();
+            macros.apply(&mut keypad);
+            turbo.apply(&mut keypad);
+            if let Some(script) = &script {
+                script.apply(&mut keypad);
+            }
+            chip8.set_keys(keypad);
+            if let Some(replay) = &mut recording {
+                let input = FrameInput::new(keypad, second_keymap.keypad_state(&pressed_keys));
+                replay.push(input, || chip8.state_checksum());
+            }
+            if let Some(active) = &mut playback {
+                match active.frames.get(active.position as usize) {
+                    Some(input) => {
+                        chip8.set_keys(input.keypad());
+                        chip8.set_second_keypad(input.second_keypad());
+                        active.position += 1;
+                        if !active.diverged {
+                            if let Verification::Diverged { last_good_frame } =
+                                active.replay.verify(active.position, || chip8.state_checksum())
+                            {
+                                warn!(
+                                    "Desync at frame {}: state no longer matches the recording (last matched at frame {})",
+                                    active.position, last_good_frame
+                                );
+                                active.diverged = true;
+                            }
+                        }
+                    }
+                    None => {
+                        if !active.diverged {
+                            info!("Replay finished, {} frames matched the recording", active.position);
+                        }
+                        playback = None;
+                    }
+                }
+            }
+            crash_log.push_input(&chip8);
+            overlay_changed |= input_display.update(chip8.keypad());
+            turbo.step(pace.per_second(speed));
+        }
+        drop(emulation);
+        let emulation_time = emulation_start.elapsed();
+
+        trace!("Frame ran to cycle {}", chip8.cycles());
+
+        // Draw screen if necessary
+        let render = profiler.as_ref().map(|profiler| profiler.span("render", Thread::Emulation));
+        let render_start = Instant::now();
+        if chip8.draw_to_buffer(&mut buffer) || overlay_changed || perf_hud.is_visible() {
+            frame.copy_from_slice(&buffer);
+            #[cfg(feature = "plugins")]
+            for plugin in &plugins {
+                plugin.filter(&mut frame, WIDTH, HEIGHT);
+            }
+            if touch_keypad.is_visible() {
+                touch_keypad.draw(&mut frame, WIDTH);
+            }
+            if input_display.is_visible() {
+                input_display.draw(&mut frame, WIDTH, HEIGHT);
+            }
+            if let Some(script) = &script {
+                script.draw(&mut frame, WIDTH);
+            }
+            if perf_hud.is_visible() {
+                perf_hud.draw(&mut frame, WIDTH);
+            }
+            window.update_with_buffer(&frame, WIDTH, HEIGHT).unwrap();
+            telemetry.frame();
+        } else {
+            // Nothing new to show, but input still has to be read and the frame rate kept
+            window.update();
+        }
+        drop(render);
+        perf_hud.record(FrameTime { emulation: emulation_time, render: render_start.elapsed(), audio: beeper.take_callback_time() });
+    }
+
+    if let Some(script) = &mut script {
+        if let Err(error) = script.finish(&mut chip8) {
+            error!("{}", error);
+            failed = true;
+        }
+    }
+
+    if let Some(debugger) = &mut debugger {
+        debugger.finish(&chip8);
+        if args.hotspots {
+            println!("{}", debugger.hotspots(HOTSPOTS));
+        }
+        if let Some(path) = &args.call_graph {
+            match debugger.save_call_graph(path) {
+                Ok(()) => info!("Saved the call graph to {}", path.display()),
+                Err(error) => error!("{}", error),
+            }
+        }
+        if let Some(path) = &args.coverage {
+            match debugger.save_coverage(path) {
+                Ok(()) => info!("Saved the coverage map to {}: {}", path.display(), debugger.coverage()),
+                Err(error) => error!("{}", error),
+            }
+        }
+    }
+    if let Some(replay) = recording {
+        save_replay(&replay, &record_to.map_or_else(|| timestamped_path(&args.rom, "c8r"), Path::to_path_buf));
+    }
+    if let Some(active) = playback {
+        info!("Replay stopped at frame {} of {}", active.position, active.frames.len());
+    }
+    if let (Some(profiler), Some(path)) = (&profiler, &args.profile) {
+        match profiler.save(path) {
+            Ok(()) => info!("Saved profile to {}", path.display()),
+            Err(error) => error!("{}", error),
+        }
+    }
+    let summary = telemetry.summary();
+    if args.telemetry {
+        println!("{}", summary.describe());
+    }
+    if let Some(path) = &args.telemetry_file {
+        match summary.save(path) {
+            Ok(()) => info!("Saved telemetry to {}", path.display()),
+            Err(error) => error!("{}", error),
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+/// Digits given on the command line, or else the font style of an Octocart
+pub(crate) fn machine_font(args: &RunArgs, options: &octocart::Options) -> Option<Font> {
+    args.font.clone().or_else(|| {
+        let name = options.font.as_deref()?;
+        let font = Font::named(name);
+        if font.is_none() {
+            warn!("The cartridge's {} font isn't known, using the usual one", name);
+        }
+        font
+    })
+}
+
+/// The machine settings the command line and the ROM's own settings ask for, which replays record
+pub(crate) fn machine(args: &RunArgs, options: &octocart::Options, font: Option<&Font>) -> Machine {
+    Machine {
+        memory_bounds: args.memory_bounds,
+        write_protection: args.write_protection,
+        index_width: args.index_bits,
+        stack_depth: args.stack_depth,
+        memory_size: args.memory_size,
+        font: font.cloned(),
+        font_address: args.font_address,
+        tickrate: options.tickrate.unwrap_or(1),
+        quirks: options.quirks.unwrap_or_default(),
+    }
+}
+
+/// How many instructions each window update runs, spreading the rate asked for over the updates
+pub(crate) struct Pace {
+    tickrate: Option<u32>,
+    /// Instructions a second counted towards the next update's, less than a whole one
+    owed: u32,
+}
+
+impl Pace {
+    pub fn new(tickrate: Option<u32>) -> Self {
+        Pace { tickrate, owed: 0 }
+    }
+
+    /// Instructions a second at `speed`: the tick rate 60 times a second, or one an update
+    /// without one
+    pub fn per_second(&self, speed: u32) -> u32 {
+        self.tickrate.map_or(FRAME_RATE * speed, |tickrate| tickrate.saturating_mul(60).saturating_mul(speed))
+    }
+
+    /// Instructions to run this update
+    pub fn frame(&mut self, speed: u32) -> u32 {
+        let owed = self.owed.saturating_add(self.per_second(speed));
+        self.owed = owed % FRAME_RATE;
+        owed / FRAME_RATE
+    }
+}
+
+/// A machine set up the way the command line and the ROM's own settings ask for
+pub(crate) fn configure(args: &RunArgs, options: &octocart::Options, variant: Variant, font: Option<&Font>) -> Chip8Builder {
+    configure_machine(args, options, variant, &machine(args, options, font))
+}
+
+/// A machine set up with the given settings, and the devices and colors of the command line and
+/// the ROM
+fn configure_machine(args: &RunArgs, options: &octocart::Options, variant: Variant, machine: &Machine) -> Chip8Builder {
+    let mut builder = machine.configure(Chip8::builder().variant(variant));
+    if let Some(address) = args.serial {
+        builder = builder.peripheral(Box::new(SerialOut::new(address)));
+    }
+    if let Some(color) = options.background {
+        builder = builder.background(color);
+    }
+    if let Some(color) = options.foreground {
+        builder = builder.foreground(color);
+    }
+    builder
+}
+
+pub(crate) fn power_on(builder: Chip8Builder, program: &[u8]) -> Chip8 {
+    let mut chip8 = builder.build();
+    chip8.load_program(program);
+    chip8
+}
+
+/// File name for replays and screenshots started with a hotkey, next to the ROM
+pub(crate) fn timestamped_path(rom: &Path, extension: &str) -> PathBuf {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let stem = rom.file_stem().map_or_else(|| String::from("replay"), |s| s.to_string_lossy().into_owned());
+    rom.with_file_name(format!("{}-{}.{}", stem, timestamp, extension))
+}
+
+fn save_replay(replay: &Replay, path: &Path) {
+    match replay.save(path) {
+        Ok(()) => info!("Recorded {} frames to {}", replay.frame_count(), path.display()),
+        Err(error) => error!("{}", error),
+    }
+}
+
+/// A ROM and the settings it came with
+pub struct Program {
+    pub bytes: Vec<u8>,
+    pub options: octocart::Options,
+    /// Files it was built from, the ROM itself and any source files it includes
+    pub files: Vec<PathBuf>,
+    /// Labels and source lines, empty unless it was assembled
+    pub symbols: Symbols,
+}
+
+/// Reads a ROM, assembling it first if it is Octo source (.8o) or an Octocart (.gif)
+pub fn load_program(path: &Path) -> Result<Program, assembler::Error> {
+    let read_error = |error: &dyn std::fmt::Display| assembler::Error {
+        file: Some(path.to_path_buf()),
+        line: 0,
+        message: format!("Could not load program!\n{}", error),
+    };
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+    let (source, options) = match extension {
+        "8o" => (fs::read_to_string(path).map_err(|e| read_error(&e))?, octocart::Options::default()),
+        "gif" => {
+            let cartridge = octocart::load(path).map_err(|e| read_error(&e))?;
+            (cartridge.program, cartridge.options)
+        }
+        _ => {
+            let bytes = fs::read(path).map_err(|e| read_error(&e))?;
+            let files = vec![path.to_path_buf()];
+            return Ok(Program { bytes, options: octocart::Options::default(), files, symbols: Symbols::default() });
+        }
+    };
+    let (bytes, symbols) = assembler::assemble_with_symbols(&source, Some(path))?;
+    let mut files = vec![path.to_path_buf()];
+    for line in &symbols.lines {
+        let file = PathBuf::from(&line.file);
+        if !file.as_os_str().is_empty() && !files.contains(&file) {
+            files.push(file);
+        }
+    }
+    Ok(Program { bytes, options, files, symbols })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::run::{Pace, FRAME_RATE};
+
+    /// Without a tick rate every update runs as many instructions as the speed, with one the
+    /// updates share out the tick rate 60 times a second
+    #[test]
+    fn test_pace() {
+        let mut pace = Pace::new(None);
+        assert_eq!((pace.frame(1), pace.frame(4)), (1, 4));
+
+        let mut pace = Pace::new(Some(15));
+        let frames: Vec<u32> = (0..FRAME_RATE).map(|_| pace.frame(1)).collect();
+        assert_eq!(frames.iter().sum::<u32>(), 15 * 60);
+        assert!(frames.iter().all(|instructions| (3..=4).contains(instructions)));
+        assert_eq!(pace.per_second(2), 15 * 60 * 2);
+    }
+}
@@ -0,0 +1,218 @@
+//! Branching savestates.
+//!
+//! Every save point is made from the one that was last saved or loaded, so alternate attempts at
+//! a section become sibling branches instead of overwriting each other. The panel lists the tree
+//! depth first, one save point per row drawn as a marker followed by its number in the built-in
+//! font; the name of the selected save point is shown in the window title.
+//!
+//! Panel controls: Up/Down select, Enter loads the selected save point, N starts typing the name
+//! of a new save point (Enter saves it, Escape cancels).
+
+use crate::chip8::CHIP8_FONTSET;
+use crate::keymap::key_name;
+use minifb::Key;
+
+const ROW_HEIGHT: usize = 7;
+const INDENT: usize = 4;
+
+const CURRENT: u32 = 0x0FFF;
+const OTHER: u32 = 0x808080;
+const SELECTED: u32 = 0x303030;
+
+pub struct Node<T> {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub state: T,
+}
+
+pub struct SaveTree<T> {
+    nodes: Vec<Node<T>>,
+    // Save point new ones branch from
+    current: Option<usize>,
+}
+
+impl<T> SaveTree<T> {
+    pub fn new() -> Self {
+        SaveTree { nodes: Vec::new(), current: None }
+    }
+
+    /// Adds a save point under the current one and makes it current
+    pub fn save(&mut self, name: String, state: T) -> usize {
+        self.nodes.push(Node { name, parent: self.current, state });
+        self.current = Some(self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    /// Makes a save point current and returns its state
+    pub fn load(&mut self, id: usize) -> &T {
+        self.current = Some(id);
+        &self.nodes[id].state
+    }
+
+    pub fn get(&self, id: usize) -> &Node<T> {
+        &self.nodes[id]
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    /// Save points in depth-first order with their depth, as listed in the panel
+    pub fn rows(&self) -> Vec<(usize, usize)> {
+        let mut rows = Vec::with_capacity(self.nodes.len());
+        self.push_children(None, 0, &mut rows);
+        rows
+    }
+
+    fn push_children(&self, parent: Option<usize>, depth: usize, rows: &mut Vec<(usize, usize)>) {
+        for (id, node) in self.nodes.iter().enumerate() {
+            if node.parent == parent {
+                rows.push((id, depth));
+                self.push_children(Some(id), depth + 1, rows);
+            }
+        }
+    }
+}
+
+/// What the user asked for in the panel
+pub enum PanelAction {
+    Save(String),
+    Load(usize),
+}
+
+pub struct TreePanel {
+    // Index into the tree's rows
+    selected: usize,
+    // Name typed so far for a new save point
+    naming: Option<String>,
+}
+
+impl TreePanel {
+    pub fn new() -> Self {
+        TreePanel { selected: 0, naming: None }
+    }
+
+    /// Handles the keys pressed since the last frame
+    pub fn handle_keys<T>(&mut self, pressed: &[Key], tree: &SaveTree<T>) -> Option<PanelAction> {
+        let rows = tree.rows();
+        for key in pressed {
+            if let Some(name) = &mut self.naming {
+                match key {
+                    Key::Enter => {
+                        let name = self.naming.take().unwrap();
+                        self.selected = rows.len();
+                        return Some(PanelAction::Save(name));
+                    }
+                    Key::Escape => self.naming = None,
+                    Key::Backspace => {
+                        name.pop();
+                    }
+                    Key::Space => name.push(' '),
+                    Key::Minus => name.push('-'),
+                    _ => {
+                        // Letters are named after themselves, digits `Key0` to `Key9`
+                        let key_name = key_name(*key);
+                        let character = key_name.trim_start_matches("Key");
+                        if character.len() == 1 {
+                            name.push_str(&character.to_ascii_lowercase());
+                        }
+                    }
+                }
+                continue;
+            }
+
+            match key {
+                Key::Up => self.selected = self.selected.saturating_sub(1),
+                Key::Down => self.selected = (self.selected + 1).min(rows.len().saturating_sub(1)),
+                Key::Enter => {
+                    if let Some((id, _)) = rows.get(self.selected) {
+                        return Some(PanelAction::Load(*id));
+                    }
+                }
+                Key::N => self.naming = Some(String::new()),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Window title describing the save point being named or the selected one
+    pub fn title<T>(&self, tree: &SaveTree<T>, describe: impl Fn(&T) -> String) -> String {
+        if let Some(name) = &self.naming {
+            return format!("New save point: {}_", name);
+        }
+        match tree.rows().get(self.selected) {
+            Some((id, _)) => {
+                let node = tree.get(*id);
+                format!("{:X}: {} ({})", id, node.name, describe(&node.state))
+            }
+            None => String::from("Save points - N to save"),
+        }
+    }
+
+    /// Draws the tree, scrolled to keep the selected row visible
+    pub fn draw<T>(&self, buffer: &mut [u32], width: usize, tree: &SaveTree<T>) {
+        for pixel in buffer.iter_mut() {
+            *pixel = 0;
+        }
+        let visible = buffer.len() / width / ROW_HEIGHT;
+        let first_row = (self.selected + 1).saturating_sub(visible);
+
+        for (row, (id, depth)) in tree.rows().iter().enumerate().skip(first_row).take(visible) {
+            let top = (row - first_row) * ROW_HEIGHT;
+            if row == self.selected {
+                for pixel in &mut buffer[top * width..(top + ROW_HEIGHT) * width] {
+                    *pixel = SELECTED;
+                }
+            }
+
+            let color = if tree.current() == Some(*id) { CURRENT } else { OTHER };
+            let left = 1 + depth * INDENT;
+            for y in 0..3 {
+                for x in 0..3 {
+                    put(buffer, width, left + x, top + 2 + y, color);
+                }
+            }
+
+            let digits = format!("{:X}", id);
+            for (position, digit) in digits.chars().enumerate() {
+                let glyph = digit.to_digit(16).unwrap() as usize * 5;
+                for (y, bits) in CHIP8_FONTSET[glyph..glyph + 5].iter().enumerate() {
+                    for x in 0..4 {
+                        if bits & (0x80 >> x) != 0 {
+                            put(buffer, width, left + 5 + position * 5 + x, top + 1 + y, color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Sets a pixel, ignoring anything past the right edge
+fn put(buffer: &mut [u32], width: usize, x: usize, y: usize, color: u32) {
+    if x < width {
+        buffer[y * width + x] = color;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::savetree::SaveTree;
+
+    /// Saving after loading an earlier save point starts a new branch
+    #[test]
+    fn test_branches() {
+        let mut tree = SaveTree::new();
+        let root = tree.save(String::from("start"), 0);
+        let first = tree.save(String::from("first try"), 1);
+        tree.save(String::from("first try, later"), 2);
+        assert_eq!(*tree.load(root), 0);
+        let second = tree.save(String::from("second try"), 3);
+
+        assert_eq!(tree.get(second).parent, Some(root));
+        assert_eq!(tree.get(first).parent, Some(root));
+        assert_eq!(tree.current(), Some(second));
+        assert_eq!(tree.rows(), vec![(0, 0), (1, 1), (2, 2), (3, 1)]);
+    }
+}
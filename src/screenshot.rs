@@ -0,0 +1,19 @@
+//! Screenshots of the display as PNG files.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Writes a buffer of 0RGB pixels as an RGB PNG image
+pub fn save(buffer: &[u32], width: usize, height: usize, path: &Path) -> Result<(), String> {
+    let error = |e: &dyn std::fmt::Display| format!("Could not write screenshot {}\n{}", path.display(), e);
+
+    let file = File::create(path).map_err(|e| error(&e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let pixels: Vec<u8> = buffer.iter().flat_map(|pixel| pixel.to_be_bytes()[1..].to_vec()).collect();
+    let mut writer = encoder.write_header().map_err(|e| error(&e))?;
+    writer.write_image_data(&pixels).map_err(|e| error(&e))
+}
@@ -0,0 +1,337 @@
+//! Scripts run alongside a ROM, for cheats, trainers, HUDs and automated tests without touching
+//! the emulator. They're written in [Rhai](https://rhai.rs). A script next to the ROM with the
+//! `.c8s` extension is loaded automatically, or one is given with `--script`:
+//!
+//! ```text
+//! // Infinite lives, and a test that the score moves within 10 seconds
+//! let best = 0;
+//!
+//! on("frame", || {
+//!     if mem[lives] < 3 {
+//!         mem[lives] = 3;
+//!     }
+//!     if mem[score] > best {
+//!         best = mem[score];
+//!     }
+//!     hud(`best ${best}`);
+//!     press(6);
+//!     assert(frame() < 600 || best > 0, "the score never moved");
+//! });
+//! ```
+//!
+//! The script itself runs once when the emulator starts, with the machine powered on. Functions
+//! given to `on` run before every frame and when the emulator closes, and keep the variables of
+//! the script they use. The labels of an assembled ROM are constants holding their addresses.
+//!
+//! | API                          |                                                                |
+//! |------------------------------|----------------------------------------------------------------|
+//! | `mem[address]`               | a byte of memory, which can be set                             |
+//! | `v[x]`                       | register `VX`, which can be set                                |
+//! | `cpu.i`, `cpu.dt`, `cpu.st`  | I and the timers, which can be set                             |
+//! | `on("frame" or "exit", fn)`  | run a function before every frame, or when the emulator closes |
+//! | `frame()`                    | the number of frames since the script started                  |
+//! | `press(key)`                 | hold a keypad key down for this frame                          |
+//! | `print(text)`                | log text                                                       |
+//! | `hud(text)`                  | show a line of text over the game this frame                   |
+//! | `assert(condition, message)` | stop the emulator with an error when the condition is false    |
+//! | `quit()`                     | close the emulator                                             |
+
+use crate::chip8::Chip8;
+use crate::symbols::Symbols;
+use crate::text::{self, LINE_HEIGHT};
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, Scope, AST};
+use std::cell::RefCell;
+use std::fs;
+use std::mem;
+use std::path::Path;
+use std::rc::Rc;
+use tracing::info;
+
+const HUD_COLOR: u32 = 0xFFFF00;
+
+/// What the script's functions reach. The machine is swapped in while the script runs
+struct Context {
+    chip8: Chip8,
+    keys: [bool; 16],
+    hud: Vec<String>,
+    frame: i64,
+    quit: bool,
+    on_frame: Vec<FnPtr>,
+    on_exit: Vec<FnPtr>,
+}
+
+type Shared = Rc<RefCell<Context>>;
+
+/// `mem`, indexed by address
+#[derive(Clone)]
+struct Memory(Shared);
+
+/// `v`, indexed by register
+#[derive(Clone)]
+struct Registers(Shared);
+
+/// `cpu`, with I and the timers as properties
+#[derive(Clone)]
+struct Cpu(Shared);
+
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    context: Shared,
+    started: bool,
+}
+
+impl Script {
+    pub fn load(path: &Path, symbols: &Symbols) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        Self::parse(&text, symbols).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str, symbols: &Symbols) -> Result<Self, String> {
+        let context = Rc::new(RefCell::new(Context {
+            chip8: Chip8::default(),
+            keys: [false; 16],
+            hud: Vec::new(),
+            frame: 0,
+            quit: false,
+            on_frame: Vec::new(),
+            on_exit: Vec::new(),
+        }));
+        let engine = engine(&context);
+        let ast = engine.compile(text).map_err(|e| e.to_string())?;
+
+        let mut scope = Scope::new();
+        for (label, address) in &symbols.labels {
+            if is_identifier(label) {
+                scope.push_constant(label.as_str(), *address as i64);
+            }
+        }
+        scope.push("mem", Memory(context.clone()));
+        scope.push("v", Registers(context.clone()));
+        scope.push("cpu", Cpu(context.clone()));
+        Ok(Script { engine, ast, scope, context, started: false })
+    }
+
+    /// Runs the script the first time, then the frame functions. Returns false once the script
+    /// quits, and an error when it fails.
+    pub fn run_frame(&mut self, chip8: &mut Chip8) -> Result<bool, String> {
+        {
+            let mut context = self.context.borrow_mut();
+            context.keys = [false; 16];
+            context.hud.clear();
+        }
+        let result = self.with_machine(chip8, |script| {
+            if !script.started {
+                script.started = true;
+                script.engine.run_ast_with_scope(&mut script.scope, &script.ast)?;
+            }
+            let hooks = script.context.borrow().on_frame.clone();
+            hooks.iter().take_while(|_| !script.context.borrow().quit).try_for_each(|hook| script.call(hook))
+        });
+        let mut context = self.context.borrow_mut();
+        context.frame += 1;
+        result.map(|_| !context.quit).map_err(|error| format!("Script failed at frame {}: {}", context.frame - 1, error))
+    }
+
+    /// Runs the exit functions
+    pub fn finish(&mut self, chip8: &mut Chip8) -> Result<(), String> {
+        let hooks = self.context.borrow().on_exit.clone();
+        self.with_machine(chip8, |script| hooks.iter().try_for_each(|hook| script.call(hook))).map_err(|error| format!("Script failed on exit: {}", error))
+    }
+
+    /// Adds the keys the script holds down to the keypad
+    pub fn apply(&self, keypad: &mut [bool; 16]) {
+        for (pressed, held) in keypad.iter_mut().zip(self.context.borrow().keys.iter()) {
+            *pressed |= *held;
+        }
+    }
+
+    pub fn hud(&self) -> Vec<String> {
+        self.context.borrow().hud.clone()
+    }
+
+    /// Draws the HUD lines over a game frame
+    pub fn draw(&self, buffer: &mut [u32], width: usize) {
+        for (row, line) in self.context.borrow().hud.iter().enumerate() {
+            text::draw(buffer, width, (1, 1 + row * LINE_HEIGHT), line, HUD_COLOR);
+        }
+    }
+
+    /// Runs with the machine where the script's functions reach it
+    fn with_machine<T>(&mut self, chip8: &mut Chip8, run: impl FnOnce(&mut Self) -> T) -> T {
+        mem::swap(chip8, &mut self.context.borrow_mut().chip8);
+        let result = run(self);
+        mem::swap(chip8, &mut self.context.borrow_mut().chip8);
+        result
+    }
+
+    /// Calls a function given to `on`
+    fn call(&self, hook: &FnPtr) -> Result<(), Box<EvalAltResult>> {
+        hook.call::<Dynamic>(&self.engine, &self.ast, ()).map(|_| ())
+    }
+}
+
+/// An engine with the API registered, reaching the machine through the context
+fn engine(context: &Shared) -> Engine {
+    let mut engine = Engine::new();
+    engine.on_print(|text| info!("{}", text));
+
+    engine
+        .register_type_with_name::<Memory>("Memory")
+        .register_indexer_get(|memory: &mut Memory, address: i64| {
+            let context = memory.0.borrow();
+            let memory = context.chip8.memory();
+            memory[address.rem_euclid(memory.len() as i64) as usize] as i64
+        })
+        .register_indexer_set(|memory: &mut Memory, address: i64, value: i64| {
+            memory.0.borrow_mut().chip8.write_memory(address.rem_euclid(0x10000) as u16, &[value as u8]);
+        });
+    engine
+        .register_type_with_name::<Registers>("Registers")
+        .register_indexer_get(|registers: &mut Registers, x: i64| -> Result<i64, Box<EvalAltResult>> {
+            Ok(registers.0.borrow().chip8.registers()[register(x)?] as i64)
+        })
+        .register_indexer_set(|registers: &mut Registers, x: i64, value: i64| -> Result<(), Box<EvalAltResult>> {
+            registers.0.borrow_mut().chip8.set_register(register(x)?, value as u8);
+            Ok(())
+        });
+    engine
+        .register_type_with_name::<Cpu>("Cpu")
+        .register_get_set("i", |cpu: &mut Cpu| cpu.0.borrow().chip8.index() as i64, |cpu: &mut Cpu, value: i64| cpu.0.borrow_mut().chip8.set_index(value as u16))
+        .register_get_set(
+            "dt",
+            |cpu: &mut Cpu| cpu.0.borrow().chip8.timers().0 as i64,
+            |cpu: &mut Cpu, value: i64| {
+                let chip8 = &mut cpu.0.borrow_mut().chip8;
+                chip8.set_timers(value as u8, chip8.timers().1);
+            },
+        )
+        .register_get_set(
+            "st",
+            |cpu: &mut Cpu| cpu.0.borrow().chip8.timers().1 as i64,
+            |cpu: &mut Cpu, value: i64| {
+                let chip8 = &mut cpu.0.borrow_mut().chip8;
+                chip8.set_timers(chip8.timers().0, value as u8);
+            },
+        );
+
+    let shared = context.clone();
+    engine.register_fn("on", move |event: &str, hook: FnPtr| -> Result<(), Box<EvalAltResult>> {
+        let mut context = shared.borrow_mut();
+        match event {
+            "frame" => context.on_frame.push(hook),
+            "exit" => context.on_exit.push(hook),
+            _ => return Err(format!("No {} event, only frame and exit", event).into()),
+        }
+        Ok(())
+    });
+    let shared = context.clone();
+    engine.register_fn("frame", move || shared.borrow().frame);
+    let shared = context.clone();
+    engine.register_fn("press", move |key: i64| {
+        if let Some(held) = shared.borrow_mut().keys.get_mut(key as usize) {
+            *held = true;
+        }
+    });
+    let shared = context.clone();
+    engine.register_fn("hud", move |line: &str| shared.borrow_mut().hud.push(line.to_string()));
+    let shared = context.clone();
+    engine.register_fn("quit", move || shared.borrow_mut().quit = true);
+    engine.register_fn("assert", |condition: bool, message: &str| -> Result<(), Box<EvalAltResult>> {
+        match condition {
+            true => Ok(()),
+            false => Err(format!("Assertion failed: {}", message).into()),
+        }
+    });
+    engine
+}
+
+/// A register number, failing the script unless it's 0 to 15
+fn register(x: i64) -> Result<usize, Box<EvalAltResult>> {
+    match x {
+        0..=15 => Ok(x as usize),
+        _ => Err(format!("No register V{}", x).into()),
+    }
+}
+
+/// Whether a label can be used as a constant in a script
+fn is_identifier(label: &str) -> bool {
+    label.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') && label.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Chip8;
+    use crate::script::Script;
+    use crate::symbols::Symbols;
+
+    /// Variables, memory, registers and keys are set each frame until an assertion fails
+    #[test]
+    fn test_script() {
+        let mut symbols = Symbols::default();
+        symbols.labels.insert(String::from("lives"), 0x300);
+        let text = r#"
+            let count = 10;   // counts down
+            v[3] = 0x42;
+            on("frame", || {
+                if mem[lives] < 3 {
+                    mem[lives] = 3;
+                } else {
+                    count -= 1;
+                }
+                press(count % 16);
+                hud(`count ${count} #${frame()}`);
+                assert(count > 7, "count ran out");
+            });
+        "#;
+        let mut script = Script::parse(text, &symbols).unwrap();
+        let mut chip8 = Chip8::new();
+
+        assert_eq!(script.run_frame(&mut chip8), Ok(true));
+        assert_eq!(chip8.registers()[3], 0x42);
+        assert_eq!(chip8.memory()[0x300], 3);
+        let mut keypad = [false; 16];
+        script.apply(&mut keypad);
+        assert!(keypad[10]);
+        assert_eq!(script.hud(), ["count 10 #0"]);
+
+        assert_eq!(script.run_frame(&mut chip8), Ok(true));
+        assert_eq!(script.hud(), ["count 9 #1"]);
+        script.run_frame(&mut chip8).unwrap();
+        let error = script.run_frame(&mut chip8).unwrap_err();
+        assert!(error.starts_with("Script failed at frame 3: "), "{}", error);
+        assert!(error.contains("Assertion failed: count ran out"), "{}", error);
+    }
+
+    /// I and the timers can be set, exit functions run at the end, and quitting stops the frames
+    #[test]
+    fn test_cpu_exit_and_quit() {
+        let text = r#"
+            on("frame", || {
+                cpu.i = cpu.i + 2;
+                cpu.dt = 9;
+                if frame() == 1 { quit(); }
+            });
+            on("exit", || { v[0xF] = cpu.dt; });
+        "#;
+        let mut script = Script::parse(text, &Symbols::default()).unwrap();
+        let mut chip8 = Chip8::new();
+        assert_eq!(script.run_frame(&mut chip8), Ok(true));
+        assert_eq!(script.run_frame(&mut chip8), Ok(false));
+        assert_eq!((chip8.index(), chip8.timers().0), (4, 9));
+        script.finish(&mut chip8).unwrap();
+        assert_eq!(chip8.registers()[0xF], 9);
+    }
+
+    /// Mistakes are found when the script is loaded, or fail it when it runs
+    #[test]
+    fn test_errors() {
+        assert!(Script::parse("on(\"frame\", || {", &Symbols::default()).is_err());
+        let mut script = Script::parse("v[16] = 1;", &Symbols::default()).unwrap();
+        let error = script.run_frame(&mut Chip8::new()).unwrap_err();
+        assert!(error.contains("No register V16"), "{}", error);
+        let mut script = Script::parse("on(\"draw\", || {});", &Symbols::default()).unwrap();
+        assert!(script.run_frame(&mut Chip8::new()).is_err());
+    }
+}
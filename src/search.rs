@@ -0,0 +1,113 @@
+//! Memory search to find where a game keeps its lives or score, like cheat finders do: snapshot
+//! memory, play a little, keep the addresses whose value changed the way the game's did, and
+//! repeat until one is left. Driven by the `search` console command:
+//!
+//! | Command                             |                                                       |
+//! |-------------------------------------|-------------------------------------------------------|
+//! | `search`                            | start over with every address of the program area     |
+//! | `search = value`, `search != value` | keep the addresses holding a value, or not            |
+//! | `search changed`, `unchanged`       | keep the addresses that changed since the last search |
+//! | `search increased`, `decreased`     | keep the addresses that went up, or down              |
+//! | `search list`                       | show the addresses left and their values              |
+//! | `search freeze value`               | freeze the addresses left at a value, see `cheats`    |
+
+use crate::chip8::Chip8;
+use crate::debugger;
+
+/// Addresses listed at most
+const LISTED: usize = 16;
+/// Addresses frozen at most, more means the search isn't done yet
+const FROZEN: usize = 8;
+
+#[derive(Default)]
+pub struct MemorySearch {
+    /// Memory at the last search
+    snapshot: Vec<u8>,
+    candidates: Vec<u16>,
+}
+
+impl MemorySearch {
+    /// Starts over with every address from the start of programs
+    pub fn start(&mut self, chip8: &Chip8) -> String {
+        self.snapshot = chip8.memory().to_vec();
+        self.candidates = (0x200..chip8.memory().len() as u16).collect();
+        format!("{} addresses", self.candidates.len())
+    }
+
+    /// Keeps the addresses passing a filter, comparing with the memory at the last search
+    pub fn filter(&mut self, filter: &[&str], chip8: &Chip8) -> Result<String, String> {
+        if self.snapshot.is_empty() {
+            self.start(chip8);
+        }
+        let value = |text: &str| debugger::parse_address(text).filter(|value| *value <= 0xFF).map(|value| value as u8).ok_or_else(|| format!("Not a byte: {}", text));
+        let keep: Box<dyn Fn(u8, u8) -> bool> = match filter {
+            ["=", text] | ["==", text] => {
+                let value = value(text)?;
+                Box::new(move |_, now| now == value)
+            }
+            ["!=", text] => {
+                let value = value(text)?;
+                Box::new(move |_, now| now != value)
+            }
+            ["changed"] => Box::new(|before, now| now != before),
+            ["unchanged"] => Box::new(|before, now| now == before),
+            ["increased"] => Box::new(|before, now| now > before),
+            ["decreased"] => Box::new(|before, now| now < before),
+            _ => return Err(String::from("search [= value, != value, changed, unchanged, increased, decreased, list, freeze value]")),
+        };
+        let memory = chip8.memory();
+        let snapshot = &self.snapshot;
+        self.candidates.retain(|address| keep(snapshot[*address as usize], memory[*address as usize]));
+        self.snapshot = memory.to_vec();
+        Ok(match self.candidates.len() {
+            0 => String::from("No addresses left, search to start over"),
+            1 => format!("Found {:#05X}", self.candidates[0]),
+            count if count <= LISTED => format!("{} addresses left\n{}", count, self.list(chip8)),
+            count => format!("{} addresses left", count),
+        })
+    }
+
+    /// The addresses left and their values
+    pub fn list(&self, chip8: &Chip8) -> String {
+        let mut lines: Vec<String> =
+            self.candidates.iter().take(LISTED).map(|address| format!("{:#05X} = {}", address, chip8.memory()[*address as usize])).collect();
+        if self.candidates.len() > LISTED {
+            lines.push(format!("and {} more", self.candidates.len() - LISTED));
+        }
+        if lines.is_empty() {
+            return String::from("No addresses left");
+        }
+        lines.join("\n")
+    }
+
+    /// Cheat codes freezing the addresses left at a value
+    pub fn codes(&self, value: &str) -> Result<Vec<String>, String> {
+        match self.candidates.len() {
+            0 => Err(String::from("No addresses left")),
+            count if count > FROZEN => Err(format!("{} addresses left, narrow the search down first", count)),
+            _ => Ok(self.candidates.iter().map(|address| format!("{:#05X}:{}", address, value)).collect()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Chip8;
+    use crate::search::MemorySearch;
+
+    /// Filters narrow the search down to the one address that went from 3 to 2
+    #[test]
+    fn test_search() {
+        let mut chip8 = Chip8::new();
+        let mut search = MemorySearch::default();
+        chip8.write_memory(0x300, &[3, 3]);
+        assert_eq!(search.start(&chip8), "3584 addresses");
+        assert_eq!(search.filter(&["=", "3"], &chip8), Ok(String::from("2 addresses left\n0x300 = 3\n0x301 = 3")));
+
+        chip8.write_memory(0x300, &[2, 4]);
+        chip8.write_memory(0x400, &[1]);
+        assert_eq!(search.filter(&["decreased"], &chip8), Ok(String::from("Found 0x300")));
+        assert_eq!(search.codes("3"), Ok(vec![String::from("0x300:3")]));
+        assert!(search.filter(&["bigger"], &chip8).is_err());
+    }
+}
@@ -0,0 +1,38 @@
+//! A serial port mapped into memory with `--serial ADDRESS`: bytes a ROM stores at the address
+//! with FX33 or FX55 are printed to standard output as text, for printf debugging. Reading the
+//! address gives 0.
+
+use crate::chip8::Peripheral;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+#[derive(Clone)]
+pub struct SerialOut {
+    address: u16,
+}
+
+impl SerialOut {
+    pub fn new(address: u16) -> Self {
+        SerialOut { address }
+    }
+}
+
+impl Peripheral for SerialOut {
+    fn range(&self) -> RangeInclusive<u16> {
+        self.address..=self.address
+    }
+
+    fn read(&mut self, _address: u16) -> u8 {
+        0
+    }
+
+    fn write(&mut self, _address: u16, value: u8) {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(&[value]);
+        let _ = stdout.flush();
+    }
+
+    fn box_clone(&self) -> Box<dyn Peripheral> {
+        Box::new(self.clone())
+    }
+}
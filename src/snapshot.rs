@@ -0,0 +1,207 @@
+//! Machine state written to JSON, to compare two moments of a run. The debugger's `snapshot`
+//! command takes one, `diff` compares the machine with it, and `chip8 diff A B` compares two
+//! files. Crash dumps have the same fields, so they can be compared too.
+
+use crate::chip8::Chip8;
+use crate::run;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+/// Bytes of memory to a line of the file
+const MEMORY_LINE: usize = 64;
+/// Differing pixels listed one by one at most, more are only counted
+const LISTED_PIXELS: usize = 8;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Snapshot {
+    /// Instructions run since power-on
+    pub cycles: u64,
+    pub pc: u16,
+    pub i: u16,
+    pub registers: Vec<u8>,
+    /// Addresses of the calls being run, the innermost last
+    pub stack: Vec<u16>,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    /// All of memory in hex, 64 bytes to a line
+    #[serde(serialize_with = "memory_to_hex", deserialize_with = "memory_from_hex")]
+    pub memory: Vec<u8>,
+    /// A line per row, `#` for the pixels that are on
+    #[serde(serialize_with = "display_to_rows", deserialize_with = "display_from_rows")]
+    pub display: Vec<u8>,
+}
+
+impl Snapshot {
+    pub fn new(chip8: &Chip8) -> Self {
+        let (delay_timer, sound_timer) = chip8.timers();
+        Snapshot {
+            cycles: chip8.cycles(),
+            pc: chip8.program_counter(),
+            i: chip8.index(),
+            registers: chip8.registers().to_vec(),
+            stack: chip8.call_stack().to_vec(),
+            delay_timer,
+            sound_timer,
+            memory: chip8.memory().to_vec(),
+            display: chip8.display().iter().map(|pixel| (*pixel != 0) as u8).collect(),
+        }
+    }
+
+    /// Reads a snapshot, or the machine state of a crash dump
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        serde_json::from_str(&contents).map_err(|e| format!("Invalid snapshot {}\n{}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let contents = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, contents).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+}
+
+/// What changed from one snapshot to another, a line per register, run of memory or screen
+/// change
+pub fn diff(before: &Snapshot, after: &Snapshot) -> Vec<String> {
+    let mut lines = Vec::new();
+    if before.cycles != after.cycles {
+        lines.push(format!("{} instructions apart", after.cycles as i64 - before.cycles as i64));
+    }
+    let mut compare = |name: String, before: String, after: String| {
+        if before != after {
+            lines.push(format!("{}: {} -> {}", name, before, after));
+        }
+    };
+    compare(String::from("PC"), format!("{:#05X}", before.pc), format!("{:#05X}", after.pc));
+    compare(String::from("I"), format!("{:#05X}", before.i), format!("{:#05X}", after.i));
+    for (register, (old, new)) in before.registers.iter().zip(&after.registers).enumerate() {
+        compare(format!("V{:X}", register), format!("{:#04X}", old), format!("{:#04X}", new));
+    }
+    compare(String::from("DT"), before.delay_timer.to_string(), after.delay_timer.to_string());
+    compare(String::from("ST"), before.sound_timer.to_string(), after.sound_timer.to_string());
+    let stack = |stack: &[u16]| format!("[{}]", stack.iter().map(|call| format!("{:#05X}", call)).collect::<Vec<_>>().join(", "));
+    compare(String::from("Stack"), stack(&before.stack), stack(&after.stack));
+
+    for (start, end) in changed_runs(&before.memory, &after.memory) {
+        let bytes = |memory: &[u8]| memory[start..end].iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" ");
+        let range = if end - start == 1 { format!("{:#05X}", start) } else { format!("{:#05X}-{:#05X}", start, end - 1) };
+        lines.push(format!("Memory {}: {} -> {}", range, bytes(&before.memory), bytes(&after.memory)));
+    }
+
+    let pixels: Vec<(usize, usize, u8)> = before
+        .display
+        .iter()
+        .zip(&after.display)
+        .enumerate()
+        .filter(|(_, (old, new))| old != new)
+        .map(|(index, (_, new))| (index % run::WIDTH, index / run::WIDTH, *new))
+        .collect();
+    if pixels.len() > LISTED_PIXELS {
+        let columns = (pixels.iter().map(|pixel| pixel.0).min().unwrap(), pixels.iter().map(|pixel| pixel.0).max().unwrap());
+        let rows = (pixels.iter().map(|pixel| pixel.1).min().unwrap(), pixels.iter().map(|pixel| pixel.1).max().unwrap());
+        let on = pixels.iter().filter(|pixel| pixel.2 != 0).count();
+        lines.push(format!(
+            "Screen: {} pixels turned on and {} off, in columns {}-{} and rows {}-{}",
+            on,
+            pixels.len() - on,
+            columns.0,
+            columns.1,
+            rows.0,
+            rows.1
+        ));
+    } else {
+        for (x, y, on) in pixels {
+            lines.push(format!("Pixel ({}, {}) turned {}", x, y, if on != 0 { "on" } else { "off" }));
+        }
+    }
+    if lines.is_empty() {
+        lines.push(String::from("No differences"));
+    }
+    lines
+}
+
+/// Runs of addresses whose byte differs, as start and end past the last
+fn changed_runs(before: &[u8], after: &[u8]) -> Vec<(usize, usize)> {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for (address, _) in before.iter().zip(after).enumerate().filter(|(_, (old, new))| old != new) {
+        match runs.last_mut() {
+            Some((_, end)) if *end == address => *end += 1,
+            _ => runs.push((address, address + 1)),
+        }
+    }
+    runs
+}
+
+fn memory_to_hex<S: Serializer>(memory: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    let lines: Vec<String> = memory
+        .chunks(MEMORY_LINE)
+        .map(|line| {
+            let mut text = String::with_capacity(line.len() * 2);
+            for byte in line {
+                let _ = write!(text, "{:02x}", byte);
+            }
+            text
+        })
+        .collect();
+    lines.serialize(serializer)
+}
+
+fn memory_from_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let lines = Vec::<String>::deserialize(deserializer)?;
+    let text = lines.concat();
+    if !text.len().is_multiple_of(2) {
+        return Err(serde::de::Error::custom("memory has an odd number of hex digits"));
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|start| text.get(start..start + 2).and_then(|digits| u8::from_str_radix(digits, 16).ok()))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| serde::de::Error::custom("memory isn't hex"))
+}
+
+fn display_to_rows<S: Serializer>(display: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    let rows: Vec<String> = display.chunks(run::WIDTH).map(|row| row.iter().map(|pixel| if *pixel != 0 { '#' } else { '.' }).collect()).collect();
+    rows.serialize(serializer)
+}
+
+fn display_from_rows<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let rows = Vec::<String>::deserialize(deserializer)?;
+    Ok(rows.concat().chars().map(|pixel| (pixel == '#') as u8).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Chip8;
+    use crate::snapshot::{diff, Snapshot};
+
+    /// Registers, runs of memory and pixels that changed are reported, and survive a save and load
+    #[test]
+    fn test_diff() {
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&[0x63, 0x05, 0xA3, 0x00, 0xF3, 0x33, 0xD0, 0x03]);
+        let before = Snapshot::new(&chip8);
+        assert_eq!(diff(&before, &before), vec!["No differences"]);
+        for _ in 0..4 {
+            chip8.emulate_cycle();
+        }
+        let after = Snapshot::new(&chip8);
+        assert_eq!(
+            diff(&before, &after),
+            vec![
+                "4 instructions apart",
+                "PC: 0x200 -> 0x208",
+                "I: 0x000 -> 0x300",
+                "V3: 0x00 -> 0x05",
+                "Memory 0x302: 00 -> 05",
+                "Pixel (5, 2) turned on",
+                "Pixel (7, 2) turned on",
+            ]
+        );
+
+        let path = std::env::temp_dir().join(format!("chip8-snapshot-{}.json", std::process::id()));
+        after.save(&path).unwrap();
+        assert_eq!(Snapshot::load(&path).unwrap(), after);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,262 @@
+//! Pixel editor for sprites.
+//!
+//! One window holds the sprite, magnified, and a second one a mock 64x32 screen where the sprite
+//! is drawn twice the way DXYN draws it, XORed onto the screen: once in a fixed spot and once
+//! where the arrow keys move it, so overlapping copies show what the game would. The window title
+//! lists the bytes of the sprite as it is edited.
+//!
+//! Editor controls:
+//!
+//! | Key          |                                               |
+//! |--------------|-----------------------------------------------|
+//! | Left mouse   | draw                                          |
+//! | Right mouse  | erase                                         |
+//! | `=` / `-`    | make the sprite taller / shorter (8 wide)     |
+//! | Tab          | switch between 8xN and 16x16                  |
+//! | Arrows       | move the second copy in the preview           |
+//! | C            | clear                                         |
+//! | I            | invert                                        |
+//! | S            | save as Octo source and print the bytes       |
+
+use crate::fatal;
+use crate::run::{HEIGHT, WIDTH};
+use crate::sprites::{self, Image};
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Scale, ScaleMode, Window, WindowOptions};
+use std::path::Path;
+use tracing::{error, info};
+
+/// Largest sprite, 16x16
+const MAX_SIZE: usize = 16;
+/// Size of a sprite pixel in the editor, including the grid line
+const CELL_SIZE: usize = 8;
+const CANVAS_SIZE: usize = MAX_SIZE * CELL_SIZE;
+
+const ON: u32 = 0x0FFF;
+const OFF: u32 = 0x0000;
+const GRID: u32 = 0x202020;
+/// Area outside the sprite
+const UNUSED: u32 = 0x101010;
+
+/// Where the fixed copy is drawn in the preview
+const FIXED_POSITION: (usize, usize) = (8, 8);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sprite {
+    width: usize,
+    height: usize,
+    // Pixels past the size are kept, so shrinking and growing back loses nothing
+    pixels: [[bool; MAX_SIZE]; MAX_SIZE],
+}
+
+impl Sprite {
+    pub fn new(width: usize, height: usize) -> Self {
+        Sprite { width, height, pixels: [[false; MAX_SIZE]; MAX_SIZE] }
+    }
+
+    /// Starts from the top left corner of an image
+    pub fn from_image(image: &Image, width: usize, height: usize) -> Self {
+        let mut sprite = Sprite::new(width, height);
+        for y in 0..height.min(image.height) {
+            for x in 0..width.min(image.width) {
+                sprite.pixels[y][x] = image.pixels[y * image.width + x];
+            }
+        }
+        sprite
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, on: bool) {
+        if x < self.width && y < self.height {
+            self.pixels[y][x] = on;
+        }
+    }
+
+    /// Switches between 8 pixels wide and 16x16
+    pub fn toggle_wide(&mut self) {
+        if self.width == 16 {
+            self.width = 8;
+            self.height = self.height.min(15);
+        } else {
+            self.width = 16;
+            self.height = 16;
+        }
+    }
+
+    /// Changes the height of 8 pixel wide sprites, from 1 to 15
+    pub fn resize(&mut self, taller: bool) {
+        if self.width == 8 {
+            self.height = if taller { (self.height + 1).min(15) } else { (self.height - 1).max(1) };
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels = [[false; MAX_SIZE]; MAX_SIZE];
+    }
+
+    pub fn invert(&mut self) {
+        for row in self.pixels.iter_mut().take(self.height) {
+            for pixel in row.iter_mut().take(self.width) {
+                *pixel = !*pixel;
+            }
+        }
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        let pixels = self.pixels[..self.height].iter().flat_map(|row| row[..self.width].to_vec()).collect();
+        let image = Image { width: self.width, height: self.height, pixels };
+        sprites::cut(&image, self.width, self.height).unwrap().remove(0)
+    }
+
+    /// XORs the sprite onto a 64x32 screen, clipped at the edges
+    pub fn draw(&self, screen: &mut [bool], (left, top): (usize, usize)) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if self.pixels[y][x] && left + x < WIDTH && top + y < HEIGHT {
+                    screen[(top + y) * WIDTH + left + x] ^= true;
+                }
+            }
+        }
+    }
+}
+
+/// Opens the editor, starting from the top left corner of an image if given, and saves to
+/// `output`. 16 pixel wide sprites are always 16 high.
+pub fn run(image: Option<&Path>, width: usize, height: usize, output: &Path) {
+    let height = if width == 16 { 16 } else { height };
+    if !matches!((width, height), (8, 1..=15) | (16, 16)) {
+        error!("Sprites can be 8x1 to 8x15 or 16x16, not {}x{}", width, height);
+        return;
+    }
+    let mut sprite = match image {
+        Some(path) => match sprites::load(path, false) {
+            Ok(image) => Sprite::from_image(&image, width, height),
+            Err(error) => {
+                error!("{}", error);
+                return;
+            }
+        },
+        None => Sprite::new(width, height),
+    };
+
+    let mut window = Window::new("Sprite editor", CANVAS_SIZE, CANVAS_SIZE, window_options(Scale::X4))
+        .unwrap_or_else(fatal);
+    let mut preview_window = Window::new("Preview", WIDTH, HEIGHT, window_options(Scale::X8))
+        .unwrap_or_else(fatal);
+    let mut canvas: Vec<u32> = vec![0; CANVAS_SIZE * CANVAS_SIZE];
+    let mut preview: Vec<u32> = vec![0; WIDTH * HEIGHT];
+
+    let mut position = (FIXED_POSITION.0 + 4, FIXED_POSITION.1 + 2);
+    let mut title = String::new();
+
+    while window.is_open() && preview_window.is_open() && !window.is_key_down(Key::Escape) {
+        for key in window.get_keys_pressed(KeyRepeat::Yes).unwrap_or_default() {
+            match key {
+                Key::Equal => sprite.resize(true),
+                Key::Minus => sprite.resize(false),
+                Key::Tab => sprite.toggle_wide(),
+                Key::Left => position.0 = position.0.saturating_sub(1),
+                Key::Right => position.0 = (position.0 + 1).min(WIDTH - 1),
+                Key::Up => position.1 = position.1.saturating_sub(1),
+                Key::Down => position.1 = (position.1 + 1).min(HEIGHT - 1),
+                Key::C => sprite.clear(),
+                Key::I => sprite.invert(),
+                Key::S => save(&sprite, output),
+                _ => {}
+            }
+        }
+
+        let drawing = window.get_mouse_down(MouseButton::Left);
+        if drawing || window.get_mouse_down(MouseButton::Right) {
+            if let Some((x, y)) = window.get_mouse_pos(MouseMode::Discard) {
+                sprite.set(x as usize / CELL_SIZE, y as usize / CELL_SIZE, drawing);
+            }
+        }
+
+        draw_canvas(&sprite, &mut canvas);
+        let mut screen = [false; WIDTH * HEIGHT];
+        sprite.draw(&mut screen, FIXED_POSITION);
+        sprite.draw(&mut screen, position);
+        for (pixel, on) in preview.iter_mut().zip(screen.iter()) {
+            *pixel = if *on { ON } else { OFF };
+        }
+
+        let new_title = format!("{}x{}: {}", sprite.width, sprite.height, sprites::to_hex(&[sprite.bytes()]).trim_end());
+        if new_title != title {
+            window.set_title(&new_title);
+            title = new_title;
+        }
+        window.update_with_buffer(&canvas, CANVAS_SIZE, CANVAS_SIZE).unwrap();
+        preview_window.update_with_buffer(&preview, WIDTH, HEIGHT).unwrap();
+    }
+}
+
+fn window_options(scale: Scale) -> WindowOptions {
+    WindowOptions {
+        borderless: false,
+        transparency: false,
+        title: true,
+        resize: false,
+        scale,
+        scale_mode: ScaleMode::Stretch,
+        topmost: false,
+    }
+}
+
+/// Draws each sprite pixel as a cell with a grid line on its right and bottom edges
+fn draw_canvas(sprite: &Sprite, buffer: &mut [u32]) {
+    for y in 0..CANVAS_SIZE {
+        for x in 0..CANVAS_SIZE {
+            let (column, row) = (x / CELL_SIZE, y / CELL_SIZE);
+            buffer[y * CANVAS_SIZE + x] = if column >= sprite.width || row >= sprite.height {
+                UNUSED
+            } else if x % CELL_SIZE == CELL_SIZE - 1 || y % CELL_SIZE == CELL_SIZE - 1 {
+                GRID
+            } else if sprite.pixels[row][column] {
+                ON
+            } else {
+                OFF
+            };
+        }
+    }
+}
+
+/// Writes the sprite as a `:sprite` block named after the file, and prints its bytes
+fn save(sprite: &Sprite, output: &Path) {
+    let bytes = sprite.bytes();
+    let source = sprites::to_octo(&sprites::name_for(output), std::slice::from_ref(&bytes), sprite.width);
+    match std::fs::write(output, source) {
+        Ok(()) => info!("Saved {}: {}", output.display(), sprites::to_hex(&[bytes]).trim_end()),
+        Err(error) => error!("Could not write {}\n{}", output.display(), error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::run::{HEIGHT, WIDTH};
+    use crate::sprite_editor::Sprite;
+
+    /// Edits show up in the bytes, pixels hidden by shrinking come back, copies XOR each other
+    #[test]
+    fn test_sprite() {
+        let mut sprite = Sprite::new(8, 2);
+        sprite.set(0, 0, true);
+        sprite.set(7, 1, true);
+        sprite.set(0, 5, true); // below the sprite, ignored
+        assert_eq!(sprite.bytes(), vec![0x80, 0x01]);
+
+        sprite.resize(false);
+        assert_eq!(sprite.bytes(), vec![0x80]);
+        sprite.resize(true);
+        assert_eq!(sprite.bytes(), vec![0x80, 0x01]);
+
+        sprite.toggle_wide();
+        assert_eq!(sprite.bytes().len(), 32);
+        assert_eq!(&sprite.bytes()[..4], &[0x80, 0x00, 0x01, 0x00]);
+
+        let mut screen = [false; WIDTH * HEIGHT];
+        sprite.draw(&mut screen, (0, 0));
+        sprite.draw(&mut screen, (0, 0));
+        assert!(screen.iter().all(|pixel| !pixel));
+        sprite.draw(&mut screen, (WIDTH - 1, 0));
+        assert!(screen[WIDTH - 1]);
+    }
+}
@@ -0,0 +1,199 @@
+//! Conversion of images into sprite data.
+//!
+//! The image is cut into sprites of the requested size, left to right and top to bottom. Pixels
+//! brighter than half are on and transparent ones off; inverting swaps that, for dark art on a
+//! light background. Sprites are 8 pixels wide and up to 15 tall, or 16x16 as drawn by SCHIP's
+//! `DXY0`, two bytes per row.
+//!
+//! Octo output has a `:sprite` block per sprite, named after the image file:
+//!
+//! ```text
+//! :sprite ball {
+//!     .XX.....
+//!     XXXX....
+//! }
+//! ```
+
+use std::fs::File;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// `:sprite` blocks for the assembler
+    Octo,
+    /// Hex bytes, one sprite per line
+    Hex,
+    /// The bytes themselves
+    Binary,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "octo" => Ok(Format::Octo),
+            "hex" => Ok(Format::Hex),
+            "bin" | "binary" => Ok(Format::Binary),
+            _ => Err(format!("Unknown sprite format: {}", s)),
+        }
+    }
+}
+
+/// A 1-bit image, row by row
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<bool>,
+}
+
+/// Converts an image file into sprites in the given format. Without a height, the sprites are as
+/// tall as the image, or 16 for 16 pixel wide sprites.
+pub fn convert(path: &Path, width: usize, height: Option<usize>, invert: bool, format: Format) -> Result<Vec<u8>, String> {
+    let image = load(path, invert)?;
+    let height = height.unwrap_or(if width == 16 { 16 } else { image.height });
+    let sprites = cut(&image, width, height)?;
+    let name = name_for(path);
+    Ok(match format {
+        Format::Octo => to_octo(&name, &sprites, width).into_bytes(),
+        Format::Hex => to_hex(&sprites).into_bytes(),
+        Format::Binary => sprites.concat(),
+    })
+}
+
+/// Sprite name for a file, made of its name without the extension
+pub fn name_for(path: &Path) -> String {
+    let stem = path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+    let mut name = stem.replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+    // Names have to start with a letter
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        name.insert_str(0, "sprite_");
+    }
+    name
+}
+
+pub fn load(path: &Path, invert: bool) -> Result<Image, String> {
+    let error = |e: &dyn std::fmt::Display| format!("Could not read image {}\n{}", path.display(), e);
+
+    let file = File::open(path).map_err(|e| error(&e))?;
+    let mut decoder = png::Decoder::new(file);
+    // Palettes and bit depths below 8 become plain 8-bit samples
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(|e| error(&e))?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(|e| error(&e))?;
+
+    let samples = info.color_type.samples();
+    let pixels = buffer[..info.buffer_size()]
+        .chunks_exact(samples)
+        .map(|pixel| {
+            let (brightness, alpha) = match info.color_type {
+                png::ColorType::Grayscale => (pixel[0] as u32, 255),
+                png::ColorType::GrayscaleAlpha => (pixel[0] as u32, pixel[1]),
+                png::ColorType::Rgb => (luminance(pixel), 255),
+                _ => (luminance(pixel), pixel[3]),
+            };
+            alpha >= 128 && ((brightness >= 128) != invert)
+        })
+        .collect();
+    Ok(Image { width: info.width as usize, height: info.height as usize, pixels })
+}
+
+fn luminance(rgb: &[u8]) -> u32 {
+    (rgb[0] as u32 * 299 + rgb[1] as u32 * 587 + rgb[2] as u32 * 114) / 1000
+}
+
+/// Cuts an image into sprites of the given size, returning the bytes of each
+pub fn cut(image: &Image, width: usize, height: usize) -> Result<Vec<Vec<u8>>, String> {
+    match (width, height) {
+        (8, 1..=15) | (16, 16) => {}
+        _ => return Err(format!("Sprites can be 8x1 to 8x15 or 16x16, not {}x{}", width, height)),
+    }
+    if !image.width.is_multiple_of(width) || !image.height.is_multiple_of(height) {
+        return Err(format!(
+            "A {}x{} image can't be cut into {}x{} sprites",
+            image.width, image.height, width, height
+        ));
+    }
+
+    let mut sprites = Vec::new();
+    for top in (0..image.height).step_by(height) {
+        for left in (0..image.width).step_by(width) {
+            let mut bytes = Vec::with_capacity(height * width / 8);
+            for y in top..top + height {
+                for byte_left in (left..left + width).step_by(8) {
+                    let row = &image.pixels[y * image.width + byte_left..y * image.width + byte_left + 8];
+                    bytes.push(row.iter().fold(0, |byte, on| (byte << 1) | *on as u8));
+                }
+            }
+            sprites.push(bytes);
+        }
+    }
+    Ok(sprites)
+}
+
+/// `:sprite` blocks, numbered when there's more than one
+pub fn to_octo(name: &str, sprites: &[Vec<u8>], width: usize) -> String {
+    let mut source = String::new();
+    for (index, sprite) in sprites.iter().enumerate() {
+        if sprites.len() == 1 {
+            source.push_str(&format!(":sprite {} {{\n", name));
+        } else {
+            source.push_str(&format!(":sprite {}_{} {{\n", name, index));
+        }
+        for row in sprite.chunks(width / 8) {
+            let pixels: String = row
+                .iter()
+                .flat_map(|byte| (0..8).map(move |bit| if byte & (0x80 >> bit) != 0 { 'X' } else { '.' }))
+                .collect();
+            source.push_str(&format!("\t{}\n", pixels));
+        }
+        source.push_str("}\n");
+    }
+    source
+}
+
+pub fn to_hex(sprites: &[Vec<u8>]) -> String {
+    sprites
+        .iter()
+        .map(|sprite| {
+            let bytes: Vec<String> = sprite.iter().map(|byte| format!("{:#04X}", byte)).collect();
+            bytes.join(" ") + "\n"
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assemble;
+    use crate::sprites::{cut, to_hex, to_octo, Image};
+
+    /// An image is cut into sprites in reading order, 16 pixel rows taking two bytes
+    #[test]
+    fn test_cut() {
+        // 16x2: left half has a diagonal, right half is full
+        let rows = ["X.......XXXXXXXX", ".X......XXXXXXXX"];
+        let pixels = rows.iter().flat_map(|row| row.chars().map(|c| c == 'X')).collect();
+        let image = Image { width: 16, height: 2, pixels };
+
+        let sprites = cut(&image, 8, 2).unwrap();
+        assert_eq!(sprites, vec![vec![0x80, 0x40], vec![0xFF, 0xFF]]);
+        assert_eq!(to_hex(&sprites), "0x80 0x40\n0xFF 0xFF\n");
+        assert!(cut(&image, 8, 3).is_err());
+        assert!(cut(&image, 16, 2).is_err());
+    }
+
+    /// Octo output assembles back into the same bytes
+    #[test]
+    fn test_octo() {
+        let sprites = vec![vec![0x80, 0x40], vec![0xFF, 0x01]];
+        let source = to_octo("dot", &sprites, 8);
+        assert!(source.starts_with(":sprite dot_0 {\n\tX.......\n"));
+        let rom = assemble(&format!(": main\n{}", source), None).unwrap();
+        assert_eq!(rom, vec![0x80, 0x40, 0xFF, 0x01]);
+
+        let wide = to_octo("wide", &[vec![0x80, 0x01]], 16);
+        assert_eq!(wide, ":sprite wide {\n\tX..............X\n}\n");
+    }
+}
@@ -0,0 +1,148 @@
+//! Symbol files written by the assembler.
+//!
+//! They record the address of every label and the source line every instruction came from, so
+//! tools working on the ROM can talk about `main` and `game.8o:12` instead of raw addresses:
+//!
+//! ```toml
+//! [labels]
+//! main = 512
+//!
+//! [[lines]]
+//! address = 512
+//! file = "game.8o"
+//! line = 3
+//! ```
+
+use crate::notes::Note;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Symbols {
+    pub labels: BTreeMap<String, u16>,
+    /// In address order
+    pub lines: Vec<SourceLine>,
+    /// Names and comments given to memory, from the notes file of the ROM
+    #[serde(skip)]
+    pub notes: Vec<Note>,
+}
+
+/// Where the instruction at an address was written
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct SourceLine {
+    pub address: u16,
+    /// Empty for source that didn't come from a file
+    pub file: String,
+    pub line: usize,
+}
+
+impl Symbols {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read symbols {}\n{}", path.display(), e))?;
+        toml::from_str(&contents).map_err(|e| format!("Invalid symbol file {}\n{}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let contents = toml::to_string(self).map_err(|e| e.to_string())?;
+        fs::write(path, contents).map_err(|e| format!("Could not write symbols {}\n{}", path.display(), e))
+    }
+
+    /// Label of an address, the first in alphabetical order if there are several
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.labels.iter().find(|(_, at)| **at == address).map(|(label, _)| label.as_str())
+    }
+
+    /// An address in hex, with its label if it has one, like `0x206 (draw)`
+    pub fn describe(&self, address: u16) -> String {
+        match self.label_at(address) {
+            Some(label) => format!("{:#05X} ({})", address, label),
+            None => format!("{:#05X}", address),
+        }
+    }
+
+    /// Label of an address, or the name of the note it's in with how far into it, like
+    /// `score+1`
+    pub fn name_of(&self, address: u16) -> Option<String> {
+        if let Some(label) = self.label_at(address) {
+            return Some(label.to_string());
+        }
+        let note = self.note_at(address)?;
+        Some(format!("{}+{}", note.name, address - note.start))
+    }
+
+    /// The note covering an address, the last added if there are several
+    pub fn note_at(&self, address: u16) -> Option<&Note> {
+        self.notes.iter().rev().find(|note| note.contains(address))
+    }
+
+    /// Adds a note, replacing the one starting at the same address, and labels its start with
+    /// its name
+    pub fn add_note(&mut self, note: Note) {
+        self.remove_note(note.start);
+        self.labels.insert(note.name.clone(), note.start);
+        self.notes.push(note);
+    }
+
+    /// Removes the note starting at an address and its label, returning whether there was one
+    pub fn remove_note(&mut self, address: u16) -> bool {
+        let index = match self.notes.iter().position(|note| note.start == address) {
+            Some(index) => index,
+            None => return false,
+        };
+        let note = self.notes.remove(index);
+        if self.labels.get(&note.name) == Some(&note.start) {
+            self.labels.remove(&note.name);
+        }
+        true
+    }
+
+    pub fn line_at(&self, address: u16) -> Option<&SourceLine> {
+        self.lines.binary_search_by_key(&address, |line| line.address).ok().map(|index| &self.lines[index])
+    }
+}
+
+/// Reads source files on demand to show the text of source lines
+#[derive(Default)]
+pub struct SourceCache {
+    files: HashMap<String, Vec<String>>,
+}
+
+impl SourceCache {
+    /// `file:line: text`, without the text if the file can't be read
+    pub fn describe(&mut self, line: &SourceLine) -> String {
+        if line.file.is_empty() {
+            return format!("line {}", line.line);
+        }
+        let lines = self.files.entry(line.file.clone()).or_insert_with(|| {
+            fs::read_to_string(&line.file).map_or_else(|_| Vec::new(), |text| text.lines().map(str::to_string).collect())
+        });
+        match lines.get(line.line.wrapping_sub(1)) {
+            Some(text) => format!("{}:{}: {}", line.file, line.line, text.trim()),
+            None => format!("{}:{}", line.file, line.line),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assemble_with_symbols;
+
+    /// Labels and instructions map back to the source, and survive a save and load
+    #[test]
+    fn test_symbols() {
+        let (_, symbols) = assemble_with_symbols(": main\n\tv0 := 1\n: loop-start\n\tjump loop-start\n0xFF", None).unwrap();
+        assert_eq!(symbols.labels.get("loop-start"), Some(&0x202));
+        assert_eq!(symbols.label_at(0x200), Some("main"));
+        assert_eq!(symbols.line_at(0x202).map(|line| line.line), Some(4));
+        // Data has no line of its own
+        assert_eq!(symbols.line_at(0x204), None);
+
+        let path = std::env::temp_dir().join(format!("chip8-symbols-{}.toml", std::process::id()));
+        symbols.save(&path).unwrap();
+        assert_eq!(crate::symbols::Symbols::load(&path).unwrap(), symbols);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
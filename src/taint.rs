@@ -0,0 +1,145 @@
+//! Reads of memory and registers nothing wrote yet, with `--taint`. At power-on only the font
+//! and the program are written; registers and I start at zero on this emulator, but not on every
+//! interpreter, so a ROM reading them before setting them works here by luck. Each instruction
+//! doing it is logged once, and `taint on` in the debugger stops the machine before them.
+
+use crate::chip8::{Access, AluOp, Chip8, Instruction, MAX_MEMORY_SIZE};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+pub struct Taint {
+    /// Whether each byte of memory was written, by the font, the program or an instruction
+    memory: Vec<bool>,
+    registers: [bool; 16],
+    index: bool,
+    /// What each instruction read before it was written, by the address of the instruction
+    reads: BTreeMap<u16, String>,
+}
+
+impl Taint {
+    pub fn new(font: Range<usize>, program_length: usize) -> Self {
+        let mut memory = vec![false; MAX_MEMORY_SIZE];
+        memory[font].iter_mut().for_each(|written| *written = true);
+        memory[0x200..(0x200 + program_length).min(MAX_MEMORY_SIZE)].iter_mut().for_each(|written| *written = true);
+        Taint { memory, registers: [false; 16], index: false, reads: BTreeMap::new() }
+    }
+
+    /// What the instruction at the program counter reads that nothing wrote, like `V3, I`
+    pub fn uninitialized(&self, chip8: &Chip8) -> Option<String> {
+        let instruction = instruction(chip8)?;
+        let (read, _) = registers(instruction);
+        let mut names: Vec<String> = read.iter().filter(|register| !self.registers[**register as usize]).map(|register| format!("V{:X}", register)).collect();
+        if reads_index(instruction) && !self.index {
+            names.push(String::from("I"));
+        }
+        if let Some((Access::Read, range)) = instruction.memory_access(chip8.index()) {
+            let mut unwritten = range.filter(|address| !self.memory.get(*address as usize).copied().unwrap_or(true));
+            if let Some(start) = unwritten.next() {
+                let end = unwritten.next_back().unwrap_or(start);
+                names.push(if start == end { format!("{:#05X}", start) } else { format!("{:#05X}-{:#05X}", start, end) });
+            }
+        }
+        (!names.is_empty()).then(|| names.join(", "))
+    }
+
+    /// Checks the instruction at the program counter before it runs and marks what it writes.
+    /// Returns what it read uninitialized the first time that instruction does it.
+    pub fn record(&mut self, chip8: &Chip8) -> Option<String> {
+        let instruction = instruction(chip8)?;
+        let read = self.uninitialized(chip8);
+        let (_, written) = registers(instruction);
+        for register in written {
+            self.registers[register as usize] = true;
+        }
+        if writes_index(instruction) {
+            self.index = true;
+        }
+        if let Some((Access::Write, range)) = instruction.memory_access(chip8.index()) {
+            for address in range {
+                if let Some(written) = self.memory.get_mut(address as usize) {
+                    *written = true;
+                }
+            }
+        }
+        let read = read?;
+        let address = chip8.program_counter();
+        if self.reads.contains_key(&address) {
+            return None;
+        }
+        self.reads.insert(address, read.clone());
+        Some(read)
+    }
+
+    /// Instructions that read something uninitialized, with what they read
+    pub fn reads(&self) -> &BTreeMap<u16, String> {
+        &self.reads
+    }
+}
+
+/// The instruction at the program counter
+fn instruction(chip8: &Chip8) -> Option<Instruction> {
+    let address = chip8.program_counter() as usize;
+    let opcode = chip8.memory().get(address..address + 2)?;
+    Instruction::decode(u16::from_be_bytes([opcode[0], opcode[1]]), chip8.variant())
+}
+
+/// Registers the instruction reads and the ones it writes, VF as a flag included
+fn registers(instruction: Instruction) -> (Vec<u8>, Vec<u8>) {
+    match instruction {
+        Instruction::SkipIfEqual(x, _)
+        | Instruction::SkipIfNotEqual(x, _)
+        | Instruction::SkipIfKey(x)
+        | Instruction::SkipIfNotKey(x)
+        | Instruction::SkipIfSecondKey(x)
+        | Instruction::SkipIfNotSecondKey(x)
+        | Instruction::SetDelay(x)
+        | Instruction::SetSound(x)
+        | Instruction::AddIndex(x)
+        | Instruction::LoadFont(x)
+        | Instruction::StoreBcd(x) => (vec![x], Vec::new()),
+        Instruction::SkipIfRegistersEqual(x, y) | Instruction::SkipIfRegistersNotEqual(x, y) => (vec![x, y], Vec::new()),
+        Instruction::Load(x, _) | Instruction::Random(x, _) | Instruction::GetDelay(x) => (Vec::new(), vec![x]),
+        Instruction::Add(x, _) => (vec![x], vec![x]),
+        Instruction::Alu(AluOp::Move, x, y) => (vec![y], vec![x]),
+        Instruction::Alu(AluOp::ShiftRight, x, _) | Instruction::Alu(AluOp::ShiftLeft, x, _) => (vec![x], vec![x, 0xF]),
+        Instruction::Alu(_, x, y) => (vec![x, y], vec![x, 0xF]),
+        Instruction::Draw(x, y, _) => (vec![x, y], vec![0xF]),
+        Instruction::JumpOffset(_) => (vec![0], Vec::new()),
+        Instruction::StoreRegisters(x) => ((0..=x).collect(), Vec::new()),
+        Instruction::LoadRegisters(x) => (Vec::new(), (0..=x).collect()),
+        Instruction::ClearScreen | Instruction::Return | Instruction::Jump(_) | Instruction::Call(_) | Instruction::LoadIndex(_) => {
+            (Vec::new(), Vec::new())
+        }
+    }
+}
+
+fn reads_index(instruction: Instruction) -> bool {
+    matches!(instruction, Instruction::Draw(..) | Instruction::AddIndex(_) | Instruction::StoreBcd(_) | Instruction::StoreRegisters(_) | Instruction::LoadRegisters(_))
+}
+
+fn writes_index(instruction: Instruction) -> bool {
+    matches!(instruction, Instruction::LoadIndex(_) | Instruction::AddIndex(_) | Instruction::LoadFont(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Chip8;
+    use crate::taint::Taint;
+
+    /// Registers and memory read before anything wrote them are reported once per instruction
+    #[test]
+    fn test_taint() {
+        // v1 := v0 + v1 reads both, then i := 0x300, load v1 reads memory after the program
+        let program = [0x60, 0x05, 0x81, 0x04, 0xA3, 0x00, 0xF1, 0x65, 0xA2, 0x00, 0xF1, 0x65, 0x12, 0x02];
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&program);
+        let mut taint = Taint::new(chip8.font(), program.len());
+        let mut reads = Vec::new();
+        for _ in 0..13 {
+            reads.extend(taint.record(&chip8));
+            chip8.emulate_cycle();
+        }
+        assert_eq!(reads, vec!["V1", "0x300-0x301"]);
+        assert_eq!(taint.reads().keys().copied().collect::<Vec<_>>(), vec![0x202, 0x206]);
+    }
+}
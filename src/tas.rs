@@ -0,0 +1,391 @@
+//! Tool-assisted mode for building replays frame by frame.
+//!
+//! The game starts paused at power-on. Next to the game window, a piano roll shows the input of
+//! the frames around the playhead: one row per keypad key, one column per frame. Inputs can be
+//! toggled anywhere in the roll; editing a frame that was already emulated re-runs the machine
+//! from the nearest savestate before it, so the game always shows the result of the current
+//! input. When playing past the end of the movie, the keys held in the game window are appended.
+//! Save points are kept in a tree, shown in a third window (see `savetree`), so different attempts
+//! at a section can be kept side by side and switched between.
+//!
+//! Piano roll controls:
+//!
+//! | Key         |                                                     |
+//! |-------------|-----------------------------------------------------|
+//! | Space       | play / pause                                        |
+//! | `.`         | advance one frame                                   |
+//! | `,`         | go back one frame                                   |
+//! | Home        | go back to power-on                                 |
+//! | Arrows      | move the edit cursor                                |
+//! | Enter       | toggle the key under the edit cursor                |
+//! | F5          | save the movie                                      |
+
+use crate::chip8::{Chip8, Variant};
+use crate::cli::RunArgs;
+use crate::error_screen;
+use crate::fatal;
+use crate::replay::{self, FrameInput, Machine, Replay};
+use crate::run;
+use crate::savetree::{PanelAction, SaveTree, TreePanel};
+use minifb::{Key, KeyRepeat, Scale, ScaleMode, Window, WindowOptions};
+use std::collections::BTreeMap;
+use std::path::Path;
+use tracing::{error, info};
+
+/// Frames between two savestates
+const SAVESTATE_INTERVAL: usize = 60;
+
+/// Frames shown in the piano roll, and how many of them are before the playhead
+const ROLL_FRAMES: usize = 64;
+const ROLL_HISTORY: usize = 8;
+const CELL_SIZE: usize = 4;
+
+const PRESSED: u32 = 0x0FFF;
+const RELEASED: u32 = 0x202020;
+const PLAYHEAD: u32 = 0x404040;
+const CURSOR: u32 = 0xFFFF00;
+
+const TREE_WIDTH: usize = 96;
+const TREE_HEIGHT: usize = 64;
+
+/// Movie and machine state at a save point
+pub(crate) struct SavePoint {
+    inputs: Vec<FrameInput>,
+    chip8: Chip8,
+    position: usize,
+}
+
+/// Inputs being edited together with the machine state at the playhead
+pub(crate) struct Movie {
+    program: Vec<u8>,
+    seed: u64,
+    variant: Variant,
+    inputs: Vec<FrameInput>,
+    // State after the given number of frames, always including power-on
+    savestates: BTreeMap<usize, Chip8>,
+    chip8: Chip8,
+    // Frames emulated so far
+    position: usize,
+}
+
+impl Movie {
+    pub fn new(program: Vec<u8>, seed: u64, variant: Variant, inputs: Vec<FrameInput>) -> Self {
+        let chip8 = run::power_on(Chip8::builder().variant(variant).seed(seed), &program);
+        let mut savestates = BTreeMap::new();
+        savestates.insert(0, chip8.clone());
+        Movie { program, seed, variant, inputs, savestates, chip8, position: 0 }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn chip8_mut(&mut self) -> &mut Chip8 {
+        &mut self.chip8
+    }
+
+    /// Input for a frame, released keys past the end of the movie
+    pub fn input(&self, frame: usize) -> FrameInput {
+        self.inputs.get(frame).copied().unwrap_or(FrameInput { keypad: 0, second_keypad: 0 })
+    }
+
+    /// Emulates the frame at the playhead. `live` is appended when the playhead is at the end.
+    pub fn advance(&mut self, live: FrameInput) {
+        if self.position == self.inputs.len() {
+            self.inputs.push(live);
+        }
+        let input = self.inputs[self.position];
+        self.chip8.emulate_cycle();
+        self.chip8.set_keys(input.keypad());
+        self.chip8.set_second_keypad(input.second_keypad());
+        self.position += 1;
+        if self.position.is_multiple_of(SAVESTATE_INTERVAL) {
+            self.savestates.insert(self.position, self.chip8.clone());
+        }
+    }
+
+    /// Moves the playhead to any frame up to the end of the movie, loading the nearest savestate
+    /// before it and re-running the frames in between
+    pub fn seek(&mut self, frame: usize) {
+        let frame = frame.min(self.inputs.len());
+        if frame < self.position {
+            self.load_savestate(frame);
+        }
+        while self.position < frame {
+            self.advance(self.input(self.position));
+        }
+    }
+
+    /// Toggles a key in the input of a frame, padding the movie with released keys if the frame is
+    /// past its end. Keys 0x10-0x1F are on the second keypad.
+    pub fn toggle(&mut self, frame: usize, key: usize) {
+        if frame >= self.inputs.len() {
+            self.inputs.resize(frame + 1, FrameInput { keypad: 0, second_keypad: 0 });
+        }
+        let input = &mut self.inputs[frame];
+        if key < 16 {
+            input.keypad ^= 1 << key;
+        } else {
+            input.second_keypad ^= 1 << (key - 16);
+        }
+
+        // Everything after this frame was emulated with the old input
+        self.savestates.split_off(&(frame + 1));
+        if frame < self.position {
+            let position = self.position;
+            self.load_savestate(frame);
+            self.seek(position);
+        }
+    }
+
+    /// Loads the latest savestate at or before a frame
+    fn load_savestate(&mut self, frame: usize) {
+        let (saved_at, savestate) = self.savestates.range(..=frame).next_back().unwrap();
+        self.position = *saved_at;
+        self.chip8 = savestate.clone();
+        self.chip8.force_redraw();
+    }
+
+    pub fn save_point(&self) -> SavePoint {
+        SavePoint { inputs: self.inputs.clone(), chip8: self.chip8.clone(), position: self.position }
+    }
+
+    /// Switches to the movie of a save point, with the playhead where it was saved
+    pub fn restore(&mut self, save_point: &SavePoint) {
+        self.inputs = save_point.inputs.clone();
+        self.savestates.split_off(&1);
+        self.savestates.insert(save_point.position, save_point.chip8.clone());
+        self.load_savestate(save_point.position);
+    }
+
+    /// Replay of the whole movie, emulated from power-on to fill in the state checksums
+    pub fn to_replay(&self) -> Replay {
+        let rom_hash = replay::rom_hash(&self.program);
+        let mut replay = Replay::new(rom_hash, self.seed, self.variant, Machine::default());
+        let mut chip8 = run::power_on(Chip8::builder().variant(self.variant).seed(self.seed), &self.program);
+        for input in &self.inputs {
+            chip8.emulate_cycle();
+            chip8.set_keys(input.keypad());
+            chip8.set_second_keypad(input.second_keypad());
+            replay.push(*input, || chip8.state_checksum());
+        }
+        replay
+    }
+}
+
+/// Edits a movie for a ROM, starting from an existing replay if given, and saves it to `output`
+pub fn run(args: &RunArgs, replay: Option<Replay>, output: &Path) {
+    let config = args.input.load_config();
+    let keymap = args.input.keymap(&config);
+    let second_keymap = config.second_keymap.build(config.keymap.preset).unwrap_or_else(fatal);
+
+    let program = match run::load_program(&args.rom) {
+        Ok(program) => program.bytes,
+        Err(error) => {
+            error!("{}", error);
+            error_screen::show(&error);
+            return;
+        }
+    };
+    let mut movie = match replay {
+        Some(replay) => {
+            if replay.rom_hash != replay::rom_hash(&program) {
+                error!("Replay was recorded with a different ROM than {}", args.rom.display());
+                return;
+            }
+            let inputs = replay.frames().collect();
+            Movie::new(program, replay.seed, replay.variant, inputs)
+        }
+        None => Movie::new(program, args.seed.unwrap_or_else(rand::random), args.variant, Vec::new()),
+    };
+
+    // Second keypad rows are only shown for CHIP-8X
+    let rows = if movie.variant == Variant::Chip8X { 32 } else { 16 };
+    let roll_width = ROLL_FRAMES * CELL_SIZE;
+    let roll_height = rows * CELL_SIZE;
+
+    let mut window = Window::new("Chip8 Emulator - TAS", run::WIDTH, run::HEIGHT, window_options(Scale::X16))
+        .unwrap_or_else(fatal);
+    let mut roll_window = Window::new("Piano roll", roll_width, roll_height, window_options(Scale::X2))
+        .unwrap_or_else(fatal);
+    let mut tree_window = Window::new("Save points", TREE_WIDTH, TREE_HEIGHT, window_options(Scale::X4))
+        .unwrap_or_else(fatal);
+    let mut buffer: Vec<u32> = vec![0; run::WIDTH * run::HEIGHT];
+    let mut roll_buffer: Vec<u32> = vec![0; roll_width * roll_height];
+    let mut tree_buffer: Vec<u32> = vec![0; TREE_WIDTH * TREE_HEIGHT];
+
+    let mut save_tree = SaveTree::new();
+    let mut tree_panel = TreePanel::new();
+
+    let mut playing = false;
+    // Edit cursor, as a column of the roll and a key row
+    let mut cursor_column = ROLL_HISTORY;
+    let mut cursor_row = 0;
+
+    while window.is_open() && roll_window.is_open() && tree_window.is_open() && !window.is_key_down(Key::Escape) {
+        let pressed_keys = window.get_keys().unwrap_or_default();
+        let live = FrameInput::new(keymap.keypad_state(&pressed_keys), second_keymap.keypad_state(&pressed_keys));
+        let first_frame = movie.position().saturating_sub(ROLL_HISTORY);
+
+        for key in roll_window.get_keys_pressed(KeyRepeat::Yes).unwrap_or_default() {
+            match key {
+                Key::Space => playing = !playing,
+                Key::Period if !playing => movie.advance(live),
+                Key::Comma if !playing => movie.seek(movie.position().saturating_sub(1)),
+                Key::Home => movie.seek(0),
+                Key::Left => cursor_column = cursor_column.saturating_sub(1),
+                Key::Right => cursor_column = (cursor_column + 1).min(ROLL_FRAMES - 1),
+                Key::Up => cursor_row = (cursor_row + rows - 1) % rows,
+                Key::Down => cursor_row = (cursor_row + 1) % rows,
+                Key::Enter => movie.toggle(first_frame + cursor_column, cursor_row),
+                Key::F5 => save(&movie, output),
+                _ => {}
+            }
+        }
+        let tree_keys = tree_window.get_keys_pressed(KeyRepeat::Yes).unwrap_or_default();
+        match tree_panel.handle_keys(&tree_keys, &save_tree) {
+            Some(PanelAction::Save(name)) => {
+                save_tree.save(name, movie.save_point());
+            }
+            Some(PanelAction::Load(id)) => {
+                playing = false;
+                movie.restore(save_tree.load(id));
+            }
+            None => {}
+        }
+        if playing {
+            movie.advance(live);
+        }
+
+        movie.chip8_mut().draw_to_buffer(&mut buffer);
+        window.update_with_buffer(&buffer, run::WIDTH, run::HEIGHT).unwrap();
+
+        let first_frame = movie.position().saturating_sub(ROLL_HISTORY);
+        draw_roll(&movie, &mut roll_buffer, roll_width, rows, first_frame, (cursor_column, cursor_row));
+        roll_window.update_with_buffer(&roll_buffer, roll_width, roll_height).unwrap();
+
+        tree_panel.draw(&mut tree_buffer, TREE_WIDTH, &save_tree);
+        tree_window.set_title(&tree_panel.title(&save_tree, |save_point| format!("frame {}", save_point.position)));
+        tree_window.update_with_buffer(&tree_buffer, TREE_WIDTH, TREE_HEIGHT).unwrap();
+    }
+
+    save(&movie, output);
+}
+
+fn window_options(scale: Scale) -> WindowOptions {
+    WindowOptions {
+        borderless: false,
+        transparency: false,
+        title: true,
+        resize: false,
+        scale,
+        scale_mode: ScaleMode::Stretch,
+        topmost: false,
+    }
+}
+
+/// Draws one cell per (frame, key), the playhead column highlighted and the edit cursor outlined
+fn draw_roll(movie: &Movie, buffer: &mut [u32], width: usize, rows: usize, first_frame: usize, cursor: (usize, usize)) {
+    for pixel in buffer.iter_mut() {
+        *pixel = 0;
+    }
+    for column in 0..ROLL_FRAMES {
+        let frame = first_frame + column;
+        let input = movie.input(frame);
+        let keys = (input.keypad as u32) | (input.second_keypad as u32) << 16;
+        for row in 0..rows {
+            let color = if keys & (1 << row) != 0 {
+                PRESSED
+            } else if frame == movie.position() {
+                PLAYHEAD
+            } else {
+                RELEASED
+            };
+            let outline = (column, row) == cursor;
+            for y in 0..CELL_SIZE - 1 {
+                for x in 0..CELL_SIZE - 1 {
+                    let edge = x == 0 || y == 0 || x == CELL_SIZE - 2 || y == CELL_SIZE - 2;
+                    let pixel = (row * CELL_SIZE + y) * width + column * CELL_SIZE + x;
+                    buffer[pixel] = if outline && edge { CURSOR } else { color };
+                }
+            }
+        }
+    }
+}
+
+fn save(movie: &Movie, path: &Path) {
+    let replay = movie.to_replay();
+    match replay.save(path) {
+        Ok(()) => info!("Saved {} frames to {}", replay.frame_count(), path.display()),
+        Err(error) => error!("{}", error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Variant;
+    use crate::replay::FrameInput;
+    use crate::tas::{Movie, SAVESTATE_INTERVAL};
+
+    // Counts frames in V1, and frames with key 0 held in V2
+    const PROGRAM: [u8; 10] = [0x71, 0x01, 0xE0, 0x9E, 0x12, 0x00, 0x72, 0x01, 0x12, 0x00];
+
+    fn inputs(frames: usize, held: &[usize]) -> Vec<FrameInput> {
+        let mut inputs = vec![FrameInput { keypad: 0, second_keypad: 0 }; frames];
+        for frame in held {
+            inputs[*frame].keypad = 1;
+        }
+        inputs
+    }
+
+    /// Editing an emulated frame gives the same state as running the edited movie from power-on
+    #[test]
+    fn test_edit_past_frame() {
+        let frames = SAVESTATE_INTERVAL * 2 + 10;
+        let mut movie = Movie::new(PROGRAM.to_vec(), 1, Variant::Chip8, inputs(frames, &[]));
+        movie.seek(frames);
+        let unedited = movie.chip8_mut().state_checksum();
+
+        movie.toggle(SAVESTATE_INTERVAL + 3, 0);
+        assert_eq!(movie.position(), frames);
+        let edited = movie.chip8_mut().state_checksum();
+        assert_ne!(edited, unedited);
+
+        let mut expected = Movie::new(PROGRAM.to_vec(), 1, Variant::Chip8, inputs(frames, &[SAVESTATE_INTERVAL + 3]));
+        expected.seek(frames);
+        assert_eq!(edited, expected.chip8_mut().state_checksum());
+    }
+
+    /// Going back loads a savestate and re-runs up to the requested frame
+    #[test]
+    fn test_seek_back() {
+        let frames = SAVESTATE_INTERVAL * 3;
+        let mut movie = Movie::new(PROGRAM.to_vec(), 1, Variant::Chip8, inputs(frames, &[5, 70, 150]));
+        movie.seek(100);
+        let at_100 = movie.chip8_mut().state_checksum();
+        movie.seek(frames);
+        movie.seek(100);
+        assert_eq!(movie.position(), 100);
+        assert_eq!(movie.chip8_mut().state_checksum(), at_100);
+        assert_eq!(movie.to_replay().frame_count(), frames as u64);
+    }
+
+    /// Restoring a save point brings back its movie and state, wherever the playhead is
+    #[test]
+    fn test_restore_save_point() {
+        let mut movie = Movie::new(PROGRAM.to_vec(), 1, Variant::Chip8, inputs(100, &[10]));
+        movie.seek(SAVESTATE_INTERVAL + 5);
+        let save_point = movie.save_point();
+        let saved = movie.chip8_mut().state_checksum();
+
+        movie.toggle(20, 0);
+        movie.seek(100);
+        movie.restore(&save_point);
+        assert_eq!(movie.position(), SAVESTATE_INTERVAL + 5);
+        assert_eq!(movie.chip8_mut().state_checksum(), saved);
+        assert_eq!(movie.input(20), FrameInput { keypad: 0, second_keypad: 0 });
+        movie.seek(0);
+        movie.seek(SAVESTATE_INTERVAL + 5);
+        assert_eq!(movie.chip8_mut().state_checksum(), saved);
+    }
+}
@@ -0,0 +1,143 @@
+//! Counts of what happened during a session, printed on exit with `--telemetry`, written as JSON
+//! with `--telemetry-file` and shown by the console's `telemetry` command, for tuning the speed
+//! and for bug reports.
+//!
+//! Instructions are counted across resets and reloads, unlike the machine's own cycle count.
+//! Errors and warnings are the ones logged, counted as the logger prints them.
+
+use crate::chip8::Chip8;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+use tracing::Level;
+
+static ERRORS: AtomicU64 = AtomicU64::new(0);
+static WARNINGS: AtomicU64 = AtomicU64::new(0);
+
+/// Counts a message the logger prints
+pub fn count_log(level: Level) {
+    match level {
+        Level::ERROR => ERRORS.fetch_add(1, Ordering::Relaxed),
+        Level::WARN => WARNINGS.fetch_add(1, Ordering::Relaxed),
+        _ => 0,
+    };
+}
+
+pub struct Telemetry {
+    start: Instant,
+    instructions: u64,
+    frames: u64,
+    draws: u64,
+    state_saves: u64,
+    state_loads: u64,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Summary {
+    pub seconds: f64,
+    pub instructions: u64,
+    pub instructions_per_second: f64,
+    /// Window updates with a new picture
+    pub frames: u64,
+    pub frames_per_second: f64,
+    /// DXYN run
+    pub draws: u64,
+    pub state_saves: u64,
+    pub state_loads: u64,
+    pub errors: u64,
+    pub warnings: u64,
+}
+
+impl Telemetry {
+    pub fn new() -> Self {
+        Telemetry { start: Instant::now(), instructions: 0, frames: 0, draws: 0, state_saves: 0, state_loads: 0 }
+    }
+
+    /// Counts the instruction about to run
+    pub fn instruction(&mut self, chip8: &Chip8) {
+        self.instructions += 1;
+        let program_counter = chip8.program_counter() as usize;
+        if chip8.memory().get(program_counter).is_some_and(|byte| byte >> 4 == 0xD) {
+            self.draws += 1;
+        }
+    }
+
+    pub fn frame(&mut self) {
+        self.frames += 1;
+    }
+
+    pub fn state_saved(&mut self) {
+        self.state_saves += 1;
+    }
+
+    pub fn state_loaded(&mut self) {
+        self.state_loads += 1;
+    }
+
+    pub fn summary(&self) -> Summary {
+        let seconds = self.start.elapsed().as_secs_f64();
+        let rate = |count: u64| if seconds > 0.0 { count as f64 / seconds } else { 0.0 };
+        Summary {
+            seconds,
+            instructions: self.instructions,
+            instructions_per_second: rate(self.instructions),
+            frames: self.frames,
+            frames_per_second: rate(self.frames),
+            draws: self.draws,
+            state_saves: self.state_saves,
+            state_loads: self.state_loads,
+            errors: ERRORS.load(Ordering::Relaxed),
+            warnings: WARNINGS.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Summary {
+    pub fn describe(&self) -> String {
+        format!(
+            "{:.1}s, {} instructions ({:.0}/s), {} frames ({:.1}/s), {} sprite draws\n{} states saved, {} loaded, {} errors and {} warnings logged",
+            self.seconds,
+            self.instructions,
+            self.instructions_per_second,
+            self.frames,
+            self.frames_per_second,
+            self.draws,
+            self.state_saves,
+            self.state_loads,
+            self.errors,
+            self.warnings
+        )
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, text).map_err(|e| format!("Could not write {}\n{}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Chip8;
+    use crate::run::power_on;
+    use crate::telemetry::Telemetry;
+
+    /// Instructions are counted with the draws among them, across machines
+    #[test]
+    fn test_telemetry() {
+        let mut telemetry = Telemetry::new();
+        // Clear, draw, draw
+        let mut chip8 = power_on(Chip8::builder(), &[0x00, 0xE0, 0xD0, 0x05, 0xD1, 0x15]);
+        for _ in 0..3 {
+            telemetry.instruction(&chip8);
+            chip8.emulate_cycle();
+        }
+        let chip8 = power_on(Chip8::builder(), &[0xD0, 0x05]);
+        telemetry.instruction(&chip8);
+        telemetry.frame();
+        telemetry.state_saved();
+        let summary = telemetry.summary();
+        assert_eq!((summary.instructions, summary.draws, summary.frames, summary.state_saves, summary.state_loads), (4, 3, 1, 1, 0));
+    }
+}
@@ -0,0 +1,303 @@
+//! `chip8 test-suite`, running Timendus' chip8-test-suite headless as CHIP-8 and CHIP-8X and
+//! reporting which ROMs pass.
+//!
+//! The suite's ROMs are looked for by name in a directory (`3-corax+.ch8` and so on). The ones
+//! that need a person at the keypad or SCHIP are left out, and 0x1FF is set to 1 before each run
+//! so the quirks test picks CHIP-8 without asking.
+//!
+//! The tests show their results as marks on the screen. The marks drawn are read as they're drawn:
+//! a run with marks passes when it halts with none of them failing. The OK and NO of corax89's
+//! opcode test, `roms/test_opcode.ch8`, are known by their bytes. The checkmarks and crosses of
+//! the suite's own tests are known by their shape, so any size of them is read: a cross is the
+//! two diagonals of a square, a checkmark a V with a short left arm. A ROM drawing no marks passes
+//! when its screen hash matches the one recorded for it in the expected file instead: after
+//! checking the screens by eye with `--show`, `--save` records them.
+//!
+//! Quirk profiles like the VIP's or SCHIP's are out of scope: the ROMs run with the default
+//! `Quirks`, so the quirks test only reports how this emulator behaves unless told otherwise.
+
+use crate::chip8::{Chip8, Chip8Builder, Instruction, Variant};
+use crate::headless::{self, Outcome};
+use crate::run;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
+
+/// The suite's ROMs that run on their own, in order
+const ROMS: [&str; 5] = ["1-chip8-logo", "2-ibm-logo", "3-corax+", "4-flags", "5-quirks"];
+
+/// Address the suite reads the platform to test from instead of asking, and CHIP-8's number
+const PLATFORM_ADDRESS: u16 = 0x1FF;
+const PLATFORM_CHIP8: u8 = 1;
+
+/// Sprites of corax89's OK and NO, and whether each is a pass
+const CORAX_MARKS: [(&[u8], bool); 2] = [(&[0xEA, 0xAC, 0xAA, 0xEA], true), (&[0xCE, 0xAA, 0xAA, 0xAE], false)];
+
+/// Pixels of a sprite, row first
+type Pixels = BTreeSet<(usize, usize)>;
+
+/// Recorded screen hashes by profile and ROM
+pub type Expected = BTreeMap<String, BTreeMap<String, String>>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Profile {
+    /// The emulator's defaults
+    Chip8,
+    /// CHIP-8X, with the second keypad
+    Chip8X,
+}
+
+pub const PROFILES: [Profile; 2] = [Profile::Chip8, Profile::Chip8X];
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" => Ok(Profile::Chip8),
+            "chip8x" | "chip-8x" => Ok(Profile::Chip8X),
+            _ => Err(format!("Unknown profile: {}, expected chip8 or chip8x", s)),
+        }
+    }
+}
+
+impl Profile {
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::Chip8 => "chip8",
+            Profile::Chip8X => "chip8x",
+        }
+    }
+
+    fn builder(self) -> Chip8Builder {
+        let builder = Chip8::builder().seed(0);
+        match self {
+            Profile::Chip8 => builder,
+            Profile::Chip8X => builder.variant(Variant::Chip8X),
+        }
+    }
+}
+
+/// How one ROM did under one profile
+pub struct RomResult {
+    pub rom: String,
+    pub outcome: Outcome,
+    pub hash: String,
+    /// The marks left on the screen in reading order, true for a pass
+    pub marks: Vec<bool>,
+    /// The screen as rows of `#` and `.`
+    pub screen: String,
+}
+
+/// Runs the suite's ROMs found in a directory for up to a number of frames each, skipping the
+/// missing ones
+pub fn run(dir: &Path, profile: Profile, frames: u64) -> Result<Vec<RomResult>, String> {
+    let mut results = Vec::new();
+    for rom in ROMS {
+        let path = dir.join(format!("{}.ch8", rom));
+        if !path.exists() {
+            continue;
+        }
+        let program = fs::read(&path).map_err(|e| format!("Could not read {}\n{}", path.display(), e))?;
+        results.push(run_rom(rom, &program, profile, frames));
+    }
+    if results.is_empty() {
+        return Err(format!("None of the test suite's ROMs are in {}, like {}.ch8", dir.display(), ROMS[2]));
+    }
+    Ok(results)
+}
+
+/// Runs a ROM for up to a number of frames, reading the marks it draws along the way
+fn run_rom(rom: &str, program: &[u8], profile: Profile, frames: u64) -> RomResult {
+    let mut chip8 = run::power_on(profile.builder(), program);
+    chip8.write_memory(PLATFORM_ADDRESS, &[PLATFORM_CHIP8]);
+    // Marks by where they are, row first. Drawing the same mark again erases it
+    let marks = Rc::new(RefCell::new(BTreeMap::new()));
+    let drawn = marks.clone();
+    let mut interpreter = headless::interpreter();
+    let step = Box::new(move |chip8: &mut Chip8, budget| {
+        let address = chip8.program_counter() as usize;
+        let opcode = chip8.memory().get(address..address + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]));
+        if let Some(Instruction::Draw(x, y, rows)) = opcode.and_then(|opcode| Instruction::decode(opcode, chip8.variant())) {
+            let index = chip8.index() as usize;
+            let sprite = chip8.memory().get(index..index + rows as usize).unwrap_or_default();
+            if let Some(pass) = mark(sprite) {
+                let position = (chip8.registers()[y as usize], chip8.registers()[x as usize]);
+                let mut drawn = drawn.borrow_mut();
+                if drawn.get(&position) == Some(&pass) {
+                    drawn.remove(&position);
+                } else {
+                    drawn.insert(position, pass);
+                }
+            }
+        }
+        interpreter(chip8, budget)
+    });
+    let outcome = headless::run_frames_with(&mut chip8, 1, frames, true, step);
+    let marks = marks.borrow().values().copied().collect();
+    RomResult { rom: String::from(rom), outcome, hash: headless::screen_hash(&chip8), marks, screen: screen_text(&chip8) }
+}
+
+/// Whether a sprite is one of the marks the tests draw, and whether it's a pass
+fn mark(sprite: &[u8]) -> Option<bool> {
+    if let Some((_, pass)) = CORAX_MARKS.iter().find(|(mark, _)| *mark == sprite) {
+        return Some(*pass);
+    }
+    let (pixels, width, height) = shape(sprite)?;
+    if cross(width, height).as_ref() == Some(&pixels) {
+        Some(false)
+    } else if checkmark(width, height).as_ref() == Some(&pixels) {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+/// The pixels of a sprite as (row, column) from the corner of the box around them, with the box's
+/// width and height
+fn shape(sprite: &[u8]) -> Option<(Pixels, usize, usize)> {
+    let pixels: Vec<(usize, usize)> = sprite
+        .iter()
+        .enumerate()
+        .flat_map(|(row, byte)| (0..8).filter(move |column| byte & (0x80 >> column) != 0).map(move |column| (row, column)))
+        .collect();
+    let top = pixels.iter().map(|(row, _)| *row).min()?;
+    let bottom = pixels.iter().map(|(row, _)| *row).max()?;
+    let left = pixels.iter().map(|(_, column)| *column).min()?;
+    let right = pixels.iter().map(|(_, column)| *column).max()?;
+    let shape = pixels.iter().map(|(row, column)| (row - top, column - left)).collect();
+    Some((shape, right - left + 1, bottom - top + 1))
+}
+
+/// Both diagonals of a square at least 3 pixels wide
+fn cross(width: usize, height: usize) -> Option<Pixels> {
+    if width != height || width < 3 {
+        return None;
+    }
+    Some((0..width).flat_map(|row| [(row, row), (row, width - 1 - row)]).collect())
+}
+
+/// A right arm from the top right corner down to the bottom, and a shorter left arm from there up
+/// to the left edge. The bottom is left of the middle, so a V isn't one
+fn checkmark(width: usize, height: usize) -> Option<Pixels> {
+    let bottom = width.checked_sub(height).filter(|bottom| *bottom >= 1 && bottom + 1 < height)?;
+    let right = (0..height).map(|row| (row, width - 1 - row));
+    let left = (0..=bottom).map(|step| (height - 1 - step, bottom - step));
+    Some(right.chain(left).collect())
+}
+
+fn screen_text(chip8: &Chip8) -> String {
+    let rows: Vec<String> = chip8.display().chunks(run::WIDTH).map(|row| row.iter().map(|pixel| if *pixel != 0 { '#' } else { '.' }).collect()).collect();
+    rows.join("\n")
+}
+
+/// Whether the run halted with only passing marks, or without marks on the recorded screen.
+/// `None` for a run without marks or a recorded screen
+pub fn passed(result: &RomResult, profile: Profile, expected: &Expected) -> Option<bool> {
+    let halted = matches!(result.outcome, Outcome::Halted { .. });
+    if !result.marks.is_empty() {
+        return Some(halted && result.marks.iter().all(|pass| *pass));
+    }
+    let hash = expected.get(profile.name())?.get(&result.rom)?;
+    Some(halted && *hash == result.hash)
+}
+
+/// A line per ROM, with the screens if asked
+pub fn report(profile: Profile, results: &[RomResult], expected: &Expected, show: bool) -> String {
+    let mut text = format!("Profile {}\n", profile.name());
+    for result in results {
+        let verdict = match passed(result, profile, expected) {
+            Some(true) => "pass",
+            Some(false) => "FAIL",
+            None => "no expected screen",
+        };
+        let _ = write!(text, "  {:<14} {:<18} {}", result.rom, verdict, result.outcome.describe());
+        if !result.marks.is_empty() {
+            let fails = result.marks.iter().filter(|pass| !**pass).count();
+            let _ = write!(text, ", {} of {} marks failing", fails, result.marks.len());
+        }
+        let _ = writeln!(text);
+        if show {
+            let _ = writeln!(text, "{}", result.screen);
+        }
+    }
+    let passes = results.iter().filter(|result| passed(result, profile, expected) == Some(true)).count();
+    let _ = write!(text, "  {} of {} passed", passes, results.len());
+    text
+}
+
+/// The expected file next to the ROMs, if not given
+pub fn expected_path(dir: &Path, path: Option<PathBuf>) -> PathBuf {
+    path.unwrap_or_else(|| dir.join("expected.toml"))
+}
+
+pub fn load_expected(path: &Path) -> Result<Expected, String> {
+    if !path.exists() {
+        return Ok(Expected::new());
+    }
+    let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}\n{}", path.display(), e))?;
+    toml::from_str(&text).map_err(|e| format!("Could not parse {}\n{}", path.display(), e))
+}
+
+pub fn save_expected(expected: &Expected, path: &Path) -> Result<(), String> {
+    let text = toml::to_string(expected).map_err(|e| e.to_string())?;
+    fs::write(path, text).map_err(|e| format!("Could not write {}\n{}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::headless::Outcome;
+    use crate::test_suite::{mark, passed, run_rom, Expected, Profile, RomResult};
+
+    /// Runs without marks pass on the recorded screen once halted, and have no verdict without a
+    /// recording
+    #[test]
+    fn test_passed() {
+        let result = |outcome: Outcome| RomResult { rom: String::from("2-ibm-logo"), outcome, hash: String::from("ab"), marks: Vec::new(), screen: String::new() };
+        let mut expected = Expected::new();
+        assert_eq!(passed(&result(Outcome::Halted { frame: 10 }), Profile::Chip8, &expected), None);
+        expected.entry(String::from("chip8")).or_default().insert(String::from("2-ibm-logo"), String::from("ab"));
+        assert_eq!(passed(&result(Outcome::Halted { frame: 10 }), Profile::Chip8, &expected), Some(true));
+        assert_eq!(passed(&result(Outcome::Ran { frames: 10 }), Profile::Chip8, &expected), Some(false));
+        assert_eq!(passed(&result(Outcome::Halted { frame: 10 }), Profile::Chip8X, &expected), None);
+        assert_eq!("CHIP-8X".parse::<Profile>(), Ok(Profile::Chip8X));
+        assert!("vip".parse::<Profile>().is_err());
+    }
+
+    /// Checkmarks and crosses are read by shape wherever they sit in the sprite, letters aren't
+    #[test]
+    fn test_mark_shapes() {
+        assert_eq!(mark(&[0xEA, 0xAC, 0xAA, 0xEA]), Some(true));
+        assert_eq!(mark(&[0xCE, 0xAA, 0xAA, 0xAE]), Some(false));
+        // Checkmarks 5 by 4 and 4 by 3, one moved down a row and right
+        assert_eq!(mark(&[0b00001000, 0b00010000, 0b10100000, 0b01000000]), Some(true));
+        assert_eq!(mark(&[0, 0b00000100, 0b00101000, 0b00010000]), Some(true));
+        // Crosses 3 by 3 and 4 by 4
+        assert_eq!(mark(&[0b10100000, 0b01000000, 0b10100000]), Some(false));
+        assert_eq!(mark(&[0b00100100, 0b00011000, 0b00011000, 0b00100100]), Some(false));
+        // V, an X 3 wide and 5 tall, a line and nothing
+        assert_eq!(mark(&[0b10001000, 0b01010000, 0b00100000]), None);
+        assert_eq!(mark(&[0b10100000, 0b10100000, 0b01000000, 0b10100000, 0b10100000]), None);
+        assert_eq!(mark(&[0b11110000]), None);
+        assert_eq!(mark(&[0, 0]), None);
+    }
+
+    /// corax89's test passes on its marks alone, and fails when one of its checks does
+    #[test]
+    fn test_marks() {
+        let mut program = include_bytes!("../roms/test_opcode.ch8").to_vec();
+        let result = run_rom("test_opcode", &program, Profile::Chip8, 1000);
+        assert_eq!(result.marks, vec![true; 18]);
+        assert_eq!(passed(&result, Profile::Chip8, &Expected::new()), Some(true));
+
+        // V6 set to 0x2C instead of 0x2B, failing the 3XNN check and two more using it
+        program[0x59] = 0x2C;
+        let result = run_rom("test_opcode", &program, Profile::Chip8, 1000);
+        assert_eq!((result.marks.len(), result.marks.iter().filter(|pass| !**pass).count()), (18, 3));
+        assert_eq!(passed(&result, Profile::Chip8, &Expected::new()), Some(false));
+    }
+}
@@ -0,0 +1,111 @@
+//! Small pixel font for drawing text into window buffers.
+//!
+//! Glyphs are 3x5 pixels, drawn in 4x6 cells so neighbouring characters and lines don't touch.
+//! Letters are all capitals; characters without a glyph show as `?`.
+
+/// Width of a character, including the space after it
+pub const CHAR_WIDTH: usize = 4;
+/// Height of a line of text, including the space below it
+pub const LINE_HEIGHT: usize = 6;
+
+/// Draws text with its top left corner at (x, y), clipped to the buffer
+pub fn draw(buffer: &mut [u32], width: usize, (x, y): (usize, usize), text: &str, color: u32) {
+    let height = buffer.len() / width;
+    for (position, character) in text.chars().enumerate() {
+        let left = x + position * CHAR_WIDTH;
+        if left >= width {
+            break;
+        }
+        for (row, bits) in glyph(character).iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) != 0 && left + column < width && y + row < height {
+                    buffer[(y + row) * width + left + column] = color;
+                }
+            }
+        }
+    }
+}
+
+/// Rows of a character, the three low bits of each row being its pixels from left to right
+fn glyph(character: char) -> [u8; 5] {
+    match character.to_ascii_uppercase() {
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        ';' => [0b000, 0b010, 0b000, 0b010, 0b100],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '(' => [0b001, 0b010, 0b010, 0b010, 0b001],
+        ')' => [0b100, 0b010, 0b010, 0b010, 0b100],
+        '[' => [0b011, 0b010, 0b010, 0b010, 0b011],
+        ']' => [0b110, 0b010, 0b010, 0b010, 0b110],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '>' => [0b100, 0b010, 0b001, 0b010, 0b100],
+        '<' => [0b001, 0b010, 0b100, 0b010, 0b001],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        '*' => [0b101, 0b010, 0b101, 0b000, 0b000],
+        '&' => [0b010, 0b101, 0b010, 0b101, 0b011],
+        '|' => [0b010, 0b010, 0b010, 0b010, 0b010],
+        '^' => [0b010, 0b101, 0b000, 0b000, 0b000],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '\'' => [0b010, 0b010, 0b000, 0b000, 0b000],
+        '"' => [0b101, 0b101, 0b000, 0b000, 0b000],
+        _ => [0b110, 0b001, 0b010, 0b000, 0b010],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::text::{draw, CHAR_WIDTH};
+
+    /// Characters are drawn side by side and clipped at the edge of the buffer
+    #[test]
+    fn test_draw() {
+        let width = CHAR_WIDTH * 2 - 1;
+        let mut buffer = vec![0; width * 5];
+        draw(&mut buffer, width, (0, 0), "1L", 1);
+        let rows: Vec<String> =
+            buffer.chunks(width).map(|row| row.iter().map(|pixel| if *pixel == 1 { 'X' } else { '.' }).collect()).collect();
+        assert_eq!(rows, [".X..X..", "XX..X..", ".X..X..", ".X..X..", "XXX.XXX"]);
+    }
+}
@@ -0,0 +1,330 @@
+//! `chip8 run --threaded`, the machine on a thread of its own, paced by the clock instead of by
+//! window updates. The window's thread only reads the keyboard, plays sounds and shows frames, so
+//! a slow or stalled window drops frames instead of slowing the game down.
+//!
+//! Key states and hotkeys go to the machine over one channel, frames come back over another
+//! holding one at a time, and sounds and failures over a third. Only the basics are there: the
+//! keypads, quitting, pausing, resetting, save states, speed and screenshots. The debugger,
+//! scripts, cheats, replays and overlays need the machine and the window on the same thread.
+
+use crate::audio::Beeper;
+use crate::chip8::Chip8;
+use crate::cli::RunArgs;
+use crate::fatal;
+use crate::hotkeys::{Action, Hotkeys};
+use crate::run::{self, Pace, FRAME_RATE, HEIGHT, MAX_SPEED, WIDTH};
+use crate::screenshot;
+use minifb::{KeyRepeat, Scale, ScaleMode, Window, WindowOptions};
+use std::process;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TryRecvError, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, debug_span, error, info, warn};
+
+/// Time a frame takes, the same as in `run`, so games go as fast either way
+const FRAME: Duration = Duration::from_micros(1_000_000 / FRAME_RATE as u64);
+/// Frames the machine catches up on after falling behind the clock, before it gives up on them
+const MAX_LAG: u32 = 8;
+
+/// What the window's thread tells the machine
+#[derive(Debug)]
+enum Control {
+    /// State of both keypads
+    Keys([bool; 16], [bool; 16]),
+    Paused(bool),
+    Reset,
+    SaveState,
+    LoadState,
+    Speed(u32),
+}
+
+/// What the machine tells the window's thread
+enum Event {
+    /// Play a tone of this many ticks
+    Beep(u8),
+    Failed(String),
+}
+
+/// The machine's side, run a frame at a time
+struct Machine<'a> {
+    chip8: Chip8,
+    power_on: Box<dyn Fn() -> Chip8 + Send + 'a>,
+    savestate: Option<Chip8>,
+    keys: ([bool; 16], [bool; 16]),
+    paused: bool,
+    speed: u32,
+    pace: Pace,
+    buffer: Vec<u32>,
+    // The screen changed since the window last took a frame
+    unsent: bool,
+}
+
+impl<'a> Machine<'a> {
+    fn new(power_on: Box<dyn Fn() -> Chip8 + Send + 'a>, speed: u32, pace: Pace) -> Self {
+        Machine {
+            chip8: power_on(),
+            power_on,
+            savestate: None,
+            keys: ([false; 16], [false; 16]),
+            paused: false,
+            speed,
+            pace,
+            buffer: vec![0; WIDTH * HEIGHT],
+            unsent: false,
+        }
+    }
+
+    fn control(&mut self, control: Control) {
+        match control {
+            Control::Keys(keypad, second_keypad) => self.keys = (keypad, second_keypad),
+            Control::Paused(paused) => self.paused = paused,
+            Control::Reset => self.chip8 = (self.power_on)(),
+            Control::SaveState => self.savestate = Some(self.chip8.clone()),
+            Control::LoadState => {
+                if let Some(state) = &self.savestate {
+                    self.chip8 = state.clone();
+                    self.chip8.force_redraw();
+                }
+            }
+            Control::Speed(speed) => self.speed = speed,
+        }
+    }
+
+    /// Runs a frame's instructions unless paused, returning the last tone asked for
+    fn run_frame(&mut self) -> Result<Option<u8>, String> {
+        if self.paused {
+            return Ok(None);
+        }
+        // Set again every frame, as resets and loaded states start with other keys
+        self.chip8.set_keys(self.keys.0);
+        self.chip8.set_second_keypad(self.keys.1);
+        let mut tone = None;
+        for _ in 0..self.pace.frame(self.speed) {
+            self.chip8.try_emulate_cycle().map_err(|error| error.to_string())?;
+            tone = self.chip8.take_sound_request().or(tone);
+        }
+        Ok(tone)
+    }
+
+    /// Offers the screen to the window if it changed, again next frame if the window hasn't taken
+    /// the last one yet. False once the window is gone
+    fn send_frame(&mut self, frames: &SyncSender<Vec<u32>>) -> bool {
+        self.unsent |= self.chip8.draw_to_buffer(&mut self.buffer);
+        if self.unsent {
+            match frames.try_send(self.buffer.clone()) {
+                Ok(()) => self.unsent = false,
+                Err(TrySendError::Full(_)) => {}
+                Err(TrySendError::Disconnected(_)) => return false,
+            }
+        }
+        true
+    }
+}
+
+/// Runs the machine a frame at a time on the clock, until the window's thread hangs up or the
+/// program fails
+fn emulate(mut machine: Machine, controls: Receiver<Control>, frames: SyncSender<Vec<u32>>, events: Sender<Event>) {
+    let mut deadline = Instant::now();
+    let mut frame_number: u64 = 0;
+    loop {
+        frame_number += 1;
+        let _span = debug_span!("frame", number = frame_number).entered();
+        loop {
+            match controls.try_recv() {
+                Ok(control) => machine.control(control),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        }
+        let tone = machine.run_frame();
+        if !machine.send_frame(&frames) {
+            return;
+        }
+        match tone {
+            Ok(Some(ticks)) => {
+                let _ = events.send(Event::Beep(ticks));
+            }
+            Ok(None) => {}
+            Err(reason) => {
+                let _ = events.send(Event::Failed(reason));
+                return;
+            }
+        }
+
+        deadline += FRAME;
+        let now = Instant::now();
+        if deadline > now {
+            thread::sleep(deadline - now);
+        } else if now - deadline > FRAME * MAX_LAG {
+            deadline = now;
+        }
+    }
+}
+
+/// Runs a ROM in a window with the machine on its own thread, until the window is closed
+pub fn run(args: &RunArgs) {
+    let config = args.input.load_config();
+    let keymap = args.input.keymap(&config);
+    let second_keymap = config.second_keymap.build(config.keymap.preset).unwrap_or_else(fatal);
+    let hotkeys = Hotkeys::new(&config.hotkeys, &[&keymap, &second_keymap], &[]).unwrap_or_else(fatal);
+
+    #[cfg(feature = "plugins")]
+    if !args.plugins.is_empty() {
+        warn!("Plugins aren't loaded with --threaded");
+    }
+
+    let program = run::load_program(&args.rom).unwrap_or_else(fatal);
+    let font = run::machine_font(args, &program.options);
+    let seed = args.seed.unwrap_or_else(rand::random);
+    let mut speed = 1;
+    let power_on = || run::power_on(run::configure(args, &program.options, args.variant, font.as_ref()).seed(seed), &program.bytes);
+
+    let mut window = Window::new(
+        "Chip8 Emulator",
+        WIDTH,
+        HEIGHT,
+        WindowOptions {
+            borderless: false,
+            transparency: false,
+            title: true,
+            resize: false,
+            scale: Scale::X16,
+            scale_mode: ScaleMode::Stretch,
+            topmost: false,
+        },
+    )
+        .unwrap_or_else(fatal);
+    let beeper = Beeper::new(None);
+
+    let (controls, control_receiver) = mpsc::channel();
+    let (frame_sender, frames) = mpsc::sync_channel(1);
+    let (event_sender, events) = mpsc::channel();
+    let mut frame = vec![0; WIDTH * HEIGHT];
+    let mut keys = ([false; 16], [false; 16]);
+    let mut paused = false;
+    let mut saved = false;
+    let mut failed = false;
+    thread::scope(|scope| {
+        let machine = Machine::new(Box::new(power_on), speed, Pace::new(program.options.tickrate));
+        scope.spawn(move || emulate(machine, control_receiver, frame_sender, event_sender));
+
+        'window: while window.is_open() {
+            let pressed = window.get_keys_pressed(KeyRepeat::No).unwrap_or_default();
+            for action in hotkeys.triggered(&pressed) {
+                let control = match action {
+                    Action::Quit => break 'window,
+                    Action::Pause => {
+                        paused = !paused;
+                        info!("{}", if paused { "Paused" } else { "Resumed" });
+                        Control::Paused(paused)
+                    }
+                    Action::Reset => {
+                        debug!("Reset");
+                        Control::Reset
+                    }
+                    Action::SaveState => {
+                        saved = true;
+                        info!("State saved");
+                        Control::SaveState
+                    }
+                    Action::LoadState if !saved => {
+                        warn!("No state saved yet");
+                        continue;
+                    }
+                    Action::LoadState => {
+                        debug!("State loaded");
+                        Control::LoadState
+                    }
+                    Action::SpeedUp | Action::SpeedDown => {
+                        speed = if action == Action::SpeedUp { (speed * 2).min(MAX_SPEED) } else { (speed / 2).max(1) };
+                        info!("Speed: {}x", speed);
+                        Control::Speed(speed)
+                    }
+                    Action::Screenshot => {
+                        let path = run::timestamped_path(&args.rom, "png");
+                        match screenshot::save(&frame, WIDTH, HEIGHT, &path) {
+                            Ok(()) => info!("Saved screenshot to {}", path.display()),
+                            Err(error) => error!("{}", error),
+                        }
+                        continue;
+                    }
+                    _ => {
+                        warn!("Not available with --threaded");
+                        continue;
+                    }
+                };
+                // A machine that stopped says why with an event
+                let _ = controls.send(control);
+            }
+
+            let pressed_keys = window.get_keys().unwrap_or_default();
+            let state = (keymap.keypad_state(&pressed_keys), second_keymap.keypad_state(&pressed_keys));
+            if state != keys {
+                keys = state;
+                let _ = controls.send(Control::Keys(keys.0, keys.1));
+            }
+
+            for event in events.try_iter() {
+                match event {
+                    Event::Beep(ticks) => beeper.beep(ticks),
+                    Event::Failed(reason) => {
+                        error!("{}", reason);
+                        failed = true;
+                        break 'window;
+                    }
+                }
+            }
+
+            // Only the latest frame is ever waiting, the machine holds on to newer ones meanwhile
+            match frames.try_recv() {
+                Ok(latest) => {
+                    frame = latest;
+                    window.update_with_buffer(&frame, WIDTH, HEIGHT).unwrap();
+                }
+                Err(_) => window.update(),
+            }
+        }
+        // Hanging up stops the machine
+        drop(controls);
+    });
+    if failed {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Chip8;
+    use crate::run::{power_on, Pace};
+    use crate::threaded::{Control, Machine};
+    use std::sync::mpsc;
+
+    /// Frames the window hasn't taken yet hold back newer ones until it does, pausing stops the
+    /// machine and failures end the frame
+    #[test]
+    fn test_machine() {
+        // Draw digit 0, loop forever
+        let mut machine = Machine::new(Box::new(|| power_on(Chip8::builder(), &[0xD0, 0x05, 0x12, 0x02])), 2, Pace::new(None));
+        let (frames, window) = mpsc::sync_channel(1);
+        assert_eq!(machine.run_frame(), Ok(None));
+        assert!(machine.send_frame(&frames));
+        assert!(!machine.unsent);
+
+        machine.control(Control::Reset);
+        machine.run_frame().unwrap();
+        assert!(machine.send_frame(&frames));
+        assert!(machine.unsent);
+        let frame = window.try_recv().unwrap();
+        assert_ne!(frame[0], frame[4]);
+        assert!(machine.send_frame(&frames));
+        assert!(!machine.unsent);
+        assert!(window.try_recv().is_ok());
+
+        machine.control(Control::Paused(true));
+        machine.run_frame().unwrap();
+        assert_eq!(machine.chip8.cycles(), 2);
+
+        let mut machine = Machine::new(Box::new(|| power_on(Chip8::builder(), &[0x00, 0xEE])), 4, Pace::new(None));
+        assert!(machine.run_frame().is_err());
+    }
+}
@@ -0,0 +1,61 @@
+//! Virtual hex keypad for touchscreens.
+//!
+//! Touches reach the window as mouse input, so tapping (or clicking) a cell of the 4x4 grid holds
+//! that keypad key down. The grid is drawn faintly over the game once the screen is touched and
+//! hides itself again as soon as a keyboard key is pressed.
+
+use crate::keypad_grid::{self, CellStyle, GRID};
+use minifb::{MouseButton, MouseMode, Window};
+
+const DIGIT: u32 = 0x0000_6060;
+const TOUCHED: u32 = 0x0000_3030;
+
+pub struct TouchKeypad {
+    visible: bool,
+    // Grid index of the cell being touched
+    touched: Option<usize>,
+}
+
+impl TouchKeypad {
+    pub fn new() -> Self {
+        TouchKeypad { visible: false, touched: None }
+    }
+
+    /// Reads the pointer state and returns the keypad keys being touched. Returns true as the
+    /// second value when the overlay needs to be redrawn.
+    pub fn update(&mut self, window: &Window, keyboard_used: bool) -> ([bool; 16], bool) {
+        let was_visible = self.visible;
+        let was_touched = self.touched;
+
+        self.touched = if window.get_mouse_down(MouseButton::Left) {
+            window
+                .get_mouse_pos(MouseMode::Discard)
+                .and_then(|(x, y)| keypad_grid::cell_at(x, y))
+        } else {
+            None
+        };
+        if self.touched.is_some() {
+            self.visible = true;
+        } else if keyboard_used {
+            self.visible = false;
+        }
+
+        let mut keys = [false; 16];
+        if let Some(cell) = self.touched {
+            keys[GRID[cell]] = true;
+        }
+        (keys, self.visible != was_visible || self.touched != was_touched)
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// Draws the keypad over a game frame
+    pub fn draw(&self, buffer: &mut [u32], width: usize) {
+        keypad_grid::draw(buffer, width, |cell| CellStyle {
+            background: if self.touched == Some(cell) { Some(TOUCHED) } else { None },
+            foreground: DIGIT,
+        });
+    }
+}
@@ -0,0 +1,186 @@
+//! Ring buffer of the last instructions run, to show the path that led to a breakpoint or an
+//! error. Each entry has the registers the instruction uses and I as they were before it ran.
+//!
+//! Every instruction run can also be written to a file as a line of JSON, for other tools:
+//!
+//! ```text
+//! {"cycle":41,"dt":0,"i":522,"opcode":53269,"pc":518,"registers":{"VF":1},"st":0}
+//! ```
+//!
+//! `registers` has the registers the instruction changed with their new values, I and the
+//! timers are as it left them.
+
+use crate::chip8::{Chip8, Instruction};
+use crate::symbols::Symbols;
+use serde_json::{json, Map};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+pub struct Trace {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+}
+
+struct Entry {
+    address: u16,
+    opcode: u16,
+    registers: [u8; 16],
+    index: u16,
+}
+
+impl Trace {
+    /// Keeps the last `capacity` instructions
+    pub fn new(capacity: usize) -> Self {
+        Trace { entries: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    /// Records the instruction at the program counter, before it runs
+    pub fn record(&mut self, chip8: &Chip8) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(Entry::new(chip8));
+    }
+
+    /// The last `count` instructions, oldest first, decoded for the variant, with the memory I
+    /// pointed into named
+    pub fn lines(&self, count: usize, chip8: &Chip8, symbols: &Symbols) -> Vec<String> {
+        let skip = self.entries.len().saturating_sub(count);
+        self.entries
+            .iter()
+            .skip(skip)
+            .map(|entry| {
+                let instruction = Instruction::decode(entry.opcode, chip8.variant());
+                let mut values: Vec<String> = instruction
+                    .map(|instruction| instruction.registers())
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|register| format!("V{:X}={:02X}", register, entry.registers[*register]))
+                    .collect();
+                match symbols.name_of(entry.index) {
+                    Some(name) => values.push(format!("I={:#05X} ({})", entry.index, name)),
+                    None => values.push(format!("I={:#05X}", entry.index)),
+                }
+                let text = instruction.map_or_else(|| String::from("unknown"), |instruction| instruction.to_string());
+                format!("{:#05X}  {:04X}  {:<16}  ; {}", entry.address, entry.opcode, text, values.join(" "))
+            })
+            .collect()
+    }
+}
+
+impl Entry {
+    /// The instruction at the program counter and the machine before it runs
+    fn new(chip8: &Chip8) -> Self {
+        let address = chip8.program_counter();
+        let opcode = match chip8.memory().get(address as usize..address as usize + 2) {
+            Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]),
+            None => 0,
+        };
+        Entry { address, opcode, registers: chip8.registers(), index: chip8.index() }
+    }
+}
+
+/// Writes every instruction run as a line of JSON
+pub struct JsonTrace {
+    output: BufWriter<File>,
+    /// Instruction about to run and the cycle it runs in, written once it has
+    pending: Option<(u64, Entry)>,
+}
+
+impl JsonTrace {
+    pub fn create(path: &Path) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("Could not create {}: {}", path.display(), e))?;
+        Ok(JsonTrace { output: BufWriter::new(file), pending: None })
+    }
+
+    /// Writes the line of the instruction that ran last, if the machine just ran it, and keeps
+    /// the one at the program counter for the next call
+    pub fn record(&mut self, chip8: &Chip8) -> Result<(), String> {
+        self.finish(chip8)?;
+        self.pending = Some((chip8.cycles(), Entry::new(chip8)));
+        Ok(())
+    }
+
+    /// Writes the line of the instruction that ran last, when the machine stops
+    pub fn finish(&mut self, chip8: &Chip8) -> Result<(), String> {
+        let (cycle, before) = match self.pending.take() {
+            Some((cycle, before)) if chip8.cycles() == cycle + 1 => (cycle, before),
+            // Reset, or a savestate was loaded, instead of running the instruction
+            _ => return Ok(()),
+        };
+        let mut registers = Map::new();
+        for (register, (old, new)) in before.registers.iter().zip(chip8.registers().iter()).enumerate() {
+            if old != new {
+                registers.insert(format!("V{:X}", register), json!(new));
+            }
+        }
+        let (delay, sound) = chip8.timers();
+        let line = json!({
+            "cycle": cycle,
+            "pc": before.address,
+            "opcode": before.opcode,
+            "registers": registers,
+            "i": chip8.index(),
+            "dt": delay,
+            "st": sound,
+        });
+        writeln!(self.output, "{}", line).map_err(|e| e.to_string())
+    }
+}
+
+impl Drop for JsonTrace {
+    fn drop(&mut self) {
+        let _ = self.output.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Chip8;
+    use crate::symbols::Symbols;
+    use crate::trace::{JsonTrace, Trace};
+    use std::fs;
+
+    /// Only the last instructions are kept, with the registers they use
+    #[test]
+    fn test_trace() {
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&[0x60, 0x07, 0x61, 0x02, 0x80, 0x14, 0x12, 0x00]);
+        let mut trace = Trace::new(2);
+        for _ in 0..3 {
+            trace.record(&chip8);
+            chip8.emulate_cycle();
+        }
+        assert_eq!(
+            trace.lines(10, &chip8, &Symbols::default()),
+            vec!["0x202  6102  LD V1, 0x02       ; V1=00 I=0x000", "0x204  8014  ADD V0, V1        ; V0=07 V1=02 I=0x000"]
+        );
+        assert_eq!(trace.lines(1, &chip8, &Symbols::default()).len(), 1);
+    }
+
+    /// Lines have the registers each instruction changed
+    #[test]
+    fn test_json_trace() {
+        let path = std::env::temp_dir().join("chip8-json-trace-test.jsonl");
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&[0x60, 0x07, 0x61, 0x02, 0x80, 0x14]);
+        let mut trace = JsonTrace::create(&path).unwrap();
+        for _ in 0..3 {
+            trace.record(&chip8).unwrap();
+            chip8.emulate_cycle();
+        }
+        trace.finish(&chip8).unwrap();
+        drop(trace);
+
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[2], r#"{"cycle":2,"dt":0,"i":0,"opcode":32788,"pc":516,"registers":{"V0":9},"st":0}"#);
+    }
+}
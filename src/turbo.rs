@@ -0,0 +1,149 @@
+//! Autofire for individual keypad keys.
+//!
+//! Each entry in the config file names a CHIP-8 key, a key that switches autofire on and off
+//! for it, and a rate. While autofire is on, holding the key (from any input device) presses
+//! and releases it repeatedly at that rate instead of holding it down. The rate is kept in
+//! emulated instructions, so autofire stops while the game is paused, keeps up with the game when
+//! it is sped up, and presses the same way on every machine.
+//!
+//! ```toml
+//! [[turbo]]
+//! key = "5"
+//! toggle = "F11"
+//! rate = 10.0      # presses per second
+//! enabled = false  # state at startup
+//! ```
+
+use crate::keymap::key_from_name;
+use minifb::Key;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+#[derive(Deserialize, Serialize)]
+pub struct TurboConfig {
+    pub key: String,
+    pub toggle: String,
+    #[serde(default = "default_rate")]
+    pub rate: f32,
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+fn default_rate() -> f32 {
+    10.0
+}
+
+struct TurboKey {
+    chip8_key: usize,
+    toggle: Key,
+    rate: f32,
+    enabled: bool,
+}
+
+pub struct Turbo {
+    keys: Vec<TurboKey>,
+    // Emulated seconds since the emulator started
+    seconds: f64,
+}
+
+impl Turbo {
+    pub fn new(configs: &[TurboConfig]) -> Result<Self, String> {
+        let keys = configs
+            .iter()
+            .map(|config| {
+                let chip8_key = usize::from_str_radix(&config.key, 16)
+                    .ok()
+                    .filter(|k| *k < 16)
+                    .ok_or_else(|| format!("Invalid CHIP-8 key for turbo: {}", config.key))?;
+                let toggle = key_from_name(&config.toggle)
+                    .ok_or_else(|| format!("Unknown turbo toggle key: {}", config.toggle))?;
+                if config.rate <= 0.0 {
+                    return Err(format!("Turbo rate must be positive: {}", config.rate));
+                }
+                Ok(TurboKey { chip8_key, toggle, rate: config.rate, enabled: config.enabled })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Turbo { keys, seconds: 0.0 })
+    }
+
+    /// Keys switching autofire on and off
+    pub fn toggles(&self) -> Vec<Key> {
+        self.keys.iter().map(|turbo_key| turbo_key.toggle).collect()
+    }
+
+    /// Switches autofire for the keys whose toggle was pressed this frame
+    pub fn handle_toggles(&mut self, pressed: &[Key]) {
+        for turbo_key in self.keys.iter_mut() {
+            if pressed.contains(&turbo_key.toggle) {
+                turbo_key.enabled = !turbo_key.enabled;
+                info!(
+                    "Turbo {} for key {:X}",
+                    if turbo_key.enabled { "on" } else { "off" },
+                    turbo_key.chip8_key
+                );
+            }
+        }
+    }
+
+    /// Counts an instruction emulated while running `per_second` instructions a second, moving
+    /// autofire along
+    pub fn step(&mut self, per_second: u32) {
+        self.seconds += 1.0 / per_second as f64;
+    }
+
+    /// Releases held autofire keys during the second half of each press period
+    pub fn apply(&self, keypad: &mut [bool; 16]) {
+        for turbo_key in self.keys.iter().filter(|k| k.enabled) {
+            if (self.seconds * turbo_key.rate as f64).fract() >= 0.5 {
+                keypad[turbo_key.chip8_key] = false;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::run::FRAME_RATE;
+    use crate::turbo::{Turbo, TurboConfig};
+
+    fn config(key: &str, enabled: bool) -> TurboConfig {
+        TurboConfig { key: key.into(), toggle: String::from("F11"), rate: 10.0, enabled }
+    }
+
+    /// Which of `steps` instructions at `speed` autofire released key 5 on
+    fn released(turbo: &mut Turbo, speed: u32, steps: u64) -> Vec<bool> {
+        (0..steps)
+            .map(|_| {
+                let mut keypad = [true; 16];
+                turbo.apply(&mut keypad);
+                assert!(keypad[6]);
+                turbo.step(FRAME_RATE * speed);
+                !keypad[5]
+            })
+            .collect()
+    }
+
+    /// Held keys are released for the second half of each period, only while enabled
+    #[test]
+    fn test_apply() {
+        let mut turbo = Turbo::new(&[config("5", true), config("6", false)]).unwrap();
+        let period = FRAME_RATE as u64 / 10;
+        let expected: Vec<bool> = (0..period * 2).map(|frame| frame % period * 2 >= period).collect();
+        assert_eq!(released(&mut turbo, 1, period * 2), expected);
+    }
+
+    /// The period is counted in instructions, so sped up it lasts as many frames and more
+    /// instructions
+    #[test]
+    fn test_step_with_speed() {
+        let mut turbo = Turbo::new(&[config("5", true)]).unwrap();
+        let period = FRAME_RATE as u64 / 10 * 4;
+        let expected: Vec<bool> = (0..period * 2).map(|step| step % period * 2 >= period).collect();
+        assert_eq!(released(&mut turbo, 4, period * 2), expected);
+
+        // Changing speed part way keeps the time already counted
+        let mut turbo = Turbo::new(&[config("5", true)]).unwrap();
+        released(&mut turbo, 2, FRAME_RATE as u64 / 10);
+        assert_eq!(released(&mut turbo, 1, 1), [true]);
+    }
+}
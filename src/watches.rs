@@ -0,0 +1,100 @@
+//! Expressions evaluated every frame and shown in the watch panel of the debugger window. A
+//! value that changes is highlighted for half a second.
+
+use crate::chip8::Chip8;
+use crate::expression::Expression;
+
+/// Updates a changed value stays highlighted for
+const HIGHLIGHT_FRAMES: u32 = 30;
+
+#[derive(Default)]
+pub struct Watches {
+    watches: Vec<Watch>,
+}
+
+struct Watch {
+    expression: Expression,
+    /// `None` until first evaluated
+    value: Option<i64>,
+    /// Updates since the value last changed
+    since_change: u32,
+}
+
+impl Watches {
+    pub fn add(&mut self, expression: Expression) {
+        self.watches.push(Watch { expression, value: None, since_change: HIGHLIGHT_FRAMES });
+    }
+
+    /// Removes a watch by its number, counting from 1
+    pub fn remove(&mut self, number: usize) -> bool {
+        if (1..=self.watches.len()).contains(&number) {
+            self.watches.remove(number - 1);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    /// Evaluates every expression, once a frame
+    pub fn update(&mut self, chip8: &Chip8) {
+        for watch in &mut self.watches {
+            let value = watch.expression.evaluate(chip8);
+            if watch.value.is_some_and(|old| old != value) {
+                watch.since_change = 0;
+            } else {
+                watch.since_change = watch.since_change.saturating_add(1);
+            }
+            watch.value = Some(value);
+        }
+    }
+
+    /// `expression = value` for each watch, and whether the value changed recently
+    pub fn lines(&self) -> Vec<(String, bool)> {
+        self.watches
+            .iter()
+            .map(|watch| {
+                let value = match watch.value {
+                    Some(value) if value >= 0 => format!("{:#X}", value),
+                    Some(value) => value.to_string(),
+                    None => String::from("?"),
+                };
+                (format!("{} = {}", watch.expression, value), watch.since_change < HIGHLIGHT_FRAMES)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Chip8;
+    use crate::expression::Expression;
+    use crate::symbols::Symbols;
+    use crate::watches::Watches;
+
+    /// Values show in hex and are highlighted after they change, until the highlight runs out
+    #[test]
+    fn test_watches() {
+        let mut watches = Watches::default();
+        watches.add(Expression::parse("[0xE00] + 1", &Symbols::default()).unwrap());
+        let mut chip8 = Chip8::new();
+        assert_eq!(watches.lines(), vec![(String::from("[0xE00] + 1 = ?"), false)]);
+        watches.update(&chip8);
+        assert_eq!(watches.lines(), vec![(String::from("[0xE00] + 1 = 0x1"), false)]);
+
+        chip8.write_memory(0xE00, &[0x1E]);
+        watches.update(&chip8);
+        assert_eq!(watches.lines(), vec![(String::from("[0xE00] + 1 = 0x1F"), true)]);
+        for _ in 0..30 {
+            watches.update(&chip8);
+        }
+        assert!(!watches.lines()[0].1);
+
+        assert!(!watches.remove(2));
+        assert!(watches.remove(1));
+        assert!(watches.is_empty());
+    }
+}
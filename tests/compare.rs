@@ -0,0 +1,32 @@
+//! Runs `compare` with this emulator serving as its own reference, which has to match until its
+//! random numbers are made different.
+
+use std::path::Path;
+use std::process::Command;
+
+fn compare(reference_seed: u64) -> (bool, String) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let reference = format!("{} compare --serve --seed {}", env!("CARGO_BIN_EXE_chip-8-emu"), reference_seed);
+    let output = Command::new(env!("CARGO_BIN_EXE_chip-8-emu"))
+        .arg("compare")
+        .arg(root.join("roms/pong.rom"))
+        .args(["--reference", &reference, "--every", "100", "--instructions", "3000"])
+        .output()
+        .unwrap();
+    (output.status.success(), String::from_utf8_lossy(&output.stdout).into_owned() + &String::from_utf8_lossy(&output.stderr))
+}
+
+#[test]
+fn test_compare_with_itself() {
+    let (success, output) = compare(0);
+    assert!(success, "{}", output);
+    assert!(output.contains("States match after 3000 instructions"), "{}", output);
+}
+
+#[test]
+fn test_compare_finds_divergence() {
+    let (success, output) = compare(1);
+    assert!(!success, "{}", output);
+    assert!(output.contains("States differ after instruction"), "{}", output);
+    assert!(output.contains("RND"), "{}", output);
+}
@@ -0,0 +1,43 @@
+//! Runs the bundled ROMs headless and compares the hash of their screens with the ones listed in
+//! `golden.toml`, catching instructions that stopped doing what they did.
+
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+#[derive(Deserialize)]
+struct Golden {
+    rom: Vec<GoldenRom>,
+}
+
+#[derive(Deserialize)]
+struct GoldenRom {
+    path: String,
+    frames: u64,
+    #[serde(default)]
+    exit_on_halt: bool,
+    hash: String,
+}
+
+#[test]
+fn test_golden_screens() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let golden: Golden = toml::from_str(&fs::read_to_string(root.join("tests/golden.toml")).unwrap()).unwrap();
+    let mut mismatches = Vec::new();
+    for rom in &golden.rom {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_chip-8-emu"));
+        command.arg(root.join(&rom.path)).args(["--headless", "--print-hash", "--max-frames", &rom.frames.to_string()]);
+        if rom.exit_on_halt {
+            command.arg("--exit-on-halt");
+        }
+        let output = command.output().unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{} did not finish: {}{}", rom.path, stdout, String::from_utf8_lossy(&output.stderr));
+        let hash = stdout.lines().last().unwrap_or_default();
+        if hash != rom.hash {
+            mismatches.push(format!("{}: expected {}, got {}", rom.path, rom.hash, hash));
+        }
+    }
+    assert!(mismatches.is_empty(), "Screens differ from golden.toml\n{}", mismatches.join("\n"));
+}
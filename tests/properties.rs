@@ -0,0 +1,141 @@
+//! Property tests for the arithmetic instructions and the flags they leave in VF, run through
+//! `Chip8::execute_opcode` on random register values, and with the `jit` feature for compiled
+//! blocks doing what the interpreter does.
+
+use chip_8_emu::chip8::Chip8;
+use proptest::prelude::*;
+
+/// A machine with VX and VY set, after running `8XYN`, with VX and VY as they were before
+fn alu(x: usize, y: usize, n: u16, vx: u8, vy: u8) -> (Chip8, u8, u8) {
+    let mut chip8 = Chip8::builder().seed(0).build();
+    chip8.set_register(x, vx);
+    chip8.set_register(y, vy);
+    let before = chip8.registers();
+    chip8.execute_opcode(0x8000 | (x as u16) << 8 | (y as u16) << 4 | n).unwrap();
+    (chip8, before[x], before[y])
+}
+
+proptest! {
+    /// 8XY4 wraps the sum and flags the carry
+    #[test]
+    fn add_sets_carry(x in 0..15usize, y in 0..15usize, vx: u8, vy: u8) {
+        let (chip8, vx, vy) = alu(x, y, 4, vx, vy);
+        let (sum, carry) = vx.overflowing_add(vy);
+        prop_assert_eq!(chip8.registers()[x], sum);
+        prop_assert_eq!(chip8.registers()[0xF], carry as u8);
+    }
+
+    /// 8XY5 wraps the difference and flags there being no borrow
+    #[test]
+    fn sub_sets_no_borrow(x in 0..15usize, y in 0..15usize, vx: u8, vy: u8) {
+        let (chip8, vx, vy) = alu(x, y, 5, vx, vy);
+        prop_assert_eq!(chip8.registers()[x], vx.wrapping_sub(vy));
+        prop_assert_eq!(chip8.registers()[0xF], (vx >= vy) as u8);
+    }
+
+    /// 8XY7 is 8XY5 the other way around
+    #[test]
+    fn sub_reverse_sets_no_borrow(x in 0..15usize, y in 0..15usize, vx: u8, vy: u8) {
+        let (chip8, vx, vy) = alu(x, y, 7, vx, vy);
+        prop_assert_eq!(chip8.registers()[x], vy.wrapping_sub(vx));
+        prop_assert_eq!(chip8.registers()[0xF], (vy >= vx) as u8);
+    }
+
+    /// 8XY6 and 8XYE shift VX and put the bit shifted out in VF
+    #[test]
+    fn shifts_keep_the_bit_out(x in 0..15usize, y in 0..15usize, vx: u8) {
+        let (chip8, vx, _) = alu(x, y, 6, vx, 0);
+        prop_assert_eq!(chip8.registers()[x], vx >> 1);
+        prop_assert_eq!(chip8.registers()[0xF], vx & 1);
+        let (chip8, vx, _) = alu(x, y, 0xE, vx, 0);
+        prop_assert_eq!(chip8.registers()[x], vx << 1);
+        prop_assert_eq!(chip8.registers()[0xF], vx >> 7);
+    }
+
+    /// The logic operations leave VF alone
+    #[test]
+    fn logic_keeps_vf(x in 0..15usize, y in 0..15usize, n in 1..4u16, vx: u8, vy: u8, vf: u8) {
+        let mut chip8 = Chip8::builder().seed(0).build();
+        chip8.set_register(0xF, vf);
+        chip8.set_register(x, vx);
+        chip8.set_register(y, vy);
+        let before = chip8.registers();
+        chip8.execute_opcode(0x8000 | (x as u16) << 8 | (y as u16) << 4 | n).unwrap();
+        let expected = match n {
+            1 => before[x] | before[y],
+            2 => before[x] & before[y],
+            _ => before[x] ^ before[y],
+        };
+        prop_assert_eq!(chip8.registers()[x], expected);
+        prop_assert_eq!(chip8.registers()[0xF], vf);
+    }
+
+    /// 7XNN wraps without touching VF
+    #[test]
+    fn add_immediate_keeps_vf(x in 0..15usize, vx: u8, nn: u8, vf: u8) {
+        let mut chip8 = Chip8::builder().seed(0).build();
+        chip8.set_register(0xF, vf);
+        chip8.set_register(x, vx);
+        chip8.execute_opcode(0x7000 | (x as u16) << 8 | nn as u16).unwrap();
+        prop_assert_eq!(chip8.registers()[x], vx.wrapping_add(nn));
+        prop_assert_eq!(chip8.registers()[0xF], vf);
+    }
+
+    /// FX33 writes the hundreds, tens and ones of VX at I
+    #[test]
+    fn bcd_digits(x in 0..16usize, vx: u8, index in 0x200..0xFFDu16) {
+        let mut chip8 = Chip8::builder().seed(0).build();
+        chip8.set_register(x, vx);
+        chip8.set_index(index);
+        chip8.execute_opcode(0xF033 | (x as u16) << 8).unwrap();
+        let index = index as usize;
+        prop_assert_eq!(&chip8.memory()[index..index + 3], &[vx / 100, vx / 10 % 10, vx % 10]);
+        prop_assert_eq!(chip8.program_counter(), 0x202);
+    }
+}
+
+/// An instruction the JIT compiles, from its opcode's first nibble and the random rest
+#[cfg(feature = "jit")]
+fn compiled_opcode(kind: u8, rest: u16) -> u16 {
+    let rest = rest & 0x0FFF;
+    match kind {
+        0 => 0x3000 | rest,
+        1 => 0x4000 | rest,
+        2 => 0x5000 | rest & 0x0FF0,
+        3 => 0x6000 | rest,
+        4 => 0x7000 | rest,
+        5 => 0x8000 | rest & 0x0FF0 | [0, 1, 2, 3, 4, 5, 6, 7, 0xE][rest as usize % 9],
+        6 => 0x9000 | rest & 0x0FF0,
+        7 => 0xA000 | rest,
+        _ => 0xF01E | rest & 0x0F00,
+    }
+}
+
+#[cfg(feature = "jit")]
+proptest! {
+    /// Programs of compiled instructions end the same run by the JIT as by the interpreter
+    #[test]
+    fn jit_matches_interpreter(opcodes in prop::collection::vec((0..9u8, any::<u16>()), 1..40), registers: [u8; 16], instructions in 1..200u64) {
+        // Looping back to the start, twice for a skip over the first jump
+        let mut program: Vec<u8> = opcodes.iter().flat_map(|(kind, rest)| compiled_opcode(*kind, *rest).to_be_bytes()).collect();
+        program.extend([0x12, 0x00, 0x12, 0x00]);
+        let mut interpreted = Chip8::builder().seed(0).build();
+        interpreted.load_program(&program);
+        for (x, value) in registers.iter().enumerate() {
+            interpreted.set_register(x, *value);
+        }
+        // DT = V0
+        interpreted.execute_opcode(0xF015).unwrap();
+        let mut compiled = interpreted.clone();
+        for _ in 0..instructions {
+            interpreted.try_emulate_cycle().unwrap();
+        }
+        let mut jit = chip_8_emu::chip8::jit::Jit::new().unwrap();
+        let mut left = instructions;
+        while left > 0 {
+            left -= jit.step(&mut compiled, left).unwrap();
+        }
+        let state = |chip8: &Chip8| (chip8.registers(), chip8.index(), chip8.program_counter(), chip8.timers(), chip8.cycles());
+        prop_assert_eq!(state(&interpreted), state(&compiled));
+    }
+}